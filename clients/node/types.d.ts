@@ -148,16 +148,82 @@ export interface paths {
         patch?: never;
         trace?: never;
     };
+    "/languages": {
+        parameters: {
+            query?: never;
+            header?: never;
+            path?: never;
+            cookie?: never;
+        };
+        /**
+         * List languages
+         * @description List the languages on this server, with their versions, files, function input/output and resource limits
+         */
+        get: operations["list_languages"];
+        put?: never;
+        post?: never;
+        delete?: never;
+        options?: never;
+        head?: never;
+        patch?: never;
+        trace?: never;
+    };
+    "/languages/{lang}/versions": {
+        parameters: {
+            query?: never;
+            header?: never;
+            path?: never;
+            cookie?: never;
+        };
+        /**
+         * Get language versions
+         * @description Get the available versions of a language, and its default version
+         */
+        get: operations["get_language_versions"];
+        put?: never;
+        post?: never;
+        delete?: never;
+        options?: never;
+        head?: never;
+        patch?: never;
+        trace?: never;
+    };
 }
 export type webhooks = Record<string, never>;
 export interface components {
     schemas: {
         /** @description Options for the code runner */
         CodeRunnerInput: {
-            /** @description The code to run */
+            /**
+             * @description The code to run. Can be omitted if the entrypoint is one of the `sources`.
+             */
             code: string;
+            /**
+             * @description Additional source files of a multi-file program (e.g. modules or packages),
+             *     placed next to the entrypoint in the language's source directory.
+             */
+            sources?: components["schemas"]["SourceFile"][] | null;
+            /**
+             * @description Path of the file in `sources` to run as the entrypoint, instead of `code`.
+             *     The entrypoint must be in the top-level source directory.
+             * @example main.py
+             */
+            entrypoint?: string | null;
             /** @description Language of the code */
             lang: components["schemas"]["CodeRunnerLanguage"];
+            /**
+             * @description Version of the language (e.g. `3.12` for Python). The language's default
+             *     version is used if omitted.
+             * @example 3.12
+             */
+            version?: string | null;
+            /** @description Base64 encoded WASI module (`.wasm`) to run, for the `wasm` language */
+            module?: string | null;
+            /**
+             * @description Runtime to run the code in
+             * @default container
+             */
+            runtime: components["schemas"]["CodeRunnerRuntime"];
             /**
              * @description Dependencies for the code execution. Versions and features can be specified
              *     depending on the language's package manager.
@@ -177,6 +243,28 @@ export interface components {
              *     under the `./files` directory.
              */
             files?: components["schemas"]["CodeRunnerFile"][] | null;
+            /** @description Input to write to the program's stdin */
+            stdin?: string | null;
+            /**
+             * @description Command-line arguments appended to the language's command
+             * @example [
+             *       "--verbose",
+             *       "input.txt"
+             *     ]
+             */
+            args?: string[] | null;
+            /**
+             * @description Environment variables for the program. Variables needed by the runtime,
+             *     such as `PATH` and `HOME`, cannot be set.
+             */
+            env?: {
+                [key: string]: string;
+            } | null;
+            /**
+             * @description Name of the egress allowlist configured on the server. Network access is
+             *     disabled if omitted.
+             */
+            egress?: string | null;
             /**
              * Format: uint32
              * @description Timeout for the code execution in seconds
@@ -195,12 +283,27 @@ export interface components {
              * @default 0.5
              */
             cpu_limit: number;
+            /**
+             * Format: uint32
+             * @description Maximum number of bytes of stdout and stderr (each) to include in the result.
+             *     Streamed output is not limited.
+             * @default 1048576
+             */
+            max_output_bytes: number;
         };
         /**
-         * @description Supported languages for the code runner
+         * @description ID of a language for the code runner. The built-in languages are `bash`, `go`,
+         *     `javascript`, `python`, `rust` and `typescript`, and the server can define more.
+         *     The `wasm` language is a precompiled WASI module, provided as the `module` of the
+         *     input, and always runs in the WebAssembly runtime.
+         * @example python
+         */
+        CodeRunnerLanguage: string;
+        /**
+         * @description Runtime that runs the code
          * @enum {string}
          */
-        CodeRunnerLanguage: "bash" | "go" | "javascript" | "python" | "rust" | "typescript";
+        CodeRunnerRuntime: "container" | "wasm";
         CodeRunnerFile: {
             /**
              * @description Path of the file relative to the `./files` directory. Must be
@@ -214,6 +317,21 @@ export interface components {
         };
         /** @description Chunk of the code runner stream output */
         CodeRunnerChunk: {
+            /** @constant */
+            event: "run_id";
+            data: string;
+        } | {
+            /** @constant */
+            event: "function_version";
+            data: {
+                /** Format: uint32 */
+                version: number;
+                /** @description Alias that selected the version, if the function was run through an alias */
+                alias?: string | null;
+                /** @description Whether the version was selected as the canary version of the alias */
+                canary: boolean;
+            };
+        } | {
             /** @constant */
             event: "info";
             data: string;
@@ -229,6 +347,29 @@ export interface components {
             /** @constant */
             event: "stderr";
             data: string;
+        } | {
+            /** @constant */
+            event: "stdout_binary";
+            data: string;
+        } | {
+            /** @constant */
+            event: "stderr_binary";
+            data: string;
+        } | {
+            /** @constant */
+            event: "queued";
+            data: {
+                stage: components["schemas"]["ExecutionStage"];
+                /**
+                 * Format: uint32
+                 * @description Position in the queue, starting from 1
+                 */
+                position: number;
+            };
+        } | {
+            /** @constant */
+            event: "artifact";
+            data: components["schemas"]["Artifact"];
         } | {
             /** @constant */
             event: "error";
@@ -236,14 +377,140 @@ export interface components {
         } | {
             /** @constant */
             event: "result";
-            data: {
-                stdout: string;
-                stderr: string;
-                /** Format: int64 */
-                exit_code?: number | null;
-                timeout: boolean;
-            };
+            data: components["schemas"]["CodeRunnerResult"];
+        };
+        RunFunctionOutput: components["schemas"]["CodeRunnerResult"] & {
+            /**
+             * Format: uint32
+             * @description Version of the function that was run
+             */
+            version?: number | null;
+            /** @description Files written to the output directory */
+            artifacts: components["schemas"]["Artifact"][];
+        };
+        /** @description Result of a code execution */
+        CodeRunnerResult: {
+            /**
+             * @description Stdout of the program. Invalid UTF-8 sequences are replaced, see `stdout_binary`
+             *     for the exact bytes.
+             */
+            stdout: string;
+            /**
+             * @description Stderr of the program. Invalid UTF-8 sequences are replaced, see `stderr_binary`
+             *     for the exact bytes.
+             */
+            stderr: string;
+            /** @description Exact bytes of stdout, base64 encoded. Only present if stdout isn't valid UTF-8. */
+            stdout_binary?: string | null;
+            /** @description Exact bytes of stderr, base64 encoded. Only present if stderr isn't valid UTF-8. */
+            stderr_binary?: string | null;
+            /** @description Whether stdout exceeded the output limit and was truncated */
+            stdout_truncated: boolean;
+            /** @description Whether stderr exceeded the output limit and was truncated */
+            stderr_truncated: boolean;
+            /** Format: int64 */
+            exit_code?: number | null;
+            /** @description Whether the execution exceeded the time limit */
+            timeout: boolean;
+            termination: components["schemas"]["TerminationReason"];
+            usage: components["schemas"]["ResourceUsage"];
+            /** @description Return value of a function in JSON mode */
+            output?: unknown;
+            /** @description Error thrown by a function in JSON mode */
+            error?: components["schemas"]["FunctionError"] | null;
+        };
+        /** @description Error thrown by a function in JSON mode */
+        FunctionError: {
+            /**
+             * @description Type of the error, e.g. the name of the exception class
+             * @example ValueError
+             */
+            type: string;
+            message: string;
+        };
+        /** @description Reason the program terminated */
+        TerminationReason: {
+            /** @constant */
+            type: "exited";
+        } | {
+            /** @constant */
+            type: "oom_killed";
+        } | {
+            /** @description Name of the signal, e.g. `SIGSEGV` */
+            signal: string;
+            /** Format: int64 */
+            number: number;
+            /** @constant */
+            type: "signal";
+        } | {
+            /** @constant */
+            type: "timeout";
+        } | {
+            /** @constant */
+            type: "server_timeout";
+        } | {
+            /** @constant */
+            type: "unknown";
+        };
+        /**
+         * @description Resource usage of an execution, collected from the container stats. Values
+         *     are missing if they were not reported by Docker (e.g. for very short runs).
+         */
+        ResourceUsage: {
+            /**
+             * Format: uint64
+             * @description Wall-clock duration of the execution in milliseconds
+             */
+            wall_time_ms: number;
+            /**
+             * Format: uint64
+             * @description CPU time used in milliseconds
+             */
+            cpu_time_ms?: number | null;
+            /**
+             * Format: uint64
+             * @description Peak memory usage in bytes
+             */
+            peak_memory_bytes?: number | null;
+            /**
+             * Format: uint64
+             * @description Peak number of processes and threads
+             */
+            peak_pids?: number | null;
+            /**
+             * Format: uint64
+             * @description Bytes written to disk
+             */
+            bytes_written?: number | null;
         };
+        /** @description A file written by the program to the output directory */
+        Artifact: {
+            /**
+             * @description Path of the file relative to the output directory
+             * @example result.png
+             */
+            path: string;
+            /**
+             * Format: uint64
+             * @description Size of the file in bytes
+             */
+            size: number;
+            /**
+             * @description Base64 encoded content of the file. Not included for large files, which
+             *     can be downloaded from `download_url` instead.
+             */
+            content?: string | null;
+            /**
+             * @description URL path (relative to the server) to download a large file. The file is
+             *     only kept for a limited time.
+             */
+            download_url?: string | null;
+        };
+        /**
+         * @description Stage of an execution that is limited by the server's concurrency limits
+         * @enum {string}
+         */
+        ExecutionStage: "build" | "run";
         CodeRunnerError: {
             message: string;
             logs: string;
@@ -258,12 +525,26 @@ export interface components {
             image_tag: string;
             /** @constant */
             error: "function_image_not_found";
+        } | {
+            message: string;
+            /** @constant */
+            error: "cancelled";
+        } | {
+            message: string;
+            /** @constant */
+            error: "sandbox";
+        } | {
+            message: string;
+            /** @constant */
+            error: "queue_full";
         };
         CreateFunctionInput: {
             /** @description Name of the function to create */
             name: string;
             /** @description Language of the function */
             language: components["schemas"]["CodeRunnerLanguage"];
+            /** @description Version of the language. The language's default version is used if omitted. */
+            version?: string | null;
         };
         FunctionItem: {
             name: string;
@@ -272,7 +553,11 @@ export interface components {
         /** @description Selected function info stored in Redis */
         FunctionInfo: {
             lang: components["schemas"]["CodeRunnerLanguage"];
+            lang_version?: string | null;
             description?: string | null;
+            io?: components["schemas"]["FunctionIo"];
+            input_schema?: unknown;
+            output_schema?: unknown;
             status: components["schemas"]["FunctionStatus"];
             /** Format: date-time */
             created_at: string;
@@ -304,9 +589,23 @@ export interface components {
         /** @description Full function info stored in Redis */
         FunctionDetail: {
             code: string;
+            /** @description Additional source files of the function */
+            sources?: components["schemas"]["SourceFile"][] | null;
             lang: components["schemas"]["CodeRunnerLanguage"];
+            /** @description Version of the language, if not the default version */
+            lang_version?: string | null;
             description?: string | null;
             dependencies?: string | null;
+            /** @description Name of the egress allowlist used for the function's runs */
+            egress?: string | null;
+            /** @description Name of the sandbox profile used for the function's runs */
+            sandbox?: string | null;
+            /** @description How the function receives its input and returns its output */
+            io?: components["schemas"]["FunctionIo"];
+            /** @description JSON Schema of the function's input */
+            input_schema?: unknown;
+            /** @description JSON Schema of the function's output */
+            output_schema?: unknown;
             status: components["schemas"]["FunctionStatus"];
             /** Format: date-time */
             created_at: string;
@@ -314,11 +613,36 @@ export interface components {
             updated_at: string;
             version: string;
         };
+        /** @description A source file of a multi-file program */
+        SourceFile: {
+            /**
+             * @description Path of the file relative to the language's source directory (e.g. `src/`
+             *     for Rust). Must be a relative path, and cannot contain `..` or `.`
+             * @example utils.py
+             */
+            path: string;
+            /** @description Content of the file */
+            content: string;
+        };
         UpdateFunctionInput: {
-            /** @description New code for the function */
+            /**
+             * @description New code for the function. Can be omitted if the entrypoint is one of the `sources`.
+             */
             code: string;
+            /**
+             * @description Additional source files of the function (e.g. modules or packages), placed
+             *     next to the function file in the language's source directory.
+             */
+            sources?: components["schemas"]["SourceFile"][] | null;
+            /**
+             * @description Path of the file in `sources` to use as the function file, instead of `code`.
+             *     The entrypoint must be in the top-level source directory.
+             */
+            entrypoint?: string | null;
             /** @description An optional description for the function */
             description?: string | null;
+            /** @description Version of the language. The language's default version is used if omitted. */
+            version?: string | null;
             /**
              * @description Dependencies for the code execution. Versions and features can be specified
              *     depending on the language's package manager.
@@ -333,10 +657,43 @@ export interface components {
              *     ]
              */
             dependencies?: string[] | null;
+            /**
+             * @description Name of the egress allowlist configured on the server, used for the function's
+             *     runs. Network access is disabled if omitted.
+             */
+            egress?: string | null;
+            /**
+             * @description Name of the sandbox profile configured on the server, used for the function's
+             *     runs instead of the language's profile
+             */
+            sandbox?: string | null;
+            /** @description How the function receives its input and returns its output */
+            io?: components["schemas"]["FunctionIo"];
+            /**
+             * @description JSON Schema that the input of each run must match. Runs with invalid input are
+             *     rejected before the function is started.
+             */
+            input_schema?: unknown;
+            /** @description JSON Schema that the output of each run is validated against. Requires JSON mode. */
+            output_schema?: unknown;
         };
+        /** @description How a function receives its input and returns its output */
+        FunctionIo: "text" | "json";
         RunFunctionInput: {
-            /** @description Input to the function */
-            input: string;
+            /**
+             * @description Input to the function. Must be a string for functions in text mode, and can be
+             *     any JSON value for functions in JSON mode.
+             * @example world
+             */
+            input: unknown;
+            /**
+             * Format: uint32
+             * @description Version of the function to run, instead of the current version. Previous versions
+             *     can be run while they are kept in the function's version history.
+             */
+            version?: number | null;
+            /** @description Name of the egress allowlist to use for this run, instead of the function's allowlist */
+            egress?: string | null;
             /**
              * Format: uint32
              * @description Timeout for the code execution in seconds
@@ -355,13 +712,81 @@ export interface components {
              * @default 0.5
              */
             cpu_limit: number;
+            /**
+             * Format: uint32
+             * @description Maximum number of bytes of stdout and stderr (each) to include in the result.
+             *     Streamed output is not limited.
+             * @default 1048576
+             */
+            max_output_bytes: number;
         };
-        RunFunctionOutput: {
-            stdout: string;
-            stderr: string;
-            /** Format: int64 */
-            exit_code?: number | null;
-            timeout: boolean;
+        /** @description Info about a language, for clients */
+        LanguageInfo: {
+            /** @description ID of the language, used as the `lang` of runs and functions */
+            id: components["schemas"]["CodeRunnerLanguage"];
+            /** @description Name of the version used if none is selected */
+            default_version: string;
+            versions: components["schemas"]["LanguageVersionInfo"][];
+            /** @description The base Docker image of the default version */
+            image: string;
+            /** @description Name of the entrypoint file of one-off code */
+            main_file: string;
+            /** @description Name of the function file */
+            fn_file: string;
+            /** @description Sample function file, used as the code of new functions */
+            fn_template: string;
+            /** @description How functions receive their input and return their output */
+            function: components["schemas"]["FunctionContract"];
+            /** @description Default resource limits of runs */
+            default_limits: components["schemas"]["ResourceLimits"];
+            /** @description Maximum resource limits of runs */
+            max_limits: components["schemas"]["ResourceLimits"];
+            /** @description Whether dependencies can be installed */
+            dependencies: boolean;
+        };
+        /** @description A version of a language */
+        LanguageVersionInfo: {
+            /** @description Name of the version, e.g. `3.13` */
+            name: string;
+            /** @description The base Docker image of the version */
+            image: string;
+        };
+        /** @description How a function receives its input and returns its output */
+        FunctionContract: {
+            input: string;
+            output: string;
+        };
+        /** @description Resource limits of a run */
+        ResourceLimits: {
+            /**
+             * Format: uint32
+             * @description Timeout for the code execution in seconds
+             */
+            timeout: number;
+            /**
+             * Format: uint32
+             * @description Memory limit for the code execution in megabytes
+             */
+            mem_limit_mb: number;
+            /**
+             * Format: float
+             * @description CPU quota (1.0 = 1 CPU core)
+             */
+            cpu_limit: number;
+            /**
+             * Format: uint32
+             * @description Maximum number of bytes of stdout and stderr (each) to include in the result
+             */
+            max_output_bytes: number;
+        };
+        /** @description Available versions of a language */
+        LanguageVersions: {
+            /** @description Name of the version used if none is selected */
+            default_version: string;
+            versions: components["schemas"]["LanguageVersionInfo"][];
+        };
+        LanguagePath: {
+            lang: components["schemas"]["CodeRunnerLanguage"];
         };
     };
     responses: never;
@@ -565,4 +990,45 @@ export interface operations {
             };
         };
     };
+    list_languages: {
+        parameters: {
+            query?: never;
+            header?: never;
+            path?: never;
+            cookie?: never;
+        };
+        requestBody?: never;
+        responses: {
+            200: {
+                headers: {
+                    [name: string]: unknown;
+                };
+                content: {
+                    "application/json": components["schemas"]["LanguageInfo"][];
+                };
+            };
+        };
+    };
+    get_language_versions: {
+        parameters: {
+            query?: never;
+            header?: never;
+            path: {
+                lang: components["schemas"]["CodeRunnerLanguage"];
+            };
+            cookie?: never;
+        };
+        requestBody?: never;
+        responses: {
+            /** @description Available versions of a language */
+            200: {
+                headers: {
+                    [name: string]: unknown;
+                };
+                content: {
+                    "application/json": components["schemas"]["LanguageVersions"];
+                };
+            };
+        };
+    };
 }
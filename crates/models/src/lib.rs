@@ -1,32 +1,105 @@
 //! Shared models for the tinirun API
 
-use std::path::{Component, Path, PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+};
 
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_with::{base64::Base64, serde_as};
 use validator::{Validate, ValidationError};
 
-/// Supported languages for the code runner
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+/// ID of a language for the code runner. The built-in languages are `bash`, `go`,
+/// `javascript`, `python`, `rust` and `typescript`, and the server can define more.
+/// The `wasm` language is a precompiled WASI module, provided as the `module` of the
+/// input, and always runs in the WebAssembly runtime.
+#[derive(
+    Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(transparent)]
+#[schemars(example = &"python")]
+pub struct CodeRunnerLanguage(String);
+
+impl CodeRunnerLanguage {
+    /// ID of the language for precompiled WASI modules
+    pub const WASM: &str = "wasm";
+
+    pub fn new(id: impl Into<String>) -> Self {
+        Self(id.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether this is the language for precompiled WASI modules
+    pub fn is_wasm(&self) -> bool {
+        self.0 == Self::WASM
+    }
+}
+
+impl Default for CodeRunnerLanguage {
+    fn default() -> Self {
+        Self::new("javascript")
+    }
+}
+
+impl std::fmt::Display for CodeRunnerLanguage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<&str> for CodeRunnerLanguage {
+    fn from(id: &str) -> Self {
+        Self::new(id)
+    }
+}
+
+/// Runtime that runs the code
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "lowercase")]
-pub enum CodeRunnerLanguage {
-    Bash,
-    Go,
+pub enum CodeRunnerRuntime {
+    /// Run the code in a container, or in the local sandbox if the server uses
+    /// the local backend
     #[default]
-    JavaScript,
-    Python,
-    Rust,
-    TypeScript,
+    Container,
+    /// Run the code in the server's in-process WebAssembly (WASI) runtime, with the
+    /// language's interpreter. Starts much faster, but dependencies are not supported.
+    Wasm,
 }
 
 /// Options for the code runner
-#[derive(Debug, Default, Serialize, Deserialize, JsonSchema, Validate)]
+#[serde_as]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema, Validate)]
+#[validate(schema(function = "validate_code_input"))]
 pub struct CodeRunnerInput {
-    /// The code to run
+    /// The code to run. Can be omitted if the entrypoint is one of the `sources`.
+    #[serde(default)]
     pub code: String,
+    /// Additional source files of a multi-file program (e.g. modules or packages),
+    /// placed next to the entrypoint in the language's source directory.
+    #[validate(length(max = 50), nested)]
+    pub sources: Option<Vec<SourceFile>>,
+    /// Path of the file in `sources` to run as the entrypoint, instead of `code`.
+    /// The entrypoint must be in the top-level source directory.
+    #[schemars(example = "main.py")]
+    pub entrypoint: Option<PathBuf>,
     /// Language of the code
     pub lang: CodeRunnerLanguage,
+    /// Version of the language (e.g. `3.12` for Python). The language's default
+    /// version is used if omitted.
+    #[schemars(example = "3.12")]
+    pub version: Option<String>,
+    /// Base64 encoded WASI module (`.wasm`) to run, for the `wasm` language
+    #[serde(default)]
+    #[serde_as(as = "Option<Base64>")]
+    #[schemars(with = "Option<String>")]
+    pub module: Option<Vec<u8>>,
+    /// Runtime to run the code in
+    #[serde(default)]
+    pub runtime: CodeRunnerRuntime,
     /// Dependencies for the code execution. Versions and features can be specified
     /// depending on the language's package manager.
     #[schemars(example = vec!["lodash"])]
@@ -36,31 +109,101 @@ pub struct CodeRunnerInput {
     /// under the `./files` directory.
     #[validate(length(max = 50))]
     pub files: Option<Vec<CodeRunnerFile>>,
+    /// Input to write to the program's stdin
+    pub stdin: Option<String>,
+    /// Command-line arguments appended to the language's command
+    #[validate(length(max = 50))]
+    #[schemars(example = vec!["--verbose", "input.txt"])]
+    pub args: Option<Vec<String>>,
+    /// Environment variables for the program. Variables needed by the runtime,
+    /// such as `PATH` and `HOME`, cannot be set.
+    #[validate(length(max = 50))]
+    pub env: Option<HashMap<String, String>>,
+    /// Name of the egress allowlist configured on the server. Network access is
+    /// disabled if omitted.
+    pub egress: Option<String>,
     /// Timeout for the code execution in seconds
     #[serde(default = "default_timeout")]
-    #[validate(range(min = 5, max = 300))]
+    #[validate(range(
+        min = ResourceLimits::MIN.timeout,
+        max = ResourceLimits::MAX.timeout
+    ))]
     pub timeout: u32,
     /// Memory limit for the code execution in megabytes
     #[serde(default = "default_mem_limit")]
-    #[validate(range(min = 1, max = 2048))]
+    #[validate(range(
+        min = ResourceLimits::MIN.mem_limit_mb,
+        max = ResourceLimits::MAX.mem_limit_mb
+    ))]
     pub mem_limit_mb: u32,
     /// CPU quota (1.0 = 1 CPU core)
     #[serde(default = "default_cpu_limit")]
-    #[validate(range(min = 0.1, max = 4.0))]
+    #[validate(range(
+        min = ResourceLimits::MIN.cpu_limit,
+        max = ResourceLimits::MAX.cpu_limit
+    ))]
     pub cpu_limit: f32,
+    /// Maximum number of bytes of stdout and stderr (each) to include in the result.
+    /// Streamed output is not limited.
+    #[serde(default = "default_max_output_bytes")]
+    #[validate(range(
+        min = ResourceLimits::MIN.max_output_bytes,
+        max = ResourceLimits::MAX.max_output_bytes
+    ))]
+    pub max_output_bytes: u32,
 }
 fn default_timeout() -> u32 {
-    60
+    ResourceLimits::DEFAULT.timeout
 }
 fn default_mem_limit() -> u32 {
-    256
+    ResourceLimits::DEFAULT.mem_limit_mb
 }
 fn default_cpu_limit() -> f32 {
-    0.5
+    ResourceLimits::DEFAULT.cpu_limit
+}
+fn default_max_output_bytes() -> u32 {
+    ResourceLimits::DEFAULT.max_output_bytes
+}
+
+/// Resource limits of a run
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ResourceLimits {
+    /// Timeout for the code execution in seconds
+    pub timeout: u32,
+    /// Memory limit for the code execution in megabytes
+    pub mem_limit_mb: u32,
+    /// CPU quota (1.0 = 1 CPU core)
+    pub cpu_limit: f32,
+    /// Maximum number of bytes of stdout and stderr (each) to include in the result
+    pub max_output_bytes: u32,
+}
+
+impl ResourceLimits {
+    /// Limits used if they're omitted from the input
+    pub const DEFAULT: Self = Self {
+        timeout: 60,
+        mem_limit_mb: 256,
+        cpu_limit: 0.5,
+        max_output_bytes: 1024 * 1024,
+    };
+    /// Lowest limits that can be selected
+    pub const MIN: Self = Self {
+        timeout: 5,
+        mem_limit_mb: 1,
+        cpu_limit: 0.1,
+        max_output_bytes: 1,
+    };
+    /// Highest limits that can be selected
+    pub const MAX: Self = Self {
+        timeout: 300,
+        mem_limit_mb: 2048,
+        cpu_limit: 4.0,
+        max_output_bytes: 10 * 1024 * 1024,
+    };
 }
 
 #[serde_as]
-#[derive(Debug, Serialize, Deserialize, JsonSchema, Validate)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
 pub struct CodeRunnerFile {
     /// Path of the file relative to the `./files` directory. Must be
     /// a relative path, and cannot contain `..` or `.`
@@ -73,6 +216,95 @@ pub struct CodeRunnerFile {
     #[schemars(with = "String")]
     pub content: Vec<u8>,
 }
+/// A source file of a multi-file program
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct SourceFile {
+    /// Path of the file relative to the language's source directory (e.g. `src/`
+    /// for Rust). Must be a relative path, and cannot contain `..` or `.`
+    #[validate(custom(function = "validate_path"))]
+    #[schemars(example = "utils.py")]
+    #[schemars(example = "utils/mod.rs")]
+    pub path: PathBuf,
+    /// Content of the file
+    pub content: String,
+}
+
+/// Split the code and source files into the code of the entrypoint and the
+/// remaining source files. Expects the input to already be validated.
+pub fn split_entrypoint(
+    code: String,
+    sources: Option<Vec<SourceFile>>,
+    entrypoint: Option<&Path>,
+) -> (String, Vec<SourceFile>) {
+    let mut sources = sources.unwrap_or_default();
+    let entry_idx = entrypoint.and_then(|entry| sources.iter().position(|s| s.path == entry));
+    match entry_idx {
+        Some(idx) => (sources.remove(idx).content, sources),
+        None => (code, sources),
+    }
+}
+
+/// Split a function reference of the form `name@alias` into the function name and
+/// the alias, if any
+pub fn split_fn_alias(name: &str) -> (&str, Option<&str>) {
+    match name.split_once('@') {
+        Some((name, alias)) => (name, Some(alias)),
+        None => (name, None),
+    }
+}
+
+fn validate_code_input(input: &CodeRunnerInput) -> Result<(), ValidationError> {
+    validate_sources(
+        &input.code,
+        input.sources.as_deref(),
+        input.entrypoint.as_deref(),
+    )?;
+    let is_wasm = input.lang.is_wasm();
+    if is_wasm && input.module.is_none() {
+        Err(ValidationError::new("module_missing")
+            .with_message("module is required for the wasm language".into()))
+    } else if is_wasm && (!input.code.is_empty() || input.sources.is_some()) {
+        Err(ValidationError::new("module_with_code")
+            .with_message("cannot provide code or sources with a module".into()))
+    } else if !is_wasm && input.module.is_some() {
+        Err(ValidationError::new("module_language")
+            .with_message("module can only be provided for the wasm language".into()))
+    } else {
+        Ok(())
+    }
+}
+fn validate_fn_input_sources(input: &UpdateFunctionInput) -> Result<(), ValidationError> {
+    validate_sources(&input.code, input.sources.as_deref(), input.entrypoint.as_deref())
+}
+fn validate_sources(
+    code: &str,
+    sources: Option<&[SourceFile]>,
+    entrypoint: Option<&Path>,
+) -> Result<(), ValidationError> {
+    let sources = sources.unwrap_or_default();
+    for (idx, source) in sources.iter().enumerate() {
+        if sources[..idx].iter().any(|s| s.path == source.path) {
+            return Err(ValidationError::new("duplicate_source")
+                .with_message(format!("duplicate source file {:?}", source.path).into()));
+        }
+    }
+    let Some(entrypoint) = entrypoint else {
+        return Ok(());
+    };
+    if !sources.iter().any(|s| s.path == entrypoint) {
+        Err(ValidationError::new("entrypoint_not_found")
+            .with_message("entrypoint must be one of the source files".into()))
+    } else if entrypoint.components().count() != 1 {
+        Err(ValidationError::new("entrypoint_nested")
+            .with_message("entrypoint must be in the top-level source directory".into()))
+    } else if !code.is_empty() {
+        Err(ValidationError::new("entrypoint_with_code")
+            .with_message("cannot provide both code and an entrypoint".into()))
+    } else {
+        Ok(())
+    }
+}
+
 fn validate_path(path: &PathBuf) -> Result<(), ValidationError> {
     if path.is_absolute() {
         Err(ValidationError::new("absolute_path").with_message("file path must be relative".into()))
@@ -99,43 +331,387 @@ pub struct CreateFunctionInput {
     pub name: String,
     /// Language of the function
     pub language: CodeRunnerLanguage,
+    /// Version of the language. The language's default version is used if omitted.
+    pub version: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema, Validate)]
+#[validate(schema(function = "validate_fn_input_sources"))]
 pub struct UpdateFunctionInput {
-    /// New code for the function
+    /// New code for the function. Can be omitted if the entrypoint is one of the `sources`.
+    #[serde(default)]
     pub code: String,
+    /// Additional source files of the function (e.g. modules or packages), placed
+    /// next to the function file in the language's source directory.
+    #[validate(length(max = 50), nested)]
+    pub sources: Option<Vec<SourceFile>>,
+    /// Path of the file in `sources` to use as the function file, instead of `code`.
+    /// The entrypoint must be in the top-level source directory.
+    pub entrypoint: Option<PathBuf>,
     /// An optional description for the function
     pub description: Option<String>,
+    /// Version of the language. The language's default version is used if omitted.
+    pub version: Option<String>,
     /// Dependencies for the code execution. Versions and features can be specified
     /// depending on the language's package manager.
     #[schemars(example = vec!["lodash"])]
     #[schemars(example = vec!["serde=1.0", "tokio=1.0", "--features", "serde/derive"])]
     pub dependencies: Option<Vec<String>>,
+    /// Name of the egress allowlist configured on the server, used for the function's
+    /// runs. Network access is disabled if omitted.
+    pub egress: Option<String>,
+    /// Name of the sandbox profile configured on the server, used for the function's
+    /// runs instead of the language's profile
+    pub sandbox: Option<String>,
+    /// How the function receives its input and returns its output
+    #[serde(default)]
+    pub io: FunctionIo,
+    /// JSON Schema that the input of each run must match. Runs with invalid input are
+    /// rejected before the function is started.
+    pub input_schema: Option<serde_json::Value>,
+    /// JSON Schema that the output of each run is validated against. Requires JSON mode.
+    pub output_schema: Option<serde_json::Value>,
+}
+
+/// How a function receives its input and returns its output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FunctionIo {
+    /// The input is a string written to stdin, and the output is the printed result
+    #[default]
+    Text,
+    /// The input is a JSON value passed to the function, and the function's return
+    /// value is returned as `output`, separately from stdout. Errors thrown by the
+    /// function are returned as `error`.
+    Json,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct RollbackFunctionInput {
+    /// Previous version of the function to restore. It becomes a new version that
+    /// reuses the image of the previous version, so no rebuild is needed.
+    pub version: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct SetAliasInput {
+    /// Version of the function that the alias points to
+    pub version: u32,
+    /// Another version that receives a share of the alias's runs, e.g. to canary
+    /// a new version before pointing the alias at it
+    #[validate(nested)]
+    pub canary: Option<AliasCanary>,
+}
+
+/// Version that receives a share of an alias's runs
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct AliasCanary {
+    /// Version of the function
+    pub version: u32,
+    /// Percentage of the alias's runs that use this version
+    #[validate(range(min = 1, max = 99))]
+    pub weight: u8,
 }
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct SetSecretInput {
+    /// Value of the secret. It is encrypted at rest, and can't be retrieved.
+    #[validate(length(max = 65536))]
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
 pub struct RunFunctionInput {
-    /// Input to the function
-    pub input: String,
+    /// Input to the function. Must be a string for functions in text mode, and can be
+    /// any JSON value for functions in JSON mode.
+    #[schemars(example = &"world")]
+    pub input: serde_json::Value,
+    /// Version of the function to run, instead of the current version. Previous versions
+    /// can be run while they are kept in the function's version history.
+    pub version: Option<u32>,
+    /// Name of the egress allowlist to use for this run, instead of the function's allowlist
+    pub egress: Option<String>,
     /// Timeout for the code execution in seconds
     #[serde(default = "default_timeout")]
-    #[validate(range(min = 5, max = 300))]
+    #[validate(range(
+        min = ResourceLimits::MIN.timeout,
+        max = ResourceLimits::MAX.timeout
+    ))]
     pub timeout: u32,
     /// Memory limit for the code execution in megabytes
     #[serde(default = "default_mem_limit")]
-    #[validate(range(min = 1, max = 2048))]
+    #[validate(range(
+        min = ResourceLimits::MIN.mem_limit_mb,
+        max = ResourceLimits::MAX.mem_limit_mb
+    ))]
     pub mem_limit_mb: u32,
     /// CPU quota (1.0 = 1 CPU core)
     #[serde(default = "default_cpu_limit")]
-    #[validate(range(min = 0.1, max = 4.0))]
+    #[validate(range(
+        min = ResourceLimits::MIN.cpu_limit,
+        max = ResourceLimits::MAX.cpu_limit
+    ))]
     pub cpu_limit: f32,
+    /// Maximum number of bytes of stdout and stderr (each) to include in the result.
+    /// Streamed output is not limited.
+    #[serde(default = "default_max_output_bytes")]
+    #[validate(range(
+        min = ResourceLimits::MIN.max_output_bytes,
+        max = ResourceLimits::MAX.max_output_bytes
+    ))]
+    pub max_output_bytes: u32,
+}
+
+/// Input for judging a program against test cases
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct JudgeInput {
+    /// The program to judge. Its `timeout` and `mem_limit_mb` are the limits of each case
+    /// unless the case sets them, and its `stdin` is ignored.
+    #[validate(nested)]
+    pub program: CodeRunnerInput,
+    /// Test cases to run the program against, in order
+    #[validate(length(min = 1, max = 100), nested)]
+    pub cases: Vec<JudgeCase>,
+    /// How the output of each case is compared to the expected output
+    #[serde(default)]
+    pub compare: OutputComparison,
+    /// Program that checks the output of each case, instead of comparing it
+    pub checker: Option<JudgeChecker>,
+}
+
+/// A test case in judge mode
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct JudgeCase {
+    /// Input to write to the program's stdin
+    #[serde(default)]
+    pub stdin: String,
+    /// Expected stdout of the program
+    pub expected_stdout: String,
+    /// Time limit of the case in seconds, instead of the program's timeout
+    #[validate(range(
+        min = ResourceLimits::MIN.timeout,
+        max = ResourceLimits::MAX.timeout
+    ))]
+    pub timeout: Option<u32>,
+    /// Memory limit of the case in megabytes, instead of the program's memory limit
+    #[validate(range(
+        min = ResourceLimits::MIN.mem_limit_mb,
+        max = ResourceLimits::MAX.mem_limit_mb
+    ))]
+    pub mem_limit_mb: Option<u32>,
+}
+
+/// How the output of a case is compared to the expected output
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum OutputComparison {
+    /// The output must be exactly the same
+    #[default]
+    Exact,
+    /// The output must have the same whitespace-separated tokens
+    Whitespace,
+    /// Like `whitespace`, but numbers may differ by the tolerance, either absolute or
+    /// relative to the expected number
+    Float {
+        #[serde(default = "default_float_tolerance")]
+        tolerance: f64,
+    },
+}
+
+fn default_float_tolerance() -> f64 {
+    1e-6
+}
+
+/// Program that checks the output of each case. The case's input, the expected output
+/// and the program's output are available to the checker as `./files/input.txt`,
+/// `./files/expected.txt` and `./files/output.txt`. The checker accepts the output by
+/// exiting with code 0, or rejects it with code 1, and its stdout is reported as the
+/// message of the case.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct JudgeChecker {
+    /// The code of the checker
+    pub code: String,
+    /// Language of the checker
+    pub lang: CodeRunnerLanguage,
+    /// Version of the language. The language's default version is used if omitted.
+    pub version: Option<String>,
+}
+
+/// Verdict of a test case
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JudgeVerdict {
+    /// The output is correct
+    Accepted,
+    /// The output is incorrect
+    WrongAnswer,
+    /// The program exited with a non-zero exit code or was killed by a signal
+    RuntimeError,
+    /// The program exceeded the time limit
+    TimeLimitExceeded,
+    /// The program exceeded the memory limit
+    MemoryLimitExceeded,
+    /// The checker failed to check the output
+    CheckerError,
+}
+
+/// Verdict and result of a test case
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct JudgeCaseResult {
+    /// Index of the case in the input, starting from 0
+    pub index: u32,
+    pub verdict: JudgeVerdict,
+    /// Output of the checker, if a checker was used
+    pub message: Option<String>,
+    /// Result of the program's run
+    pub result: CodeRunnerResult,
+}
+
+/// Summary of the verdicts of all test cases
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct JudgeSummary {
+    /// `accepted` if all cases were accepted, otherwise the verdict of the first case
+    /// that wasn't accepted
+    pub verdict: JudgeVerdict,
+    /// Number of accepted cases
+    pub passed: u32,
+    /// Number of cases
+    pub total: u32,
+    /// Longest wall-clock duration of a case in milliseconds
+    pub max_wall_time_ms: u64,
+    /// Highest peak memory usage of a case in bytes
+    pub max_memory_bytes: Option<u64>,
+}
+
+/// Input for benchmarking a program
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct BenchmarkInput {
+    /// The program to benchmark. Its limits apply to each run.
+    #[validate(nested)]
+    pub program: CodeRunnerInput,
+    /// Number of runs before the measured runs, which aren't included in the statistics
+    #[serde(default = "default_warmup_runs")]
+    #[validate(range(max = 10))]
+    pub warmup_runs: u32,
+    /// Number of measured runs
+    #[serde(default = "default_benchmark_runs")]
+    #[validate(range(min = 1, max = 50))]
+    pub runs: u32,
+}
+
+fn default_warmup_runs() -> u32 {
+    1
+}
+fn default_benchmark_runs() -> u32 {
+    10
+}
+
+/// A run in benchmark mode
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BenchmarkRun {
+    /// Number of the run among the warmup or measured runs, starting from 1
+    pub run: u32,
+    /// Whether this is a warmup run, which isn't included in the statistics
+    pub warmup: bool,
+    pub exit_code: Option<i64>,
+    pub usage: ResourceUsage,
+}
+
+/// Statistics of a measurement over the measured runs, in milliseconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct BenchmarkStats {
+    pub min: f64,
+    pub median: f64,
+    pub mean: f64,
+    /// Sample standard deviation (0 for a single run)
+    pub stddev: f64,
+}
+
+/// Summary of the measured runs in benchmark mode
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BenchmarkSummary {
+    /// Number of measured runs
+    pub runs: u32,
+    /// Wall-clock duration of the runs
+    pub wall_time_ms: BenchmarkStats,
+    /// CPU time of the runs. Only present if it was reported for every run.
+    pub cpu_time_ms: Option<BenchmarkStats>,
+    /// Highest peak memory usage of a run in bytes
+    pub peak_memory_bytes: Option<u64>,
+}
+
+/// Input for an asynchronous job
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct CreateJobInput {
+    /// The code or function to run
+    #[validate(nested)]
+    pub task: JobTask,
+    /// Optional policy for retrying failed runs
+    #[validate(nested)]
+    pub retry: Option<RetryPolicy>,
+}
+
+/// Task of an asynchronous job
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobTask {
+    /// # Code
+    /// Run a one-off script
+    Code(Box<CodeRunnerInput>),
+    /// # Function
+    /// Run a saved function
+    Function {
+        /// Name of the function. Use `name@alias` to run the version that the alias
+        /// points to.
+        name: String,
+        #[serde(flatten)]
+        input: RunFunctionInput,
+    },
+}
+
+impl Validate for JobTask {
+    fn validate(&self) -> Result<(), validator::ValidationErrors> {
+        match self {
+            JobTask::Code(input) => input.validate(),
+            JobTask::Function { input, .. } => input.validate(),
+        }
+    }
+}
+
+/// Policy for retrying a job. A job is retried if the code could not be executed due to
+/// a transient error (e.g. a Docker error), and optionally if the code exits with a
+/// non-zero exit code or times out. Invalid tasks and failed builds are not retried.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first one
+    #[validate(range(min = 1, max = 10))]
+    pub max_attempts: u32,
+    /// Delay in seconds between attempts
+    #[serde(default)]
+    #[validate(range(max = 300))]
+    pub delay: u32,
+    /// Also retry if the code exits with a non-zero exit code or times out
+    #[serde(default)]
+    pub retry_on_exit_failure: bool,
 }
 
 /// Chunk of the code runner stream output
+#[serde_as]
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "event", content = "data", rename_all = "snake_case")]
 pub enum CodeRunnerChunk {
+    /// # Run ID
+    /// ID of the run, which can be used to cancel it. This is the first chunk of the stream.
+    RunId(String),
+    /// # Function version
+    /// Version of the function that is run. Sent after the run ID when running a function.
+    FunctionVersion {
+        version: u32,
+        /// Alias that selected the version, if the function was run through an alias
+        alias: Option<String>,
+        /// Whether the version was selected as the canary version of the alias
+        canary: bool,
+    },
     /// # Info
     /// Streamed info logs
     Info(String),
@@ -148,26 +724,174 @@ pub enum CodeRunnerChunk {
     /// # Stderr
     /// Streamed stderr log from the container
     Stderr(String),
+    /// # Stdout (binary)
+    /// Streamed stdout from the container that isn't valid UTF-8, base64 encoded
+    StdoutBinary(
+        #[serde_as(as = "Base64")]
+        #[schemars(with = "String")]
+        Vec<u8>,
+    ),
+    /// # Stderr (binary)
+    /// Streamed stderr from the container that isn't valid UTF-8, base64 encoded
+    StderrBinary(
+        #[serde_as(as = "Base64")]
+        #[schemars(with = "String")]
+        Vec<u8>,
+    ),
+    /// # Queued
+    /// The execution is waiting for a free build or run slot. Sent whenever the
+    /// position in the queue changes.
+    Queued {
+        stage: ExecutionStage,
+        /// Position in the queue, starting from 1
+        position: u32,
+    },
+    /// # Artifact
+    /// A file written by the program to the `/tmp/out` directory. Sent after the
+    /// program exits, before the result.
+    Artifact(Artifact),
     /// # Error
     /// This represents an issue that occurred before code could be executed. This
     /// should be the final chunk of the stream.
     Error(CodeRunnerError),
     /// # Execution result
     /// Full result of the code execution. This should be the final chunk of the stream.
-    Result {
-        stdout: String,
-        stderr: String,
-        exit_code: Option<i64>,
-        timeout: bool,
-    },
+    Result(CodeRunnerResult),
+    /// # Judge case
+    /// Verdict and result of a test case in judge mode. Sent after each case.
+    JudgeCase(JudgeCaseResult),
+    /// # Judge summary
+    /// Summary of the verdicts in judge mode. This should be the final chunk of the stream.
+    JudgeSummary(JudgeSummary),
+    /// # Benchmark run
+    /// Exit code and resource usage of a run in benchmark mode. Sent after each run.
+    BenchmarkRun(BenchmarkRun),
+    /// # Benchmark summary
+    /// Statistics of the measured runs in benchmark mode. This should be the final chunk
+    /// of the stream.
+    BenchmarkSummary(BenchmarkSummary),
 }
 
-pub struct CodeRunnerFunctionResult {
-    pub input: String,
+/// Result of a code execution
+#[serde_as]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct CodeRunnerResult {
+    /// Stdout of the program. Invalid UTF-8 sequences are replaced, see `stdout_binary`
+    /// for the exact bytes.
     pub stdout: String,
+    /// Stderr of the program. Invalid UTF-8 sequences are replaced, see `stderr_binary`
+    /// for the exact bytes.
     pub stderr: String,
+    /// Exact bytes of stdout, base64 encoded. Only present if stdout isn't valid UTF-8.
+    #[serde_as(as = "Option<Base64>")]
+    #[schemars(with = "Option<String>")]
+    pub stdout_binary: Option<Vec<u8>>,
+    /// Exact bytes of stderr, base64 encoded. Only present if stderr isn't valid UTF-8.
+    #[serde_as(as = "Option<Base64>")]
+    #[schemars(with = "Option<String>")]
+    pub stderr_binary: Option<Vec<u8>>,
+    /// Whether stdout exceeded the output limit and was truncated
+    pub stdout_truncated: bool,
+    /// Whether stderr exceeded the output limit and was truncated
+    pub stderr_truncated: bool,
     pub exit_code: Option<i64>,
+    /// Whether the execution exceeded the time limit
     pub timeout: bool,
+    pub termination: TerminationReason,
+    pub usage: ResourceUsage,
+    /// Return value of a function in JSON mode
+    pub output: Option<serde_json::Value>,
+    /// Error thrown by a function in JSON mode
+    pub error: Option<FunctionError>,
+}
+
+/// Error thrown by a function in JSON mode
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FunctionError {
+    /// Type of the error, e.g. the name of the exception class
+    #[serde(rename = "type")]
+    #[schemars(example = &"ValueError")]
+    pub error_type: String,
+    pub message: String,
+}
+
+/// File written by the program to the output directory
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Artifact {
+    /// Path of the file relative to the output directory
+    #[schemars(example = "result.png")]
+    pub path: String,
+    /// Size of the file in bytes
+    pub size: u64,
+    /// Base64 encoded content of the file. Not included for large files, which
+    /// can be downloaded from `download_url` instead.
+    #[serde_as(as = "Option<Base64>")]
+    #[schemars(with = "Option<String>")]
+    pub content: Option<Vec<u8>>,
+    /// URL path (relative to the server) to download a large file. The file is
+    /// only kept for a limited time.
+    pub download_url: Option<String>,
+}
+
+/// Reason the program terminated
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TerminationReason {
+    /// The program exited by itself (with any exit code)
+    #[default]
+    Exited,
+    /// The program was killed for exceeding the memory limit
+    OomKilled,
+    /// The program was killed by a signal
+    Signal {
+        /// Name of the signal, e.g. `SIGSEGV`
+        signal: String,
+        number: i64,
+    },
+    /// The program exceeded the time limit and was stopped
+    Timeout,
+    /// The container didn't exit in time and was stopped by the server
+    ServerTimeout,
+    /// The exit status could not be determined
+    Unknown,
+}
+
+impl TerminationReason {
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, Self::Timeout | Self::ServerTimeout)
+    }
+}
+
+/// Resource usage of an execution, collected from the container stats. Values
+/// are missing if they were not reported by Docker (e.g. for very short runs).
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ResourceUsage {
+    /// Wall-clock duration of the execution in milliseconds
+    pub wall_time_ms: u64,
+    /// CPU time used in milliseconds
+    pub cpu_time_ms: Option<u64>,
+    /// Peak memory usage in bytes
+    pub peak_memory_bytes: Option<u64>,
+    /// Peak number of processes and threads
+    pub peak_pids: Option<u64>,
+    /// Bytes written to disk
+    pub bytes_written: Option<u64>,
+}
+
+/// Stage of an execution that is limited by the server's concurrency limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExecutionStage {
+    /// Building an image
+    Build,
+    /// Running a container
+    Run,
+}
+
+pub struct CodeRunnerFunctionResult {
+    pub input: serde_json::Value,
+    pub result: CodeRunnerResult,
 }
 
 #[derive(Debug, Clone, thiserror::Error, Serialize, Deserialize, JsonSchema)]
@@ -182,6 +906,15 @@ pub enum CodeRunnerError {
     /// The function image was not found and may need to be rebuilt
     #[error("Function image not found")]
     FunctionImageNotFound { message: String, image_tag: String },
+    /// The run was cancelled
+    #[error("Cancelled: {message}")]
+    Cancelled { message: String },
+    /// There was an unexpected error in the local or WebAssembly sandbox backend
+    #[error("Sandbox error: {message}")]
+    Sandbox { message: String },
+    /// The queue of the server was full when the run needed a build or run slot
+    #[error("Queue full: {message}")]
+    QueueFull { message: String },
 }
 
 #[cfg(feature = "bollard")]
@@ -0,0 +1,15 @@
+import { createServerFn } from "@tanstack/react-start";
+import { apiClient } from ".";
+
+/** Get the languages of the server, with their versions and limits */
+export const getLanguagesServerFn = createServerFn({ method: "GET" }).handler(
+	async () => {
+		const res = await apiClient.GET("/languages");
+		if (!res.data) {
+			throw new Error(
+				`Failed to get languages: ${res.response.status} - ${res.error ?? "unknown error"}`,
+			);
+		}
+		return res.data;
+	},
+);
@@ -61,5 +61,7 @@ async function getExtension(language: TinirunSchemas["CodeRunnerLanguage"]) {
 			const { shell } = await import("@codemirror/legacy-modes/mode/shell");
 			return StreamLanguage.define(shell);
 		}
+		default:
+			return [];
 	}
 }
@@ -1,4 +1,3 @@
-import type { TinirunSchemas } from "@tinirun/client";
 import {
 	type EventSourceMessage,
 	EventSourceParserStream,
@@ -10,20 +9,39 @@ import {
 	PackagePlus,
 	Play,
 } from "lucide-react";
-import React, { Suspense, useCallback, useRef, useState } from "react";
+import type { TinirunSchemas } from "@tinirun/client";
+import React, { Suspense, useCallback, useMemo, useRef, useState } from "react";
 import { runCodeSnippetServerFn } from "@/api/runCode";
-import { DEFAULT_CODE, DEPS_PLACEHOLDER, LANGUAGES } from "./constants";
+import {
+	DEFAULT_CODE,
+	DEFAULT_LANGUAGE_STYLE,
+	DEPS_PLACEHOLDER,
+	LANGUAGE_STYLES,
+} from "./constants";
 import { type LogOutputLine, LogOutputPanel } from "./LogOutputPanel";
 
 const CodeEditor = React.lazy(() => import("./CodeEditor"));
 
-type Language = TinirunSchemas["CodeRunnerLanguage"];
-
 let nextId = 0;
 
-export function CodeRunner() {
-	const [language, setLanguage] = useState<Language>("python");
-	const [code, setCode] = useState(DEFAULT_CODE.python);
+type LanguageInfo = TinirunSchemas["LanguageInfo"];
+
+/** Get the label and color of the language */
+function getLanguageStyle(id: string) {
+	return LANGUAGE_STYLES[id] ?? { ...DEFAULT_LANGUAGE_STYLE, label: id };
+}
+
+export function CodeRunner({ languages }: { languages: LanguageInfo[] }) {
+	// Show the built-in languages first, in their usual order
+	const sortedLanguages = useMemo(() => {
+		const order = Object.keys(LANGUAGE_STYLES);
+		const rank = (id: string) =>
+			order.includes(id) ? order.indexOf(id) : order.length;
+		return [...languages].sort((a, b) => rank(a.id) - rank(b.id));
+	}, [languages]);
+	const [language, setLanguage] = useState(sortedLanguages[0]?.id ?? "python");
+	const [version, setVersion] = useState<string>();
+	const [code, setCode] = useState(DEFAULT_CODE[language] ?? "");
 	const [dependencies, setDependencies] = useState("");
 	const [showDeps, setShowDeps] = useState(false);
 	const [status, setStatus] = useState<"initial" | "running" | "completed">(
@@ -33,9 +51,13 @@ export function CodeRunner() {
 	const readerRef =
 		useRef<ReadableStreamDefaultReader<EventSourceMessage> | null>(null);
 
-	const handleLanguageChange = useCallback((lang: Language) => {
+	const activeLang = languages.find((l) => l.id === language);
+	const activeStyle = getLanguageStyle(language);
+
+	const handleLanguageChange = useCallback((lang: string) => {
 		setLanguage(lang);
-		setCode(DEFAULT_CODE[lang]);
+		setVersion(undefined);
+		setCode(DEFAULT_CODE[lang] ?? "");
 		setDependencies("");
 	}, []);
 
@@ -46,6 +68,7 @@ export function CodeRunner() {
 			setStatus("initial");
 			return;
 		}
+		if (!activeLang) return;
 
 		const deps = dependencies
 			.split("\n")
@@ -60,10 +83,10 @@ export function CodeRunner() {
 				data: {
 					code,
 					lang: language,
+					version,
 					dependencies: deps.length > 0 ? deps : undefined,
-					timeout: 60,
-					mem_limit_mb: 256,
-					cpu_limit: 0.5,
+					runtime: "container",
+					...activeLang.default_limits,
 				},
 			});
 			if (!res.body) throw new Error("No response body");
@@ -105,28 +128,26 @@ export function CodeRunner() {
 		setStatus("initial");
 	};
 
-	const activeLang =
-		LANGUAGES.find((l) => l.value === language) ?? LANGUAGES[0];
-
 	return (
 		<div className="flex flex-col h-full overflow-hidden">
 			{/* Language selector bar */}
 			<div className="flex items-center gap-1 px-3 py-2 bg-zinc-900 border-b border-zinc-800 shrink-0 overflow-x-auto">
-				{LANGUAGES.map((lang) => {
-					const isActive = lang.value === language;
+				{sortedLanguages.map((lang) => {
+					const isActive = lang.id === language;
+					const style = getLanguageStyle(lang.id);
 					return (
 						<button
-							key={lang.value}
+							key={lang.id}
 							type="button"
-							onClick={() => handleLanguageChange(lang.value)}
+							onClick={() => handleLanguageChange(lang.id)}
 							className={`px-3 py-1.5 rounded-md text-sm font-medium transition-all whitespace-nowrap ${
 								isActive
 									? "text-zinc-950 shadow-sm"
 									: "text-zinc-400 hover:text-zinc-200 hover:bg-zinc-800"
 							}`}
-							style={isActive ? { backgroundColor: lang.color } : undefined}
+							style={isActive ? { backgroundColor: style.color } : undefined}
 						>
-							{lang.label}
+							{style.label}
 						</button>
 					);
 				})}
@@ -141,12 +162,27 @@ export function CodeRunner() {
 						<span className="text-xs text-zinc-500 font-medium uppercase tracking-wider">
 							Editor
 						</span>
-						<span
-							className="text-xs font-medium"
-							style={{ color: activeLang.color }}
-						>
-							{activeLang.label}
-						</span>
+						<div className="flex items-center gap-2">
+							<span
+								className="text-xs font-medium"
+								style={{ color: activeStyle.color }}
+							>
+								{activeStyle.label}
+							</span>
+							{activeLang && activeLang.versions.length > 1 && (
+								<select
+									value={version ?? activeLang.default_version}
+									onChange={(e) => setVersion(e.target.value)}
+									className="bg-zinc-950 text-zinc-300 text-xs rounded border border-zinc-700 px-1.5 py-0.5 outline-none focus:border-zinc-500"
+								>
+									{activeLang.versions.map((v) => (
+										<option key={v.name} value={v.name}>
+											{v.name}
+										</option>
+									))}
+								</select>
+							)}
+						</div>
 					</div>
 
 					{/* Code editor */}
@@ -166,35 +202,37 @@ export function CodeRunner() {
 					</Suspense>
 
 					{/* Dependencies section */}
-					<div className="shrink-0 border-t border-zinc-800 bg-zinc-900">
-						<button
-							type="button"
-							onClick={() => setShowDeps((v) => !v)}
-							className="flex items-center gap-2 w-full px-4 py-2.5 text-xs text-zinc-500 hover:text-zinc-300 transition-colors"
-						>
-							<PackagePlus size={13} />
-							<span className="font-medium uppercase tracking-wider">
-								Dependencies
-							</span>
-							{showDeps ? (
-								<ChevronUp size={13} className="ml-auto" />
-							) : (
-								<ChevronDown size={13} className="ml-auto" />
+					{activeLang?.dependencies && (
+						<div className="shrink-0 border-t border-zinc-800 bg-zinc-900">
+							<button
+								type="button"
+								onClick={() => setShowDeps((v) => !v)}
+								className="flex items-center gap-2 w-full px-4 py-2.5 text-xs text-zinc-500 hover:text-zinc-300 transition-colors"
+							>
+								<PackagePlus size={13} />
+								<span className="font-medium uppercase tracking-wider">
+									Dependencies
+								</span>
+								{showDeps ? (
+									<ChevronUp size={13} className="ml-auto" />
+								) : (
+									<ChevronDown size={13} className="ml-auto" />
+								)}
+							</button>
+							{showDeps && (
+								<div className="px-3 pb-3">
+									<textarea
+										value={dependencies}
+										onChange={(e) => setDependencies(e.target.value)}
+										spellCheck={false}
+										rows={3}
+										placeholder={DEPS_PLACEHOLDER[language] || "One per line"}
+										className="w-full bg-zinc-950 text-zinc-200 font-mono text-xs leading-relaxed p-2.5 rounded border border-zinc-700 outline-none focus:border-zinc-500 resize-none placeholder-zinc-700 transition-colors"
+									/>
+								</div>
 							)}
-						</button>
-						{showDeps && (
-							<div className="px-3 pb-3">
-								<textarea
-									value={dependencies}
-									onChange={(e) => setDependencies(e.target.value)}
-									spellCheck={false}
-									rows={3}
-									placeholder={DEPS_PLACEHOLDER[language] || "One per line"}
-									className="w-full bg-zinc-950 text-zinc-200 font-mono text-xs leading-relaxed p-2.5 rounded border border-zinc-700 outline-none focus:border-zinc-500 resize-none placeholder-zinc-700 transition-colors"
-								/>
-							</div>
-						)}
-					</div>
+						</div>
+					)}
 
 					{/* Run button */}
 					<div className="shrink-0 p-3 bg-zinc-900 border-t border-zinc-800">
@@ -209,8 +247,8 @@ export function CodeRunner() {
 							style={
 								status !== "running"
 									? {
-											backgroundColor: activeLang.color,
-											boxShadow: `0 0 20px ${activeLang.color}40`,
+											backgroundColor: activeStyle.color,
+											boxShadow: `0 0 20px ${activeStyle.color}40`,
 										}
 									: undefined
 							}
@@ -3,6 +3,14 @@ import type { TinirunSchemas } from "@tinirun/client";
 type Chunk = TinirunSchemas["CodeRunnerChunk"];
 
 export function LogChunk({ chunk }: { chunk: Chunk }) {
+	if (chunk.event === "run_id") {
+		return (
+			<span className="text-zinc-500 whitespace-pre-wrap wrap-break-word">
+				<span className="opacity-60 select-none text-xs mr-1">[run]</span>
+				{chunk.data}
+			</span>
+		);
+	}
 	if (chunk.event === "stdout") {
 		return (
 			<span className="text-zinc-100 whitespace-pre-wrap wrap-break-word">
@@ -17,6 +25,17 @@ export function LogChunk({ chunk }: { chunk: Chunk }) {
 			</span>
 		);
 	}
+	if (chunk.event === "stdout_binary" || chunk.event === "stderr_binary") {
+		const size = Math.floor((chunk.data.length * 3) / 4);
+		return (
+			<span className="text-zinc-500 whitespace-pre-wrap wrap-break-word">
+				<span className="opacity-60 select-none text-xs mr-1">
+					[{chunk.event === "stdout_binary" ? "stdout" : "stderr"}]
+				</span>
+				~{size} bytes of binary output
+			</span>
+		);
+	}
 	if (chunk.event === "info") {
 		return (
 			<span className="text-sky-500 whitespace-pre-wrap wrap-break-word">
@@ -33,6 +52,35 @@ export function LogChunk({ chunk }: { chunk: Chunk }) {
 			</span>
 		);
 	}
+	if (chunk.event === "queued") {
+		const { stage, position } = chunk.data;
+		return (
+			<span className="text-zinc-400 whitespace-pre-wrap wrap-break-word">
+				<span className="opacity-60 select-none text-xs mr-1">[queued]</span>
+				Waiting for a free {stage} slot (position {position})
+			</span>
+		);
+	}
+	if (chunk.event === "artifact") {
+		const { path, size, content } = chunk.data;
+		return (
+			<span className="text-emerald-400 whitespace-pre-wrap wrap-break-word">
+				<span className="opacity-60 select-none text-xs mr-1">[artifact]</span>
+				{content ? (
+					<a
+						href={`data:application/octet-stream;base64,${content}`}
+						download={path.split("/").pop()}
+						className="underline"
+					>
+						{path}
+					</a>
+				) : (
+					path
+				)}{" "}
+				({size} bytes)
+			</span>
+		);
+	}
 	if (chunk.event === "error") {
 		const err = chunk.data;
 		const detail = "logs" in err && err.logs ? `\n${err.logs}` : "";
@@ -49,9 +97,13 @@ export function LogChunk({ chunk }: { chunk: Chunk }) {
 		const success = exited && r.exit_code === 0 && !r.timeout;
 		const label = r.timeout
 			? "⏱ Timed out"
-			: success
-				? "✓ Exited 0"
-				: `✗ Exited ${r.exit_code ?? "?"}`;
+			: r.termination.type === "oom_killed"
+				? "✗ Killed: out of memory"
+				: r.termination.type === "signal"
+					? `✗ Killed by ${r.termination.signal}`
+					: success
+						? "✓ Exited 0"
+						: `✗ Exited ${r.exit_code ?? "?"}`;
 		return (
 			<span
 				className={`font-semibold whitespace-pre-wrap wrap-break-word ${
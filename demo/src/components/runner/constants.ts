@@ -1,17 +1,18 @@
-import type { TinirunSchemas } from "@tinirun/client";
+/** Display style of the built-in languages. Other languages use their ID as the label. */
+export const LANGUAGE_STYLES: Record<string, { label: string; color: string }> =
+	{
+		python: { label: "Python", color: "#3b82f6" },
+		javascript: { label: "JavaScript", color: "#eab308" },
+		typescript: { label: "TypeScript", color: "#6366f1" },
+		go: { label: "Go", color: "#06b6d4" },
+		rust: { label: "Rust", color: "#f97316" },
+		bash: { label: "Bash", color: "#22c55e" },
+	};
 
-type Language = TinirunSchemas["CodeRunnerLanguage"];
+export const DEFAULT_LANGUAGE_STYLE = { color: "#a1a1aa" };
 
-export const LANGUAGES: { value: Language; label: string; color: string }[] = [
-	{ value: "python", label: "Python", color: "#3b82f6" },
-	{ value: "javascript", label: "JavaScript", color: "#eab308" },
-	{ value: "typescript", label: "TypeScript", color: "#6366f1" },
-	{ value: "go", label: "Go", color: "#06b6d4" },
-	{ value: "rust", label: "Rust", color: "#f97316" },
-	{ value: "bash", label: "Bash", color: "#22c55e" },
-];
-
-export const DEFAULT_CODE: Record<Language, string> = {
+/** Sample one-off code of the built-in languages */
+export const DEFAULT_CODE: Record<string, string> = {
 	python: `print("Hello from tinirun!")
 
 for i in range(1, 6):
@@ -57,7 +58,7 @@ done
 `,
 };
 
-export const DEPS_PLACEHOLDER: Record<Language, string> = {
+export const DEPS_PLACEHOLDER: Record<string, string> = {
 	python: "requests\nnumpy==1.26.0",
 	javascript: "lodash\naxios@1.6.0",
 	typescript: "zod",
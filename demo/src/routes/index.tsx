@@ -1,14 +1,17 @@
 import { createFileRoute } from "@tanstack/react-router";
+import { getLanguagesServerFn } from "@/api/languages";
 import { CodeRunner } from "@/components/runner/CodeRunner";
 
 export const Route = createFileRoute("/")({
 	component: HomePage,
+	loader: () => getLanguagesServerFn(),
 });
 
 function HomePage() {
+	const languages = Route.useLoaderData();
 	return (
 		<div className="h-full flex flex-col overflow-hidden">
-			<CodeRunner />
+			<CodeRunner languages={languages} />
 		</div>
 	);
 }
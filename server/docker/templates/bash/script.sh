@@ -2,6 +2,19 @@
 
 read -r input_string
 
+if [ "$TINIRUN_FN_IO" = "json" ]; then
+  # The input is passed as a JSON document, and the output must be a JSON document
+  if output=$(./run "$input_string"); then
+    printf '{"output":%s}' "$output" > "$TINIRUN_RESULT_FILE"
+    exit 0
+  else
+    exit_code=$?
+    message="run.sh exited with code $exit_code"
+    printf '{"error":{"type":"ExitError","message":"%s"}}' "$message" > "$TINIRUN_RESULT_FILE"
+    exit "$exit_code"
+  fi
+fi
+
 output=$(./run "$input_string")
 
 echo "$output"
@@ -2,8 +2,10 @@ package main
 
 import (
     "bufio"
+    "encoding/json"
     "fmt"
     "os"
+    "reflect"
     "strings"
 )
 
@@ -19,10 +21,56 @@ func main() {
 	}
 	input := strings.Join(lines, "\n")
 
-	result, err := run(input)
-	if err != nil {
+	if os.Getenv("TINIRUN_FN_IO") == "json" {
+		os.Exit(runJSON(input))
+	}
+
+	// The function is called by reflection, since its argument and return types
+	// can differ in JSON mode
+	results := reflect.ValueOf(run).Call([]reflect.Value{reflect.ValueOf(input)})
+	if err, _ := results[1].Interface().(error); err != nil {
 	    fmt.Fprintf(os.Stderr, "run error: %v\n", err)
 	    os.Exit(1)
 	}
-	fmt.Println(result)
+	fmt.Println(results[0].Interface())
+}
+
+// Deserialize the input JSON document into the function's argument, and write its
+// return value (serialized to JSON) or its error to the result file. Returns the exit code.
+func runJSON(input string) int {
+	var result any
+	exitCode := 0
+	output, err := callJSON(input)
+	if err != nil {
+		errorInfo := map[string]string{"type": fmt.Sprintf("%T", err), "message": err.Error()}
+		result = map[string]any{"error": errorInfo}
+		exitCode = 1
+	} else {
+		result = map[string]json.RawMessage{"output": output}
+	}
+
+	data, _ := json.Marshal(result)
+	if err := os.WriteFile(os.Getenv("TINIRUN_RESULT_FILE"), data, 0o644); err != nil {
+		fmt.Fprintf(os.Stderr, "failed to write result: %v\n", err)
+		return 1
+	}
+	return exitCode
+}
+
+// Call the function with the input decoded into its argument type, and encode its output
+func callJSON(input string) (json.RawMessage, error) {
+	handler := reflect.ValueOf(run)
+	arg := reflect.New(handler.Type().In(0))
+	if err := json.Unmarshal([]byte(input), arg.Interface()); err != nil {
+		return nil, fmt.Errorf("invalid input: %w", err)
+	}
+	results := handler.Call([]reflect.Value{arg.Elem()})
+	if err, _ := results[1].Interface().(error); err != nil {
+		return nil, err
+	}
+	output, err := json.Marshal(results[0].Interface())
+	if err != nil {
+		return nil, fmt.Errorf("output can't be encoded as JSON: %w", err)
+	}
+	return output, nil
 }
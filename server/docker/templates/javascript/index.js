@@ -1,4 +1,5 @@
 import { run } from "./function.js";
+import { writeFileSync } from "node:fs";
 import { createInterface } from "node:readline";
 
 const rl = createInterface({ input: process.stdin });
@@ -13,6 +14,21 @@ rl.on("error", (err) => {
 
 rl.on("close", async () => {
   const input = lines.join("\n");
+  if (process.env.TINIRUN_FN_IO === "json") {
+    let result;
+    try {
+      const output = await run(JSON.parse(input));
+      result = JSON.stringify({ output: output ?? null });
+    } catch (err) {
+      const type = err instanceof Error ? err.name : typeof err;
+      const message = err instanceof Error ? err.message : String(err);
+      result = JSON.stringify({ error: { type, message } });
+      process.exitCode = 1;
+    }
+    writeFileSync(process.env.TINIRUN_RESULT_FILE, result);
+    return;
+  }
+
   try {
     const output = await run(input);
     process.stdout.write(output);
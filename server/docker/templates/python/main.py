@@ -1,8 +1,23 @@
 import importlib
+import json
+import os
 import sys
 
 mod = importlib.import_module("function")
 input_data = sys.stdin.read()
+
+if os.environ.get("TINIRUN_FN_IO") == "json":
+    try:
+        result = json.dumps({"output": mod.run(json.loads(input_data))})
+        exit_code = 0
+    except Exception as e:
+        error = {"type": type(e).__name__, "message": str(e)}
+        result = json.dumps({"error": error})
+        exit_code = 1
+    with open(os.environ["TINIRUN_RESULT_FILE"], "w") as f:
+        f.write(result)
+    sys.exit(exit_code)
+
 try:
     result = mod.run(input_data)
     print(result)
@@ -1,3 +1,6 @@
+// `tinirun_json` is set by the Dockerfile of functions in JSON mode
+#![allow(unexpected_cfgs)]
+
 use std::io::{self, Read};
 
 mod function;
@@ -7,8 +10,47 @@ fn main() {
     io::stdin()
         .read_to_string(&mut input)
         .expect("failed to read from stdin");
+
+    #[cfg(tinirun_json)]
+    std::process::exit(run_json(input));
+
+    #[cfg(not(tinirun_json))]
     match function::run(input) {
         Ok(output) => println!("{output}"),
         Err(err) => eprintln!("error: {err}"),
     }
 }
+
+/// Deserialize the input JSON document into the function's argument, and write its
+/// serialized return value or its error to the result file. Returns the exit code.
+#[cfg(tinirun_json)]
+fn run_json(input: String) -> i32 {
+    let result = match serde_json::from_str(&input) {
+        Ok(input) => match function::run(input) {
+            Ok(output) => serde_json::to_value(output).map_err(|err| {
+                let message = format!("output can't be serialized to JSON: {err}");
+                ("serde_json::Error", message)
+            }),
+            Err(err) => Err((type_name_of(&err), err.to_string())),
+        },
+        Err(err) => Err(("serde_json::Error", format!("invalid input: {err}"))),
+    };
+    let (result, exit_code) = match result {
+        Ok(output) => (serde_json::json!({ "output": output }), 0),
+        Err((error_type, message)) => {
+            let error = serde_json::json!({ "type": error_type, "message": message });
+            (serde_json::json!({ "error": error }), 1)
+        }
+    };
+    let result_file = std::env::var("TINIRUN_RESULT_FILE").unwrap_or_default();
+    if let Err(err) = std::fs::write(result_file, result.to_string()) {
+        eprintln!("failed to write result: {err}");
+        return 1;
+    }
+    exit_code
+}
+
+#[cfg(tinirun_json)]
+fn type_name_of<T>(_: &T) -> &'static str {
+    std::any::type_name::<T>()
+}
@@ -0,0 +1,33 @@
+use axum::extract::State;
+use futures::Stream;
+use tinirun_models::{BenchmarkInput, CodeRunnerChunk};
+
+use crate::{
+    api::ApiTag,
+    errors::AppError,
+    input::{AppJson, StreamType},
+    responses::StreamResponse,
+    state::AppState,
+};
+
+pub fn route() -> aide::axum::routing::ApiMethodRouter<AppState> {
+    aide::axum::routing::post_with(handler, |op| {
+        op.id("benchmark_code")
+            .tag(ApiTag::Run.into())
+            .summary("Benchmark code")
+            .description(
+                "Run a program repeatedly, each time in a fresh container, and stream the \
+                resource usage of each run and the timing statistics",
+            )
+    })
+}
+
+async fn handler(
+    State(state): State<AppState>,
+    stream_type: StreamType,
+    AppJson(input): AppJson<BenchmarkInput>,
+) -> Result<StreamResponse<impl Stream<Item = CodeRunnerChunk>>, AppError> {
+    let stream = state.runner.benchmark(input).await?;
+
+    Ok(StreamResponse::new(stream, stream_type))
+}
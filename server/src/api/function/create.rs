@@ -29,15 +29,19 @@ async fn handler(
     if let Some(_) = state.redis.get_fn_info(&input.name).await? {
         return Err(AppError::BadRequest("Function already exists".into()));
     }
+    if input.language.is_wasm() {
+        return Err(AppError::BadRequest(
+            "Functions can't be created from WebAssembly modules".into(),
+        ));
+    }
 
-    let templates = state
+    let code = state
         .runner
-        .templates
-        .get(&input.language)
-        .ok_or(AppError::Server("Language templates not found".into()))?;
+        .get_fn_template(&input.language, input.version.as_deref())?;
     let fn_detail = FunctionDetail {
-        code: templates.fn_file.to_owned(),
+        code,
         lang: input.language,
+        lang_version: input.version,
         status: FunctionStatus::Building,
         created_at: chrono::Utc::now(),
         updated_at: chrono::Utc::now(),
@@ -46,9 +50,8 @@ async fn handler(
 
     let build_stream = state
         .runner
-        .build_function(&input.name, fn_detail.clone())
+        .build_function(&input.name, fn_detail, state.config.fn_version_retention)
         .await?;
-    state.redis.set_fn(&input.name, fn_detail).await?;
 
     Ok(StreamResponse::new(build_stream, stream_type))
 }
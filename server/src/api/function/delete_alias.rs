@@ -0,0 +1,28 @@
+use aide::axum::routing::ApiMethodRouter;
+use axum::extract::{Path, State};
+
+use crate::{
+    api::{ApiTag, function::FunctionAliasPath},
+    errors::AppError,
+    state::AppState,
+};
+
+pub fn route() -> ApiMethodRouter<AppState> {
+    aide::axum::routing::delete_with(handler, |op| {
+        op.id("delete_function_alias")
+            .tag(ApiTag::Functions.into())
+            .summary("Delete function alias")
+            .description("Delete an alias of the function")
+    })
+}
+
+async fn handler(
+    State(state): State<AppState>,
+    Path(FunctionAliasPath { name, alias }): Path<FunctionAliasPath>,
+) -> Result<(), AppError> {
+    if !state.redis.delete_fn_alias(&name, &alias).await? {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use aide::axum::routing::ApiMethodRouter;
+use axum::extract::{Path, State};
+
+use crate::{
+    api::{ApiTag, function::FunctionSecretPath},
+    errors::AppError,
+    state::AppState,
+};
+
+pub fn route() -> ApiMethodRouter<AppState> {
+    aide::axum::routing::delete_with(handler, |op| {
+        op.id("delete_function_secret")
+            .tag(ApiTag::Functions.into())
+            .summary("Delete function secret")
+            .description("Delete a secret of the function")
+    })
+}
+
+async fn handler(
+    State(state): State<AppState>,
+    Path(FunctionSecretPath { name, key }): Path<FunctionSecretPath>,
+) -> Result<(), AppError> {
+    if !state.secrets.delete(&name, &key).await? {
+        return Err(AppError::NotFound);
+    }
+    // Pooled containers were created with the previous secrets
+    state.runner.invalidate_pool(&name);
+
+    Ok(())
+}
@@ -16,7 +16,7 @@ pub fn route() -> ApiMethodRouter<AppState> {
         op.id("get_function_detail")
             .tag(ApiTag::Functions.into())
             .summary("Get function details")
-            .description("Get full function details and code")
+            .description("Get full function details and code. Secret values are redacted.")
     })
 }
 
@@ -24,8 +24,13 @@ async fn handler(
     State(state): State<AppState>,
     Path(FunctionNamePath { name }): Path<FunctionNamePath>,
 ) -> Result<Json<FunctionDetail>, AppError> {
-    match state.redis.get_fn_detail(&name).await? {
-        Some(function) => Ok(Json(function)),
-        None => Err(AppError::NotFound),
-    }
+    let mut function = state
+        .redis
+        .get_fn_detail(&name)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let secrets = state.secrets.get_all(&name).await?;
+    function.redact(secrets.values());
+
+    Ok(Json(function))
 }
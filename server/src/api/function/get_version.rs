@@ -0,0 +1,38 @@
+use aide::axum::routing::ApiMethodRouter;
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+
+use crate::{
+    api::{ApiTag, function::FunctionVersionPath},
+    errors::AppError,
+    redis::FunctionDetail,
+    state::AppState,
+};
+
+pub fn route() -> ApiMethodRouter<AppState> {
+    aide::axum::routing::get_with(handler, |op| {
+        op.id("get_function_version")
+            .tag(ApiTag::Functions.into())
+            .summary("Get function version")
+            .description(
+                "Get full details and code of a function version. Secret values are redacted.",
+            )
+    })
+}
+
+async fn handler(
+    State(state): State<AppState>,
+    Path(FunctionVersionPath { name, version }): Path<FunctionVersionPath>,
+) -> Result<Json<FunctionDetail>, AppError> {
+    let mut function = state
+        .redis
+        .get_fn_version(&name, version)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let secrets = state.secrets.get_all(&name).await?;
+    function.redact(secrets.values());
+
+    Ok(Json(function))
+}
@@ -0,0 +1,34 @@
+use std::collections::HashMap;
+
+use aide::axum::routing::ApiMethodRouter;
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+
+use crate::{
+    api::{ApiTag, function::FunctionNamePath},
+    errors::AppError,
+    redis::FunctionAlias,
+    state::AppState,
+};
+
+pub fn route() -> ApiMethodRouter<AppState> {
+    aide::axum::routing::get_with(handler, |op| {
+        op.id("list_function_aliases")
+            .tag(ApiTag::Functions.into())
+            .summary("List function aliases")
+            .description("List the function's aliases and the versions they point to")
+    })
+}
+
+async fn handler(
+    State(state): State<AppState>,
+    Path(FunctionNamePath { name }): Path<FunctionNamePath>,
+) -> Result<Json<HashMap<String, FunctionAlias>>, AppError> {
+    if state.redis.get_fn_info(&name).await?.is_none() {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(Json(state.redis.get_fn_aliases(&name).await?))
+}
@@ -0,0 +1,33 @@
+use aide::axum::routing::ApiMethodRouter;
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+
+use crate::{
+    api::{ApiTag, function::FunctionNamePath},
+    errors::AppError,
+    state::AppState,
+};
+
+pub fn route() -> ApiMethodRouter<AppState> {
+    aide::axum::routing::get_with(handler, |op| {
+        op.id("list_function_secrets")
+            .tag(ApiTag::Functions.into())
+            .summary("List function secrets")
+            .description(
+                "List the names of the function's secrets. Secret values are never returned.",
+            )
+    })
+}
+
+async fn handler(
+    State(state): State<AppState>,
+    Path(FunctionNamePath { name }): Path<FunctionNamePath>,
+) -> Result<Json<Vec<String>>, AppError> {
+    if state.redis.get_fn_info(&name).await?.is_none() {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(Json(state.secrets.list(&name).await?))
+}
@@ -0,0 +1,35 @@
+use aide::axum::routing::ApiMethodRouter;
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+
+use crate::{
+    api::{ApiTag, function::FunctionNamePath},
+    errors::AppError,
+    redis::FunctionInfo,
+    state::AppState,
+};
+
+pub fn route() -> ApiMethodRouter<AppState> {
+    aide::axum::routing::get_with(handler, |op| {
+        op.id("list_function_versions")
+            .tag(ApiTag::Functions.into())
+            .summary("List function versions")
+            .description(
+                "List the info and status of each version in the function's history, newest first",
+            )
+    })
+}
+
+async fn handler(
+    State(state): State<AppState>,
+    Path(FunctionNamePath { name }): Path<FunctionNamePath>,
+) -> Result<Json<Vec<FunctionInfo>>, AppError> {
+    if state.redis.get_fn_info(&name).await?.is_none() {
+        return Err(AppError::NotFound);
+    }
+    let versions = state.redis.get_fn_versions(&name).await?;
+
+    Ok(Json(versions.into_iter().map(FunctionInfo::from).collect()))
+}
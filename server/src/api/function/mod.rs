@@ -5,11 +5,20 @@ use serde::Deserialize;
 use crate::state::AppState;
 
 mod create;
+mod delete_alias;
+mod delete_secret;
 mod get;
 mod get_detail;
+mod get_version;
 mod list;
+mod list_aliases;
+mod list_secrets;
+mod list_versions;
+mod rollback;
 mod run;
 mod run_stream;
+mod set_alias;
+mod set_secret;
 mod update;
 
 pub fn routes() -> ApiRouter<AppState> {
@@ -21,9 +30,42 @@ pub fn routes() -> ApiRouter<AppState> {
         .api_route("/{name}", update::route())
         .api_route("/{name}/run", run::route())
         .api_route("/{name}/run/stream", run_stream::route())
+        .api_route("/{name}/versions", list_versions::route())
+        .api_route("/{name}/versions/{version}", get_version::route())
+        .api_route("/{name}/rollback", rollback::route())
+        .api_route("/{name}/aliases", list_aliases::route())
+        .api_route("/{name}/aliases/{alias}", set_alias::route())
+        .api_route("/{name}/aliases/{alias}", delete_alias::route())
+        .api_route("/{name}/secrets", list_secrets::route())
+        .api_route("/{name}/secrets/{key}", set_secret::route())
+        .api_route("/{name}/secrets/{key}", delete_secret::route())
 }
 
 #[derive(Debug, Deserialize, JsonSchema)]
 struct FunctionNamePath {
     name: String,
 }
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct FunctionVersionPath {
+    /// Name of the function
+    name: String,
+    /// Version of the function
+    version: u32,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct FunctionAliasPath {
+    /// Name of the function
+    name: String,
+    /// Name of the alias
+    alias: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct FunctionSecretPath {
+    /// Name of the function
+    name: String,
+    /// Name of the secret (the environment variable)
+    key: String,
+}
@@ -0,0 +1,95 @@
+use aide::axum::routing::ApiMethodRouter;
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use tinirun_models::RollbackFunctionInput;
+
+use crate::{
+    api::{ApiTag, function::FunctionNamePath},
+    errors::AppError,
+    input::AppJson,
+    redis::{FunctionInfo, FunctionStatus},
+    state::AppState,
+};
+
+pub fn route() -> ApiMethodRouter<AppState> {
+    aide::axum::routing::post_with(handler, |op| {
+        op.id("rollback_function")
+            .tag(ApiTag::Functions.into())
+            .summary("Roll back function")
+            .description("Restore a previous version of the function as a new version")
+    })
+}
+
+async fn handler(
+    State(state): State<AppState>,
+    Path(FunctionNamePath { name }): Path<FunctionNamePath>,
+    AppJson(input): AppJson<RollbackFunctionInput>,
+) -> Result<Json<FunctionInfo>, AppError> {
+    let fn_info = state
+        .redis
+        .get_fn_info(&name)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    if matches!(fn_info.status, FunctionStatus::Building) {
+        return Err(AppError::BadRequest(
+            "The function can't be rolled back while it is being built".to_owned(),
+        ));
+    }
+    if input.version == fn_info.version {
+        return Err(AppError::BadRequest(format!(
+            "Version {} is already the current version",
+            input.version
+        )));
+    }
+    let mut fn_detail = state
+        .redis
+        .get_fn_version(&name, input.version)
+        .await?
+        .ok_or_else(|| {
+            AppError::BadRequest(format!(
+                "Version {} of the function not found",
+                input.version
+            ))
+        })?;
+
+    // Claim the new version, which fails if the function was updated, rolled back or
+    // started building in the meantime
+    let version = fn_info.version + 1;
+    if !state
+        .redis
+        .swap_fn_version(&name, fn_info.version, version, true)
+        .await?
+    {
+        return Err(AppError::BadRequest(
+            "The function was changed during the rollback. Please try again.".to_owned(),
+        ));
+    }
+    fn_detail.status = match state
+        .runner
+        .rollback_function(&name, &fn_detail, version)
+        .await
+    {
+        Ok(status) => status,
+        Err(err) => {
+            state
+                .redis
+                .swap_fn_version(&name, version, fn_info.version, false)
+                .await?;
+            return Err(err);
+        }
+    };
+    fn_detail.version = version;
+    fn_detail.created_at = fn_info.created_at;
+    fn_detail.updated_at = chrono::Utc::now();
+
+    let retention = state.config.fn_version_retention;
+    state
+        .redis
+        .add_fn_version(&name, &fn_detail, retention)
+        .await?;
+    state.redis.set_fn(&name, fn_detail.clone()).await?;
+
+    Ok(Json(FunctionInfo::from(fn_detail)))
+}
@@ -6,13 +6,14 @@ use axum::{
 use futures::StreamExt;
 use schemars::JsonSchema;
 use serde::Serialize;
-use tinirun_models::{CodeRunnerChunk, RunFunctionInput};
+use tinirun_models::{
+    Artifact, CodeRunnerChunk, CodeRunnerResult, RunFunctionInput, split_fn_alias,
+};
 
 use crate::{
     api::{ApiTag, function::FunctionNamePath},
     errors::AppError,
     input::AppJson,
-    redis::FunctionStatus,
     state::AppState,
 };
 
@@ -21,7 +22,10 @@ pub fn route() -> ApiMethodRouter<AppState> {
         op.id("run_function")
             .tag(ApiTag::Functions.into())
             .summary("Run function")
-            .description("Run a saved function with the given inputs")
+            .description(
+                "Run a saved function with the given inputs. Use `name@alias` as the name to \
+                run the version that the alias points to.",
+            )
     })
 }
 
@@ -30,30 +34,36 @@ async fn handler(
     Path(FunctionNamePath { name }): Path<FunctionNamePath>,
     AppJson(input): AppJson<RunFunctionInput>,
 ) -> Result<Json<RunFunctionOutput>, AppError> {
+    let (name, alias) = split_fn_alias(&name);
     let fn_info = state
         .redis
-        .get_fn_info(&name)
+        .get_fn_info(name)
         .await?
         .ok_or(AppError::NotFound)?;
-    if !matches!(fn_info.status, FunctionStatus::Ready { .. }) {
-        return Err(AppError::BadRequest("Function not ready".to_owned()));
-    }
 
-    let mut stream = state.runner.run_function(name, fn_info, input).await?;
+    let secrets = state.secrets.get_all(name).await?;
+    let mut stream = state
+        .runner
+        .run_function(
+            name.to_owned(),
+            alias.map(str::to_owned),
+            fn_info,
+            input,
+            secrets,
+        )
+        .await?;
+    let mut version = None;
+    let mut artifacts = Vec::new();
     while let Some(chunk) = stream.next().await {
         match chunk {
             CodeRunnerChunk::Error(err) => return Err(AppError::ExecutionFailed(err)),
-            CodeRunnerChunk::Result {
-                stdout,
-                stderr,
-                exit_code,
-                timeout,
-            } => {
+            CodeRunnerChunk::FunctionVersion { version: v, .. } => version = Some(v),
+            CodeRunnerChunk::Artifact(artifact) => artifacts.push(artifact),
+            CodeRunnerChunk::Result(result) => {
                 return Ok(Json(RunFunctionOutput {
-                    stdout,
-                    stderr,
-                    exit_code,
-                    timeout,
+                    result,
+                    version,
+                    artifacts,
                 }));
             }
             _ => {}
@@ -65,8 +75,10 @@ async fn handler(
 
 #[derive(Debug, Clone, Serialize, JsonSchema)]
 struct RunFunctionOutput {
-    stdout: String,
-    stderr: String,
-    exit_code: Option<i64>,
-    timeout: bool,
+    #[serde(flatten)]
+    result: CodeRunnerResult,
+    /// Version of the function that was run
+    version: Option<u32>,
+    /// Files written to the output directory
+    artifacts: Vec<Artifact>,
 }
@@ -1,12 +1,11 @@
 use aide::axum::routing::ApiMethodRouter;
 use axum::extract::{Path, State};
-use tinirun_models::{CodeRunnerChunk, RunFunctionInput};
+use tinirun_models::{CodeRunnerChunk, RunFunctionInput, split_fn_alias};
 
 use crate::{
     api::{ApiTag, function::FunctionNamePath},
     errors::AppError,
     input::{AppJson, StreamType},
-    redis::FunctionStatus,
     responses::StreamResponse,
     state::AppState,
 };
@@ -16,7 +15,10 @@ pub fn route() -> ApiMethodRouter<AppState> {
         op.id("run_function_stream")
             .tag(ApiTag::Functions.into())
             .summary("Run function")
-            .description("Run a saved function with the given inputs and stream the output")
+            .description(
+                "Run a saved function with the given inputs and stream the output. Use \
+                `name@alias` as the name to run the version that the alias points to.",
+            )
     })
 }
 
@@ -26,15 +28,23 @@ async fn handler(
     Path(FunctionNamePath { name }): Path<FunctionNamePath>,
     AppJson(input): AppJson<RunFunctionInput>,
 ) -> Result<StreamResponse<impl futures::Stream<Item = CodeRunnerChunk>>, AppError> {
+    let (name, alias) = split_fn_alias(&name);
     let fn_info = state
         .redis
-        .get_fn_info(&name)
+        .get_fn_info(name)
         .await?
         .ok_or(AppError::NotFound)?;
-    if !matches!(fn_info.status, FunctionStatus::Ready { .. }) {
-        return Err(AppError::BadRequest("Function not ready".to_owned()));
-    }
 
-    let stream = state.runner.run_function(name, fn_info, input).await?;
+    let secrets = state.secrets.get_all(name).await?;
+    let stream = state
+        .runner
+        .run_function(
+            name.to_owned(),
+            alias.map(str::to_owned),
+            fn_info,
+            input,
+            secrets,
+        )
+        .await?;
     Ok(StreamResponse::new(stream, stream_type))
 }
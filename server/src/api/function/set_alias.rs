@@ -0,0 +1,64 @@
+use aide::axum::routing::ApiMethodRouter;
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use tinirun_models::SetAliasInput;
+
+use crate::{
+    api::{ApiTag, function::FunctionAliasPath},
+    errors::AppError,
+    input::AppJson,
+    redis::{FunctionAlias, FunctionStatus},
+    runner::validate_alias_name,
+    state::AppState,
+};
+
+pub fn route() -> ApiMethodRouter<AppState> {
+    aide::axum::routing::put_with(handler, |op| {
+        op.id("set_function_alias")
+            .tag(ApiTag::Functions.into())
+            .summary("Set function alias")
+            .description(
+                "Create an alias of the function, or switch it to another version. Runs through \
+                the alias use the new version as soon as it is set.",
+            )
+    })
+}
+
+async fn handler(
+    State(state): State<AppState>,
+    Path(FunctionAliasPath { name, alias }): Path<FunctionAliasPath>,
+    AppJson(input): AppJson<SetAliasInput>,
+) -> Result<Json<FunctionAlias>, AppError> {
+    validate_alias_name(&alias).map_err(AppError::BadRequest)?;
+    if state.redis.get_fn_info(&name).await?.is_none() {
+        return Err(AppError::NotFound);
+    }
+    if input
+        .canary
+        .as_ref()
+        .is_some_and(|c| c.version == input.version)
+    {
+        return Err(AppError::BadRequest(
+            "The canary version must be different from the alias's version".to_owned(),
+        ));
+    }
+    let alias_info = FunctionAlias::from(input);
+    for version in alias_info.versions() {
+        let Some(fn_version) = state.redis.get_fn_version(&name, version).await? else {
+            return Err(AppError::BadRequest(format!(
+                "Version {version} of the function not found"
+            )));
+        };
+        if !matches!(fn_version.status, FunctionStatus::Ready { .. }) {
+            return Err(AppError::BadRequest(format!(
+                "Version {version} of the function was not built successfully"
+            )));
+        }
+    }
+
+    state.redis.set_fn_alias(&name, &alias, &alias_info).await?;
+
+    Ok(Json(alias_info))
+}
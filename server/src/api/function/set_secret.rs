@@ -0,0 +1,40 @@
+use aide::axum::routing::ApiMethodRouter;
+use axum::extract::{Path, State};
+use tinirun_models::SetSecretInput;
+
+use crate::{
+    api::{ApiTag, function::FunctionSecretPath},
+    errors::AppError,
+    input::AppJson,
+    runner::validate_env_input,
+    state::AppState,
+};
+
+pub fn route() -> ApiMethodRouter<AppState> {
+    aide::axum::routing::put_with(handler, |op| {
+        op.id("set_function_secret")
+            .tag(ApiTag::Functions.into())
+            .summary("Set function secret")
+            .description(
+                "Create or replace a secret of the function. Secrets are added to the \
+                environment of the function at run time, and redacted from its output.",
+            )
+    })
+}
+
+async fn handler(
+    State(state): State<AppState>,
+    Path(FunctionSecretPath { name, key }): Path<FunctionSecretPath>,
+    AppJson(input): AppJson<SetSecretInput>,
+) -> Result<(), AppError> {
+    validate_env_input([&key]).map_err(AppError::BadRequest)?;
+    if state.redis.get_fn_info(&name).await?.is_none() {
+        return Err(AppError::NotFound);
+    }
+
+    state.secrets.set(&name, &key, &input.value).await?;
+    // Pooled containers were created with the previous secrets
+    state.runner.invalidate_pool(&name);
+
+    Ok(())
+}
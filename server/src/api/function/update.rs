@@ -1,7 +1,7 @@
 use aide::axum::routing::ApiMethodRouter;
 use axum::extract::{Path, State};
 use futures::Stream;
-use tinirun_models::{CodeRunnerChunk, UpdateFunctionInput};
+use tinirun_models::{CodeRunnerChunk, FunctionIo, UpdateFunctionInput};
 
 use crate::{
     api::{ApiTag, function::FunctionNamePath},
@@ -31,6 +31,17 @@ async fn handler(
         validate_deps_input(dependencies)
             .map_err(|e| AppError::BadRequest(format!("Invalid dependencies: {e}")))?;
     }
+    if let Some(allowlist) = &input.egress {
+        state.runner.check_egress_allowlist(allowlist)?;
+    }
+    if let Some(profile) = &input.sandbox {
+        state.runner.check_sandbox_profile(profile)?;
+    }
+    if input.output_schema.is_some() && input.io != FunctionIo::Json {
+        return Err(AppError::BadRequest(
+            "An output schema can only be set in JSON mode".to_owned(),
+        ));
+    }
 
     let mut fn_detail = state
         .redis
@@ -41,9 +52,8 @@ async fn handler(
 
     let build_stream = state
         .runner
-        .build_function(&name, fn_detail.clone())
+        .build_function(&name, fn_detail, state.config.fn_version_retention)
         .await?;
-    state.redis.set_fn(&name, fn_detail).await?;
 
     Ok(StreamResponse::new(build_stream, stream_type))
 }
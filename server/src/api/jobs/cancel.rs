@@ -0,0 +1,50 @@
+use aide::axum::routing::ApiMethodRouter;
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+
+use crate::{
+    api::{ApiTag, jobs::JobIdPath},
+    errors::AppError,
+    redis::{JobInfo, JobStatus},
+    state::AppState,
+};
+
+pub fn route() -> ApiMethodRouter<AppState> {
+    aide::axum::routing::delete_with(handler, |op| {
+        op.id("cancel_job")
+            .tag(ApiTag::Jobs.into())
+            .summary("Cancel job")
+            .description(
+                "Cancel a pending or running job. Finished jobs are left unchanged. \
+                Fails if the job is running on another server.",
+            )
+    })
+}
+
+async fn handler(
+    State(state): State<AppState>,
+    Path(JobIdPath { id }): Path<JobIdPath>,
+) -> Result<Json<JobInfo>, AppError> {
+    let mut job = state.redis.get_job(&id).await?.ok_or(AppError::NotFound)?;
+    if job.status.is_finished() {
+        return Ok(Json(job));
+    }
+
+    if !state.jobs.cancel(&id) {
+        // The job may have just finished, or is running on another server
+        let job = state.redis.get_job(&id).await?.ok_or(AppError::NotFound)?;
+        if job.status.is_finished() {
+            return Ok(Json(job));
+        }
+        return Err(AppError::BadRequest(format!(
+            "Job '{id}' is not running on this server"
+        )));
+    }
+    job.status = JobStatus::Cancelled;
+    job.updated_at = chrono::Utc::now();
+    state.redis.set_job(&job, state.config.job_ttl).await?;
+
+    Ok(Json(job))
+}
@@ -0,0 +1,34 @@
+use aide::axum::routing::ApiMethodRouter;
+use axum::{Json, extract::State};
+use tinirun_models::{CreateJobInput, JobTask};
+
+use crate::{api::ApiTag, errors::AppError, input::AppJson, redis::JobInfo, state::AppState};
+
+pub fn route() -> ApiMethodRouter<AppState> {
+    aide::axum::routing::post_with(handler, |op| {
+        op.id("create_job")
+            .tag(ApiTag::Jobs.into())
+            .summary("Create job")
+            .description(
+                "Run code or a saved function in the background. Returns the job immediately, \
+                and the status and result can be fetched later with the job ID.",
+            )
+    })
+}
+
+async fn handler(
+    State(state): State<AppState>,
+    AppJson(input): AppJson<CreateJobInput>,
+) -> Result<Json<JobInfo>, AppError> {
+    match &input.task {
+        JobTask::Code(code_input) => state.runner.validate_code_input(code_input)?,
+        JobTask::Function { input, .. } => {
+            if let Some(allowlist) = &input.egress {
+                state.runner.check_egress_allowlist(allowlist)?;
+            }
+        }
+    }
+
+    let job = state.jobs.submit(state.clone(), input).await?;
+    Ok(Json(job))
+}
@@ -0,0 +1,31 @@
+use aide::axum::routing::ApiMethodRouter;
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+
+use crate::{
+    api::{ApiTag, jobs::JobIdPath},
+    errors::AppError,
+    redis::JobInfo,
+    state::AppState,
+};
+
+pub fn route() -> ApiMethodRouter<AppState> {
+    aide::axum::routing::get_with(handler, |op| {
+        op.id("get_job")
+            .tag(ApiTag::Jobs.into())
+            .summary("Get job")
+            .description("Get the status and result of a job")
+    })
+}
+
+async fn handler(
+    State(state): State<AppState>,
+    Path(JobIdPath { id }): Path<JobIdPath>,
+) -> Result<Json<JobInfo>, AppError> {
+    match state.redis.get_job(&id).await? {
+        Some(job) => Ok(Json(job)),
+        None => Err(AppError::NotFound),
+    }
+}
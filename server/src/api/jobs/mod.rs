@@ -0,0 +1,21 @@
+use aide::axum::ApiRouter;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::state::AppState;
+
+mod cancel;
+mod create;
+mod get;
+
+pub fn routes() -> ApiRouter<AppState> {
+    ApiRouter::new()
+        .api_route("/", create::route())
+        .api_route("/{id}", get::route())
+        .api_route("/{id}", cancel::route())
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct JobIdPath {
+    id: String,
+}
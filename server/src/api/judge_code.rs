@@ -0,0 +1,33 @@
+use axum::extract::State;
+use futures::Stream;
+use tinirun_models::{CodeRunnerChunk, JudgeInput};
+
+use crate::{
+    api::ApiTag,
+    errors::AppError,
+    input::{AppJson, StreamType},
+    responses::StreamResponse,
+    state::AppState,
+};
+
+pub fn route() -> aide::axum::routing::ApiMethodRouter<AppState> {
+    aide::axum::routing::post_with(handler, |op| {
+        op.id("judge_code")
+            .tag(ApiTag::Run.into())
+            .summary("Judge code")
+            .description(
+                "Run a program against test cases, each in a fresh container, and stream \
+                the verdict of each case and a summary",
+            )
+    })
+}
+
+async fn handler(
+    State(state): State<AppState>,
+    stream_type: StreamType,
+    AppJson(input): AppJson<JudgeInput>,
+) -> Result<StreamResponse<impl Stream<Item = CodeRunnerChunk>>, AppError> {
+    let stream = state.runner.judge(input).await?;
+
+    Ok(StreamResponse::new(stream, stream_type))
+}
@@ -0,0 +1,20 @@
+use aide::axum::routing::ApiMethodRouter;
+use axum::{Json, extract::State};
+
+use crate::{api::ApiTag, runner::LanguageInfo, state::AppState};
+
+pub fn route() -> ApiMethodRouter<AppState> {
+    aide::axum::routing::get_with(handler, |op| {
+        op.id("list_languages")
+            .tag(ApiTag::Languages.into())
+            .summary("List languages")
+            .description(
+                "List the languages on this server, with their versions, files, \
+                function input/output and resource limits",
+            )
+    })
+}
+
+async fn handler(State(state): State<AppState>) -> Json<Vec<LanguageInfo>> {
+    Json(state.runner.list_languages())
+}
@@ -0,0 +1,20 @@
+use aide::axum::ApiRouter;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tinirun_models::CodeRunnerLanguage;
+
+use crate::state::AppState;
+
+mod list;
+mod versions;
+
+pub fn routes() -> ApiRouter<AppState> {
+    ApiRouter::new()
+        .api_route("/", list::route())
+        .api_route("/{lang}/versions", versions::route())
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct LanguagePath {
+    lang: CodeRunnerLanguage,
+}
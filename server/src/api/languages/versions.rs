@@ -0,0 +1,28 @@
+use aide::axum::routing::ApiMethodRouter;
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+
+use crate::{
+    api::{ApiTag, languages::LanguagePath},
+    errors::AppError,
+    runner::LanguageVersions,
+    state::AppState,
+};
+
+pub fn route() -> ApiMethodRouter<AppState> {
+    aide::axum::routing::get_with(handler, |op| {
+        op.id("get_language_versions")
+            .tag(ApiTag::Languages.into())
+            .summary("Get language versions")
+            .description("Get the available versions of a language, and its default version")
+    })
+}
+
+async fn handler(
+    State(state): State<AppState>,
+    Path(LanguagePath { lang }): Path<LanguagePath>,
+) -> Result<Json<LanguageVersions>, AppError> {
+    Ok(Json(state.runner.get_lang_versions(&lang)?))
+}
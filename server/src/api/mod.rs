@@ -6,8 +6,13 @@ use crate::{
     state::AppState,
 };
 
+pub mod benchmark_code;
 pub mod function;
+pub mod jobs;
+pub mod judge_code;
+pub mod languages;
 pub mod run_code;
+pub mod runs;
 
 /// Tags in the OpenAPI specification
 #[derive(Debug, IntoStaticStr, Display, EnumMessage, EnumIter)]
@@ -16,6 +21,10 @@ enum ApiTag {
     Run,
     #[strum(message = "Run and manage functions")]
     Functions,
+    #[strum(message = "Run code and functions in the background")]
+    Jobs,
+    #[strum(message = "Available languages and versions")]
+    Languages,
 }
 
 /// Adds all API routes to the server under `/api`
@@ -24,7 +33,12 @@ pub fn plugin() -> AdHocPlugin<AppState> {
         // Build API routes
         let api_router = aide::axum::ApiRouter::new()
             .api_route("/code/run", run_code::route())
+            .api_route("/code/judge", judge_code::route())
+            .api_route("/code/benchmark", benchmark_code::route())
             .nest("/function", function::routes())
+            .nest("/jobs", jobs::routes())
+            .nest("/languages", languages::routes())
+            .nest("/runs", runs::routes())
             .layer(axum::middleware::from_extractor_with_state::<ApiKey, _>(
                 state.clone(),
             ));
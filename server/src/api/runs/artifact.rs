@@ -0,0 +1,40 @@
+use aide::axum::routing::ApiMethodRouter;
+use axum::{
+    extract::{Path, State},
+    http::{HeaderName, header},
+};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{api::ApiTag, errors::AppError, state::AppState};
+
+pub fn route() -> ApiMethodRouter<AppState> {
+    aide::axum::routing::get_with(handler, |op| {
+        op.id("get_artifact")
+            .tag(ApiTag::Run.into())
+            .summary("Download artifact")
+            .description("Download an artifact of a run that was too large to send in the stream")
+    })
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct ArtifactPath {
+    id: String,
+    path: String,
+}
+
+async fn handler(
+    State(state): State<AppState>,
+    Path(ArtifactPath { id, path }): Path<ArtifactPath>,
+) -> Result<([(HeaderName, &'static str); 1], Vec<u8>), AppError> {
+    let content = state
+        .runner
+        .get_artifact(&id, &path)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/octet-stream")],
+        content,
+    ))
+}
@@ -0,0 +1,27 @@
+use aide::axum::routing::ApiMethodRouter;
+use axum::extract::{Path, State};
+
+use crate::{
+    api::{ApiTag, runs::RunIdPath},
+    errors::AppError,
+    state::AppState,
+};
+
+pub fn route() -> ApiMethodRouter<AppState> {
+    aide::axum::routing::post_with(handler, |op| {
+        op.id("cancel_run")
+            .tag(ApiTag::Run.into())
+            .summary("Cancel run")
+            .description("Cancel a run in progress, stopping its build or container")
+    })
+}
+
+async fn handler(
+    State(state): State<AppState>,
+    Path(RunIdPath { id }): Path<RunIdPath>,
+) -> Result<(), AppError> {
+    match state.runner.cancel_run(&id) {
+        true => Ok(()),
+        false => Err(AppError::NotFound),
+    }
+}
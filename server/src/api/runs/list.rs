@@ -0,0 +1,17 @@
+use aide::axum::routing::ApiMethodRouter;
+use axum::{Json, extract::State};
+
+use crate::{api::ApiTag, runner::RunInfo, state::AppState};
+
+pub fn route() -> ApiMethodRouter<AppState> {
+    aide::axum::routing::get_with(handler, |op| {
+        op.id("list_runs")
+            .tag(ApiTag::Run.into())
+            .summary("List runs")
+            .description("List the runs and builds in progress on this server")
+    })
+}
+
+async fn handler(State(state): State<AppState>) -> Json<Vec<RunInfo>> {
+    Json(state.runner.list_runs())
+}
@@ -0,0 +1,21 @@
+use aide::axum::ApiRouter;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::state::AppState;
+
+mod artifact;
+mod cancel;
+mod list;
+
+pub fn routes() -> ApiRouter<AppState> {
+    ApiRouter::new()
+        .api_route("/", list::route())
+        .api_route("/{id}/cancel", cancel::route())
+        .api_route("/{id}/artifacts/{*path}", artifact::route())
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct RunIdPath {
+    id: String,
+}
@@ -1,11 +1,20 @@
-use std::net::{IpAddr, Ipv4Addr};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+};
 
 use anyhow::Context;
 use axum_app_wrapper::AdHocPlugin;
 use schemars::JsonSchema;
 use serde::Deserialize;
 
-use crate::state::AppState;
+#[cfg(feature = "wasm")]
+use tinirun_models::CodeRunnerLanguage;
+
+#[cfg(feature = "wasm")]
+use crate::runner::WasmInterpreterConfig;
+use crate::{runner::SandboxProfileConfig, state::AppState};
 
 #[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct AppConfig {
@@ -16,6 +25,106 @@ pub struct AppConfig {
     /// Interval in seconds between image cleanup runs.
     #[serde(default = "default_cleanup_interval")]
     pub cleanup_interval: u32,
+    /// Maximum number of cached dependency images to keep for one-off executions.
+    /// The least recently used images are removed during cleanup.
+    #[serde(default = "default_image_cache_size")]
+    pub image_cache_size: usize,
+    /// Number of versions of each function to keep in its version history, including the
+    /// current version. Older versions and their images are removed during cleanup.
+    #[serde(default = "default_fn_version_retention")]
+    pub fn_version_retention: usize,
+    /// Number of pre-created containers to keep ready for each function (0 to disable)
+    #[serde(default = "default_fn_pool_size")]
+    pub fn_pool_size: usize,
+    /// Time in seconds after which an unused pooled container is removed
+    #[serde(default = "default_fn_pool_idle_ttl")]
+    pub fn_pool_idle_ttl: u32,
+    /// Maximum number of pooled containers across all functions
+    #[serde(default = "default_fn_pool_max_containers")]
+    pub fn_pool_max_containers: usize,
+    /// Maximum number of image builds running at once
+    #[serde(default = "default_max_concurrent_builds")]
+    pub max_concurrent_builds: usize,
+    /// Maximum number of containers running at once
+    #[serde(default = "default_max_concurrent_runs")]
+    pub max_concurrent_runs: usize,
+    /// Maximum number of executions waiting for a build or run slot. Requests
+    /// are rejected with a 503 status when the queue is full.
+    #[serde(default = "default_max_queue_size")]
+    pub max_queue_size: usize,
+    /// Time in seconds to keep asynchronous jobs and their results in Redis
+    #[serde(default = "default_job_ttl")]
+    pub job_ttl: u32,
+    /// Maximum number of artifacts returned from a run
+    #[serde(default = "default_artifacts_max_files")]
+    pub artifacts_max_files: usize,
+    /// Maximum total size in MB of the output directory of a run
+    #[serde(default = "default_artifacts_max_size_mb")]
+    pub artifacts_max_size_mb: u32,
+    /// Limit the output directory volume of Docker containers to `artifacts_max_size_mb`
+    /// with a disk quota. Requires the Docker data root to support quotas (e.g. XFS
+    /// mounted with `pquota`). If disabled, the size is only enforced when collecting.
+    #[serde(default)]
+    pub artifacts_volume_quota: bool,
+    /// Artifacts up to this size in bytes are sent in the stream. Larger artifacts
+    /// are stored in Redis and can be downloaded separately.
+    #[serde(default = "default_artifact_inline_max_bytes")]
+    pub artifact_inline_max_bytes: u64,
+    /// Time in seconds to keep stored artifacts in Redis
+    #[serde(default = "default_artifact_ttl")]
+    pub artifact_ttl: u32,
+    /// Base64 encoded 32-byte key used to encrypt function secrets. Secrets
+    /// are disabled if not set.
+    pub secrets_key: Option<String>,
+    /// Allowlists of destinations (`host[:port]`, host can start with `*.`) that runs
+    /// and functions can opt into for network egress, by name. Egress is disabled if empty.
+    #[serde(default)]
+    pub egress_allowlists: HashMap<String, Vec<String>>,
+    /// Name of the internal Docker network that containers with egress are connected to
+    #[serde(default = "default_egress_network")]
+    pub egress_network: String,
+    /// Address that the egress proxy listens on. Defaults to port 3128 on the gateway
+    /// address of the egress network, so that the proxy is only reachable from the network.
+    pub egress_proxy_bind: Option<SocketAddr>,
+    /// Address (`host:port`) of the egress proxy as reachable by containers on the
+    /// egress network. Defaults to the bind address, and required if the proxy
+    /// listens on all interfaces.
+    pub egress_proxy_address: Option<String>,
+    /// Sandbox profiles that languages and functions can select, by name. The options
+    /// of each profile are validated against the Docker daemon at startup.
+    #[serde(default)]
+    pub sandbox_profiles: HashMap<String, SandboxProfileConfig>,
+    /// Directory with language definitions (`data.toml`) and templates (`templates`)
+    /// that override and extend the built-in languages. Changes are reloaded
+    /// automatically, or on `SIGHUP`.
+    pub languages_dir: Option<PathBuf>,
+    /// Backend that runs the code. The local backend runs one-off code without Docker,
+    /// using the toolchains installed on the server.
+    #[serde(default)]
+    pub backend: BackendKind,
+    /// Path to the bubblewrap (`bwrap`) executable used by the local backend
+    #[serde(default = "default_local_bwrap_path")]
+    pub local_bwrap_path: PathBuf,
+    /// Directory where the local backend stores the files of each run
+    #[serde(default = "default_local_work_dir")]
+    pub local_work_dir: PathBuf,
+    /// Host directories with the toolchains, mounted read-only in the local sandbox.
+    /// Directories that don't exist are skipped.
+    #[serde(default = "default_local_ro_binds")]
+    pub local_ro_binds: Vec<PathBuf>,
+    /// cgroup (v2) directory delegated to the server, where the local backend creates a
+    /// cgroup for each run to enforce the memory, CPU and process limits. Required by the
+    /// local backend.
+    pub local_cgroup_dir: Option<PathBuf>,
+    /// WASI interpreters of the languages that can run in the WebAssembly runtime.
+    /// Uploaded `.wasm` modules can always run in the WebAssembly runtime.
+    #[cfg(feature = "wasm")]
+    #[serde(default)]
+    pub wasm_interpreters: HashMap<CodeRunnerLanguage, WasmInterpreterConfig>,
+    /// Directory where the WebAssembly runtime stores the files of each run
+    #[cfg(feature = "wasm")]
+    #[serde(default = "default_wasm_work_dir")]
+    pub wasm_work_dir: PathBuf,
 
     #[serde(default = "default_host")]
     pub host: IpAddr,
@@ -24,9 +133,89 @@ pub struct AppConfig {
     #[serde(default = "default_log_level")]
     pub log_level: String,
 }
+
+/// Backend that runs the code
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// Run code in Docker containers
+    #[default]
+    Docker,
+    /// Run one-off code in a local bubblewrap sandbox
+    Local,
+}
+
 fn default_cleanup_interval() -> u32 {
     300
 }
+fn default_image_cache_size() -> usize {
+    20
+}
+fn default_fn_version_retention() -> usize {
+    10
+}
+fn default_fn_pool_size() -> usize {
+    1
+}
+fn default_fn_pool_idle_ttl() -> u32 {
+    300
+}
+fn default_fn_pool_max_containers() -> usize {
+    20
+}
+fn default_max_concurrent_builds() -> usize {
+    2
+}
+fn default_max_concurrent_runs() -> usize {
+    10
+}
+fn default_max_queue_size() -> usize {
+    50
+}
+fn default_job_ttl() -> u32 {
+    86400
+}
+fn default_artifacts_max_files() -> usize {
+    20
+}
+fn default_artifacts_max_size_mb() -> u32 {
+    10
+}
+fn default_artifact_inline_max_bytes() -> u64 {
+    65536
+}
+fn default_artifact_ttl() -> u32 {
+    3600
+}
+fn default_egress_network() -> String {
+    "tinirun-egress".to_string()
+}
+fn default_local_bwrap_path() -> PathBuf {
+    PathBuf::from("bwrap")
+}
+fn default_local_work_dir() -> PathBuf {
+    std::env::temp_dir().join("tinirun")
+}
+fn default_local_ro_binds() -> Vec<PathBuf> {
+    [
+        "/usr",
+        "/bin",
+        "/sbin",
+        "/lib",
+        "/lib32",
+        "/lib64",
+        "/etc/alternatives",
+        "/etc/ld.so.cache",
+        "/etc/ssl",
+    ]
+    .into_iter()
+    .map(PathBuf::from)
+    .collect()
+}
+#[cfg(feature = "wasm")]
+fn default_wasm_work_dir() -> PathBuf {
+    std::env::temp_dir().join("tinirun-wasm")
+}
 fn default_host() -> IpAddr {
     IpAddr::V4(Ipv4Addr::LOCALHOST)
 }
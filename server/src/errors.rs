@@ -1,6 +1,6 @@
 use aide::OperationOutput;
 use axum::{
-    http::StatusCode,
+    http::{StatusCode, header},
     response::{IntoResponse, Response},
 };
 use tinirun_models::CodeRunnerError;
@@ -21,6 +21,8 @@ pub enum AppError {
     ExecutionFailed(CodeRunnerError),
     #[error("Server error: {0}")]
     Server(String),
+    #[error("Execution queue is full")]
+    QueueFull { retry_after: u32 },
 }
 
 impl IntoResponse for AppError {
@@ -49,6 +51,12 @@ impl IntoResponse for AppError {
                 tracing::error!("Docker client error: {err}");
                 (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
             }
+            AppError::QueueFull { retry_after } => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                "Too many executions in progress, please try again later",
+            )
+                .into_response(),
         }
     }
 }
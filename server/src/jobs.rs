@@ -0,0 +1,376 @@
+//! Asynchronous jobs that run detached from the HTTP connection
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use axum_app_wrapper::AdHocPlugin;
+use fred::prelude::FredResult;
+use futures::{StreamExt, stream::BoxStream};
+use tinirun_models::{
+    CodeRunnerChunk, CodeRunnerError, CreateJobInput, JobTask, RetryPolicy, split_fn_alias,
+};
+use tokio::task::AbortHandle;
+
+use crate::{
+    config::AppConfig,
+    errors::AppError,
+    redis::{JobInfo, JobStatus, RedisClient},
+    state::AppState,
+};
+
+/// Time in seconds that a job stays marked as active on a server without being refreshed.
+/// Unfinished jobs without an active lease were interrupted, e.g. by a server restart.
+const JOB_LEASE_TTL: u32 = 30;
+
+/// Keeps track of the jobs running on this server, so they can be cancelled
+#[derive(Default)]
+pub struct JobManager {
+    tasks: Arc<Mutex<HashMap<String, AbortHandle>>>,
+}
+
+/// Add the job manager to server state, and start the task that resolves interrupted jobs
+pub fn plugin() -> AdHocPlugin<AppState> {
+    AdHocPlugin::new().on_init(|mut state| async {
+        let redis = state
+            .get::<RedisClient>()
+            .ok_or_else(|| anyhow!("Redis client not found"))?
+            .clone();
+        let job_ttl = state
+            .get::<AppConfig>()
+            .ok_or_else(|| anyhow!("app config not found"))?
+            .job_ttl;
+        tokio::spawn(interrupted_jobs_task(redis, job_ttl));
+        state.insert(JobManager::default());
+        Ok(state)
+    })
+}
+
+impl JobManager {
+    /// Save a new job in Redis and start running it in the background
+    pub async fn submit(
+        &self,
+        state: AppState,
+        input: CreateJobInput,
+    ) -> Result<JobInfo, AppError> {
+        let now = chrono::Utc::now();
+        let job = JobInfo {
+            id: format!("job-{}", uuid::Uuid::new_v4()),
+            task: input.task,
+            status: JobStatus::Pending,
+            attempts: 0,
+            result: None,
+            created_at: now,
+            updated_at: now,
+        };
+        state.redis.set_job_lease(&job.id, JOB_LEASE_TTL).await?;
+        state.redis.set_job(&job, state.config.job_ttl).await?;
+
+        let id = job.id.clone();
+        let tasks = self.tasks.clone();
+        let mut tasks_guard = self.tasks.lock().expect("jobs lock poisoned");
+        let task = tokio::spawn({
+            let job = job.clone();
+            async move {
+                tokio::select! {
+                    () = run_job(&state, job, input.retry) => {}
+                    () = keep_job_lease(&state.redis, &id) => {}
+                }
+                tasks.lock().expect("jobs lock poisoned").remove(&id);
+            }
+        });
+        tasks_guard.insert(job.id.clone(), task.abort_handle());
+
+        Ok(job)
+    }
+
+    /// Stop the job if it's running on this server. Dropping the job's stream cancels
+    /// the execution and removes its container.
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.tasks.lock().expect("jobs lock poisoned").remove(id) {
+            Some(task) => {
+                task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Run the job until it completes or runs out of attempts, updating its status in Redis
+async fn run_job(state: &AppState, mut job: JobInfo, retry: Option<RetryPolicy>) {
+    let max_attempts = retry.as_ref().map_or(1, |r| r.max_attempts);
+    loop {
+        job.attempts += 1;
+        update_job(state, &mut job, JobStatus::Running).await;
+
+        let outcome = AttemptOutcome::new(run_attempt(state, &job.task).await);
+        let should_retry = outcome.should_retry(retry.as_ref());
+        job.result = outcome.result;
+        if !should_retry || job.attempts >= max_attempts {
+            tracing::info!(
+                "Job '{}' finished after {} attempt(s)",
+                job.id,
+                job.attempts
+            );
+            update_job(state, &mut job, outcome.status).await;
+            return;
+        }
+
+        tracing::info!(
+            "Retrying job '{}' (attempt {} failed)",
+            job.id,
+            job.attempts
+        );
+        update_job(state, &mut job, JobStatus::Pending).await;
+        let delay = retry.as_ref().map_or(0, |r| r.delay);
+        tokio::time::sleep(Duration::from_secs(delay.into())).await;
+    }
+}
+
+/// Status and final chunk of an attempt of a job
+#[derive(Debug)]
+struct AttemptOutcome {
+    status: JobStatus,
+    result: Option<CodeRunnerChunk>,
+    /// Whether the attempt failed due to an error that may not happen again
+    transient: bool,
+}
+
+impl AttemptOutcome {
+    fn new(attempt: Result<CodeRunnerChunk, AppError>) -> Self {
+        let (status, result, transient) = match attempt {
+            Ok(chunk @ CodeRunnerChunk::Result(_)) => (JobStatus::Completed, Some(chunk), false),
+            Ok(chunk @ CodeRunnerChunk::Error(CodeRunnerError::Cancelled { .. })) => {
+                (JobStatus::Cancelled, Some(chunk), false)
+            }
+            Ok(CodeRunnerChunk::Error(err)) => {
+                let message = err.to_string();
+                let transient = is_transient_error(&err);
+                (
+                    JobStatus::Failed { message },
+                    Some(CodeRunnerChunk::Error(err)),
+                    transient,
+                )
+            }
+            Ok(_) => {
+                let message = "Job ended without a result or error".to_owned();
+                (JobStatus::Failed { message }, None, false)
+            }
+            Err(err) => {
+                let transient = match &err {
+                    AppError::NotFound | AppError::BadRequest(_) | AppError::Serialization(_) => {
+                        false
+                    }
+                    AppError::ExecutionFailed(err) => is_transient_error(err),
+                    AppError::Redis(_)
+                    | AppError::Docker(_)
+                    | AppError::Server(_)
+                    | AppError::QueueFull { .. } => true,
+                };
+                let message = err.to_string();
+                (JobStatus::Failed { message }, None, transient)
+            }
+        };
+        Self {
+            status,
+            result,
+            transient,
+        }
+    }
+
+    /// Whether the job should be retried after this attempt, if it has attempts left
+    fn should_retry(&self, retry: Option<&RetryPolicy>) -> bool {
+        match (&self.status, &self.result) {
+            (JobStatus::Failed { .. }, _) => self.transient,
+            (_, Some(CodeRunnerChunk::Result(result))) => {
+                retry.is_some_and(|r| r.retry_on_exit_failure)
+                    && (result.timeout || result.exit_code != Some(0))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether the execution error may not happen again when retrying
+fn is_transient_error(err: &CodeRunnerError) -> bool {
+    matches!(
+        err,
+        CodeRunnerError::Docker { .. }
+            | CodeRunnerError::Sandbox { .. }
+            | CodeRunnerError::QueueFull { .. }
+    )
+}
+
+/// Run the job's task once, and return the final chunk of the stream
+async fn run_attempt(state: &AppState, task: &JobTask) -> Result<CodeRunnerChunk, AppError> {
+    let mut stream: BoxStream<CodeRunnerChunk> = match task {
+        JobTask::Code(input) => state.runner.execute(input.as_ref().clone()).await?.boxed(),
+        JobTask::Function { name, input } => {
+            let (name, alias) = split_fn_alias(name);
+            let fn_info = state
+                .redis
+                .get_fn_info(name)
+                .await?
+                .ok_or_else(|| AppError::BadRequest(format!("Function '{name}' not found")))?;
+            let secrets = state.secrets.get_all(name).await?;
+            state
+                .runner
+                .run_function(
+                    name.to_owned(),
+                    alias.map(str::to_owned),
+                    fn_info,
+                    input.clone(),
+                    secrets,
+                )
+                .await?
+                .boxed()
+        }
+    };
+    while let Some(chunk) = stream.next().await {
+        if matches!(
+            chunk,
+            CodeRunnerChunk::Result(_) | CodeRunnerChunk::Error(_)
+        ) {
+            return Ok(chunk);
+        }
+    }
+
+    Err(AppError::Server("No result/error from job".to_owned()))
+}
+
+async fn update_job(state: &AppState, job: &mut JobInfo, status: JobStatus) {
+    job.status = status;
+    job.updated_at = chrono::Utc::now();
+    if let Err(err) = state.redis.set_job(job, state.config.job_ttl).await {
+        tracing::error!("Failed to update job '{}' in Redis: {err}", job.id);
+    }
+}
+
+/// Keep the job marked as active on this server, until the future is dropped
+async fn keep_job_lease(redis: &RedisClient, id: &str) {
+    let interval = Duration::from_secs((JOB_LEASE_TTL / 3).into());
+    loop {
+        tokio::time::sleep(interval).await;
+        if let Err(err) = redis.set_job_lease(id, JOB_LEASE_TTL).await {
+            tracing::warn!("Failed to refresh lease of job '{id}': {err}");
+        }
+    }
+}
+
+/// Task that periodically marks unfinished jobs as failed if they're no longer active on
+/// any server, e.g. because the server running them was restarted
+async fn interrupted_jobs_task(redis: RedisClient, job_ttl: u32) {
+    let mut interval = tokio::time::interval(Duration::from_secs(JOB_LEASE_TTL.into()));
+    loop {
+        interval.tick().await;
+        if let Err(err) = fail_interrupted_jobs(&redis, job_ttl).await {
+            tracing::error!("Failed to check for interrupted jobs: {err}");
+        }
+    }
+}
+
+async fn fail_interrupted_jobs(redis: &RedisClient, job_ttl: u32) -> FredResult<()> {
+    for mut job in redis.list_jobs().await? {
+        if job.status.is_finished() || redis.has_job_lease(&job.id).await? {
+            continue;
+        }
+        tracing::warn!("Job '{}' was interrupted, marking as failed", job.id);
+        job.status = JobStatus::Failed {
+            message: "Job was interrupted by a server restart".to_owned(),
+        };
+        job.updated_at = chrono::Utc::now();
+        redis.set_job(&job, job_ttl).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tinirun_models::CodeRunnerResult;
+
+    use super::*;
+
+    fn result(exit_code: Option<i64>, timeout: bool) -> CodeRunnerChunk {
+        CodeRunnerChunk::Result(CodeRunnerResult {
+            exit_code,
+            timeout,
+            ..Default::default()
+        })
+    }
+
+    fn policy(retry_on_exit_failure: bool) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            delay: 0,
+            retry_on_exit_failure,
+        }
+    }
+
+    #[test]
+    fn retries_transient_errors() {
+        let docker_error = CodeRunnerError::Docker {
+            message: "connection reset".into(),
+        };
+        let outcome = AttemptOutcome::new(Ok(CodeRunnerChunk::Error(docker_error)));
+        assert!(matches!(outcome.status, JobStatus::Failed { .. }));
+        assert!(outcome.should_retry(Some(&policy(false))));
+
+        let outcome = AttemptOutcome::new(Err(AppError::QueueFull { retry_after: 5 }));
+        assert!(outcome.should_retry(Some(&policy(false))));
+
+        let outcome = AttemptOutcome::new(Err(AppError::Server("No result".into())));
+        assert!(outcome.should_retry(Some(&policy(false))));
+    }
+
+    #[test]
+    fn does_not_retry_permanent_errors() {
+        let not_found = AppError::BadRequest("Function 'greet' not found".into());
+        let outcome = AttemptOutcome::new(Err(not_found));
+        assert!(matches!(outcome.status, JobStatus::Failed { .. }));
+        assert!(!outcome.should_retry(Some(&policy(true))));
+
+        let outcome = AttemptOutcome::new(Err(AppError::NotFound));
+        assert!(!outcome.should_retry(Some(&policy(true))));
+
+        let build_error = CodeRunnerError::BuildFailed {
+            message: "syntax error".into(),
+            logs: String::new(),
+        };
+        let outcome = AttemptOutcome::new(Ok(CodeRunnerChunk::Error(build_error)));
+        assert!(!outcome.should_retry(Some(&policy(true))));
+
+        let cancelled = CodeRunnerError::Cancelled {
+            message: "cancelled".into(),
+        };
+        let outcome = AttemptOutcome::new(Ok(CodeRunnerChunk::Error(cancelled)));
+        assert!(matches!(outcome.status, JobStatus::Cancelled));
+        assert!(!outcome.should_retry(Some(&policy(true))));
+    }
+
+    #[test]
+    fn retries_exit_failures_if_enabled() {
+        let outcome = AttemptOutcome::new(Ok(result(Some(1), false)));
+        assert!(matches!(outcome.status, JobStatus::Completed));
+        assert!(outcome.should_retry(Some(&policy(true))));
+        assert!(!outcome.should_retry(Some(&policy(false))));
+        assert!(!outcome.should_retry(None));
+
+        let outcome = AttemptOutcome::new(Ok(result(None, true)));
+        assert!(outcome.should_retry(Some(&policy(true))));
+
+        let outcome = AttemptOutcome::new(Ok(result(Some(0), false)));
+        assert!(!outcome.should_retry(Some(&policy(true))));
+    }
+
+    #[test]
+    fn fails_attempt_without_result() {
+        let outcome = AttemptOutcome::new(Ok(CodeRunnerChunk::Info("done".into())));
+        assert!(matches!(outcome.status, JobStatus::Failed { .. }));
+        assert!(outcome.result.is_none());
+        assert!(!outcome.should_retry(Some(&policy(true))));
+    }
+}
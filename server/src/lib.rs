@@ -7,16 +7,20 @@ mod auth;
 mod config;
 mod errors;
 mod input;
+mod jobs;
 mod redis;
 mod responses;
 mod runner;
+mod secrets;
 mod state;
 
 pub async fn create_app() -> anyhow::Result<(axum::Router, AppConfig, impl Future + Send)> {
     let (router, state, on_shutdown) = App::new()
         .register(config::plugin()) // Extract configuration and add it to state
         .register(redis::plugin()) // Connect to Redis and add Redis client to state
+        .register(secrets::plugin()) // Add function secret store to state
         .register(runner::plugin()) // Connect to Docker and add code runner service to state
+        .register(jobs::plugin()) // Add job manager to state
         .register(api::plugin()) // Add API routes
         .init()
         .await?;
@@ -1,16 +1,48 @@
 use std::collections::HashMap;
 
 use fred::{
-    prelude::{ClientLike, FredResult, HashesInterface},
-    types::scan::ScanType,
+    prelude::{ClientLike, FredResult, HashesInterface, KeysInterface, LuaInterface},
+    types::{Expiration, scan::ScanType},
 };
 use futures::{StreamExt, TryStreamExt};
 
 use crate::redis::{
-    FunctionDetail,
-    structs::{FUNCTION_INFO_KEYS, FunctionInfo, FunctionStatus},
+    FunctionAlias, FunctionDetail,
+    structs::{FUNCTION_INFO_KEYS, FUNCTION_OPTIONAL_KEYS, FunctionInfo, FunctionStatus, JobInfo},
 };
 
+/// Lua script that sets the status of a function version, if the version's details are
+/// unchanged since they were read, and the function's status if it's still the same version.
+/// `KEYS`: version history and function. `ARGV`: version, details that were read, updated
+/// details, version in the function's hash, and status. Returns 0 if the details changed.
+const SET_FN_STATUS_SCRIPT: &str = r#"
+local current = redis.call('HGET', KEYS[1], ARGV[1]) or ''
+if current ~= ARGV[2] then
+    return 0
+end
+if ARGV[3] ~= '' then
+    redis.call('HSET', KEYS[1], ARGV[1], ARGV[3])
+end
+if redis.call('HGET', KEYS[2], 'version') == ARGV[4] then
+    redis.call('HSET', KEYS[2], 'status', ARGV[5])
+end
+return 1
+"#;
+
+/// Lua script that changes the current version of a function, if it is still the expected
+/// version. `KEYS`: function. `ARGV`: expected version, new version, `Building` status, and
+/// whether to fail if the function is being built. Returns 0 if the version wasn't changed.
+const SWAP_FN_VERSION_SCRIPT: &str = r#"
+if redis.call('HGET', KEYS[1], 'version') ~= ARGV[1] then
+    return 0
+end
+if ARGV[4] == '1' and redis.call('HGET', KEYS[1], 'status') == ARGV[3] then
+    return 0
+end
+redis.call('HSET', KEYS[1], 'version', ARGV[2])
+return 1
+"#;
+
 #[derive(Clone)]
 pub struct RedisClient {
     client: fred::prelude::Pool,
@@ -29,6 +61,34 @@ impl RedisClient {
         format!("{}fn:{name}", self.prefix)
     }
 
+    fn versions_key(&self, name: &str) -> String {
+        format!("{}fn-versions:{name}", self.prefix)
+    }
+
+    fn aliases_key(&self, name: &str) -> String {
+        format!("{}fn-aliases:{name}", self.prefix)
+    }
+
+    fn image_cache_key(&self) -> String {
+        format!("{}image-cache", self.prefix)
+    }
+
+    fn job_key(&self, id: &str) -> String {
+        format!("{}job:{id}", self.prefix)
+    }
+
+    fn job_lease_key(&self, id: &str) -> String {
+        format!("{}job-lease:{id}", self.prefix)
+    }
+
+    fn secrets_key(&self, fn_name: &str) -> String {
+        format!("{}secrets:{fn_name}", self.prefix)
+    }
+
+    fn artifact_key(&self, run_id: &str, path: &str) -> String {
+        format!("{}artifact:{run_id}:{path}", self.prefix)
+    }
+
     pub async fn get_fn_detail(&self, name: &str) -> FredResult<Option<FunctionDetail>> {
         let key = self.key(name);
         if let Some(info) = self
@@ -64,15 +124,222 @@ impl RedisClient {
 
     pub async fn set_fn(&self, name: &str, info: FunctionDetail) -> FredResult<()> {
         let values = HashMap::try_from(info)?;
+        let unset_keys: Vec<_> = FUNCTION_OPTIONAL_KEYS
+            .iter()
+            .copied()
+            .filter(|key| !values.contains_key(*key))
+            .collect();
+        if !unset_keys.is_empty() {
+            self.client
+                .hdel::<(), _, _>(self.key(name), unset_keys)
+                .await?;
+        }
         self.client.hset(self.key(name), values).await
     }
 
-    pub async fn set_fn_status(&self, name: &str, status: FunctionStatus) -> FredResult<()> {
+    /// Change the current version of the function from `from` to `to`, so that concurrent
+    /// updates and rollbacks can't create the same version. Returns `false` if the current
+    /// version is no longer `from`, or if `fail_if_building` is set and a build is in progress.
+    pub async fn swap_fn_version(
+        &self,
+        name: &str,
+        from: u32,
+        to: u32,
+        fail_if_building: bool,
+    ) -> FredResult<bool> {
+        let args = vec![
+            serde_json::to_string(&from.to_string())?,
+            serde_json::to_string(&to.to_string())?,
+            serde_json::to_string(&FunctionStatus::Building)?,
+            u8::from(fail_if_building).to_string(),
+        ];
+        let swapped: u32 = self
+            .client
+            .eval(SWAP_FN_VERSION_SCRIPT, vec![self.key(name)], args)
+            .await?;
+        Ok(swapped == 1)
+    }
+
+    /// Set the build status of a version of the function. The function's status is only
+    /// updated if it is still the current version. The update is retried if the version's
+    /// details are changed concurrently.
+    pub async fn set_fn_status(
+        &self,
+        name: &str,
+        version: u32,
+        status: FunctionStatus,
+    ) -> FredResult<()> {
+        let keys = vec![self.versions_key(name), self.key(name)];
+        let version_arg = serde_json::to_string(&version.to_string())?;
+        let status_arg = serde_json::to_string(&status)?;
+        loop {
+            let (current, updated) = match self.get_fn_version_json(name, version).await? {
+                Some(current) => {
+                    let mut info: FunctionDetail = serde_json::from_str(&current)?;
+                    info.status = status.clone();
+                    (current, serde_json::to_string(&info)?)
+                }
+                None => (String::new(), String::new()),
+            };
+            let args = vec![
+                version.to_string(),
+                current,
+                updated,
+                version_arg.clone(),
+                status_arg.clone(),
+            ];
+            let result: u32 = self
+                .client
+                .eval(SET_FN_STATUS_SCRIPT, keys.clone(), args)
+                .await?;
+            if result == 1 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Save the function details in the function's version history, and remove the
+    /// oldest versions beyond the given number of versions to keep. Versions that an
+    /// alias points to are always kept.
+    pub async fn add_fn_version(
+        &self,
+        name: &str,
+        info: &FunctionDetail,
+        retention: usize,
+    ) -> FredResult<()> {
+        let key = self.versions_key(name);
+        self.client
+            .hset::<(), _, _>(
+                &key,
+                (info.version.to_string(), serde_json::to_string(info)?),
+            )
+            .await?;
+
+        let mut versions: Vec<u32> = self
+            .client
+            .hkeys::<Vec<String>, _>(&key)
+            .await?
+            .iter()
+            .filter_map(|version| version.parse().ok())
+            .collect();
+        versions.sort_unstable_by(|a, b| b.cmp(a));
+        let aliased: Vec<u32> = self
+            .get_fn_aliases(name)
+            .await?
+            .values()
+            .flat_map(|alias| alias.versions())
+            .collect();
+        let expired: Vec<_> = versions
+            .into_iter()
+            .skip(retention)
+            .filter(|version| !aliased.contains(version))
+            .map(|version| version.to_string())
+            .collect();
+        if !expired.is_empty() {
+            self.client.hdel::<(), _, _>(&key, expired).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn get_fn_version(
+        &self,
+        name: &str,
+        version: u32,
+    ) -> FredResult<Option<FunctionDetail>> {
+        match self.get_fn_version_json(name, version).await? {
+            Some(info) => Ok(Some(serde_json::from_str(&info)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_fn_version_json(&self, name: &str, version: u32) -> FredResult<Option<String>> {
+        let key = self.versions_key(name);
+        let info = self
+            .client
+            .hget::<Option<String>, _, _>(&key, version.to_string())
+            .await?;
+        match info {
+            Some(info) => Ok(Some(info)),
+            None if self.backfill_fn_version(name).await? => {
+                self.client.hget(&key, version.to_string()).await
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Add the current version to the version history of a function created before
+    /// versions were kept. Returns `false` if the function already had a version history.
+    async fn backfill_fn_version(&self, name: &str) -> FredResult<bool> {
+        let key = self.versions_key(name);
+        if self.client.hlen::<u64, _>(&key).await? > 0 {
+            return Ok(false);
+        }
+        let Some(info) = self.get_fn_detail(name).await? else {
+            return Ok(false);
+        };
+        self.client
+            .hsetnx::<(), _, _, _>(
+                &key,
+                info.version.to_string(),
+                serde_json::to_string(&info)?,
+            )
+            .await?;
+        Ok(true)
+    }
+
+    /// Get the version history of the function, newest first
+    pub async fn get_fn_versions(&self, name: &str) -> FredResult<Vec<FunctionDetail>> {
+        self.backfill_fn_version(name).await?;
+        let versions: HashMap<String, String> =
+            self.client.hgetall(self.versions_key(name)).await?;
+        let mut versions = versions
+            .values()
+            .map(|info| serde_json::from_str(info))
+            .collect::<Result<Vec<FunctionDetail>, _>>()?;
+        versions.sort_unstable_by(|a, b| b.version.cmp(&a.version));
+        Ok(versions)
+    }
+
+    pub async fn get_fn_alias(&self, name: &str, alias: &str) -> FredResult<Option<FunctionAlias>> {
+        match self
+            .client
+            .hget::<Option<String>, _, _>(self.aliases_key(name), alias)
+            .await?
+        {
+            Some(alias) => Ok(Some(serde_json::from_str(&alias)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn get_fn_aliases(&self, name: &str) -> FredResult<HashMap<String, FunctionAlias>> {
+        let aliases: HashMap<String, String> = self.client.hgetall(self.aliases_key(name)).await?;
+        Ok(aliases
+            .into_iter()
+            .map(|(alias, info)| Ok((alias, serde_json::from_str(&info)?)))
+            .collect::<Result<_, serde_json::Error>>()?)
+    }
+
+    /// Create or replace the alias. The alias is switched to the new versions at once.
+    pub async fn set_fn_alias(
+        &self,
+        name: &str,
+        alias: &str,
+        info: &FunctionAlias,
+    ) -> FredResult<()> {
         self.client
-            .hset(self.key(name), ("status", serde_json::to_string(&status)?))
+            .hset(
+                self.aliases_key(name),
+                (alias, serde_json::to_string(info)?),
+            )
             .await
     }
 
+    /// Delete an alias of a function. Returns `false` if the alias didn't exist.
+    pub async fn delete_fn_alias(&self, name: &str, alias: &str) -> FredResult<bool> {
+        let num_deleted: u32 = self.client.hdel(self.aliases_key(name), alias).await?;
+        Ok(num_deleted > 0)
+    }
+
     pub async fn list_functions(&self, limit: u32) -> FredResult<Vec<(String, FunctionInfo)>> {
         let keys: Vec<_> = self
             .client
@@ -93,6 +360,116 @@ impl RedisClient {
         Ok(functions)
     }
 
+    /// Record the last use of a cached dependency image
+    pub async fn touch_cached_image(&self, tag: &str) -> FredResult<()> {
+        let now = chrono::Utc::now().timestamp();
+        self.client.hset(self.image_cache_key(), (tag, now)).await
+    }
+
+    /// Get the last use (Unix timestamp) of each cached dependency image
+    pub async fn get_cached_image_usage(&self) -> FredResult<HashMap<String, i64>> {
+        self.client.hgetall(self.image_cache_key()).await
+    }
+
+    pub async fn remove_cached_image(&self, tag: &str) -> FredResult<()> {
+        self.client.hdel(self.image_cache_key(), tag).await
+    }
+
+    pub async fn get_job(&self, id: &str) -> FredResult<Option<JobInfo>> {
+        match self
+            .client
+            .get::<Option<String>, _>(self.job_key(id))
+            .await?
+        {
+            Some(job) => Ok(Some(serde_json::from_str(&job)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Save the job, resetting its expiration to the given TTL in seconds
+    pub async fn set_job(&self, job: &JobInfo, ttl: u32) -> FredResult<()> {
+        let value = serde_json::to_string(job)?;
+        let expiration = Expiration::EX(ttl.into());
+        self.client
+            .set(self.job_key(&job.id), value, Some(expiration), None, false)
+            .await
+    }
+
+    /// List all stored jobs
+    pub async fn list_jobs(&self) -> FredResult<Vec<JobInfo>> {
+        let keys: Vec<_> = self
+            .client
+            .next()
+            .scan_buffered(self.job_key("*"), Some(100), Some(ScanType::String))
+            .try_collect()
+            .await?;
+        let mut jobs = Vec::new();
+        for key in keys {
+            if let Some(job) = self.client.get::<Option<String>, _>(key).await? {
+                jobs.push(serde_json::from_str(&job)?);
+            }
+        }
+
+        Ok(jobs)
+    }
+
+    /// Mark the job as active on a server, expiring after the given TTL in seconds
+    pub async fn set_job_lease(&self, id: &str, ttl: u32) -> FredResult<()> {
+        let expiration = Expiration::EX(ttl.into());
+        self.client
+            .set(self.job_lease_key(id), 1, Some(expiration), None, false)
+            .await
+    }
+
+    pub async fn has_job_lease(&self, id: &str) -> FredResult<bool> {
+        self.client.exists(self.job_lease_key(id)).await
+    }
+
+    /// Get the encrypted secrets of a function
+    pub async fn get_secrets(&self, fn_name: &str) -> FredResult<HashMap<String, String>> {
+        self.client.hgetall(self.secrets_key(fn_name)).await
+    }
+
+    pub async fn get_secret_names(&self, fn_name: &str) -> FredResult<Vec<String>> {
+        self.client.hkeys(self.secrets_key(fn_name)).await
+    }
+
+    pub async fn set_secret(&self, fn_name: &str, name: &str, encrypted: String) -> FredResult<()> {
+        self.client
+            .hset(self.secrets_key(fn_name), (name, encrypted))
+            .await
+    }
+
+    /// Delete a secret of a function. Returns `false` if the secret didn't exist.
+    pub async fn delete_secret(&self, fn_name: &str, name: &str) -> FredResult<bool> {
+        let num_deleted: u32 = self.client.hdel(self.secrets_key(fn_name), name).await?;
+        Ok(num_deleted > 0)
+    }
+
+    /// Store the content of an artifact, expiring after the given TTL in seconds
+    pub async fn set_artifact(
+        &self,
+        run_id: &str,
+        path: &str,
+        content: Vec<u8>,
+        ttl: u32,
+    ) -> FredResult<()> {
+        let expiration = Expiration::EX(ttl.into());
+        self.client
+            .set(
+                self.artifact_key(run_id, path),
+                content,
+                Some(expiration),
+                None,
+                false,
+            )
+            .await
+    }
+
+    pub async fn get_artifact(&self, run_id: &str, path: &str) -> FredResult<Option<Vec<u8>>> {
+        self.client.get(self.artifact_key(run_id, path)).await
+    }
+
     pub async fn shutdown(&self) -> FredResult<()> {
         self.client.quit().await
     }
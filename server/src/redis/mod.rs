@@ -10,7 +10,9 @@ mod client;
 mod structs;
 
 pub use client::RedisClient;
-pub use structs::{FunctionDetail, FunctionInfo, FunctionStatus};
+pub use structs::{
+    FunctionAlias, FunctionDetail, FunctionInfo, FunctionStatus, JobInfo, JobStatus,
+};
 
 const CLIENT_TIMEOUT: Duration = Duration::from_secs(6);
 
@@ -2,7 +2,10 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize, ser::Error};
 use serde_with::{DisplayFromStr, serde_as, skip_serializing_none};
 use std::collections::HashMap;
-use tinirun_models::{CodeRunnerError, CodeRunnerLanguage, UpdateFunctionInput};
+use tinirun_models::{
+    AliasCanary, CodeRunnerChunk, CodeRunnerError, CodeRunnerLanguage, FunctionIo, JobTask,
+    SetAliasInput, SourceFile, UpdateFunctionInput,
+};
 
 /// Build status of the function
 #[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
@@ -30,9 +33,24 @@ pub enum FunctionStatus {
 #[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct FunctionDetail {
     pub code: String,
+    /// Additional source files of the function
+    pub sources: Option<Vec<SourceFile>>,
     pub lang: CodeRunnerLanguage,
+    /// Version of the language, if not the default version
+    pub lang_version: Option<String>,
     pub description: Option<String>,
     pub dependencies: Option<String>,
+    /// Name of the egress allowlist used for the function's runs
+    pub egress: Option<String>,
+    /// Name of the sandbox profile used for the function's runs
+    pub sandbox: Option<String>,
+    /// How the function receives its input and returns its output
+    #[serde(default)]
+    pub io: FunctionIo,
+    /// JSON Schema of the function's input
+    pub input_schema: Option<serde_json::Value>,
+    /// JSON Schema of the function's output
+    pub output_schema: Option<serde_json::Value>,
     pub status: FunctionStatus,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
@@ -71,7 +89,14 @@ impl TryFrom<FunctionDetail> for HashMap<String, String> {
 #[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct FunctionInfo {
     pub lang: CodeRunnerLanguage,
+    pub lang_version: Option<String>,
     pub description: Option<String>,
+    pub egress: Option<String>,
+    pub sandbox: Option<String>,
+    #[serde(default)]
+    pub io: FunctionIo,
+    pub input_schema: Option<serde_json::Value>,
+    pub output_schema: Option<serde_json::Value>,
     pub status: FunctionStatus,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
@@ -80,15 +105,33 @@ pub struct FunctionInfo {
 }
 
 /// Keys in FunctionInfo (e.g. to fetch via `HMGET` from Redis)
-pub const FUNCTION_INFO_KEYS: &[&str; 6] = &[
+pub const FUNCTION_INFO_KEYS: &[&str; 12] = &[
     "lang",
+    "lang_version",
     "description",
+    "egress",
+    "sandbox",
+    "io",
+    "input_schema",
+    "output_schema",
     "status",
     "created_at",
     "updated_at",
     "version",
 ];
 
+/// Optional keys in FunctionDetail, that are removed from Redis when unset
+pub const FUNCTION_OPTIONAL_KEYS: &[&str; 8] = &[
+    "sources",
+    "lang_version",
+    "description",
+    "dependencies",
+    "egress",
+    "sandbox",
+    "input_schema",
+    "output_schema",
+];
+
 impl TryFrom<HashMap<String, String>> for FunctionInfo {
     type Error = serde_json::Error;
     fn try_from(hash: HashMap<String, String>) -> Result<Self, serde_json::Error> {
@@ -100,15 +143,163 @@ impl TryFrom<HashMap<String, String>> for FunctionInfo {
     }
 }
 
+impl From<FunctionDetail> for FunctionInfo {
+    fn from(detail: FunctionDetail) -> Self {
+        Self {
+            lang: detail.lang,
+            lang_version: detail.lang_version,
+            description: detail.description,
+            egress: detail.egress,
+            sandbox: detail.sandbox,
+            io: detail.io,
+            input_schema: detail.input_schema,
+            output_schema: detail.output_schema,
+            status: detail.status,
+            created_at: detail.created_at,
+            updated_at: detail.updated_at,
+            version: detail.version,
+        }
+    }
+}
+
 impl FunctionDetail {
     /// Update function details with new input, set status to `Building`,
     /// bump version and `updated_at` timestamp
     pub fn update(&mut self, updated_info: UpdateFunctionInput) {
-        self.code = updated_info.code;
+        let (code, sources) = tinirun_models::split_entrypoint(
+            updated_info.code,
+            updated_info.sources,
+            updated_info.entrypoint.as_deref(),
+        );
+        self.code = code;
+        self.sources = Some(sources).filter(|sources| !sources.is_empty());
+        self.lang_version = updated_info.version;
         self.description = updated_info.description;
         self.dependencies = updated_info.dependencies.map(|d| d.join(" "));
+        self.egress = updated_info.egress;
+        self.sandbox = updated_info.sandbox;
+        self.io = updated_info.io;
+        self.input_schema = updated_info.input_schema;
+        self.output_schema = updated_info.output_schema;
         self.status = FunctionStatus::Building;
         self.updated_at = chrono::Utc::now();
         self.version += 1;
     }
+
+    /// Replace any secret values in the function's code and details
+    pub fn redact<'a>(&mut self, secrets: impl IntoIterator<Item = &'a String>) {
+        for secret in secrets.into_iter().filter(|s| !s.is_empty()) {
+            self.code = self.code.replace(secret, "[REDACTED]");
+            for source in self.sources.iter_mut().flatten() {
+                source.content = source.content.replace(secret, "[REDACTED]");
+            }
+            if let Some(description) = &mut self.description {
+                *description = description.replace(secret, "[REDACTED]");
+            }
+        }
+    }
+}
+
+/// Alias of a function stored in Redis, pointing at a version of the function
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FunctionAlias {
+    /// Version of the function that the alias points to
+    pub version: u32,
+    /// Another version that receives a share of the alias's runs
+    pub canary: Option<AliasCanary>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<SetAliasInput> for FunctionAlias {
+    fn from(input: SetAliasInput) -> Self {
+        Self {
+            version: input.version,
+            canary: input.canary,
+            updated_at: chrono::Utc::now(),
+        }
+    }
+}
+
+impl FunctionAlias {
+    /// Select the version for a run, given a random number from 0 to 99. Returns
+    /// the version, and whether it is the canary version.
+    pub fn select_version(&self, roll: u8) -> (u32, bool) {
+        match &self.canary {
+            Some(canary) if roll < canary.weight => (canary.version, true),
+            _ => (self.version, false),
+        }
+    }
+
+    /// Versions of the function that the alias points to
+    pub fn versions(&self) -> impl Iterator<Item = u32> {
+        std::iter::once(self.version).chain(self.canary.as_ref().map(|c| c.version))
+    }
+}
+
+/// Status of an asynchronous job
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobStatus {
+    /// Job is waiting to start, or waiting for the next attempt
+    Pending,
+    /// Job is running
+    Running,
+    /// The code was executed. The result may still have a non-zero exit code.
+    Completed,
+    /// The code could not be executed
+    Failed {
+        /// Error message of the last attempt
+        message: String,
+    },
+    /// Job was cancelled
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, JobStatus::Pending | JobStatus::Running)
+    }
+}
+
+/// Asynchronous job info stored in Redis
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct JobInfo {
+    pub id: String,
+    pub task: JobTask,
+    pub status: JobStatus,
+    /// Number of attempts started so far
+    pub attempts: u32,
+    /// Final `result` or `error` chunk of the last attempt
+    pub result: Option<CodeRunnerChunk>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_alias_version() {
+        let mut alias = FunctionAlias {
+            version: 3,
+            canary: None,
+            updated_at: chrono::Utc::now(),
+        };
+        assert_eq!(alias.select_version(0), (3, false));
+        assert_eq!(alias.versions().collect::<Vec<_>>(), [3]);
+
+        alias.canary = Some(AliasCanary {
+            version: 4,
+            weight: 10,
+        });
+        let canary_runs = (0..100)
+            .filter(|&roll| alias.select_version(roll).1)
+            .count();
+        assert_eq!(canary_runs, 10);
+        assert_eq!(alias.select_version(9), (4, true));
+        assert_eq!(alias.select_version(10), (3, false));
+        assert_eq!(alias.versions().collect::<Vec<_>>(), [3, 4]);
+    }
 }
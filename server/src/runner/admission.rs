@@ -0,0 +1,271 @@
+//! Admission control for image builds and container runs
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use tinirun_models::{CodeRunnerChunk, CodeRunnerError, ExecutionStage};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, mpsc, watch};
+
+use crate::errors::AppError;
+
+/// Suggested number of seconds to wait before retrying when the queue is full
+const RETRY_AFTER_SECS: u32 = 10;
+
+/// Configuration of the admission controller
+#[derive(Debug, Clone, Copy)]
+pub struct AdmissionConfig {
+    /// Maximum number of concurrent image builds
+    pub max_builds: usize,
+    /// Maximum number of concurrent container runs
+    pub max_runs: usize,
+    /// Maximum number of requests waiting for a build or run slot
+    pub max_queue: usize,
+}
+
+/// # Admission controller
+///
+/// Limits the number of image builds and container runs that are executed concurrently
+/// against the Docker daemon. Requests beyond the limits wait in a FIFO queue, and
+/// receive `Queued` chunks with their position while waiting. New requests are rejected
+/// when the queue is full: upfront with [`Self::check_capacity`], and when the request
+/// waits for a slot, so that the limit also holds for concurrent requests and for runs
+/// that need several slots (e.g. the test cases of a judge run).
+pub struct AdmissionController {
+    builds: Limiter,
+    runs: Limiter,
+}
+
+impl AdmissionController {
+    pub fn new(config: AdmissionConfig) -> Self {
+        Self {
+            builds: Limiter::new(config.max_builds, config.max_queue),
+            runs: Limiter::new(config.max_runs, config.max_queue),
+        }
+    }
+
+    /// Check that there is room in the queue of the given stages, returning
+    /// an error if the request should be rejected. This is only an early check, the
+    /// limit is enforced when acquiring a slot.
+    pub fn check_capacity(&self, stages: &[ExecutionStage]) -> Result<(), AppError> {
+        for stage in stages {
+            if self.limiter(*stage).is_queue_full() {
+                return Err(AppError::QueueFull {
+                    retry_after: RETRY_AFTER_SECS,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait for a slot for the given stage, sending the position in the queue while
+    /// waiting. The slot is released when the returned permit is dropped. Returns an
+    /// error if there is no free slot and the queue is full.
+    pub async fn acquire(
+        &self,
+        stage: ExecutionStage,
+        tx: &mpsc::Sender<CodeRunnerChunk>,
+    ) -> Result<OwnedSemaphorePermit, CodeRunnerError> {
+        self.limiter(stage).acquire(stage, tx).await
+    }
+
+    fn limiter(&self, stage: ExecutionStage) -> &Limiter {
+        match stage {
+            ExecutionStage::Build => &self.builds,
+            ExecutionStage::Run => &self.runs,
+        }
+    }
+}
+
+/// Semaphore with a queue of waiting tickets to keep track of queue positions.
+/// The semaphore is fair, so permits are handed out in the same order as the queue.
+struct Limiter {
+    semaphore: Arc<Semaphore>,
+    queue: Mutex<VecDeque<u64>>,
+    next_ticket: AtomicU64,
+    queue_changed: watch::Sender<()>,
+    max_queue: usize,
+}
+
+impl Limiter {
+    fn new(max_concurrent: usize, max_queue: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_concurrent)),
+            queue: Mutex::default(),
+            next_ticket: AtomicU64::new(0),
+            queue_changed: watch::Sender::new(()),
+            max_queue,
+        }
+    }
+
+    fn is_queue_full(&self) -> bool {
+        self.semaphore.available_permits() == 0
+            && self.queue.lock().expect("queue lock poisoned").len() >= self.max_queue
+    }
+
+    async fn acquire(
+        &self,
+        stage: ExecutionStage,
+        tx: &mpsc::Sender<CodeRunnerChunk>,
+    ) -> Result<OwnedSemaphorePermit, CodeRunnerError> {
+        // Take a free slot, or join the queue if it isn't full. The queue stays locked
+        // while checking, so that concurrent requests can't go over the limit.
+        let ticket = {
+            let mut queue = self.queue.lock().expect("queue lock poisoned");
+            if let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+                return Ok(permit);
+            }
+            if queue.len() >= self.max_queue {
+                let stage = match stage {
+                    ExecutionStage::Build => "build",
+                    ExecutionStage::Run => "run",
+                };
+                return Err(CodeRunnerError::QueueFull {
+                    message: format!("Too many requests waiting for a {stage} slot"),
+                });
+            }
+            let id = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+            queue.push_back(id);
+            QueueTicket { limiter: self, id }
+        };
+
+        // Wait in the queue, and send the position whenever it changes
+        let mut queue_changed = self.queue_changed.subscribe();
+        let permit = self.semaphore.clone().acquire_owned();
+        tokio::pin!(permit);
+        loop {
+            if let Some(position) = ticket.position() {
+                let chunk = CodeRunnerChunk::Queued { stage, position };
+                let _ = tx.send(chunk).await;
+            }
+            tokio::select! {
+                permit = &mut permit => {
+                    return Ok(permit.expect("semaphore should not be closed"));
+                }
+                _ = queue_changed.changed() => {}
+            }
+        }
+    }
+}
+
+/// Ticket in the queue of a limiter, removed from the queue when dropped
+struct QueueTicket<'a> {
+    limiter: &'a Limiter,
+    id: u64,
+}
+
+impl QueueTicket<'_> {
+    /// 1-based position in the queue
+    fn position(&self) -> Option<u32> {
+        let queue = self.limiter.queue.lock().expect("queue lock poisoned");
+        let idx = queue.iter().position(|id| *id == self.id)?;
+        Some(idx as u32 + 1)
+    }
+}
+
+impl Drop for QueueTicket<'_> {
+    fn drop(&mut self) {
+        self.limiter
+            .queue
+            .lock()
+            .expect("queue lock poisoned")
+            .retain(|id| *id != self.id);
+        self.limiter.queue_changed.send_replace(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Receive the queue positions sent so far
+    fn positions(rx: &mut mpsc::Receiver<CodeRunnerChunk>) -> Vec<u32> {
+        let mut positions = Vec::new();
+        while let Ok(chunk) = rx.try_recv() {
+            if let CodeRunnerChunk::Queued { position, .. } = chunk {
+                positions.push(position);
+            }
+        }
+        positions
+    }
+
+    /// Let the waiting tasks run
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    #[tokio::test]
+    async fn sends_queue_positions_and_grants_in_order() {
+        let limiter = Arc::new(Limiter::new(1, 2));
+        let (tx, _rx) = mpsc::channel(10);
+        let running = limiter.acquire(ExecutionStage::Run, &tx).await.unwrap();
+
+        let (order_tx, mut order_rx) = mpsc::unbounded_channel();
+        let mut waiters = Vec::new();
+        for name in ["first", "second"] {
+            let (tx, rx) = mpsc::channel(10);
+            let (limiter, order_tx) = (limiter.clone(), order_tx.clone());
+            let task = tokio::spawn(async move {
+                let permit = limiter.acquire(ExecutionStage::Run, &tx).await.unwrap();
+                order_tx.send(name).unwrap();
+                settle().await;
+                drop(permit);
+            });
+            waiters.push((task, rx));
+            settle().await;
+        }
+        assert_eq!(positions(&mut waiters[0].1), [1]);
+        assert_eq!(positions(&mut waiters[1].1), [2]);
+
+        drop(running);
+        for (task, _) in &mut waiters {
+            task.await.unwrap();
+        }
+        assert_eq!(order_rx.recv().await, Some("first"));
+        assert_eq!(order_rx.recv().await, Some("second"));
+        // The second request moved up when the first one got the slot
+        assert_eq!(positions(&mut waiters[1].1), [1]);
+        assert!(limiter.queue.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_requests_when_queue_is_full() {
+        let admission = Arc::new(AdmissionController::new(AdmissionConfig {
+            max_builds: 1,
+            max_runs: 1,
+            max_queue: 1,
+        }));
+        let (tx, _rx) = mpsc::channel(10);
+        let _running = admission.acquire(ExecutionStage::Run, &tx).await.unwrap();
+        assert!(admission.check_capacity(&[ExecutionStage::Run]).is_ok());
+
+        let waiting = tokio::spawn({
+            let (admission, tx) = (admission.clone(), tx.clone());
+            async move {
+                admission
+                    .acquire(ExecutionStage::Run, &tx)
+                    .await
+                    .map(|_| ())
+            }
+        });
+        settle().await;
+        assert!(matches!(
+            admission.check_capacity(&[ExecutionStage::Run]),
+            Err(AppError::QueueFull { .. })
+        ));
+        let rejected = admission.acquire(ExecutionStage::Run, &tx).await;
+        assert!(matches!(rejected, Err(CodeRunnerError::QueueFull { .. })));
+
+        // Other stages have their own limit, and a cancelled request leaves the queue
+        assert!(admission.check_capacity(&[ExecutionStage::Build]).is_ok());
+        waiting.abort();
+        let _ = waiting.await;
+        assert!(admission.check_capacity(&[ExecutionStage::Run]).is_ok());
+    }
+}
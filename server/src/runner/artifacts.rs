@@ -0,0 +1,292 @@
+//! Output artifacts written by programs to the output directory
+
+use std::path::PathBuf;
+
+use fred::prelude::FredResult;
+use futures::StreamExt;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use tinirun_models::{Artifact, CodeRunnerChunk};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    sync::mpsc,
+};
+
+use crate::{
+    redis::RedisClient,
+    runner::{
+        backend::SandboxBackend,
+        helpers::{Redactor, log},
+    },
+};
+
+/// Limits of the output artifacts
+#[derive(Debug, Clone, Copy)]
+pub struct ArtifactConfig {
+    /// Maximum number of artifacts returned from a run
+    pub max_files: usize,
+    /// Maximum total size of the output directory in bytes
+    pub max_total_bytes: u64,
+    /// Files larger than this are stored for download instead of being sent in the stream
+    pub inline_max_bytes: u64,
+    /// Time in seconds to keep stored artifacts
+    pub ttl: u32,
+    /// Whether to limit the output directory volume of containers with a disk quota
+    pub volume_quota: bool,
+}
+
+impl ArtifactConfig {
+    /// Disk quota of the output directory volume of containers, if enabled
+    pub fn output_dir_quota(&self) -> Option<u64> {
+        Some(self.max_total_bytes).filter(|_| self.volume_quota)
+    }
+}
+
+/// Characters that are percent-encoded in a path segment of the download URL
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// URL to download a stored artifact of a run
+fn download_url(run_id: &str, path: &str) -> String {
+    let segments: Vec<_> = path
+        .split('/')
+        .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT).to_string())
+        .collect();
+    format!("/api/runs/{run_id}/artifacts/{}", segments.join("/"))
+}
+
+/// Files read from the output directory of a sandbox
+#[derive(Debug, Default)]
+struct OutputFiles {
+    /// Path relative to the output directory, and content of each file
+    files: Vec<(String, Vec<u8>)>,
+    /// Content of the result file, if it was found
+    result: Option<Vec<u8>>,
+}
+
+/// Read the files from the tar archive of the output directory, within the limits of
+/// the config, and redact the secrets in their content. Files beyond the limits are
+/// skipped with an info message.
+async fn read_output_files(
+    archive: impl AsyncRead + Unpin,
+    config: &ArtifactConfig,
+    run_id: &str,
+    result_file: Option<&str>,
+    redactor: &Redactor,
+    tx: &mpsc::Sender<CodeRunnerChunk>,
+) -> OutputFiles {
+    let mut output = OutputFiles::default();
+    let mut archive = tokio_tar::Archive::new(archive);
+    let mut entries = match archive.entries() {
+        Ok(entries) => entries,
+        Err(err) => {
+            tracing::debug!("Failed to read artifacts of '{run_id}': {err}");
+            return output;
+        }
+    };
+
+    let mut total_bytes = 0;
+    while let Some(entry) = entries.next().await {
+        let mut entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                tracing::debug!("Failed to read artifacts of '{run_id}': {err}");
+                break;
+            }
+        };
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        // Paths in the archive start with the name of the output directory
+        let path = match entry.path() {
+            Ok(path) => path.components().skip(1).collect::<PathBuf>(),
+            Err(_) => continue,
+        };
+        let path = path.to_string_lossy().into_owned();
+        if result_file == Some(path.as_str()) {
+            let mut content = Vec::new();
+            match entry.read_to_end(&mut content).await {
+                Ok(_) => output.result = Some(redact_result(redactor, &content)),
+                Err(err) => tracing::debug!("Failed to read result of '{run_id}': {err}"),
+            }
+            continue;
+        }
+        let size = entry.header().size().unwrap_or_default();
+        if output.files.len() >= config.max_files {
+            let max_files = config.max_files;
+            let message = format!("Skipping artifact '{path}': limit of {max_files} files");
+            log::send_info(tx, message).await;
+            continue;
+        }
+        if total_bytes + size > config.max_total_bytes {
+            let message = format!("Skipping artifact '{path}': total size limit exceeded");
+            log::send_info(tx, message).await;
+            continue;
+        }
+
+        let mut content = Vec::with_capacity(size as usize);
+        if let Err(err) = entry.read_to_end(&mut content).await {
+            log::send_info(tx, format!("Failed to read artifact '{path}': {err}")).await;
+            continue;
+        }
+        total_bytes += size;
+        output.files.push((path, redactor.redact(&content)));
+    }
+
+    output
+}
+
+/// Redact the secrets in the result file. A JSON result is redacted in its string values,
+/// so that it can still be parsed.
+fn redact_result(redactor: &Redactor, content: &[u8]) -> Vec<u8> {
+    match serde_json::from_slice::<serde_json::Value>(content) {
+        Ok(mut value) => {
+            redactor.redact_json(&mut value);
+            serde_json::to_vec(&value).unwrap_or_else(|_| redactor.redact(content))
+        }
+        Err(_) => redactor.redact(content),
+    }
+}
+
+/// # Artifact store
+///
+/// Collects the files in the output directory of a finished sandbox and sends them
+/// as `Artifact` chunks. Large files are stored in Redis for a limited time, and can be
+/// downloaded separately.
+pub struct ArtifactStore {
+    redis: RedisClient,
+    pub config: ArtifactConfig,
+}
+
+impl ArtifactStore {
+    pub fn new(redis: RedisClient, config: ArtifactConfig) -> Self {
+        Self { redis, config }
+    }
+
+    /// Download the output directory of the sandbox, and send each file as an artifact.
+    /// The content of the `result_file` (e.g. the result of a function in JSON mode) is
+    /// returned instead of being sent. Secrets are redacted from all files.
+    pub async fn collect(
+        &self,
+        backend: &impl SandboxBackend,
+        run_id: &str,
+        result_file: Option<&str>,
+        redactor: &Redactor,
+        tx: &mpsc::Sender<CodeRunnerChunk>,
+    ) -> Option<Vec<u8>> {
+        let archive = backend.output_archive(run_id).await;
+        let config = &self.config;
+        let output = read_output_files(archive, config, run_id, result_file, redactor, tx).await;
+        for (path, content) in output.files {
+            let size = content.len() as u64;
+            let artifact = if size <= self.config.inline_max_bytes {
+                Artifact {
+                    path,
+                    size,
+                    content: Some(content),
+                    download_url: None,
+                }
+            } else {
+                let ttl = self.config.ttl;
+                if let Err(err) = self.redis.set_artifact(run_id, &path, content, ttl).await {
+                    tracing::error!("Failed to store artifact '{path}' of '{run_id}': {err}");
+                    log::send_info(tx, format!("Failed to store artifact '{path}'")).await;
+                    continue;
+                }
+                Artifact {
+                    download_url: Some(download_url(run_id, &path)),
+                    path,
+                    size,
+                    content: None,
+                }
+            };
+            let _ = tx.send(CodeRunnerChunk::Artifact(artifact)).await;
+        }
+
+        output.result
+    }
+
+    /// Get the content of a stored artifact
+    pub async fn get(&self, run_id: &str, path: &str) -> FredResult<Option<Vec<u8>>> {
+        self.redis.get_artifact(run_id, path).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::{
+        backend::{SandboxRun, SandboxSpec, fake::FakeBackend, run_sandbox},
+        constants::FN_RESULT_FILE,
+    };
+
+    const CONFIG: ArtifactConfig = ArtifactConfig {
+        max_files: 2,
+        max_total_bytes: 64,
+        inline_max_bytes: 8,
+        ttl: 60,
+        volume_quota: false,
+    };
+
+    #[tokio::test]
+    async fn reads_artifacts_written_by_run() {
+        let backend = FakeBackend::new(0).with_output_files(&[
+            ("report.txt", b"token=hunter2"),
+            (FN_RESULT_FILE, b"{\"output\":\"token=hunter2\"}"),
+            ("plots/chart 1.png", &[0; 16]),
+            ("extra.txt", b"skipped"),
+        ]);
+        let spec = SandboxSpec {
+            id: "run-1".into(),
+            ..Default::default()
+        };
+        backend.create(spec).await.unwrap();
+        let (tx, mut rx) = mpsc::channel(16);
+        let run = SandboxRun {
+            id: "run-1",
+            timeout: 5,
+            max_output_bytes: 1024,
+            stdin: None,
+            redactor: &Redactor::default(),
+        };
+        run_sandbox(&backend, run, &tx).await.unwrap();
+
+        let archive = backend.output_archive("run-1").await;
+        let redactor = Redactor::new(&["hunter2".to_owned()]);
+        let result_file = Some(FN_RESULT_FILE);
+        let output =
+            read_output_files(archive, &CONFIG, "run-1", result_file, &redactor, &tx).await;
+        let paths: Vec<_> = output.files.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, ["report.txt", "plots/chart 1.png"]);
+        assert_eq!(output.files[0].1, b"token=[REDACTED]");
+        let result: serde_json::Value = serde_json::from_slice(&output.result.unwrap()).unwrap();
+        assert_eq!(result, serde_json::json!({ "output": "token=[REDACTED]" }));
+
+        drop(tx);
+        let mut messages = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            if let CodeRunnerChunk::Info(message) = chunk {
+                messages.push(message);
+            }
+        }
+        assert_eq!(
+            messages,
+            ["Skipping artifact 'extra.txt': limit of 2 files"]
+        );
+    }
+
+    #[test]
+    fn encodes_download_url() {
+        assert_eq!(
+            download_url("run-1", "plots/chart 1.png"),
+            "/api/runs/run-1/artifacts/plots/chart%201.png"
+        );
+        assert_eq!(
+            download_url("run-1", "a?b#c%.txt"),
+            "/api/runs/run-1/artifacts/a%3Fb%23c%25.txt"
+        );
+    }
+}
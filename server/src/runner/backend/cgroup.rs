@@ -0,0 +1,161 @@
+//! cgroups (v2) of the local sandboxes, that enforce the memory, CPU and process limits
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+use tinirun_models::CodeRunnerError;
+
+use crate::runner::backend::host;
+
+/// Controllers used by the cgroups of the sandboxes
+const CONTROLLERS: [&str; 3] = ["cpu", "memory", "pids"];
+/// Period of the CPU quota in microseconds
+const CPU_PERIOD_US: u64 = 100_000;
+/// Maximum number of processes in a sandbox, as in the containers
+const MAX_PIDS: u32 = 50;
+/// Number of attempts to remove a cgroup while its killed processes exit
+const REMOVE_ATTEMPTS: u32 = 10;
+
+/// Check that the cgroup delegated to the server has the needed controllers, and enable
+/// them for the cgroups of the sandboxes. The cgroup can't contain any processes itself.
+pub async fn check_parent(dir: &Path) -> anyhow::Result<()> {
+    let controllers = tokio::fs::read_to_string(dir.join("cgroup.controllers"))
+        .await
+        .with_context(|| format!("{dir:?} is not a cgroup v2 directory"))?;
+    let available: Vec<_> = controllers.split_whitespace().collect();
+    for controller in CONTROLLERS {
+        if !available.contains(&controller) {
+            bail!("the {controller} controller is not available in the cgroup {dir:?}");
+        }
+    }
+
+    let enable = CONTROLLERS.map(|controller| format!("+{controller}"));
+    tokio::fs::write(dir.join("cgroup.subtree_control"), enable.join(" "))
+        .await
+        .with_context(|| format!("could not enable the controllers in the cgroup {dir:?}"))
+}
+
+/// cgroup of a sandbox, in the cgroup delegated to the server
+pub struct SandboxCgroup {
+    dir: PathBuf,
+}
+
+impl SandboxCgroup {
+    pub fn new(parent: &Path, id: &str) -> Self {
+        Self {
+            dir: parent.join(id),
+        }
+    }
+
+    /// Create the cgroup with the limits of the run
+    pub async fn create(&self, mem_limit_mb: u32, cpu_limit: f32) -> Result<(), CodeRunnerError> {
+        tokio::fs::create_dir(&self.dir)
+            .await
+            .map_err(|err| host::sandbox_error(format!("failed to create cgroup: {err}")))?;
+        let memory_bytes = u64::from(mem_limit_mb) * 1024 * 1024;
+        self.write("memory.max", &memory_bytes.to_string()).await?;
+        self.write("cpu.max", &cpu_max(cpu_limit)).await?;
+        self.write("pids.max", &MAX_PIDS.to_string()).await?;
+        // The file is missing if swap accounting is disabled
+        let _ = self.write("memory.swap.max", "0").await;
+
+        Ok(())
+    }
+
+    /// File to write the ID of a process into, to move it into the cgroup
+    pub fn procs_file(&self) -> PathBuf {
+        self.dir.join("cgroup.procs")
+    }
+
+    /// Whether a process of the sandbox was killed for exceeding the memory limit
+    pub async fn oom_killed(&self) -> bool {
+        tokio::fs::read_to_string(self.dir.join("memory.events"))
+            .await
+            .is_ok_and(|events| oom_kills(&events) > 0)
+    }
+
+    /// Peak memory usage of the sandbox in bytes (since Linux 5.19)
+    pub async fn peak_memory_bytes(&self) -> Option<u64> {
+        let peak = tokio::fs::read_to_string(self.dir.join("memory.peak")).await;
+        peak.ok()?.trim().parse().ok()
+    }
+
+    /// Kill all processes of the sandbox
+    pub async fn kill(&self) {
+        let _ = tokio::fs::write(self.dir.join("cgroup.kill"), "1").await;
+    }
+
+    /// Kill the remaining processes and remove the cgroup. The cgroup can only be removed
+    /// once the killed processes have exited.
+    pub async fn remove(&self) {
+        if !tokio::fs::try_exists(&self.dir).await.unwrap_or(false) {
+            return;
+        }
+        self.kill().await;
+        for _ in 0..REMOVE_ATTEMPTS {
+            match tokio::fs::remove_dir(&self.dir).await {
+                Ok(()) => return,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => return,
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(50)).await,
+            }
+        }
+        tracing::info!("Could not remove cgroup {:?}", self.dir);
+    }
+
+    async fn write(&self, file: &str, value: &str) -> Result<(), CodeRunnerError> {
+        tokio::fs::write(self.dir.join(file), value)
+            .await
+            .map_err(|err| host::sandbox_error(format!("failed to set cgroup {file}: {err}")))
+    }
+}
+
+/// Value of `cpu.max` for the CPU limit (number of CPUs)
+fn cpu_max(cpu_limit: f32) -> String {
+    let quota = (f64::from(cpu_limit) * CPU_PERIOD_US as f64).round() as u64;
+    format!("{quota} {CPU_PERIOD_US}")
+}
+
+/// Number of processes killed by the OOM killer, from the `memory.events` file
+fn oom_kills(events: &str) -> u64 {
+    events
+        .lines()
+        .find_map(|line| line.strip_prefix("oom_kill "))
+        .and_then(|count| count.trim().parse().ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn creates_cgroup_with_limits() {
+        // A plain directory stands in for the delegated cgroup
+        let parent = std::env::temp_dir().join("tinirun-cgroup");
+        let _ = tokio::fs::remove_dir_all(&parent).await;
+        tokio::fs::create_dir_all(&parent).await.unwrap();
+
+        let cgroup = SandboxCgroup::new(&parent, "run-1");
+        cgroup.create(256, 0.5).await.unwrap();
+        let read = |file: &str| std::fs::read_to_string(parent.join("run-1").join(file)).unwrap();
+        assert_eq!(read("memory.max"), "268435456");
+        assert_eq!(read("cpu.max"), "50000 100000");
+        assert_eq!(read("pids.max"), "50");
+        assert_eq!(cgroup.procs_file(), parent.join("run-1/cgroup.procs"));
+        assert!(!cgroup.oom_killed().await);
+
+        let events = "low 0\nhigh 0\nmax 3\noom 1\noom_kill 1\noom_group_kill 0\n";
+        std::fs::write(parent.join("run-1/memory.events"), events).unwrap();
+        assert!(cgroup.oom_killed().await);
+
+        std::fs::remove_dir_all(&parent).unwrap();
+    }
+
+    #[test]
+    fn parses_oom_kills() {
+        assert_eq!(oom_kills("oom 0\noom_kill 0\n"), 0);
+        assert_eq!(oom_kills("oom 2\noom_kill 2\noom_group_kill 0\n"), 2);
+        assert_eq!(oom_kills(""), 0);
+        assert_eq!(cpu_max(1.5), "150000 100000");
+    }
+}
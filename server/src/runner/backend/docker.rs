@@ -0,0 +1,164 @@
+//! Backend that runs programs in Docker containers
+
+use std::{
+    pin::Pin,
+    time::{Duration, Instant},
+};
+
+use bollard::{
+    Docker,
+    container::LogOutput,
+    query_parameters::{AttachContainerOptionsBuilder, DownloadFromContainerOptionsBuilder},
+};
+use futures::{StreamExt, TryStreamExt};
+use tinirun_models::CodeRunnerError;
+use tokio::{io::AsyncRead, sync::oneshot};
+use tokio_util::io::StreamReader;
+
+use crate::runner::{
+    backend::{SandboxBackend, SandboxExit, SandboxIo, SandboxOutput, SandboxSpec},
+    constants::{APP_DIR, OUTPUT_DIR},
+    helpers,
+};
+
+/// Time to wait for the container after the time limit, before stopping it
+const GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+/// # Docker backend
+///
+/// Runs each program in its own locked-down container (see [`helpers::setup_container`]).
+/// Files are uploaded into an anonymous volume at the app directory, and the output
+/// directory is an anonymous volume, with a disk quota if enabled.
+#[derive(Clone)]
+pub struct DockerBackend {
+    client: Docker,
+    /// Disk quota of the output directory volume, if enabled
+    output_dir_quota: Option<u64>,
+}
+
+impl DockerBackend {
+    pub fn new(client: Docker, output_dir_quota: Option<u64>) -> Self {
+        Self {
+            client,
+            output_dir_quota,
+        }
+    }
+
+    pub fn client(&self) -> &Docker {
+        &self.client
+    }
+}
+
+impl SandboxBackend for DockerBackend {
+    async fn create(&self, spec: SandboxSpec) -> Result<(), CodeRunnerError> {
+        let (mut body, options) = helpers::setup_container(
+            &spec.id,
+            &spec.image,
+            &spec.command,
+            spec.stdin,
+            spec.timeout,
+            spec.mem_limit_mb,
+            spec.cpu_limit,
+        );
+        if let Some(profile) = &spec.profile {
+            profile.apply(&mut body);
+        }
+        if let Some(network) = &spec.network {
+            helpers::connect_network(&mut body, network);
+        }
+        helpers::add_args_and_env(&mut body, &spec.args, &spec.env);
+        if !spec.files.is_empty() {
+            helpers::mount_volume(&mut body, &spec.id, APP_DIR);
+        }
+        helpers::mount_output_dir(&mut body, &spec.id, self.output_dir_quota);
+        self.client.create_container(Some(options), body).await?;
+        if !spec.files.is_empty() {
+            helpers::upload_files(&self.client, &spec.id, APP_DIR, spec.files).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn attach(&self, id: &str, stdin: bool) -> Result<SandboxIo, CodeRunnerError> {
+        let attach_options = AttachContainerOptionsBuilder::new()
+            .stream(true)
+            .stdin(stdin)
+            .stdout(true)
+            .stderr(true)
+            .logs(true)
+            .build();
+        let container = self
+            .client
+            .attach_container(id, Some(attach_options))
+            .await?;
+        let output = container
+            .output
+            .map_err(std::io::Error::other)
+            .try_filter_map(|output| async move {
+                Ok(match output {
+                    LogOutput::StdOut { message } => Some(SandboxOutput::Stdout(message.to_vec())),
+                    LogOutput::StdErr { message } => Some(SandboxOutput::Stderr(message.to_vec())),
+                    _ => None,
+                })
+            });
+
+        Ok(SandboxIo {
+            output: Box::pin(output),
+            input: container.input,
+        })
+    }
+
+    async fn start(&self, id: &str) -> Result<(), CodeRunnerError> {
+        Ok(self.client.start_container(id, None).await?)
+    }
+
+    async fn wait(&self, id: &str, timeout: u32) -> SandboxExit {
+        // Collect stats of the container while waiting for it to exit
+        let started_at = Instant::now();
+        let (stop_stats, stop_rx) = oneshot::channel();
+        let stats_task = tokio::spawn(helpers::stats_task(
+            self.client.clone(),
+            id.to_owned(),
+            stop_rx,
+        ));
+        // The `timeout` wrapper stops the program at the time limit
+        let time_limit = Duration::from_secs(timeout.into());
+        let exit_result = tokio::time::timeout(
+            time_limit + GRACE_PERIOD,
+            self.client.wait_container(id, None).next(),
+        )
+        .await;
+        let wall_time = started_at.elapsed();
+        let _ = stop_stats.send(());
+
+        // Get resource usage and exit status. The container ran until the time limit
+        // if its wall-clock duration reached it.
+        let state = helpers::inspect_state(&self.client, id).await;
+        let stats = stats_task.await.unwrap_or_default();
+        let usage = helpers::resource_usage(state.as_ref(), stats, wall_time);
+        let timed_out = Duration::from_millis(usage.wall_time_ms) >= time_limit;
+        let (termination, exit_code) =
+            helpers::process_exit_status(exit_result, state.as_ref(), timed_out);
+
+        SandboxExit {
+            termination,
+            exit_code,
+            usage,
+        }
+    }
+
+    async fn output_archive(&self, id: &str) -> Pin<Box<dyn AsyncRead + Send>> {
+        let options = DownloadFromContainerOptionsBuilder::new()
+            .path(OUTPUT_DIR)
+            .build();
+        let archive_stream = self
+            .client
+            .download_from_container(id, Some(options))
+            .map_err(std::io::Error::other);
+        Box::pin(StreamReader::new(Box::pin(archive_stream)))
+    }
+
+    async fn cleanup(&self, id: &str) {
+        helpers::run_cleanup(&self.client, id).await;
+    }
+}
@@ -0,0 +1,160 @@
+//! Fake backend for testing the execution logic without a sandbox
+
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tinirun_models::{CodeRunnerError, ResourceUsage};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, DuplexStream},
+    sync::mpsc,
+    task::JoinHandle,
+};
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::runner::{
+    backend::{SandboxBackend, SandboxExit, SandboxIo, SandboxOutput, SandboxSpec},
+    helpers,
+};
+
+type OutputSender = mpsc::Sender<std::io::Result<SandboxOutput>>;
+
+#[derive(Default)]
+struct FakeSandbox {
+    output: Option<OutputSender>,
+    stdin: Option<DuplexStream>,
+    program: Option<JoinHandle<()>>,
+    started_at: Option<Instant>,
+}
+
+/// Backend whose program echoes its stdin to stdout, writes the given output files,
+/// and exits with the given code
+pub struct FakeBackend {
+    exit_code: i64,
+    output_files: Vec<(String, Vec<u8>)>,
+    events: Mutex<Vec<&'static str>>,
+    sandboxes: Mutex<HashMap<String, FakeSandbox>>,
+}
+
+impl FakeBackend {
+    pub fn new(exit_code: i64) -> Self {
+        Self {
+            exit_code,
+            output_files: Vec::new(),
+            events: Mutex::default(),
+            sandboxes: Mutex::default(),
+        }
+    }
+
+    /// Files that the program writes to the output directory
+    pub fn with_output_files(mut self, files: &[(&str, &[u8])]) -> Self {
+        self.output_files = files
+            .iter()
+            .map(|(path, content)| (path.to_string(), content.to_vec()))
+            .collect();
+        self
+    }
+
+    /// The backend methods that were called, in order
+    pub fn events(&self) -> Vec<&'static str> {
+        self.events.lock().unwrap().clone()
+    }
+
+    fn record(&self, event: &'static str) {
+        self.events.lock().unwrap().push(event);
+    }
+
+    fn not_found(id: &str) -> CodeRunnerError {
+        CodeRunnerError::Sandbox {
+            message: format!("sandbox '{id}' not found"),
+        }
+    }
+}
+
+impl SandboxBackend for FakeBackend {
+    async fn create(&self, spec: SandboxSpec) -> Result<(), CodeRunnerError> {
+        self.record("create");
+        let mut sandboxes = self.sandboxes.lock().unwrap();
+        sandboxes.insert(spec.id, FakeSandbox::default());
+        Ok(())
+    }
+
+    async fn attach(&self, id: &str, stdin: bool) -> Result<SandboxIo, CodeRunnerError> {
+        self.record("attach");
+        let mut sandboxes = self.sandboxes.lock().unwrap();
+        let sandbox = sandboxes.get_mut(id).ok_or_else(|| Self::not_found(id))?;
+        let (output_tx, output_rx) = mpsc::channel(16);
+        let (input, stdin_reader) = tokio::io::duplex(1024);
+        sandbox.output = Some(output_tx);
+        sandbox.stdin = Some(stdin_reader).filter(|_| stdin);
+
+        Ok(SandboxIo {
+            output: Box::pin(ReceiverStream::new(output_rx)),
+            input: Box::pin(input),
+        })
+    }
+
+    async fn start(&self, id: &str) -> Result<(), CodeRunnerError> {
+        self.record("start");
+        let mut sandboxes = self.sandboxes.lock().unwrap();
+        let sandbox = sandboxes.get_mut(id).ok_or_else(|| Self::not_found(id))?;
+        let output = sandbox.output.take();
+        let stdin = sandbox.stdin.take();
+        sandbox.started_at = Some(Instant::now());
+        sandbox.program = Some(tokio::spawn(async move {
+            let mut input = Vec::new();
+            if let Some(mut stdin) = stdin {
+                let _ = stdin.read_to_end(&mut input).await;
+            }
+            if let Some(output) = output.filter(|_| !input.is_empty()) {
+                let _ = output.send(Ok(SandboxOutput::Stdout(input))).await;
+            }
+        }));
+        Ok(())
+    }
+
+    async fn wait(&self, id: &str, timeout: u32) -> SandboxExit {
+        self.record("wait");
+        let (program, started_at) = {
+            let mut sandboxes = self.sandboxes.lock().unwrap();
+            let sandbox = sandboxes.entry(id.to_owned()).or_default();
+            (sandbox.program.take(), sandbox.started_at)
+        };
+        let timeout = Duration::from_secs(timeout.into());
+        let exited = match program {
+            Some(program) => tokio::time::timeout(timeout, program).await.is_ok(),
+            None => false,
+        };
+        let exit_code = Some(self.exit_code).filter(|_| exited);
+
+        SandboxExit {
+            termination: helpers::termination_reason(exit_code, false, false),
+            exit_code,
+            usage: ResourceUsage {
+                wall_time_ms: started_at.map_or(0, |t| t.elapsed().as_millis() as u64),
+                ..Default::default()
+            },
+        }
+    }
+
+    async fn output_archive(&self, _id: &str) -> Pin<Box<dyn AsyncRead + Send>> {
+        let mut tar = tokio_tar::Builder::new(Vec::new());
+        for (path, content) in &self.output_files {
+            let mut header = tokio_tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            let path = format!("out/{path}");
+            let _ = tar.append_data(&mut header, path, content.as_slice()).await;
+        }
+        let archive = tar.into_inner().await.unwrap_or_default();
+        Box::pin(std::io::Cursor::new(archive))
+    }
+
+    async fn cleanup(&self, id: &str) {
+        self.record("cleanup");
+        self.sandboxes.lock().unwrap().remove(id);
+    }
+}
@@ -0,0 +1,162 @@
+//! Helpers for backends that run programs on the server itself, with a directory per run
+
+use std::{
+    path::{Component, Path, PathBuf},
+    pin::Pin,
+    time::Duration,
+};
+
+use tinirun_models::CodeRunnerError;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    sync::mpsc,
+};
+use tokio_util::io::StreamReader;
+
+use crate::runner::{backend::SandboxOutput, helpers};
+
+/// Size of the buffer for stdin and output frames
+pub const BUFFER_SIZE: usize = 8192;
+/// Interval between checks of the disk space used by a run
+const DISK_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+/// Exit code reported when the file size limit is exceeded, like `SIGXFSZ`
+pub const FILE_SIZE_EXIT_CODE: i64 = 128 + 25;
+
+pub type OutputSender = mpsc::Sender<std::io::Result<SandboxOutput>>;
+
+/// Create the directory of a run with the given subdirectories, and write the files
+/// into the `app` subdirectory
+pub async fn create_run_dir(
+    dir: &Path,
+    sub_dirs: &[&str],
+    files: Vec<(PathBuf, Vec<u8>)>,
+) -> Result<(), CodeRunnerError> {
+    for sub_dir in sub_dirs {
+        tokio::fs::create_dir_all(dir.join(sub_dir))
+            .await
+            .map_err(|err| sandbox_error(format!("failed to create directory: {err}")))?;
+    }
+
+    for (path, content) in files {
+        if path
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return Err(sandbox_error(format!("invalid file path {path:?}")));
+        }
+        let file_path = dir.join("app").join(&path);
+        if let Some(parent) = file_path.parent() {
+            tokio::fs::create_dir_all(parent).await.ok();
+        }
+        tokio::fs::write(&file_path, content)
+            .await
+            .map_err(|err| sandbox_error(format!("failed to write {path:?}: {err}")))?;
+    }
+
+    Ok(())
+}
+
+/// Get a tar archive of the files in the output directory, up to the size limit
+pub async fn output_archive(out_dir: &Path, max_bytes: u64) -> Pin<Box<dyn AsyncRead + Send>> {
+    let mut files = Vec::new();
+    let mut total_bytes = 0;
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(rel_dir) = dirs.pop() {
+        let Ok(mut entries) = tokio::fs::read_dir(out_dir.join(&rel_dir)).await else {
+            continue;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            // Symlinks are not followed, as they could point outside the sandbox
+            let Ok(file_type) = entry.file_type().await else {
+                continue;
+            };
+            let rel_path = rel_dir.join(entry.file_name());
+            if file_type.is_dir() {
+                dirs.push(rel_path);
+                continue;
+            }
+            let Ok(metadata) = entry.metadata().await else {
+                continue;
+            };
+            if !file_type.is_file() || total_bytes + metadata.len() > max_bytes {
+                continue;
+            }
+            if let Ok(content) = tokio::fs::read(entry.path()).await {
+                total_bytes += content.len() as u64;
+                files.push((Path::new("out").join(rel_path), content));
+            }
+        }
+    }
+
+    Box::pin(StreamReader::new(helpers::create_build_context(files)))
+}
+
+/// Resolves when the files in the directory exceed the size limit
+pub async fn disk_limit_exceeded(dir: &Path, max_bytes: u64) {
+    let mut interval = tokio::time::interval(DISK_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        if dir_size(dir).await > max_bytes {
+            return;
+        }
+    }
+}
+
+/// Total size of the files in the directory, without following symlinks
+pub async fn dir_size(dir: &Path) -> u64 {
+    let mut total_bytes = 0;
+    let mut dirs = vec![dir.to_owned()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(mut entries) = tokio::fs::read_dir(&dir).await else {
+            continue;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let Ok(metadata) = tokio::fs::symlink_metadata(entry.path()).await else {
+                continue;
+            };
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                total_bytes += metadata.len();
+            }
+        }
+    }
+
+    total_bytes
+}
+
+/// Remove the directory of a run
+pub async fn remove_run_dir(dir: &Path) {
+    if let Err(err) = tokio::fs::remove_dir_all(dir).await
+        && err.kind() != std::io::ErrorKind::NotFound
+    {
+        tracing::info!("Could not remove sandbox directory {dir:?}: {err}");
+    }
+}
+
+/// Forward the output of the program to the output stream
+pub async fn forward_output(
+    mut reader: impl AsyncRead + Unpin,
+    output: OutputSender,
+    frame: fn(Vec<u8>) -> SandboxOutput,
+) {
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        match reader.read(&mut buffer).await {
+            Ok(0) => break,
+            Ok(n) => {
+                if output.send(Ok(frame(buffer[..n].to_vec()))).await.is_err() {
+                    break;
+                }
+            }
+            Err(err) => {
+                let _ = output.send(Err(err)).await;
+                break;
+            }
+        }
+    }
+}
+
+pub fn sandbox_error(message: String) -> CodeRunnerError {
+    CodeRunnerError::Sandbox { message }
+}
@@ -0,0 +1,367 @@
+//! Backend that runs programs in a local, unprivileged namespace sandbox using bubblewrap
+
+use std::{
+    collections::HashMap,
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    pin::Pin,
+    process::Stdio,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, bail};
+use tinirun_models::{CodeRunnerError, ResourceUsage, TerminationReason};
+use tokio::{
+    io::{AsyncRead, DuplexStream},
+    process::{Child, Command},
+    sync::mpsc,
+    task::JoinHandle,
+};
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::runner::{
+    backend::{
+        SandboxBackend, SandboxExit, SandboxIo, SandboxOutput, SandboxSpec,
+        cgroup::{self, SandboxCgroup},
+        host::{self, BUFFER_SIZE, OutputSender},
+    },
+    constants::{APP_DIR, OUTPUT_DIR},
+    helpers,
+};
+
+/// `PATH` of programs in the sandbox
+const SANDBOX_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+/// Home directory of programs in the sandbox
+const SANDBOX_HOME: &str = "/tmp/home";
+/// Time to wait for the program after the `timeout` wrapper should have stopped it
+const GRACE_PERIOD: Duration = Duration::from_secs(2);
+/// Size limit of the `/tmp` directory (including the home directory), as in the containers
+const TMP_DIR_BYTES: u64 = 100 * 1024 * 1024;
+/// Shell command that moves itself into the cgroup (whose `cgroup.procs` file is the first
+/// argument) and then runs bubblewrap, so that all processes of the sandbox are in the cgroup
+const ENTER_CGROUP: &str = r#"echo $$ > "$0" && exec "$@""#;
+
+/// Configuration of the local backend
+#[derive(Debug, Clone)]
+pub struct LocalConfig {
+    /// Path to the `bwrap` executable
+    pub bwrap_path: PathBuf,
+    /// Directory on the host where the files of each run are stored
+    pub work_dir: PathBuf,
+    /// Host directories with the pre-installed toolchains, mounted read-only in the sandbox
+    pub ro_binds: Vec<PathBuf>,
+    /// cgroup (v2) delegated to the server, where a cgroup is created for each run to
+    /// enforce the memory, CPU and process limits
+    pub cgroup_dir: PathBuf,
+    /// Size limit of the output directory, also used as the maximum size of files
+    /// written by the program
+    pub output_dir_bytes: u64,
+}
+
+struct LocalSandbox {
+    dir: PathBuf,
+    spec: SandboxSpec,
+    output: Option<OutputSender>,
+    stdin: Option<DuplexStream>,
+    child: Option<Child>,
+    started_at: Option<Instant>,
+    /// Task that stops the program if the output directory exceeds its size limit
+    disk_watch: Option<JoinHandle<()>>,
+    disk_limit_exceeded: Arc<AtomicBool>,
+}
+
+/// # Local backend
+///
+/// Runs each program with [bubblewrap](https://github.com/containers/bubblewrap) in new,
+/// unprivileged namespaces, using the toolchains installed on the host. This allows
+/// running code where a Docker daemon isn't available, such as on build agents.
+///
+/// The sandbox has no network, a private PID and IPC namespace, and only sees the
+/// read-only toolchain directories, the run's app and output directories, and a
+/// size-limited `/tmp`. Each run gets its own cgroup that enforces the memory, CPU and
+/// process limits. The program is stopped when it exceeds the time limit, or when the
+/// output directory exceeds its size limit. Dependencies can't be installed, and
+/// languages that need a build step are not supported.
+pub struct LocalBackend {
+    config: LocalConfig,
+    sandboxes: Mutex<HashMap<String, LocalSandbox>>,
+}
+
+impl LocalBackend {
+    pub fn new(config: LocalConfig) -> Self {
+        Self {
+            config,
+            sandboxes: Mutex::default(),
+        }
+    }
+
+    /// Check that bubblewrap is installed and supports the needed options, prepare the
+    /// cgroup and the work directory. Returns the version of bubblewrap.
+    pub async fn check(&self) -> anyhow::Result<String> {
+        let output = Command::new(&self.config.bwrap_path)
+            .arg("--version")
+            .output()
+            .await
+            .with_context(|| format!("could not run {:?}", self.config.bwrap_path))?;
+        let help = Command::new(&self.config.bwrap_path)
+            .arg("--help")
+            .output()
+            .await
+            .with_context(|| format!("could not run {:?}", self.config.bwrap_path))?;
+        if !String::from_utf8_lossy(&help.stdout).contains("--size") {
+            bail!("bubblewrap doesn't support the --size option, please upgrade it");
+        }
+        cgroup::check_parent(&self.config.cgroup_dir).await?;
+        tokio::fs::create_dir_all(&self.config.work_dir)
+            .await
+            .with_context(|| format!("could not create {:?}", self.config.work_dir))?;
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    }
+
+    fn cgroup(&self, id: &str) -> SandboxCgroup {
+        SandboxCgroup::new(&self.config.cgroup_dir, id)
+    }
+
+    /// Build the bubblewrap command that runs the program in its cgroup
+    fn command(&self, dir: &Path, spec: &SandboxSpec) -> Command {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", ENTER_CGROUP])
+            .arg(self.cgroup(&spec.id).procs_file())
+            .arg(&self.config.bwrap_path);
+        cmd.args([
+            "--unshare-all",
+            "--die-with-parent",
+            "--new-session",
+            "--clearenv",
+        ]);
+        for path in &self.config.ro_binds {
+            cmd.arg("--ro-bind-try").arg(path).arg(path);
+        }
+        cmd.args(["--proc", "/proc", "--dev", "/dev"]);
+        cmd.args(["--size", &TMP_DIR_BYTES.to_string(), "--tmpfs", "/tmp"]);
+        cmd.args(["--dir", SANDBOX_HOME]);
+        cmd.arg("--bind").arg(dir.join("app")).arg(APP_DIR);
+        cmd.arg("--bind").arg(dir.join("out")).arg(OUTPUT_DIR);
+        cmd.args(["--chdir", APP_DIR]);
+        let default_env = [("HOME", SANDBOX_HOME), ("PATH", SANDBOX_PATH)];
+        let env = default_env
+            .into_iter()
+            .chain(spec.env.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        for (name, value) in env {
+            cmd.args(["--setenv", name, value]);
+        }
+
+        // The arguments are passed to the shell as positional parameters, like in the container
+        let max_file_blocks = self.config.output_dir_bytes.div_ceil(512);
+        let mut command = format!("ulimit -f {max_file_blocks}; {}", spec.command);
+        if !spec.args.is_empty() {
+            command.push_str(r#" "$@""#);
+        }
+        let timeout = format!("{}s", spec.timeout);
+        cmd.args(["--", "timeout", &timeout, "sh", "-c", &command]);
+        if !spec.args.is_empty() {
+            cmd.arg("sh").args(&spec.args);
+        }
+
+        cmd.stdin(if spec.stdin {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+        cmd
+    }
+
+    fn not_found(id: &str) -> CodeRunnerError {
+        host::sandbox_error(format!("sandbox '{id}' not found"))
+    }
+}
+
+impl SandboxBackend for LocalBackend {
+    async fn create(&self, spec: SandboxSpec) -> Result<(), CodeRunnerError> {
+        let dir = self.config.work_dir.join(&spec.id);
+        let mut spec = spec;
+        let files = std::mem::take(&mut spec.files);
+        host::create_run_dir(&dir, &["app", "out"], files).await?;
+        self.cgroup(&spec.id)
+            .create(spec.mem_limit_mb, spec.cpu_limit)
+            .await?;
+
+        let sandbox = LocalSandbox {
+            dir,
+            spec,
+            output: None,
+            stdin: None,
+            child: None,
+            started_at: None,
+            disk_watch: None,
+            disk_limit_exceeded: Arc::default(),
+        };
+        let mut sandboxes = self.sandboxes.lock().expect("sandbox lock poisoned");
+        sandboxes.insert(sandbox.spec.id.clone(), sandbox);
+        Ok(())
+    }
+
+    async fn attach(&self, id: &str, stdin: bool) -> Result<SandboxIo, CodeRunnerError> {
+        let mut sandboxes = self.sandboxes.lock().expect("sandbox lock poisoned");
+        let sandbox = sandboxes.get_mut(id).ok_or_else(|| Self::not_found(id))?;
+
+        // Output and input are connected to the process when it's started
+        let (output_tx, output_rx) = mpsc::channel(64);
+        let (input, stdin_reader) = tokio::io::duplex(BUFFER_SIZE);
+        sandbox.output = Some(output_tx);
+        sandbox.stdin = Some(stdin_reader).filter(|_| stdin);
+
+        Ok(SandboxIo {
+            output: Box::pin(ReceiverStream::new(output_rx)),
+            input: Box::pin(input),
+        })
+    }
+
+    async fn start(&self, id: &str) -> Result<(), CodeRunnerError> {
+        let mut sandboxes = self.sandboxes.lock().expect("sandbox lock poisoned");
+        let sandbox = sandboxes.get_mut(id).ok_or_else(|| Self::not_found(id))?;
+        let mut child = self
+            .command(&sandbox.dir, &sandbox.spec)
+            .spawn()
+            .map_err(|err| host::sandbox_error(format!("failed to start bubblewrap: {err}")))?;
+        let disk_output = sandbox.output.clone();
+
+        if let Some(output) = sandbox.output.take() {
+            if let Some(stdout) = child.stdout.take() {
+                tokio::spawn(host::forward_output(
+                    stdout,
+                    output.clone(),
+                    SandboxOutput::Stdout,
+                ));
+            }
+            if let Some(stderr) = child.stderr.take() {
+                tokio::spawn(host::forward_output(stderr, output, SandboxOutput::Stderr));
+            }
+        }
+        if let (Some(mut input), Some(mut child_stdin)) = (sandbox.stdin.take(), child.stdin.take())
+        {
+            tokio::spawn(async move {
+                let _ = tokio::io::copy(&mut input, &mut child_stdin).await;
+            });
+        }
+        sandbox.child = Some(child);
+        sandbox.started_at = Some(Instant::now());
+
+        // Files are limited to the size of the output directory, but not their total size
+        let out_dir = sandbox.dir.join("out");
+        let max_bytes = self.config.output_dir_bytes;
+        let cgroup = self.cgroup(id);
+        let exceeded = sandbox.disk_limit_exceeded.clone();
+        sandbox.disk_watch = Some(tokio::spawn(async move {
+            host::disk_limit_exceeded(&out_dir, max_bytes).await;
+            exceeded.store(true, Ordering::Relaxed);
+            cgroup.kill().await;
+            if let Some(output) = disk_output {
+                let message = b"File size limit exceeded\n".to_vec();
+                let _ = output.send(Ok(SandboxOutput::Stderr(message))).await;
+            }
+        }));
+
+        Ok(())
+    }
+
+    async fn wait(&self, id: &str, timeout: u32) -> SandboxExit {
+        let (child, started_at, disk_watch, disk_limit_exceeded) = {
+            let mut sandboxes = self.sandboxes.lock().expect("sandbox lock poisoned");
+            match sandboxes.get_mut(id) {
+                Some(sandbox) => (
+                    sandbox.child.take(),
+                    sandbox.started_at,
+                    sandbox.disk_watch.take(),
+                    sandbox.disk_limit_exceeded.clone(),
+                ),
+                None => (None, None, None, Arc::default()),
+            }
+        };
+        let started_at = started_at.unwrap_or_else(Instant::now);
+        let Some(mut child) = child else {
+            return SandboxExit {
+                termination: TerminationReason::Unknown,
+                exit_code: None,
+                usage: ResourceUsage::default(),
+            };
+        };
+
+        let cgroup = self.cgroup(id);
+        let time_limit = Duration::from_secs(timeout.into());
+        let wait = tokio::time::timeout(time_limit + GRACE_PERIOD, child.wait()).await;
+        if let Some(disk_watch) = disk_watch {
+            disk_watch.abort();
+        }
+        let (termination, exit_code) = match wait {
+            Ok(Ok(status)) => {
+                // Shells report a process killed by signal N with exit code 128 + N
+                let exit_code = match disk_limit_exceeded.load(Ordering::Relaxed) {
+                    true => Some(host::FILE_SIZE_EXIT_CODE),
+                    false => status
+                        .code()
+                        .map(i64::from)
+                        .or_else(|| status.signal().map(|signal| 128 + i64::from(signal))),
+                };
+                // The `timeout` wrapper stops the program at the time limit
+                let timed_out = started_at.elapsed() >= time_limit;
+                let oom_killed = cgroup.oom_killed().await;
+                let termination = helpers::termination_reason(exit_code, oom_killed, timed_out);
+                (termination, exit_code)
+            }
+            Ok(Err(err)) => {
+                tracing::warn!("Failed to wait for sandbox '{id}': {err}");
+                (TerminationReason::Unknown, None)
+            }
+            Err(_) => {
+                cgroup.kill().await;
+                let _ = child.kill().await;
+                (TerminationReason::ServerTimeout, None)
+            }
+        };
+
+        SandboxExit {
+            termination,
+            exit_code,
+            usage: ResourceUsage {
+                wall_time_ms: started_at.elapsed().as_millis() as u64,
+                peak_memory_bytes: cgroup.peak_memory_bytes().await,
+                ..Default::default()
+            },
+        }
+    }
+
+    async fn output_archive(&self, id: &str) -> Pin<Box<dyn AsyncRead + Send>> {
+        let out_dir = self.config.work_dir.join(id).join("out");
+        host::output_archive(&out_dir, self.config.output_dir_bytes).await
+    }
+
+    async fn cleanup(&self, id: &str) {
+        let sandbox = {
+            let mut sandboxes = self.sandboxes.lock().expect("sandbox lock poisoned");
+            sandboxes.remove(id)
+        };
+        let dir = match sandbox {
+            Some(mut sandbox) => {
+                if let Some(disk_watch) = sandbox.disk_watch.take() {
+                    disk_watch.abort();
+                }
+                if let Some(child) = sandbox.child.as_mut() {
+                    let _ = child.kill().await;
+                }
+                sandbox.dir
+            }
+            None => self.config.work_dir.join(id),
+        };
+        self.cgroup(id).remove().await;
+        host::remove_run_dir(&dir).await;
+    }
+}
@@ -0,0 +1,224 @@
+//! Backends that create and run the sandboxes of runs
+
+use std::{collections::HashMap, future::Future, path::PathBuf, pin::Pin, sync::Arc};
+
+use futures::Stream;
+use tinirun_models::{
+    CodeRunnerChunk, CodeRunnerError, CodeRunnerResult, ResourceUsage, TerminationReason,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::mpsc,
+};
+
+use crate::runner::{helpers, sandbox::SandboxProfile};
+
+mod cgroup;
+mod docker;
+#[cfg(test)]
+pub mod fake;
+mod host;
+mod local;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+pub use docker::DockerBackend;
+pub use local::{LocalBackend, LocalConfig};
+#[cfg(feature = "wasm")]
+pub use wasm::{WasmBackend, WasmConfig, WasmInterpreterConfig};
+
+/// A frame of output from the program running in a sandbox
+#[derive(Debug, Clone, PartialEq)]
+pub enum SandboxOutput {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+}
+
+/// Output and input streams of an attached sandbox
+pub struct SandboxIo {
+    pub output: Pin<Box<dyn Stream<Item = std::io::Result<SandboxOutput>> + Send>>,
+    pub input: Pin<Box<dyn AsyncWrite + Send>>,
+}
+
+/// How the program in the sandbox exited, and the resources it used
+#[derive(Debug, Clone)]
+pub struct SandboxExit {
+    pub termination: TerminationReason,
+    pub exit_code: Option<i64>,
+    pub usage: ResourceUsage,
+}
+
+/// Settings of the sandbox to create for a run
+#[derive(Debug, Clone, Default)]
+pub struct SandboxSpec {
+    /// ID of the run, used as the name of the sandbox
+    pub id: String,
+    /// Image to create the container from. For the WebAssembly backend, the module to
+    /// run: the path of a module in the app directory, or an interpreter.
+    pub image: String,
+    /// Shell command that runs the program. For the WebAssembly backend, the arguments
+    /// of the module, separated by whitespace.
+    pub command: String,
+    /// Command-line arguments appended to the command
+    pub args: Vec<String>,
+    /// Additional environment variables
+    pub env: HashMap<String, String>,
+    /// Files to place in the app directory before the program starts
+    pub files: Vec<(PathBuf, Vec<u8>)>,
+    /// Whether input will be written to the program's stdin
+    pub stdin: bool,
+    pub timeout: u32,
+    pub mem_limit_mb: u32,
+    pub cpu_limit: f32,
+    /// Network to connect to for network egress (Docker backend only)
+    pub network: Option<String>,
+    /// Sandbox profile that hardens the container (Docker backend only)
+    pub profile: Option<Arc<SandboxProfile>>,
+}
+
+/// # Sandbox backend
+///
+/// Creates isolated environments to run programs in, and controls their lifecycle. The
+/// executors prepare the inputs of a run, then use the backend to create the sandbox,
+/// attach to its I/O, start it, wait for it to exit, and clean it up.
+pub trait SandboxBackend: Send + Sync {
+    /// Prepare the sandbox with its files, ready to be started
+    fn create(&self, spec: SandboxSpec)
+    -> impl Future<Output = Result<(), CodeRunnerError>> + Send;
+
+    /// Attach to the output of the sandbox, and to its stdin if enabled
+    fn attach(
+        &self,
+        id: &str,
+        stdin: bool,
+    ) -> impl Future<Output = Result<SandboxIo, CodeRunnerError>> + Send;
+
+    /// Start the program in the sandbox
+    fn start(&self, id: &str) -> impl Future<Output = Result<(), CodeRunnerError>> + Send;
+
+    /// Wait for the program to exit, up to the timeout in seconds
+    fn wait(&self, id: &str, timeout: u32) -> impl Future<Output = SandboxExit> + Send;
+
+    /// Get a tar archive of the output directory, with paths starting with the directory name
+    fn output_archive(
+        &self,
+        id: &str,
+    ) -> impl Future<Output = Pin<Box<dyn AsyncRead + Send>>> + Send;
+
+    /// Stop the program if it's still running, and remove the sandbox
+    fn cleanup(&self, id: &str) -> impl Future<Output = ()> + Send;
+}
+
+/// Options for running a created sandbox
+pub struct SandboxRun<'a> {
+    pub id: &'a str,
+    pub timeout: u32,
+    pub max_output_bytes: u32,
+    /// Input to write to the program's stdin
+    pub stdin: Option<&'a [u8]>,
+    /// Replaces secret values in the output
+    pub redactor: &'a helpers::Redactor,
+}
+
+/// Run the created sandbox: attach to its output, start it, write the input, and wait
+/// for it to exit. The output is streamed as it's received, and the result of the
+/// execution is returned.
+pub async fn run_sandbox(
+    backend: &impl SandboxBackend,
+    run: SandboxRun<'_>,
+    tx: &mpsc::Sender<CodeRunnerChunk>,
+) -> Result<CodeRunnerResult, CodeRunnerError> {
+    let SandboxRun {
+        id,
+        timeout,
+        max_output_bytes,
+        stdin,
+        redactor,
+    } = run;
+
+    // Attach to sandbox and setup capturing of logs/output
+    let io = backend.attach(id, stdin.is_some()).await?;
+    let capture_output = helpers::output_task(
+        io.output,
+        timeout,
+        max_output_bytes,
+        redactor.clone(),
+        tx.clone(),
+    );
+    let output_task = tokio::spawn(capture_output);
+
+    // Start sandbox and write input to stdin while waiting for it to exit
+    backend.start(id).await?;
+    let write_input = async {
+        if let Some(stdin) = stdin {
+            helpers::write_stdin(io.input, stdin, tx).await;
+        }
+    };
+    let ((), exit) = tokio::join!(write_input, backend.wait(id, timeout));
+    let output = output_task.await.unwrap_or_default();
+
+    Ok(output.into_result(exit.termination, exit.exit_code, exit.usage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fake::FakeBackend, *};
+
+    #[tokio::test]
+    async fn runs_sandbox() {
+        let backend = FakeBackend::new(0);
+        let spec = SandboxSpec {
+            id: "run-1".into(),
+            stdin: true,
+            ..Default::default()
+        };
+        backend.create(spec).await.unwrap();
+
+        let (tx, mut rx) = mpsc::channel(16);
+        let run = SandboxRun {
+            id: "run-1",
+            timeout: 5,
+            max_output_bytes: 1024,
+            stdin: Some(b"hello"),
+            redactor: &helpers::Redactor::default(),
+        };
+        let result = run_sandbox(&backend, run, &tx).await.unwrap();
+        assert_eq!(result.stdout, "hello");
+        assert_eq!(result.exit_code, Some(0));
+        assert!(matches!(result.termination, TerminationReason::Exited));
+
+        drop(tx);
+        let mut streamed = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            if let CodeRunnerChunk::Stdout(text) = chunk {
+                streamed.push(text);
+            }
+        }
+        assert_eq!(streamed, ["hello"]);
+        assert_eq!(backend.events(), ["create", "attach", "start", "wait"]);
+    }
+
+    #[tokio::test]
+    async fn reports_exit_code_and_redacts_output() {
+        let backend = FakeBackend::new(3);
+        let spec = SandboxSpec {
+            id: "run-2".into(),
+            stdin: true,
+            ..Default::default()
+        };
+        backend.create(spec).await.unwrap();
+
+        let (tx, _rx) = mpsc::channel(16);
+        let secret = "hunter2".to_owned();
+        let run = SandboxRun {
+            id: "run-2",
+            timeout: 5,
+            max_output_bytes: 1024,
+            stdin: Some(b"token=hunter2"),
+            redactor: &helpers::Redactor::new([&secret]),
+        };
+        let result = run_sandbox(&backend, run, &tx).await.unwrap();
+        assert_eq!(result.stdout, "token=[REDACTED]");
+        assert_eq!(result.exit_code, Some(3));
+    }
+}
@@ -0,0 +1,521 @@
+//! Backend that runs WASI modules in-process with wasmtime
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, bail};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tinirun_models::{CodeRunnerError, CodeRunnerLanguage, ResourceUsage, TerminationReason};
+use tokio::{
+    io::{AsyncRead, DuplexStream},
+    sync::mpsc,
+    task::JoinHandle,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use wasmtime::{Engine, Linker, Module, ResourceLimiter, Store, UpdateDeadline};
+use wasmtime_wasi::{
+    AsyncStdinStream, AsyncStdoutStream, DirPerms, FilePerms, I32Exit, WasiCtxBuilder,
+    pipe::{AsyncReadStream, AsyncWriteStream, ClosedInputStream},
+    preview1::WasiP1Ctx,
+};
+
+use crate::runner::{
+    backend::{
+        SandboxBackend, SandboxExit, SandboxIo, SandboxOutput, SandboxSpec,
+        host::{self, BUFFER_SIZE, OutputSender},
+    },
+    constants::{APP_DIR, OUTPUT_DIR},
+    helpers,
+};
+
+/// Interval between epoch ticks, at which running modules yield to the async runtime
+const EPOCH_TICK: Duration = Duration::from_millis(100);
+/// Time to wait for the module after its time limit, e.g. if it's blocked in a host call
+const GRACE_PERIOD: Duration = Duration::from_secs(2);
+/// Maximum number of elements in a table of a module
+const MAX_TABLE_ELEMENTS: usize = 1 << 20;
+/// Prefix of the spec's `image` that selects an interpreter instead of a module file
+const INTERPRETER_PREFIX: &str = "interpreter:";
+/// Exit code reported for a trap, like a process aborted with `SIGABRT`
+const TRAP_EXIT_CODE: i64 = 128 + 6;
+
+/// WASI interpreter of a language, as defined in the server config
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct WasmInterpreterConfig {
+    /// Path to the interpreter module on the server, e.g. a WASI build of QuickJS or CPython
+    pub module: PathBuf,
+    /// Arguments passed to the interpreter before the main file
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Directories on the server mounted read-only in the sandbox, by path in the
+    /// sandbox (e.g. the standard library of CPython)
+    #[serde(default)]
+    pub dirs: HashMap<String, PathBuf>,
+    /// Environment variables for the interpreter (e.g. `PYTHONHOME`)
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+/// Configuration of the WebAssembly backend
+#[derive(Debug, Clone)]
+pub struct WasmConfig {
+    /// Directory on the server where the files of each run are stored
+    pub work_dir: PathBuf,
+    /// Interpreters of the languages that can run in the WebAssembly runtime
+    pub interpreters: HashMap<CodeRunnerLanguage, WasmInterpreterConfig>,
+    /// Size limit of the output directory, also used as the limit of disk space
+    /// written by the program
+    pub output_dir_bytes: u64,
+}
+
+/// Interpreter module compiled at startup
+struct WasmInterpreter {
+    name: String,
+    module: Module,
+    config: WasmInterpreterConfig,
+}
+
+struct WasmSandbox {
+    dir: PathBuf,
+    spec: SandboxSpec,
+    module: Module,
+    interpreter: Option<Arc<WasmInterpreter>>,
+    output: Option<OutputSender>,
+    stdin: Option<DuplexStream>,
+    task: Option<JoinHandle<RunOutcome>>,
+    started_at: Option<Instant>,
+}
+
+/// State of the store of a run
+struct RunState {
+    wasi: WasiP1Ctx,
+    limiter: MemoryLimiter,
+    timed_out: bool,
+}
+
+/// How the module exited
+struct RunOutcome {
+    exit_code: Option<i64>,
+    oom_killed: bool,
+    timed_out: bool,
+    peak_memory_bytes: u64,
+}
+
+/// Limits the memory of a run, and tracks its peak memory usage
+struct MemoryLimiter {
+    max_bytes: usize,
+    used_bytes: usize,
+    peak_bytes: usize,
+    exceeded: bool,
+}
+
+impl ResourceLimiter for MemoryLimiter {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        let used_bytes = self.used_bytes + desired.saturating_sub(current);
+        if used_bytes > self.max_bytes {
+            // Trap instead of failing the allocation, like the OOM killer
+            self.exceeded = true;
+            bail!("memory limit exceeded");
+        }
+        self.used_bytes = used_bytes;
+        self.peak_bytes = self.peak_bytes.max(used_bytes);
+        Ok(true)
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        Ok(desired <= MAX_TABLE_ELEMENTS)
+    }
+}
+
+/// # WebAssembly backend
+///
+/// Runs WASI (preview 1) modules in-process with [wasmtime](https://wasmtime.dev), either
+/// uploaded modules or the configured interpreter of the language. There are no images
+/// to build or containers to start, so this is suited to lightweight snippets.
+///
+/// Modules only have access to the run's app and output directories (and the interpreter's
+/// read-only directories), and have no network access. The memory limit is enforced on
+/// the linear memories of the module, and the time limit by checking the deadline at each
+/// epoch tick. The disk space written by the module is checked periodically.
+pub struct WasmBackend {
+    engine: Engine,
+    linker: Linker<RunState>,
+    config: WasmConfig,
+    interpreters: HashMap<String, Arc<WasmInterpreter>>,
+    sandboxes: Mutex<HashMap<String, WasmSandbox>>,
+}
+
+impl WasmBackend {
+    /// Create the engine, and compile the interpreters. Compiling can take a while,
+    /// so this should be called in a blocking task.
+    pub fn new(config: WasmConfig) -> anyhow::Result<Self> {
+        let mut engine_config = wasmtime::Config::new();
+        engine_config.async_support(true).epoch_interruption(true);
+        let engine = Engine::new(&engine_config)?;
+        let mut linker = Linker::new(&engine);
+        wasmtime_wasi::preview1::add_to_linker_async(&mut linker, |state: &mut RunState| {
+            &mut state.wasi
+        })?;
+
+        let mut interpreters = HashMap::new();
+        for (lang, interpreter) in &config.interpreters {
+            let module = Module::from_file(&engine, &interpreter.module)
+                .with_context(|| format!("could not compile WASI interpreter for {lang}"))?;
+            for (guest_path, host_path) in &interpreter.dirs {
+                if !host_path.is_dir() {
+                    bail!("directory {host_path:?} for '{guest_path}' of {lang} not found");
+                }
+            }
+            let interpreter = WasmInterpreter {
+                name: lang.to_string(),
+                module,
+                config: interpreter.clone(),
+            };
+            interpreters.insert(Self::interpreter_image(lang), Arc::new(interpreter));
+        }
+        std::fs::create_dir_all(&config.work_dir)
+            .with_context(|| format!("could not create {:?}", config.work_dir))?;
+
+        // Advance the epoch until the engine is dropped
+        let weak_engine = engine.weak();
+        std::thread::spawn(move || {
+            while let Some(engine) = weak_engine.upgrade() {
+                engine.increment_epoch();
+                drop(engine);
+                std::thread::sleep(EPOCH_TICK);
+            }
+        });
+
+        Ok(Self {
+            engine,
+            linker,
+            config,
+            interpreters,
+            sandboxes: Mutex::default(),
+        })
+    }
+
+    /// The `image` of the spec that runs the language's interpreter
+    pub fn interpreter_image(lang: &CodeRunnerLanguage) -> String {
+        format!("{INTERPRETER_PREFIX}{lang}")
+    }
+
+    /// Whether an interpreter is configured for the language
+    pub fn supports(&self, lang: &CodeRunnerLanguage) -> bool {
+        self.interpreters
+            .contains_key(&Self::interpreter_image(lang))
+    }
+
+    /// Get the interpreter selected by the spec, or compile the module file in the app directory
+    async fn load_module(
+        &self,
+        dir: &Path,
+        image: &str,
+    ) -> Result<(Module, Option<Arc<WasmInterpreter>>), CodeRunnerError> {
+        if image.starts_with(INTERPRETER_PREFIX) {
+            let interpreter = self
+                .interpreters
+                .get(image)
+                .ok_or_else(|| host::sandbox_error(format!("interpreter '{image}' not found")))?;
+            return Ok((interpreter.module.clone(), Some(interpreter.clone())));
+        }
+
+        let bytes = tokio::fs::read(dir.join("app").join(image))
+            .await
+            .map_err(|err| host::sandbox_error(format!("failed to read module: {err}")))?;
+        let engine = self.engine.clone();
+        let module = tokio::task::spawn_blocking(move || Module::new(&engine, bytes))
+            .await
+            .map_err(|err| host::sandbox_error(format!("failed to compile module: {err}")))?
+            .map_err(|err| CodeRunnerError::BuildFailed {
+                message: "Invalid WebAssembly module".into(),
+                logs: format!("{err:#}"),
+            })?;
+
+        Ok((module, None))
+    }
+
+    /// Build the WASI context of the run, with its arguments, environment, directories and stdio
+    fn wasi_ctx(
+        sandbox: &WasmSandbox,
+        stdin: Option<DuplexStream>,
+        stdout: DuplexStream,
+        stderr: DuplexStream,
+    ) -> anyhow::Result<WasiP1Ctx> {
+        let spec = &sandbox.spec;
+        let mut args: Vec<&str> = Vec::new();
+        if let Some(interpreter) = &sandbox.interpreter {
+            args.push(&interpreter.name);
+            args.extend(interpreter.config.args.iter().map(String::as_str));
+        }
+        args.extend(spec.command.split_whitespace());
+        args.extend(spec.args.iter().map(String::as_str));
+
+        let mut builder = WasiCtxBuilder::new();
+        builder.args(&args);
+        if let Some(interpreter) = &sandbox.interpreter {
+            for (name, value) in &interpreter.config.env {
+                builder.env(name, value);
+            }
+            for (guest_path, host_path) in &interpreter.config.dirs {
+                builder.preopened_dir(host_path, guest_path, DirPerms::READ, FilePerms::READ)?;
+            }
+        }
+        for (name, value) in &spec.env {
+            builder.env(name, value);
+        }
+
+        // The app directory is also the current directory of the module
+        let app_dir = sandbox.dir.join("app");
+        builder.preopened_dir(&app_dir, APP_DIR, DirPerms::all(), FilePerms::all())?;
+        builder.preopened_dir(&app_dir, ".", DirPerms::all(), FilePerms::all())?;
+        let out_dir = sandbox.dir.join("out");
+        builder.preopened_dir(&out_dir, OUTPUT_DIR, DirPerms::all(), FilePerms::all())?;
+
+        match stdin {
+            Some(stdin) => builder.stdin(AsyncStdinStream::new(AsyncReadStream::new(stdin))),
+            None => builder.stdin(ClosedInputStream),
+        };
+        builder.stdout(AsyncStdoutStream::new(AsyncWriteStream::new(
+            BUFFER_SIZE,
+            stdout,
+        )));
+        builder.stderr(AsyncStdoutStream::new(AsyncWriteStream::new(
+            BUFFER_SIZE,
+            stderr,
+        )));
+
+        Ok(builder.build_p1())
+    }
+
+    fn not_found(id: &str) -> CodeRunnerError {
+        host::sandbox_error(format!("sandbox '{id}' not found"))
+    }
+}
+
+impl SandboxBackend for WasmBackend {
+    async fn create(&self, spec: SandboxSpec) -> Result<(), CodeRunnerError> {
+        let dir = self.config.work_dir.join(&spec.id);
+        let mut spec = spec;
+        let files = std::mem::take(&mut spec.files);
+        host::create_run_dir(&dir, &["app", "out"], files).await?;
+        let (module, interpreter) = self.load_module(&dir, &spec.image).await?;
+
+        let sandbox = WasmSandbox {
+            dir,
+            spec,
+            module,
+            interpreter,
+            output: None,
+            stdin: None,
+            task: None,
+            started_at: None,
+        };
+        let mut sandboxes = self.sandboxes.lock().expect("sandbox lock poisoned");
+        sandboxes.insert(sandbox.spec.id.clone(), sandbox);
+        Ok(())
+    }
+
+    async fn attach(&self, id: &str, stdin: bool) -> Result<SandboxIo, CodeRunnerError> {
+        let mut sandboxes = self.sandboxes.lock().expect("sandbox lock poisoned");
+        let sandbox = sandboxes.get_mut(id).ok_or_else(|| Self::not_found(id))?;
+
+        // Output and input are connected to the module when it's started
+        let (output_tx, output_rx) = mpsc::channel(64);
+        let (input, stdin_reader) = tokio::io::duplex(BUFFER_SIZE);
+        sandbox.output = Some(output_tx);
+        sandbox.stdin = Some(stdin_reader).filter(|_| stdin);
+
+        Ok(SandboxIo {
+            output: Box::pin(ReceiverStream::new(output_rx)),
+            input: Box::pin(input),
+        })
+    }
+
+    async fn start(&self, id: &str) -> Result<(), CodeRunnerError> {
+        let mut sandboxes = self.sandboxes.lock().expect("sandbox lock poisoned");
+        let sandbox = sandboxes.get_mut(id).ok_or_else(|| Self::not_found(id))?;
+
+        // Forward stdout and stderr of the module to the output stream
+        let (stdout, stdout_reader) = tokio::io::duplex(BUFFER_SIZE);
+        let (stderr, stderr_reader) = tokio::io::duplex(BUFFER_SIZE);
+        let output = sandbox.output.take();
+        if let Some(output) = &output {
+            let stdout_output = output.clone();
+            let stderr_output = output.clone();
+            let frame = SandboxOutput::Stdout;
+            tokio::spawn(host::forward_output(stdout_reader, stdout_output, frame));
+            let frame = SandboxOutput::Stderr;
+            tokio::spawn(host::forward_output(stderr_reader, stderr_output, frame));
+        }
+
+        let stdin = sandbox.stdin.take();
+        let wasi = Self::wasi_ctx(sandbox, stdin, stdout, stderr)
+            .map_err(|err| host::sandbox_error(format!("failed to setup WASI: {err:#}")))?;
+        let max_bytes = usize::try_from(u64::from(sandbox.spec.mem_limit_mb) * 1024 * 1024);
+        let limiter = MemoryLimiter {
+            max_bytes: max_bytes.unwrap_or(usize::MAX),
+            used_bytes: 0,
+            peak_bytes: 0,
+            exceeded: false,
+        };
+        let state = RunState {
+            wasi,
+            limiter,
+            timed_out: false,
+        };
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limiter);
+
+        // Yield to the async runtime at each epoch tick, and stop after the time limit
+        let deadline = Instant::now() + Duration::from_secs(sandbox.spec.timeout.into());
+        store.set_epoch_deadline(1);
+        store.epoch_deadline_callback(move |mut store| {
+            if Instant::now() >= deadline {
+                store.data_mut().timed_out = true;
+                bail!("time limit exceeded");
+            }
+            Ok(UpdateDeadline::Yield(1))
+        });
+
+        let linker = self.linker.clone();
+        let module = sandbox.module.clone();
+        let disk_dir = sandbox.dir.clone();
+        let output_dir_bytes = self.config.output_dir_bytes;
+        sandbox.task = Some(tokio::spawn(async move {
+            let max_disk_bytes = host::dir_size(&disk_dir).await + output_dir_bytes;
+            let run = async {
+                let instance = linker.instantiate_async(&mut store, &module).await?;
+                let start = instance.get_typed_func::<(), ()>(&mut store, "_start")?;
+                start.call_async(&mut store, ()).await
+            };
+            let result = tokio::select! {
+                result = run => result,
+                _ = host::disk_limit_exceeded(&disk_dir, max_disk_bytes) => {
+                    if let Some(output) = &output {
+                        let message = b"File size limit exceeded\n".to_vec();
+                        let _ = output.send(Ok(SandboxOutput::Stderr(message))).await;
+                    }
+                    Err(I32Exit(host::FILE_SIZE_EXIT_CODE as i32).into())
+                }
+            };
+
+            let timed_out = store.data().timed_out;
+            let exit_code = match result {
+                Ok(()) => Some(0),
+                Err(_) if timed_out => None,
+                Err(err) => match err.downcast_ref::<I32Exit>() {
+                    Some(I32Exit(code)) => Some(i64::from(*code)),
+                    None => {
+                        if let Some(output) = &output {
+                            let message = format!("{err:?}\n").into_bytes();
+                            let _ = output.send(Ok(SandboxOutput::Stderr(message))).await;
+                        }
+                        Some(TRAP_EXIT_CODE)
+                    }
+                },
+            };
+            let limiter = &store.data().limiter;
+            RunOutcome {
+                exit_code,
+                oom_killed: limiter.exceeded,
+                timed_out,
+                peak_memory_bytes: limiter.peak_bytes as u64,
+            }
+        }));
+        sandbox.started_at = Some(Instant::now());
+
+        Ok(())
+    }
+
+    async fn wait(&self, id: &str, timeout: u32) -> SandboxExit {
+        let (task, started_at) = {
+            let mut sandboxes = self.sandboxes.lock().expect("sandbox lock poisoned");
+            match sandboxes.get_mut(id) {
+                Some(sandbox) => (sandbox.task.take(), sandbox.started_at),
+                None => (None, None),
+            }
+        };
+        let started_at = started_at.unwrap_or_else(Instant::now);
+        let Some(mut task) = task else {
+            return SandboxExit {
+                termination: TerminationReason::Unknown,
+                exit_code: None,
+                usage: ResourceUsage::default(),
+            };
+        };
+
+        // The module stops itself after the time limit. If it's still running after the
+        // grace period, the task is aborted, which stops the module at its next yield.
+        let timeout = Duration::from_secs(timeout.into()) + GRACE_PERIOD;
+        let (termination, exit_code, peak_memory_bytes) =
+            match tokio::time::timeout(timeout, &mut task).await {
+                Ok(Ok(outcome)) => {
+                    let termination = helpers::termination_reason(
+                        outcome.exit_code,
+                        outcome.oom_killed,
+                        outcome.timed_out,
+                    );
+                    let peak_memory = Some(outcome.peak_memory_bytes);
+                    (termination, outcome.exit_code, peak_memory)
+                }
+                Ok(Err(err)) => {
+                    tracing::warn!("Failed to wait for sandbox '{id}': {err}");
+                    (TerminationReason::Unknown, None, None)
+                }
+                Err(_) => {
+                    task.abort();
+                    let _ = task.await;
+                    (TerminationReason::ServerTimeout, None, None)
+                }
+            };
+
+        SandboxExit {
+            termination,
+            exit_code,
+            usage: ResourceUsage {
+                wall_time_ms: started_at.elapsed().as_millis() as u64,
+                peak_memory_bytes,
+                ..Default::default()
+            },
+        }
+    }
+
+    async fn output_archive(&self, id: &str) -> Pin<Box<dyn AsyncRead + Send>> {
+        let out_dir = self.config.work_dir.join(id).join("out");
+        host::output_archive(&out_dir, self.config.output_dir_bytes).await
+    }
+
+    async fn cleanup(&self, id: &str) {
+        let sandbox = {
+            let mut sandboxes = self.sandboxes.lock().expect("sandbox lock poisoned");
+            sandboxes.remove(id)
+        };
+        let dir = match sandbox {
+            Some(sandbox) => {
+                if let Some(task) = sandbox.task {
+                    task.abort();
+                }
+                sandbox.dir
+            }
+            None => self.config.work_dir.join(id),
+        };
+        host::remove_run_dir(&dir).await;
+    }
+}
@@ -0,0 +1,189 @@
+//! Benchmark mode: run a program repeatedly and report timing statistics
+
+use tinirun_models::{
+    BenchmarkInput, BenchmarkRun, BenchmarkStats, BenchmarkSummary, CodeRunnerChunk,
+    CodeRunnerError, CodeRunnerResult, ResourceUsage, TerminationReason,
+};
+use tokio::sync::mpsc;
+
+use crate::runner::{
+    executor::{DockerExecutor, ExecProgram},
+    helpers::log,
+    registry::{RunHandle, RunPhase},
+};
+
+/// Executor for benchmarking a program. The image of the program is built once, then
+/// each run happens in a fresh container.
+pub struct BenchmarkExecutor {
+    pub docker: DockerExecutor,
+}
+
+impl BenchmarkExecutor {
+    pub fn new(docker: DockerExecutor) -> Self {
+        Self { docker }
+    }
+
+    /// Run the warmup runs and the measured runs, and send the statistics. If a run
+    /// doesn't exit successfully, its result is sent and the benchmark stops.
+    pub async fn run(
+        &self,
+        run: &RunHandle,
+        input: BenchmarkInput,
+        program: ExecProgram,
+        tx: mpsc::Sender<CodeRunnerChunk>,
+    ) -> Result<(), CodeRunnerError> {
+        let run_id = run.id();
+        let program = self.docker.prepare_program(run_id, program, &tx).await?;
+        run.set_phase(RunPhase::Running);
+
+        let stdin = input.program.stdin.as_deref().map(str::as_bytes);
+        let max_output_bytes = input.program.max_output_bytes;
+        let mut usages = Vec::with_capacity(input.runs as usize);
+        for (warmup, count) in [(true, input.warmup_runs), (false, input.runs)] {
+            let label = if warmup { "warmup run" } else { "run" };
+            for number in 1..=count {
+                log::send_info(&tx, format!("Starting {label} {number}/{count}...")).await;
+                let spec = program.sandbox_spec(run_id, &input.program);
+                let result = self
+                    .docker
+                    .run_isolated(spec, stdin, max_output_bytes, &tx)
+                    .await?;
+                let benchmark_run = BenchmarkRun {
+                    run: number,
+                    warmup,
+                    exit_code: result.exit_code,
+                    usage: result.usage.clone(),
+                };
+                let _ = tx.send(CodeRunnerChunk::BenchmarkRun(benchmark_run)).await;
+
+                if !succeeded(&result) {
+                    log::send_info(&tx, format!("The {label} failed, stopping")).await;
+                    let _ = tx.send(CodeRunnerChunk::Result(result)).await;
+                    return Ok(());
+                }
+                if !warmup {
+                    usages.push(result.usage);
+                }
+            }
+        }
+
+        let summary = summarize(&usages);
+        let _ = tx.send(CodeRunnerChunk::BenchmarkSummary(summary)).await;
+
+        Ok(())
+    }
+}
+
+/// Whether the run exited with code 0
+fn succeeded(result: &CodeRunnerResult) -> bool {
+    result.termination == TerminationReason::Exited && result.exit_code == Some(0)
+}
+
+/// Summarize the resource usage of the measured runs
+fn summarize(usages: &[ResourceUsage]) -> BenchmarkSummary {
+    let wall_times: Vec<_> = usages.iter().map(|u| u.wall_time_ms as f64).collect();
+    let cpu_times: Option<Vec<_>> = usages
+        .iter()
+        .map(|u| u.cpu_time_ms.map(|ms| ms as f64))
+        .collect();
+
+    BenchmarkSummary {
+        runs: usages.len() as u32,
+        wall_time_ms: stats(&wall_times),
+        cpu_time_ms: cpu_times.as_deref().map(stats),
+        peak_memory_bytes: usages.iter().filter_map(|u| u.peak_memory_bytes).max(),
+    }
+}
+
+/// Calculate the statistics of the samples. Returns zeros if there are no samples.
+fn stats(samples: &[f64]) -> BenchmarkStats {
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let count = sorted.len();
+    if count == 0 {
+        return BenchmarkStats {
+            min: 0.0,
+            median: 0.0,
+            mean: 0.0,
+            stddev: 0.0,
+        };
+    }
+
+    let median = match count % 2 {
+        0 => (sorted[count / 2 - 1] + sorted[count / 2]) / 2.0,
+        _ => sorted[count / 2],
+    };
+    let mean = sorted.iter().sum::<f64>() / count as f64;
+    let variance = match count {
+        1 => 0.0,
+        _ => sorted.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (count - 1) as f64,
+    };
+
+    BenchmarkStats {
+        min: sorted[0],
+        median,
+        mean,
+        stddev: variance.sqrt(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(wall_time_ms: u64, cpu_time_ms: Option<u64>, memory: Option<u64>) -> ResourceUsage {
+        ResourceUsage {
+            wall_time_ms,
+            cpu_time_ms,
+            peak_memory_bytes: memory,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn calculates_stats() {
+        let odd = stats(&[30.0, 10.0, 20.0]);
+        assert_eq!((odd.min, odd.median, odd.mean), (10.0, 20.0, 20.0));
+        assert_eq!(odd.stddev, 10.0);
+
+        let even = stats(&[4.0, 1.0, 2.0, 3.0]);
+        assert_eq!((even.min, even.median, even.mean), (1.0, 2.5, 2.5));
+        assert!((even.stddev - 1.290_994).abs() < 1e-6);
+
+        let single = stats(&[5.0]);
+        assert_eq!((single.min, single.median, single.stddev), (5.0, 5.0, 0.0));
+    }
+
+    #[test]
+    fn summarizes_runs() {
+        let usages = [
+            usage(100, Some(80), Some(2048)),
+            usage(300, Some(240), None),
+            usage(200, Some(160), Some(4096)),
+        ];
+        let summary = summarize(&usages);
+        assert_eq!(summary.runs, 3);
+        assert_eq!(summary.wall_time_ms.median, 200.0);
+        assert_eq!(summary.cpu_time_ms.map(|cpu| cpu.mean), Some(160.0));
+        assert_eq!(summary.peak_memory_bytes, Some(4096));
+
+        // CPU time is only summarized if it was reported for every run
+        let summary = summarize(&[usage(100, Some(80), None), usage(100, None, None)]);
+        assert_eq!(summary.cpu_time_ms, None);
+        assert_eq!(summary.peak_memory_bytes, None);
+    }
+
+    #[test]
+    fn checks_run_success() {
+        let mut result = CodeRunnerResult {
+            exit_code: Some(0),
+            ..Default::default()
+        };
+        assert!(succeeded(&result));
+        result.exit_code = Some(1);
+        assert!(!succeeded(&result));
+        result.exit_code = Some(137);
+        result.termination = TerminationReason::OomKilled;
+        assert!(!succeeded(&result));
+    }
+}
@@ -0,0 +1,287 @@
+//! Cache of dependency images for one-off executions
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use bollard::{Docker, query_parameters::BuildImageOptionsBuilder};
+use sha2::{Digest, Sha256};
+use tinirun_models::{CodeRunnerChunk, CodeRunnerError, CodeRunnerLanguage, ExecutionStage};
+use tokio::sync::mpsc;
+
+use crate::{
+    redis::RedisClient,
+    runner::{
+        admission::AdmissionController,
+        constants::{APP_LABEL, CACHE_LABEL, SET_BUILD_ID},
+        helpers::{self, log},
+    },
+};
+
+/// Repository name of the cached dependency images
+const CACHE_REPOSITORY: &str = "tinirun-cache";
+
+/// Dockerfile of a one-off execution, split into the cacheable dependency stage
+/// and the stage that adds the code.
+pub struct ExecDockerfile {
+    /// Tag of the cached dependency image
+    pub cache_tag: String,
+    /// Instructions to build the dependency image
+    pub deps: String,
+    /// Remaining instructions that add (and possibly compile) the code
+    pub code: String,
+}
+
+impl ExecDockerfile {
+    /// Split the rendered Dockerfile at the build ID instructions. Everything before is
+    /// the dependency stage, and is hashed along with the language and dependencies
+    /// to get the tag of the cached image.
+    pub fn split(
+        lang: &CodeRunnerLanguage,
+        dockerfile: &str,
+        dependencies: Option<&[String]>,
+    ) -> Option<Self> {
+        let (deps, code) = dockerfile.split_once(SET_BUILD_ID)?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(format!("{lang}\0"));
+        hasher.update(deps);
+        for dep in dependencies.unwrap_or_default() {
+            hasher.update(b"\0");
+            hasher.update(dep);
+        }
+        let hash = format!("{:x}", hasher.finalize());
+
+        Some(Self {
+            cache_tag: format!("{CACHE_REPOSITORY}:{}", &hash[..24]),
+            deps: deps.to_owned(),
+            code: code.to_owned(),
+        })
+    }
+}
+
+/// Locks of the dependency images that are being checked or built, by tag
+type BuildLocks = Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>;
+
+/// Keeps track of dependency image builds, so that concurrent requests for the
+/// same image share a single build.
+pub struct ImageCache {
+    redis: RedisClient,
+    admission: Arc<AdmissionController>,
+    builds: BuildLocks,
+}
+
+impl ImageCache {
+    pub fn new(redis: RedisClient, admission: Arc<AdmissionController>) -> Self {
+        Self {
+            redis,
+            admission,
+            builds: Mutex::default(),
+        }
+    }
+
+    /// Make sure the cached dependency image exists, building it if needed. If another
+    /// request is already building the same image, wait for that build to finish.
+    pub async fn get_or_build(
+        &self,
+        client: &Docker,
+        cache_tag: &str,
+        base_image: &str,
+        dockerfile: String,
+        tx: &mpsc::Sender<CodeRunnerChunk>,
+    ) -> Result<(), CodeRunnerError> {
+        let build_lock = BuildLock::new(&self.builds, cache_tag);
+        let _guard = match build_lock.lock.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => {
+                log::send_info(tx, format!("Waiting for build of '{cache_tag}'...")).await;
+                build_lock.lock.lock().await
+            }
+        };
+
+        let result = match helpers::exists_image(client, cache_tag).await {
+            Ok(true) => {
+                log::send_info(tx, format!("Using cached image '{cache_tag}'")).await;
+                Ok(())
+            }
+            Ok(false) => {
+                self.build(client, cache_tag, base_image, dockerfile, tx)
+                    .await
+            }
+            Err(err) => Err(err.into()),
+        };
+
+        if result.is_ok()
+            && let Err(err) = self.redis.touch_cached_image(cache_tag).await
+        {
+            tracing::warn!("Failed to update last use of '{cache_tag}' in Redis: {err}");
+        }
+        result
+    }
+
+    async fn build(
+        &self,
+        client: &Docker,
+        cache_tag: &str,
+        base_image: &str,
+        dockerfile: String,
+        tx: &mpsc::Sender<CodeRunnerChunk>,
+    ) -> Result<(), CodeRunnerError> {
+        let _permit = self.admission.acquire(ExecutionStage::Build, tx).await?;
+
+        // Check if base image exists locally, and pull if needed
+        log::send_info(tx, format!("Checking base image '{base_image}'...")).await;
+        if let Err(e) = helpers::pull_image(client, base_image, tx).await {
+            let message = format!("Error while checking/pulling image: {e}");
+            return Err(CodeRunnerError::Docker { message });
+        }
+
+        // Build dependency image
+        log::send_info(tx, format!("Building image '{cache_tag}'...")).await;
+        let build_context =
+            helpers::create_build_context(vec![(PathBuf::from("Dockerfile"), dockerfile.into())]);
+        let image_labels = [
+            (APP_LABEL, "v".to_owned() + env!("CARGO_PKG_VERSION")),
+            (CACHE_LABEL, cache_tag.to_owned()),
+        ];
+        let build_stream = client.build_image(
+            BuildImageOptionsBuilder::new()
+                .t(cache_tag)
+                .labels(&image_labels.into())
+                .build(),
+            None,
+            Some(bollard::body_try_stream(build_context)),
+        );
+        match helpers::process_build_stream(build_stream, tx).await {
+            (Some(image_id), _) => {
+                log::send_info(tx, format!("Built image '{cache_tag}' with ID {image_id}")).await;
+                Ok(())
+            }
+            (None, logs) => Err(CodeRunnerError::BuildFailed {
+                message: format!("Failed to build image '{cache_tag}'"),
+                logs,
+            }),
+        }
+    }
+}
+
+/// Lock of a dependency image shared by the requests that need it. The lock is removed
+/// when the last request is done with it (or is cancelled).
+struct BuildLock<'a> {
+    builds: &'a BuildLocks,
+    cache_tag: String,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<'a> BuildLock<'a> {
+    fn new(builds: &'a BuildLocks, cache_tag: &str) -> Self {
+        let lock = builds
+            .lock()
+            .expect("image cache lock poisoned")
+            .entry(cache_tag.to_owned())
+            .or_default()
+            .clone();
+        Self {
+            builds,
+            cache_tag: cache_tag.to_owned(),
+            lock,
+        }
+    }
+}
+
+impl Drop for BuildLock<'_> {
+    fn drop(&mut self) {
+        let mut builds = self.builds.lock().expect("image cache lock poisoned");
+        // The map holds one reference, and requests waiting for the build hold the others
+        let is_last = builds
+            .get(&self.cache_tag)
+            .is_some_and(|lock| Arc::ptr_eq(lock, &self.lock) && Arc::strong_count(lock) == 2);
+        if is_last {
+            builds.remove(&self.cache_tag);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dockerfile(deps: &str, code: &str) -> String {
+        format!("FROM python:3.13\n{deps}{SET_BUILD_ID}{code}")
+    }
+
+    #[test]
+    fn splits_dockerfile() {
+        let python = CodeRunnerLanguage::from("python");
+        let rendered = dockerfile("RUN pip install requests", "COPY . .");
+        let split = ExecDockerfile::split(&python, &rendered, None).unwrap();
+        assert_eq!(split.deps, "FROM python:3.13\nRUN pip install requests");
+        assert_eq!(split.code, "COPY . .");
+        assert!(split.cache_tag.starts_with("tinirun-cache:"));
+        assert_eq!(split.cache_tag.len(), "tinirun-cache:".len() + 24);
+
+        assert!(ExecDockerfile::split(&python, "FROM python:3.13", None).is_none());
+    }
+
+    #[test]
+    fn hashes_cache_key() {
+        let python = CodeRunnerLanguage::from("python");
+        let tag = |lang: &CodeRunnerLanguage, deps: &str, code: &str, dependencies: &[&str]| {
+            let dependencies: Vec<_> = dependencies.iter().map(|d| d.to_string()).collect();
+            let rendered = dockerfile(deps, code);
+            let split = ExecDockerfile::split(lang, &rendered, Some(&dependencies)).unwrap();
+            split.cache_tag
+        };
+
+        let base = tag(&python, "RUN pip install", "COPY . .", &["requests"]);
+        // The code isn't part of the cached image
+        assert_eq!(
+            base,
+            tag(&python, "RUN pip install", "RUN echo", &["requests"])
+        );
+        assert_ne!(
+            base,
+            tag(&python, "RUN pip3 install", "COPY . .", &["requests"])
+        );
+        assert_ne!(
+            base,
+            tag(&python, "RUN pip install", "COPY . .", &["numpy"])
+        );
+        let ruby = CodeRunnerLanguage::from("ruby");
+        assert_ne!(
+            base,
+            tag(&ruby, "RUN pip install", "COPY . .", &["requests"])
+        );
+        // Dependencies are separated, so that they can't run together
+        assert_ne!(
+            tag(&python, "", "", &["ab", "c"]),
+            tag(&python, "", "", &["a", "bc"])
+        );
+        assert_eq!(
+            tag(&python, "", "", &[]),
+            ExecDockerfile::split(&python, &dockerfile("", ""), None)
+                .unwrap()
+                .cache_tag
+        );
+    }
+
+    #[test]
+    fn removes_build_lock_after_last_request() {
+        let builds = BuildLocks::default();
+        let first = BuildLock::new(&builds, "tinirun-cache:abc");
+        let waiting = BuildLock::new(&builds, "tinirun-cache:abc");
+        assert!(Arc::ptr_eq(&first.lock, &waiting.lock));
+
+        // The request waiting for the build still shares the lock with new requests
+        drop(first);
+        let next = BuildLock::new(&builds, "tinirun-cache:abc");
+        assert!(Arc::ptr_eq(&waiting.lock, &next.lock));
+
+        drop(waiting);
+        assert!(builds.lock().unwrap().contains_key("tinirun-cache:abc"));
+        drop(next);
+        assert!(builds.lock().unwrap().is_empty());
+    }
+}
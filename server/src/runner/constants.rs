@@ -7,15 +7,23 @@ pub const ID_LABEL: &str = "tinirun-id";
 pub const EXEC_LABEL: &str = "tinirun-exec";
 /// Image label for function images
 pub const FN_LABEL: &str = "tinirun-fn";
+/// Image label for cached dependency images of one-off executions
+pub const CACHE_LABEL: &str = "tinirun-cache";
+/// Container label for pre-created containers in the function pool
+pub const POOL_LABEL: &str = "tinirun-pool";
 
 // Dockerfile constants
+/// Working directory of the code in the container
+pub const APP_DIR: &str = "/app";
+/// Writable directory where programs can write output artifacts
+pub const OUTPUT_DIR: &str = "/tmp/out";
 /// User and group for code execution containers
 pub const UID_GID: &str = "1000:1000";
 /// Common Dockerfile instructions for setting up the non-root user and home directory
 pub const SET_USER_AND_HOME_DIR: &str = r#"
 RUN mkdir -p /app && chown 1000:1000 /app
 USER 1000:1000
-RUN mkdir -p /tmp/home
+RUN mkdir -p /tmp/home /tmp/out
 WORKDIR /app
 "#;
 /// Name of the build argument for the unique build ID
@@ -26,3 +34,19 @@ pub const SET_BUILD_ID: &str = r#"
 ARG TINIRUN_BUILD_ID
 ENV TINIRUN_BUILD_ID=$TINIRUN_BUILD_ID
 "#;
+/// Name of the file in the output directory that function harnesses write the result to
+/// in JSON mode
+pub const FN_RESULT_FILE: &str = ".tinirun-result.json";
+/// Dockerfile instructions appended to images of functions in JSON mode, which switch the
+/// function harness to the JSON input/output contract
+pub const SET_JSON_IO: &str = r#"
+ENV TINIRUN_FN_IO=json
+ENV TINIRUN_RESULT_FILE=/tmp/out/.tinirun-result.json
+"#;
+/// Dockerfile instructions to make the app directory read-only for the user, appended to
+/// dependency images that the code is uploaded into at container creation.
+pub const LOCK_APP_DIR: &str = r#"
+USER root
+RUN chown root:root /app && chmod 755 /app
+USER 1000:1000
+"#;
@@ -0,0 +1,478 @@
+//! Opt-in network egress through an allowlisting proxy
+
+use std::{
+    collections::HashMap,
+    net::Ipv4Addr,
+    sync::{Arc, Mutex},
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use bollard::{
+    Docker,
+    models::{NetworkCreateRequest, NetworkInspect},
+};
+use tinirun_models::CodeRunnerChunk;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+
+use crate::runner::{constants::APP_LABEL, helpers::log};
+
+/// Maximum size of the proxy request line and headers
+const MAX_HEAD_BYTES: usize = 8192;
+/// Port of the proxy if the bind address isn't configured
+pub const DEFAULT_PROXY_PORT: u16 = 3128;
+/// Bridge driver option to disable traffic between containers on the network
+const ENABLE_ICC_OPTION: &str = "com.docker.network.bridge.enable_icc";
+
+/// Configuration of the egress proxy
+pub struct EgressConfig {
+    /// Allowlists that runs can opt into, by name
+    pub allowlists: HashMap<String, Arc<[EgressRule]>>,
+    /// Name of the internal Docker network that containers with egress are connected to
+    pub network: String,
+    /// Address (`host:port`) of the proxy as reachable from the network
+    pub proxy_address: String,
+}
+
+/// An allowed destination of the egress allowlist, in the format `host[:port]`. The
+/// host can start with `*.` to allow all subdomains, and all ports are allowed if
+/// the port is omitted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EgressRule {
+    host: String,
+    port: Option<u16>,
+}
+
+impl EgressRule {
+    pub fn parse(rule: &str) -> Result<Self, String> {
+        let (host, port) = match rule.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port
+                    .parse()
+                    .map_err(|_| format!("invalid port in egress rule '{rule}'"))?;
+                (host, Some(port))
+            }
+            None => (rule, None),
+        };
+        if host.is_empty() || host.contains(['/', ' ']) {
+            return Err(format!("invalid host in egress rule '{rule}'"));
+        }
+
+        Ok(Self {
+            host: host.to_ascii_lowercase(),
+            port,
+        })
+    }
+
+    fn matches(&self, host: &str, port: u16) -> bool {
+        let host = host.to_ascii_lowercase();
+        let host_matches = match self.host.strip_prefix("*.") {
+            Some(domain) => host
+                .strip_suffix(domain)
+                .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+            None => host == self.host,
+        };
+        host_matches && self.port.is_none_or(|p| p == port)
+    }
+}
+
+#[derive(Clone)]
+struct Session {
+    rules: Arc<[EgressRule]>,
+    tx: mpsc::Sender<CodeRunnerChunk>,
+}
+
+/// # Egress proxy
+///
+/// HTTP proxy for containers connected to the internal egress network, which otherwise
+/// have no route outside the network. Each run with egress gets a session token that
+/// is passed in the proxy URL, and connections are only allowed to destinations in the
+/// run's allowlist. Allowed and denied connection attempts are reported to the run's stream.
+/// Supports `CONNECT` tunnels (e.g. for HTTPS) and plain HTTP requests.
+pub struct EgressProxy {
+    pub config: EgressConfig,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl EgressProxy {
+    pub fn new(config: EgressConfig) -> Self {
+        Self {
+            config,
+            sessions: Mutex::default(),
+        }
+    }
+
+    /// Start an egress session for a run with the given allowlist. The session
+    /// ends when the returned guard is dropped.
+    pub fn start_session(
+        self: &Arc<Self>,
+        allowlist: &str,
+        tx: mpsc::Sender<CodeRunnerChunk>,
+    ) -> Option<EgressSession> {
+        let rules = self.config.allowlists.get(allowlist)?.clone();
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        self.sessions
+            .lock()
+            .expect("egress lock poisoned")
+            .insert(token.clone(), Session { rules, tx });
+
+        Some(EgressSession {
+            proxy: self.clone(),
+            allowlist: allowlist.to_owned(),
+            token,
+        })
+    }
+
+    /// Accept and proxy connections from containers
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    tracing::warn!("Egress proxy failed to accept connection: {err}");
+                    continue;
+                }
+            };
+            let proxy = self.clone();
+            tokio::spawn(async move {
+                if let Err(err) = proxy.handle_connection(stream).await {
+                    tracing::debug!("Egress proxy connection error: {err}");
+                }
+            });
+        }
+    }
+
+    async fn handle_connection(&self, mut client: TcpStream) -> std::io::Result<()> {
+        let Some((head, body_start)) = read_head(&mut client).await? else {
+            return respond(&mut client, "400 Bad Request").await;
+        };
+        let Some(request) = ProxyRequest::parse(&head) else {
+            return respond(&mut client, "400 Bad Request").await;
+        };
+        let session = request.token.as_deref().and_then(|token| {
+            let sessions = self.sessions.lock().expect("egress lock poisoned");
+            sessions.get(token).cloned()
+        });
+        let Some(session) = session else {
+            return respond(&mut client, "407 Proxy Authentication Required").await;
+        };
+
+        let target = format!("{}:{}", request.host, request.port);
+        if !session
+            .rules
+            .iter()
+            .any(|rule| rule.matches(&request.host, request.port))
+        {
+            log::send_info(&session.tx, format!("Egress denied: {target}")).await;
+            return respond(&mut client, "403 Forbidden").await;
+        }
+        log::send_info(&session.tx, format!("Egress allowed: {target}")).await;
+
+        let mut upstream = match TcpStream::connect((request.host.as_str(), request.port)).await {
+            Ok(upstream) => upstream,
+            Err(err) => {
+                log::send_info(&session.tx, format!("Egress to {target} failed: {err}")).await;
+                return respond(&mut client, "502 Bad Gateway").await;
+            }
+        };
+        match request.forward_head {
+            None => {
+                let established = b"HTTP/1.1 200 Connection Established\r\n\r\n";
+                client.write_all(established).await?;
+            }
+            Some(forward_head) => upstream.write_all(&forward_head).await?,
+        }
+        upstream.write_all(&body_start).await?;
+        tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+
+        Ok(())
+    }
+}
+
+/// Egress session of a run. Removes the session from the proxy when dropped.
+pub struct EgressSession {
+    proxy: Arc<EgressProxy>,
+    allowlist: String,
+    token: String,
+}
+
+impl EgressSession {
+    /// Name of the session's allowlist
+    pub fn allowlist(&self) -> &str {
+        &self.allowlist
+    }
+
+    /// Name of the Docker network to connect the container to
+    pub fn network(&self) -> &str {
+        &self.proxy.config.network
+    }
+
+    /// Proxy environment variables for the container
+    pub fn env(&self) -> HashMap<String, String> {
+        let proxy_url = format!("http://{}@{}", self.token, self.proxy.config.proxy_address);
+        ["HTTP_PROXY", "HTTPS_PROXY", "http_proxy", "https_proxy"]
+            .into_iter()
+            .map(|name| (name.to_owned(), proxy_url.clone()))
+            .collect()
+    }
+}
+
+impl Drop for EgressSession {
+    fn drop(&mut self) {
+        if let Ok(mut sessions) = self.proxy.sessions.lock() {
+            sessions.remove(&self.token);
+        }
+    }
+}
+
+/// Create the internal egress network if it doesn't exist. Containers on the network
+/// can't reach each other, only the proxy. Returns the gateway address of the network,
+/// which is the address of the host on the network.
+pub async fn ensure_network(
+    client: &Docker,
+    name: &str,
+) -> Result<Option<Ipv4Addr>, bollard::errors::Error> {
+    let network = match client.inspect_network(name, None).await {
+        Ok(network) => {
+            if !icc_disabled(&network) {
+                tracing::warn!(
+                    "Egress network '{name}' allows traffic between containers. \
+                    Recreate it with the '{ENABLE_ICC_OPTION}=false' option."
+                );
+            }
+            network
+        }
+        Err(bollard::errors::Error::DockerResponseServerError {
+            status_code: 404, ..
+        }) => {
+            tracing::info!("Creating egress network '{name}'");
+            let request = NetworkCreateRequest {
+                name: name.to_owned(),
+                driver: Some("bridge".into()),
+                internal: Some(true),
+                options: Some([(ENABLE_ICC_OPTION.into(), "false".into())].into()),
+                labels: Some([(APP_LABEL.into(), "egress".into())].into()),
+                ..Default::default()
+            };
+            client.create_network(request).await?;
+            client.inspect_network(name, None).await?
+        }
+        Err(err) => return Err(err),
+    };
+
+    Ok(gateway(&network))
+}
+
+fn icc_disabled(network: &NetworkInspect) -> bool {
+    network
+        .options
+        .as_ref()
+        .and_then(|options| options.get(ENABLE_ICC_OPTION))
+        .is_some_and(|value| value == "false")
+}
+
+/// The first IPv4 gateway of the network
+fn gateway(network: &NetworkInspect) -> Option<Ipv4Addr> {
+    let ipam_configs = network.ipam.as_ref()?.config.as_ref()?;
+    ipam_configs
+        .iter()
+        .find_map(|config| config.gateway.as_deref()?.parse().ok())
+}
+
+/// Parsed request to the proxy
+#[derive(Debug, PartialEq)]
+struct ProxyRequest {
+    host: String,
+    port: u16,
+    /// Session token from the `Proxy-Authorization` header
+    token: Option<String>,
+    /// Request head to forward to the destination, for plain HTTP requests
+    forward_head: Option<Vec<u8>>,
+}
+
+impl ProxyRequest {
+    fn parse(head: &str) -> Option<Self> {
+        let mut lines = head.split("\r\n");
+        let mut request_line = lines.next()?.split(' ');
+        let (method, target, version) = (
+            request_line.next()?,
+            request_line.next()?,
+            request_line.next()?,
+        );
+        let headers: Vec<(&str, &str)> = lines
+            .filter(|line| !line.is_empty())
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .collect();
+        let token = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("proxy-authorization"))
+            .and_then(|(_, value)| parse_token(value));
+
+        if method == "CONNECT" {
+            let (host, port) = split_host_port(target, None)?;
+            return Some(Self {
+                host,
+                port,
+                token,
+                forward_head: None,
+            });
+        }
+
+        // Plain HTTP requests use the absolute URL as the target. The connection
+        // is closed after the response, so all requests are checked by the proxy.
+        let url = target.strip_prefix("http://")?;
+        let (authority, path) = match url.find('/') {
+            Some(idx) => url.split_at(idx),
+            None => (url, "/"),
+        };
+        let (host, port) = split_host_port(authority, Some(80))?;
+        let mut forward_head = format!("{method} {path} {version}\r\n");
+        for (name, value) in headers {
+            let name_lower = name.to_ascii_lowercase();
+            if !matches!(
+                name_lower.as_str(),
+                "proxy-authorization" | "proxy-connection" | "connection"
+            ) {
+                forward_head.push_str(&format!("{name}: {value}\r\n"));
+            }
+        }
+        forward_head.push_str("Connection: close\r\n\r\n");
+
+        Some(Self {
+            host,
+            port,
+            token,
+            forward_head: Some(forward_head.into_bytes()),
+        })
+    }
+}
+
+/// Get the session token (the username) from a basic `Proxy-Authorization` header
+fn parse_token(value: &str) -> Option<String> {
+    let encoded = value.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(BASE64.decode(encoded.trim()).ok()?).ok()?;
+    let token = decoded.split(':').next()?;
+    Some(token.to_owned()).filter(|t| !t.is_empty())
+}
+
+fn split_host_port(authority: &str, default_port: Option<u16>) -> Option<(String, u16)> {
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (host, port.parse().ok()?),
+        _ => (authority, default_port?),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    Some((host.to_owned(), port)).filter(|(host, _)| !host.is_empty())
+}
+
+/// Read the request head up to the empty line. Returns the head, and any bytes of
+/// the body that were already read.
+async fn read_head(stream: &mut TcpStream) -> std::io::Result<Option<(String, Vec<u8>)>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 1024];
+    loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(idx) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let body_start = buf.split_off(idx + 4);
+            return Ok(String::from_utf8(buf).ok().map(|head| (head, body_start)));
+        }
+        if buf.len() > MAX_HEAD_BYTES {
+            return Ok(None);
+        }
+    }
+}
+
+async fn respond(stream: &mut TcpStream, status: &str) -> std::io::Result<()> {
+    let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    stream.write_all(response.as_bytes()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_rules() {
+        let exact = EgressRule::parse("api.internal:8080").unwrap();
+        assert!(exact.matches("api.internal", 8080));
+        assert!(exact.matches("API.internal", 8080));
+        assert!(!exact.matches("api.internal", 80));
+        assert!(!exact.matches("evil-api.internal", 8080));
+
+        let wildcard = EgressRule::parse("*.example.com").unwrap();
+        assert!(wildcard.matches("api.example.com", 443));
+        assert!(wildcard.matches("a.b.example.com", 80));
+        assert!(!wildcard.matches("example.com", 443));
+        assert!(!wildcard.matches("evilexample.com", 443));
+
+        assert!(EgressRule::parse("host:notaport").is_err());
+        assert!(EgressRule::parse("").is_err());
+    }
+
+    #[test]
+    fn parses_connect_request() {
+        let auth = BASE64.encode("token123:");
+        let head = format!(
+            "CONNECT api.example.com:443 HTTP/1.1\r\nHost: api.example.com:443\r\n\
+            Proxy-Authorization: Basic {auth}\r\n"
+        );
+        let request = ProxyRequest::parse(&head).unwrap();
+        assert_eq!(request.host, "api.example.com");
+        assert_eq!(request.port, 443);
+        assert_eq!(request.token.as_deref(), Some("token123"));
+        assert!(request.forward_head.is_none());
+    }
+
+    #[test]
+    fn parses_plain_http_request() {
+        let auth = BASE64.encode("token123:");
+        let head = format!(
+            "GET http://api.internal/v1/items?x=1 HTTP/1.1\r\nHost: api.internal\r\n\
+            Proxy-Authorization: Basic {auth}\r\nProxy-Connection: keep-alive\r\n"
+        );
+        let request = ProxyRequest::parse(&head).unwrap();
+        assert_eq!(request.host, "api.internal");
+        assert_eq!(request.port, 80);
+        assert_eq!(request.token.as_deref(), Some("token123"));
+        let forward_head = String::from_utf8(request.forward_head.unwrap()).unwrap();
+        assert_eq!(
+            forward_head,
+            "GET /v1/items?x=1 HTTP/1.1\r\nHost: api.internal\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn reads_network_gateway_and_options() {
+        let network: NetworkInspect = serde_json::from_value(serde_json::json!({
+            "Name": "tinirun-egress",
+            "IPAM": {
+                "Config": [
+                    { "Subnet": "fd00::/64", "Gateway": "fd00::1" },
+                    { "Subnet": "172.20.0.0/16", "Gateway": "172.20.0.1" }
+                ]
+            },
+            "Options": { "com.docker.network.bridge.enable_icc": "false" }
+        }))
+        .unwrap();
+        assert_eq!(gateway(&network), Some(Ipv4Addr::new(172, 20, 0, 1)));
+        assert!(icc_disabled(&network));
+
+        let network = NetworkInspect::default();
+        assert_eq!(gateway(&network), None);
+        assert!(!icc_disabled(&network));
+    }
+
+    #[test]
+    fn rejects_unsupported_requests() {
+        assert!(ProxyRequest::parse("GET https://example.com/ HTTP/1.1\r\n").is_none());
+        assert!(ProxyRequest::parse("GET /relative HTTP/1.1\r\n").is_none());
+        assert!(ProxyRequest::parse("CONNECT example.com HTTP/1.1\r\n").is_none());
+    }
+}
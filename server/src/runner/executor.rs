@@ -1,86 +1,306 @@
-//! Executor for running code in Docker containers
+//! Executors for running one-off code
 
 use std::{
     path::{Path, PathBuf},
-    time::Duration,
+    sync::Arc,
 };
 
-use bollard::{
-    Docker,
-    query_parameters::{AttachContainerOptionsBuilder, BuildImageOptionsBuilder},
+use bollard::query_parameters::BuildImageOptionsBuilder;
+use tinirun_models::{
+    CodeRunnerChunk, CodeRunnerError, CodeRunnerFile, CodeRunnerResult, ExecutionStage, SourceFile,
 };
-use futures::StreamExt;
-use tinirun_models::{CodeRunnerChunk, CodeRunnerError};
 use tokio::sync::mpsc;
 
+#[cfg(feature = "wasm")]
+use crate::runner::backend::WasmBackend;
 use crate::runner::{
-    constants::{APP_LABEL, BUILD_ID_ARG, EXEC_LABEL},
+    admission::AdmissionController,
+    artifacts::ArtifactStore,
+    backend::{self, DockerBackend, LocalBackend, SandboxBackend, SandboxRun, SandboxSpec},
+    cache::{ExecDockerfile, ImageCache},
+    constants::{APP_LABEL, BUILD_ID_ARG, EXEC_LABEL, LOCK_APP_DIR, SET_BUILD_ID},
+    egress::EgressSession,
     helpers::{self, log},
+    registry::{RunHandle, RunPhase},
+    sandbox::SandboxProfile,
 };
 
+/// Executor for running code in Docker containers
 pub struct DockerExecutor {
-    pub client: Docker,
+    pub backend: DockerBackend,
+    image_cache: Arc<ImageCache>,
+    admission: Arc<AdmissionController>,
+    artifacts: Arc<ArtifactStore>,
+    egress: Option<EgressSession>,
+    sandbox: Option<Arc<SandboxProfile>>,
+}
+
+/// A program to build once and run in several containers
+pub struct ExecProgram {
+    pub dockerfile: ExecDockerfile,
+    pub lang_data: super::LanguageData,
+    /// Code, source files and attached files of the program
+    pub files: Vec<(PathBuf, Vec<u8>)>,
+    pub sandbox: Option<Arc<SandboxProfile>>,
+}
+
+/// A program with its image prepared, ready to run
+pub struct PreparedProgram {
+    pub image: String,
+    pub command: String,
+    pub files: Vec<(PathBuf, Vec<u8>)>,
+    pub sandbox: Option<Arc<SandboxProfile>>,
+}
+
+impl PreparedProgram {
+    /// Spec of a container running the program with the arguments, environment and
+    /// limits of the input
+    pub fn sandbox_spec(&self, id: &str, input: &super::CodeRunnerInput) -> SandboxSpec {
+        SandboxSpec {
+            id: id.to_owned(),
+            image: self.image.clone(),
+            command: self.command.clone(),
+            args: input.args.clone().unwrap_or_default(),
+            env: input.env.clone().unwrap_or_default(),
+            files: self.files.clone(),
+            stdin: input.stdin.is_some(),
+            timeout: input.timeout,
+            mem_limit_mb: input.mem_limit_mb,
+            cpu_limit: input.cpu_limit,
+            network: None,
+            profile: self.sandbox.clone(),
+        }
+    }
 }
 
 impl DockerExecutor {
-    pub fn new(client: Docker) -> Self {
-        Self { client }
+    pub fn new(
+        backend: DockerBackend,
+        image_cache: Arc<ImageCache>,
+        admission: Arc<AdmissionController>,
+        artifacts: Arc<ArtifactStore>,
+        egress: Option<EgressSession>,
+        sandbox: Option<Arc<SandboxProfile>>,
+    ) -> Self {
+        Self {
+            backend,
+            image_cache,
+            admission,
+            artifacts,
+            egress,
+            sandbox,
+        }
     }
 
     pub async fn run(
         &self,
-        run_id: &str,
+        run: &RunHandle,
         input: super::CodeRunnerInput,
-        dockerfile: String,
+        dockerfile: ExecDockerfile,
         lang_data: super::LanguageData,
         tx: mpsc::Sender<super::CodeRunnerChunk>,
     ) -> Result<(), CodeRunnerError> {
+        let run_id = run.id();
         let super::CodeRunnerInput {
             code,
+            sources,
+            entrypoint,
             files,
+            stdin,
+            args,
+            env,
             timeout,
             mem_limit_mb,
             cpu_limit,
+            max_output_bytes,
             ..
         } = input;
-        let super::LanguageData {
+        let all_files = gather_files(
+            code,
+            sources,
+            entrypoint,
+            files,
+            lang_data.main_filename.clone(),
+            lang_data.src_dir.clone(),
+        );
+        let (image_tag, upload_files) = self
+            .prepare_image(run_id, dockerfile, &lang_data, all_files, &tx)
+            .await?;
+        let command = lang_data.command;
+
+        // Wait for a run slot, then create the container
+        let _permit = self.admission.acquire(ExecutionStage::Run, &tx).await?;
+        run.set_phase(RunPhase::Running);
+        let mut env = env.unwrap_or_default();
+        if let Some(egress) = &self.egress {
+            let allowlist = egress.allowlist();
+            log::send_info(&tx, format!("Enabling network egress with '{allowlist}'")).await;
+            env.extend(egress.env());
+        }
+        if !upload_files.is_empty() {
+            let mut upload_message = String::from("Uploading files:");
+            for (path, _) in upload_files.iter() {
+                upload_message.push_str(&format!(" {path:?}"));
+            }
+            log::send_info(&tx, upload_message).await;
+        }
+        let spec = SandboxSpec {
+            id: run_id.to_owned(),
+            image: image_tag,
+            command: command.clone(),
+            args: args.unwrap_or_default(),
+            env,
+            files: upload_files,
+            stdin: stdin.is_some(),
+            timeout,
+            mem_limit_mb,
+            cpu_limit,
+            network: self
+                .egress
+                .as_ref()
+                .map(|egress| egress.network().to_owned()),
+            profile: self.sandbox.clone(),
+        };
+        self.backend.create(spec).await?;
+
+        // Run the container, then collect artifacts and send the result
+        log::send_info(&tx, format!("Starting container with '{command}'...")).await;
+        let redactor = helpers::Redactor::default();
+        let sandbox_run = SandboxRun {
+            id: run_id,
+            timeout,
+            max_output_bytes,
+            stdin: stdin.as_deref().map(str::as_bytes),
+            redactor: &redactor,
+        };
+        let result = backend::run_sandbox(&self.backend, sandbox_run, &tx).await?;
+        self.artifacts
+            .collect(&self.backend, run_id, None, &redactor, &tx)
+            .await;
+        let _ = tx.send(CodeRunnerChunk::Result(result)).await;
+
+        Ok(())
+    }
+
+    /// Get or build the cached image with the dependencies. If the code can be injected,
+    /// the files are uploaded into a container created from the cached image. Otherwise,
+    /// an image tagged with the ID is built with the code. Returns the tag of the image
+    /// to run, and the files to upload into the container.
+    pub async fn prepare_image(
+        &self,
+        id: &str,
+        dockerfile: ExecDockerfile,
+        lang_data: &super::LanguageData,
+        files: Vec<(PathBuf, Vec<u8>)>,
+        tx: &mpsc::Sender<CodeRunnerChunk>,
+    ) -> Result<(String, Vec<(PathBuf, Vec<u8>)>), CodeRunnerError> {
+        let ExecDockerfile {
+            cache_tag,
+            deps: deps_dockerfile,
+            code: code_dockerfile,
+        } = dockerfile;
+        let deps_dockerfile = match lang_data.inject_code {
+            true => deps_dockerfile + LOCK_APP_DIR,
+            false => deps_dockerfile,
+        };
+        let client = self.backend.client();
+        self.image_cache
+            .get_or_build(client, &cache_tag, &lang_data.image, deps_dockerfile, tx)
+            .await?;
+
+        match lang_data.inject_code {
+            true => Ok((cache_tag, files)),
+            false => {
+                let dockerfile = format!("FROM {cache_tag}\n{SET_BUILD_ID}{code_dockerfile}");
+                self.build_image(id, dockerfile, files, tx).await?;
+                Ok((id.to_owned(), Vec::new()))
+            }
+        }
+    }
+
+    /// Prepare the image of a program to run in several containers
+    pub async fn prepare_program(
+        &self,
+        id: &str,
+        program: ExecProgram,
+        tx: &mpsc::Sender<CodeRunnerChunk>,
+    ) -> Result<PreparedProgram, CodeRunnerError> {
+        let ExecProgram {
+            dockerfile,
+            lang_data,
+            files,
+            sandbox,
+        } = program;
+        let (image, files) = self
+            .prepare_image(id, dockerfile, &lang_data, files, tx)
+            .await?;
+
+        Ok(PreparedProgram {
             image,
-            command,
-            main_filename: main_file,
-            ..
-        } = lang_data;
+            command: lang_data.command,
+            files,
+            sandbox,
+        })
+    }
 
-        // Check if base image exists locally, and pull if needed
-        log::send_info(&tx, format!("Checking base image '{image}'...")).await;
-        if let Err(e) = helpers::pull_image(&self.client, &image, &tx).await {
-            let message = format!("Error while checking/pulling image: {e}");
-            return Err(CodeRunnerError::Docker { message });
+    /// Run a container of a prepared image, and remove it afterwards. The output isn't
+    /// streamed, and artifacts aren't collected.
+    pub async fn run_isolated(
+        &self,
+        spec: SandboxSpec,
+        stdin: Option<&[u8]>,
+        max_output_bytes: u32,
+        tx: &mpsc::Sender<CodeRunnerChunk>,
+    ) -> Result<CodeRunnerResult, CodeRunnerError> {
+        let _permit = self.admission.acquire(ExecutionStage::Run, tx).await?;
+        let (id, timeout) = (spec.id.clone(), spec.timeout);
+        let result = async {
+            self.backend.create(spec).await?;
+            let (output_tx, _) = mpsc::channel(1);
+            let sandbox_run = SandboxRun {
+                id: &id,
+                timeout,
+                max_output_bytes,
+                stdin,
+                redactor: &helpers::Redactor::default(),
+            };
+            backend::run_sandbox(&self.backend, sandbox_run, &output_tx).await
         }
+        .await;
+        self.backend.cleanup(&id).await;
+        result
+    }
+
+    /// Build the image of the one-off execution with the code, on top of the cached
+    /// dependency image.
+    async fn build_image(
+        &self,
+        run_id: &str,
+        dockerfile: String,
+        files: Vec<(PathBuf, Vec<u8>)>,
+        tx: &mpsc::Sender<CodeRunnerChunk>,
+    ) -> Result<(), CodeRunnerError> {
+        let _permit = self.admission.acquire(ExecutionStage::Build, tx).await?;
 
         // Create build context (Dockerfile, code, attached files)
-        let code_files = [
-            (PathBuf::from("Dockerfile"), dockerfile.into_bytes()),
-            (PathBuf::from(main_file), code.into_bytes()),
-        ];
-        let attached_files = files
-            .unwrap_or_default()
+        let all_files: Vec<_> = [(PathBuf::from("Dockerfile"), dockerfile.into_bytes())]
             .into_iter()
-            .map(|file| (Path::new("files").join(file.path), file.content));
-        let all_files: Vec<_> = code_files.into_iter().chain(attached_files).collect();
+            .chain(files)
+            .collect();
         let mut build_ctx_message = String::from("Creating build context:");
         for (path, _) in all_files.iter() {
             build_ctx_message.push_str(&format!(" {path:?}"));
         }
-        log::send_info(&tx, build_ctx_message).await;
+        log::send_info(tx, build_ctx_message).await;
         let build_context = helpers::create_build_context(all_files);
 
         // Build Docker image
-        log::send_info(&tx, format!("Building image '{run_id}'...")).await;
+        log::send_info(tx, format!("Building image '{run_id}'...")).await;
         let image_labels = [
             (APP_LABEL, "v".to_owned() + env!("CARGO_PKG_VERSION")),
             (EXEC_LABEL, run_id.to_owned()),
         ];
-        let build_stream = self.client.build_image(
+        let build_stream = self.backend.client().build_image(
             BuildImageOptionsBuilder::new()
                 .t(&run_id)
                 .buildargs(&[(BUILD_ID_ARG, run_id)].into())
@@ -89,64 +309,255 @@ impl DockerExecutor {
             None,
             Some(bollard::body_try_stream(build_context)),
         );
-        let (image_id, build_logs) = helpers::process_build_stream(build_stream, &tx).await;
+        let (image_id, build_logs) = helpers::process_build_stream(build_stream, tx).await;
         if let Some(image_id) = image_id {
-            log::send_info(&tx, format!("Built image '{run_id}' with ID {image_id}")).await;
+            log::send_info(tx, format!("Built image '{run_id}' with ID {image_id}")).await;
+            Ok(())
         } else {
-            let err = CodeRunnerError::BuildFailed {
+            Err(CodeRunnerError::BuildFailed {
                 message: format!("Failed to build image '{run_id}'"),
                 logs: build_logs,
-            };
-            return Err(err);
+            })
+        }
+    }
+}
+
+/// Executor for running code in the local sandbox backend, with the toolchains
+/// installed on the host
+pub struct LocalExecutor {
+    pub backend: Arc<LocalBackend>,
+    admission: Arc<AdmissionController>,
+    artifacts: Arc<ArtifactStore>,
+}
+
+impl LocalExecutor {
+    pub fn new(
+        backend: Arc<LocalBackend>,
+        admission: Arc<AdmissionController>,
+        artifacts: Arc<ArtifactStore>,
+    ) -> Self {
+        Self {
+            backend,
+            admission,
+            artifacts,
         }
+    }
 
-        // Create the container
-        let (body, options) = helpers::setup_container(
-            &run_id,
-            &run_id,
-            &command,
-            false,
+    pub async fn run(
+        &self,
+        run: &RunHandle,
+        input: super::CodeRunnerInput,
+        lang_data: super::LanguageData,
+        tx: mpsc::Sender<super::CodeRunnerChunk>,
+    ) -> Result<(), CodeRunnerError> {
+        let run_id = run.id();
+        let super::CodeRunnerInput {
+            code,
+            sources,
+            entrypoint,
+            files,
+            stdin,
+            args,
+            env,
             timeout,
             mem_limit_mb,
             cpu_limit,
-        );
-        self.client.create_container(Some(options), body).await?;
-
-        // Attach to container and setup capturing of logs/output
-        let attach_options = AttachContainerOptionsBuilder::new()
-            .stream(true)
-            .stdout(true)
-            .stderr(true)
-            .logs(true)
-            .build();
-        let container = self
-            .client
-            .attach_container(&run_id, Some(attach_options))
-            .await?;
-        let capture_output = helpers::output_task(container.output, timeout, tx.clone());
-        let output_task = tokio::spawn(capture_output);
+            max_output_bytes,
+            ..
+        } = input;
+        let super::LanguageData {
+            command,
+            main_filename: main_file,
+            src_dir,
+            ..
+        } = lang_data;
 
-        // Start container
-        log::send_info(&tx, format!("Starting container with '{command}'...")).await;
-        self.client.start_container(&run_id, None).await?;
+        // Wait for a run slot, then create the sandbox with the files
+        let _permit = self.admission.acquire(ExecutionStage::Run, &tx).await?;
+        run.set_phase(RunPhase::Running);
+        let all_files = gather_files(code, sources, entrypoint, files, main_file, src_dir);
+        let spec = SandboxSpec {
+            id: run_id.to_owned(),
+            command: command.clone(),
+            args: args.unwrap_or_default(),
+            env: env.unwrap_or_default(),
+            files: all_files,
+            stdin: stdin.is_some(),
+            timeout,
+            mem_limit_mb,
+            cpu_limit,
+            ..Default::default()
+        };
+        let backend = self.backend.as_ref();
+        backend.create(spec).await?;
 
-        // Wait for container to exit, then get exit status and final stdout and stderr
-        let exit_result = tokio::time::timeout(
-            Duration::from_secs(timeout.into()),
-            self.client.wait_container(&run_id, None).next(),
-        )
-        .await;
-        let (stdout, stderr) = output_task.await.unwrap_or_default();
-        let (timeout, exit_code) = helpers::process_exit_status(exit_result);
+        // Run the sandbox, then collect artifacts and send the result
+        log::send_info(&tx, format!("Starting local sandbox with '{command}'...")).await;
+        let redactor = helpers::Redactor::default();
+        let sandbox_run = SandboxRun {
+            id: run_id,
+            timeout,
+            max_output_bytes,
+            stdin: stdin.as_deref().map(str::as_bytes),
+            redactor: &redactor,
+        };
+        let result = backend::run_sandbox(backend, sandbox_run, &tx).await?;
+        self.artifacts
+            .collect(backend, run_id, None, &redactor, &tx)
+            .await;
+        let _ = tx.send(CodeRunnerChunk::Result(result)).await;
+
+        Ok(())
+    }
+}
 
-        let result_chunk = CodeRunnerChunk::Result {
-            stdout,
-            stderr,
+/// File name of uploaded WebAssembly modules in the app directory
+#[cfg(feature = "wasm")]
+const WASM_MAIN_FILE: &str = "main.wasm";
+
+/// Executor for running code in the WebAssembly runtime
+#[cfg(feature = "wasm")]
+pub struct WasmExecutor {
+    pub backend: Arc<WasmBackend>,
+    admission: Arc<AdmissionController>,
+    artifacts: Arc<ArtifactStore>,
+}
+
+#[cfg(feature = "wasm")]
+impl WasmExecutor {
+    pub fn new(
+        backend: Arc<WasmBackend>,
+        admission: Arc<AdmissionController>,
+        artifacts: Arc<ArtifactStore>,
+    ) -> Self {
+        Self {
+            backend,
+            admission,
+            artifacts,
+        }
+    }
+
+    /// Run the uploaded module, or the code with the language's interpreter. The language
+    /// data is only needed for interpreted languages.
+    pub async fn run(
+        &self,
+        run: &RunHandle,
+        input: super::CodeRunnerInput,
+        lang_data: Option<super::LanguageData>,
+        tx: mpsc::Sender<super::CodeRunnerChunk>,
+    ) -> Result<(), CodeRunnerError> {
+        let run_id = run.id();
+        let super::CodeRunnerInput {
+            code,
+            sources,
+            entrypoint,
+            lang,
+            module,
+            files,
+            stdin,
+            args,
+            env,
+            timeout,
+            mem_limit_mb,
+            cpu_limit,
+            max_output_bytes,
+            ..
+        } = input;
+
+        // Uploaded modules are stored as the main file, otherwise the interpreter runs it
+        let (image, command, all_files) = match (module, lang_data) {
+            (Some(module), _) => {
+                let attached_files = files
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|file| (Path::new("files").join(file.path), file.content));
+                let all_files = std::iter::once((PathBuf::from(WASM_MAIN_FILE), module))
+                    .chain(attached_files)
+                    .collect();
+                (
+                    WASM_MAIN_FILE.to_owned(),
+                    WASM_MAIN_FILE.to_owned(),
+                    all_files,
+                )
+            }
+            (None, Some(lang_data)) => {
+                let super::LanguageData {
+                    main_filename: main_file,
+                    src_dir,
+                    ..
+                } = lang_data;
+                let image = WasmBackend::interpreter_image(&lang);
+                let all_files =
+                    gather_files(code, sources, entrypoint, files, main_file.clone(), src_dir);
+                (image, main_file, all_files)
+            }
+            (None, None) => {
+                return Err(CodeRunnerError::Sandbox {
+                    message: "missing module or language data".into(),
+                });
+            }
+        };
+
+        // Wait for a run slot, then compile the module and create the sandbox
+        let _permit = self.admission.acquire(ExecutionStage::Run, &tx).await?;
+        run.set_phase(RunPhase::Running);
+        let spec = SandboxSpec {
+            id: run_id.to_owned(),
+            image,
+            command: command.clone(),
+            args: args.unwrap_or_default(),
+            env: env.unwrap_or_default(),
+            files: all_files,
+            stdin: stdin.is_some(),
+            timeout,
+            mem_limit_mb,
+            cpu_limit,
+            ..Default::default()
+        };
+        let backend = self.backend.as_ref();
+        backend.create(spec).await?;
+
+        // Run the module, then collect artifacts and send the result
+        log::send_info(&tx, format!("Starting WebAssembly module '{command}'...")).await;
+        let redactor = helpers::Redactor::default();
+        let sandbox_run = SandboxRun {
+            id: run_id,
             timeout,
-            exit_code,
+            max_output_bytes,
+            stdin: stdin.as_deref().map(str::as_bytes),
+            redactor: &redactor,
         };
-        let _ = tx.send(result_chunk).await;
+        let result = backend::run_sandbox(backend, sandbox_run, &tx).await?;
+        self.artifacts
+            .collect(backend, run_id, None, &redactor, &tx)
+            .await;
+        let _ = tx.send(CodeRunnerChunk::Result(result)).await;
 
         Ok(())
     }
 }
+
+/// Gather the code, source files, and attached files of the run. The entrypoint
+/// is the main file.
+pub fn gather_files(
+    code: String,
+    sources: Option<Vec<SourceFile>>,
+    entrypoint: Option<PathBuf>,
+    files: Option<Vec<CodeRunnerFile>>,
+    main_file: String,
+    src_dir: Option<String>,
+) -> Vec<(PathBuf, Vec<u8>)> {
+    let (code, sources) = tinirun_models::split_entrypoint(code, sources, entrypoint.as_deref());
+    let src_dir = PathBuf::from(src_dir.unwrap_or_default());
+    let code_files = std::iter::once((PathBuf::from(main_file), code.into_bytes())).chain(
+        sources
+            .into_iter()
+            .map(|source| (src_dir.join(source.path), source.content.into_bytes())),
+    );
+    let attached_files = files
+        .unwrap_or_default()
+        .into_iter()
+        .map(|file| (Path::new("files").join(file.path), file.content));
+
+    code_files.chain(attached_files).collect()
+}
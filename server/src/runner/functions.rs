@@ -1,41 +1,92 @@
-use std::{path::PathBuf, time::Duration};
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
 
-use bollard::{
-    Docker,
-    query_parameters::{AttachContainerOptionsBuilder, BuildImageOptionsBuilder},
-};
-use futures::StreamExt;
+use bollard::query_parameters::{BuildImageOptionsBuilder, RemoveImageOptionsBuilder};
+use serde::Deserialize;
 use tinirun_models::{
-    CodeRunnerChunk, CodeRunnerError, CodeRunnerFunctionResult, RunFunctionInput,
+    CodeRunnerChunk, CodeRunnerError, CodeRunnerFunctionResult, CodeRunnerResult, ExecutionStage,
+    FunctionError, FunctionIo, RunFunctionInput,
 };
-use tokio::{io::AsyncWriteExt, sync::mpsc};
+use tokio::sync::mpsc;
 
 use crate::{
     redis::FunctionDetail,
     runner::{
-        constants::{APP_LABEL, BUILD_ID_ARG, FN_LABEL},
+        admission::AdmissionController,
+        artifacts::ArtifactStore,
+        backend::{self, DockerBackend, SandboxBackend, SandboxRun, SandboxSpec},
+        constants::{APP_LABEL, BUILD_ID_ARG, FN_LABEL, FN_RESULT_FILE},
+        egress::EgressSession,
         helpers::{self, log},
+        pool::{FunctionPool, PoolSpec},
+        registry::{RunHandle, RunPhase},
+        sandbox::SandboxProfile,
+        schemas::FunctionSchemas,
         structs::LanguageData,
+        validators,
     },
 };
 
+/// Type of the error returned when the output of a function doesn't match its schema
+const OUTPUT_SCHEMA_ERROR: &str = "OutputSchemaError";
+
+/// An invocation of a function
+pub struct FunctionCall {
+    pub name: String,
+    pub input: RunFunctionInput,
+    /// How the function receives its input and returns its output
+    pub io: FunctionIo,
+    /// Compiled schemas of the function's version. The output is validated against
+    /// the output schema.
+    pub schemas: Arc<FunctionSchemas>,
+    /// The image of the function's version to run
+    pub image_tag: String,
+    /// Whether a warm container can be used. The pool only has containers of the
+    /// function's current version.
+    pub warm_pool: bool,
+}
+
 pub struct FunctionExecutor {
-    pub client: Docker,
+    pub backend: DockerBackend,
+    pool: Arc<FunctionPool>,
+    admission: Arc<AdmissionController>,
+    artifacts: Arc<ArtifactStore>,
+    egress: Option<EgressSession>,
+    sandbox: Option<Arc<SandboxProfile>>,
 }
 
 impl FunctionExecutor {
-    pub fn new(client: Docker) -> Self {
-        Self { client }
+    pub fn new(
+        backend: DockerBackend,
+        pool: Arc<FunctionPool>,
+        admission: Arc<AdmissionController>,
+        artifacts: Arc<ArtifactStore>,
+        egress: Option<EgressSession>,
+        sandbox: Option<Arc<SandboxProfile>>,
+    ) -> Self {
+        Self {
+            backend,
+            pool,
+            admission,
+            artifacts,
+            egress,
+            sandbox,
+        }
     }
 
-    /// The tag of the function's Docker image
-    fn fn_tag(name: &str) -> String {
-        format!("code-runner-fn-{name}:latest")
+    /// The repository of the function's Docker images
+    pub fn fn_repo(name: &str) -> String {
+        format!("code-runner-fn-{name}")
+    }
+
+    /// The tag of the Docker image of the function's version
+    pub fn fn_tag(name: &str, version: u32) -> String {
+        format!("{}:v{version}", Self::fn_repo(name))
     }
 
     /// Build the function's Docker image. Returns the image tag and ID on success.
     pub async fn build_fn(
         &self,
+        run: &RunHandle,
         fn_name: &str,
         fn_info: FunctionDetail,
         lang_data: LanguageData,
@@ -43,28 +94,35 @@ impl FunctionExecutor {
         main_code: String,
         tx: mpsc::Sender<CodeRunnerChunk>,
     ) -> Result<(String, String), CodeRunnerError> {
-        let image_tag = Self::fn_tag(&fn_name);
+        let image_tag = Self::fn_tag(fn_name, fn_info.version);
         let LanguageData {
             image: base_image,
             main_filename,
             fn_filename,
+            src_dir,
             ..
         } = lang_data;
+        let _permit = self.admission.acquire(ExecutionStage::Build, &tx).await?;
+        run.set_phase(RunPhase::Building);
 
         // Check if base image exists locally, and pull if needed
         log::send_info(&tx, format!("Checking base image '{base_image}'...")).await;
-        if let Err(e) = helpers::pull_image(&self.client, &base_image, &tx).await {
+        if let Err(e) = helpers::pull_image(self.backend.client(), &base_image, &tx).await {
             return Err(CodeRunnerError::Docker {
                 message: format!("Error while checking/pulling base image: {e}"),
             });
         }
 
-        // Create build context (Dockerfile, main code, function code)
-        let all_files = vec![
+        // Create build context (Dockerfile, main code, function code, source files)
+        let mut all_files = vec![
             (PathBuf::from("Dockerfile"), dockerfile.into_bytes()),
             (PathBuf::from(main_filename), main_code.into_bytes()),
             (PathBuf::from(fn_filename), fn_info.code.into_bytes()),
         ];
+        let src_dir = PathBuf::from(src_dir.unwrap_or_default());
+        for source in fn_info.sources.unwrap_or_default() {
+            all_files.push((src_dir.join(source.path), source.content.into_bytes()));
+        }
         let mut build_ctx_message = String::from("Creating build context:");
         for (path, _) in all_files.iter() {
             build_ctx_message.push_str(&format!(" {path:?}"));
@@ -78,11 +136,12 @@ impl FunctionExecutor {
             (APP_LABEL, "v".to_owned() + env!("CARGO_PKG_VERSION")),
             (FN_LABEL, fn_name.to_owned()),
         ];
-        let build_stream = self.client.build_image(
+        let build_stream = self.backend.client().build_image(
             BuildImageOptionsBuilder::new()
                 .t(&image_tag)
                 .buildargs(&[(BUILD_ID_ARG, &format!("{fn_name}-v{}", fn_info.version))].into())
                 .labels(&image_labels.into())
+                .forcerm(true)
                 .build(),
             None,
             Some(bollard::body_try_stream(build_context)),
@@ -100,96 +159,201 @@ impl FunctionExecutor {
         }
     }
 
+    /// Remove the image of a cancelled build of the function's version, if it was tagged
+    pub async fn remove_fn_image(&self, fn_name: &str, version: u32) {
+        let image_tag = Self::fn_tag(fn_name, version);
+        let options = RemoveImageOptionsBuilder::new().force(true).build();
+        match self
+            .backend
+            .client()
+            .remove_image(&image_tag, Some(options), None)
+            .await
+        {
+            Ok(_) => tracing::info!("Removed image '{image_tag}' of cancelled build"),
+            Err(bollard::errors::Error::DockerResponseServerError {
+                status_code: 404, ..
+            }) => {}
+            Err(err) => tracing::warn!("Failed to remove image '{image_tag}': {err}"),
+        }
+    }
+
     pub async fn run_function(
         &self,
-        run_id: &str,
-        fn_name: &str,
-        input: RunFunctionInput,
+        run: &RunHandle,
+        call: FunctionCall,
         lang_data: LanguageData,
+        secrets: HashMap<String, String>,
         tx: mpsc::Sender<CodeRunnerChunk>,
     ) -> Result<CodeRunnerFunctionResult, CodeRunnerError> {
         // Function input and language config
+        let run_id = run.id();
+        let FunctionCall {
+            name,
+            input,
+            io,
+            schemas,
+            image_tag,
+            warm_pool,
+        } = call;
+        let fn_name = name.as_str();
         let RunFunctionInput {
             input,
             timeout,
             mem_limit_mb,
             cpu_limit,
+            max_output_bytes,
+            ..
         } = input;
         let LanguageData { command, .. } = lang_data;
-        let image_tag = Self::fn_tag(fn_name);
+        let stdin = match (io, &input) {
+            (FunctionIo::Text, serde_json::Value::String(text)) => text.clone(),
+            _ => input.to_string(),
+        };
 
         // Ensure function image exists
-        if !helpers::exists_image(&self.client, &image_tag).await? {
+        if !helpers::exists_image(self.backend.client(), &image_tag).await? {
             return Err(CodeRunnerError::FunctionImageNotFound {
                 message: format!("Image missing for '{fn_name}'. Please rebuild the function."),
                 image_tag,
             });
         }
 
-        // Create the container
-        let (create_body, create_opt) = helpers::setup_container(
-            &run_id,
-            &image_tag,
-            &command,
-            true,
+        // Wait for a run slot, then use a pooled container if available,
+        // otherwise create the container. Pooled containers have no network or
+        // secrets (which would be visible in the environment of idle containers),
+        // so runs with egress or secrets always create a container.
+        let warm_pool = warm_pool && self.egress.is_none() && secrets.is_empty();
+        let _permit = self.admission.acquire(ExecutionStage::Run, &tx).await?;
+        run.set_phase(RunPhase::Running);
+        let pool_spec = PoolSpec {
+            image_tag: image_tag.clone(),
+            sandbox: self.sandbox.clone(),
             timeout,
             mem_limit_mb,
             cpu_limit,
-        );
-        self.client
-            .create_container(Some(create_opt), create_body)
-            .await?;
-
-        // Attach to container and setup capturing of logs/output
-        let attach_options = AttachContainerOptionsBuilder::new()
-            .stream(true)
-            .stdin(true)
-            .stdout(true)
-            .stderr(true)
-            .logs(true)
-            .build();
-        let mut container = self
-            .client
-            .attach_container(&run_id, Some(attach_options.clone()))
-            .await?;
-        let container_output =
-            tokio::spawn(helpers::output_task(container.output, timeout, tx.clone()));
-
-        // Start container and write input to stdin
-        log::send_info(&tx, format!("Starting container with '{command}'...")).await;
-        self.client.start_container(&run_id, None).await?;
-
-        log::send_info(&tx, "Writing input to container".into()).await;
-        if let Err(err) = container.input.write_all(input.as_bytes()).await {
-            log::send_info(&tx, format!("Failed to write input: {err}")).await;
+        };
+        if warm_pool && self.pool.take(fn_name, &pool_spec, run_id).await {
+            log::send_info(&tx, "Using warm container from pool".into()).await;
+        } else {
+            log::send_info(
+                &tx,
+                "No warm container in pool, creating container...".into(),
+            )
+            .await;
+            let mut env = secrets.clone();
+            if let Some(egress) = &self.egress {
+                let allowlist = egress.allowlist();
+                log::send_info(&tx, format!("Enabling network egress with '{allowlist}'")).await;
+                env.extend(egress.env());
+            }
+            let spec = SandboxSpec {
+                id: run_id.to_owned(),
+                image: image_tag.clone(),
+                command: command.clone(),
+                env,
+                stdin: true,
+                timeout,
+                mem_limit_mb,
+                cpu_limit,
+                network: self
+                    .egress
+                    .as_ref()
+                    .map(|egress| egress.network().to_owned()),
+                profile: self.sandbox.clone(),
+                ..Default::default()
+            };
+            self.backend.create(spec).await?;
         }
-        if let Err(err) = container.input.shutdown().await {
-            log::send_info(&tx, format!("Failed to flush input: {err}")).await;
+        if warm_pool {
+            self.pool.replenish(fn_name, &command, pool_spec);
         }
-        drop(container.input);
-
-        // Wait for container to exit, then get exit status and final stdout and stderr
-        let container_exit_result = tokio::time::timeout(
-            Duration::from_secs(timeout.into()),
-            self.client.wait_container(&run_id, None).next(),
-        )
-        .await;
-        let (stdout, stderr) = container_output.await.unwrap_or_default();
-        let (timeout, exit_code) = helpers::process_exit_status(container_exit_result);
-        let result_chunk = CodeRunnerChunk::Result {
-            stdout: stdout.clone(),
-            stderr: stderr.clone(),
+
+        // Run the container, then collect artifacts and the JSON result, and send the result
+        log::send_info(&tx, format!("Starting container with '{command}'...")).await;
+        let redactor = helpers::Redactor::new(secrets.values());
+        let sandbox_run = SandboxRun {
+            id: run_id,
             timeout,
-            exit_code,
+            max_output_bytes,
+            stdin: Some(stdin.as_bytes()),
+            redactor: &redactor,
         };
-        tx.send(result_chunk).await.ok();
+        let mut result = backend::run_sandbox(&self.backend, sandbox_run, &tx).await?;
+        let result_file = (io == FunctionIo::Json).then_some(FN_RESULT_FILE);
+        let json_result = self
+            .artifacts
+            .collect(&self.backend, run_id, result_file, &redactor, &tx)
+            .await;
+        if let Some(content) = json_result
+            && let Err(err) = set_json_result(&mut result, &content)
+        {
+            log::send_info(&tx, format!("Failed to parse the function's result: {err}")).await;
+        }
+        if let (Some(validator), Some(output)) = (&schemas.output, &result.output)
+            && let Err(errors) = validators::validate_json_value(validator, output)
+        {
+            result.error = Some(FunctionError {
+                error_type: OUTPUT_SCHEMA_ERROR.to_owned(),
+                message: format!("Output doesn't match the output schema: {errors}"),
+            });
+        }
+        tx.send(CodeRunnerChunk::Result(result.clone())).await.ok();
 
-        Ok(CodeRunnerFunctionResult {
-            input,
-            stdout,
-            stderr,
-            exit_code,
-            timeout,
-        })
+        Ok(CodeRunnerFunctionResult { input, result })
+    }
+}
+
+/// Result file written by the function harness in JSON mode
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum JsonResult {
+    /// Return value of the function
+    Output(serde_json::Value),
+    /// Error thrown by the function
+    Error(FunctionError),
+}
+
+/// Parse the result file of a function in JSON mode, and set the output or error
+fn set_json_result(result: &mut CodeRunnerResult, content: &[u8]) -> serde_json::Result<()> {
+    match serde_json::from_slice(content)? {
+        JsonResult::Output(output) => result.output = Some(output),
+        JsonResult::Error(error) => result.error = Some(error),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result() -> CodeRunnerResult {
+        CodeRunnerResult {
+            exit_code: Some(0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_json_result() {
+        let mut output = result();
+        set_json_result(&mut output, br#"{"output": {"greeting": "Hello"}}"#).unwrap();
+        assert_eq!(
+            output.output,
+            Some(serde_json::json!({ "greeting": "Hello" }))
+        );
+        assert_eq!(output.error, None);
+
+        let mut error = result();
+        let content = br#"{"error": {"type": "ValueError", "message": "bad input"}}"#;
+        set_json_result(&mut error, content).unwrap();
+        assert_eq!(error.output, None);
+        let error = error.error.unwrap();
+        assert_eq!(
+            (error.error_type.as_str(), error.message.as_str()),
+            ("ValueError", "bad input")
+        );
+
+        assert!(set_json_result(&mut result(), b"not json").is_err());
+        assert!(set_json_result(&mut result(), br#"{"unknown": 1}"#).is_err());
     }
 }
@@ -1,53 +1,61 @@
-use std::time::Duration;
+use std::{pin::Pin, sync::Arc, time::Duration};
 
-use bollard::container::LogOutput;
 use futures::{Stream, StreamExt};
-use tinirun_models::CodeRunnerChunk;
-use tokio::sync::mpsc;
+use tinirun_models::{CodeRunnerChunk, CodeRunnerResult, ResourceUsage, TerminationReason};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::mpsc,
+};
 
-use crate::runner::helpers::log;
+use crate::runner::{backend::SandboxOutput, helpers::log};
 
-/// Maximum number of bytes accumulated for stdout or stderr.
-/// Output beyond this limit is silently dropped to prevent memory exhaustion.
-const MAX_OUTPUT_BYTES: usize = 1024 * 1024; // 1 MB
 /// Grace period for the container to start
 const GRACE_PERIOD_SECS: u32 = 5;
 
-/// Attach to the Docker container output and send stdout/stderr logs back
+/// Accumulated stdout and stderr of the container
+#[derive(Debug, Default)]
+pub struct CapturedOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub stdout_truncated: bool,
+    pub stderr_truncated: bool,
+}
+
+/// Attach to the sandbox output and send stdout/stderr logs back
 /// to the client, while also returning the accumulated output at the
-/// end of execution.
+/// end of execution. Output beyond `max_output_bytes` is still streamed,
+/// but isn't accumulated to prevent memory exhaustion.
 pub async fn output_task(
-    mut output_stream: impl Stream<Item = Result<LogOutput, bollard::errors::Error>> + Unpin,
+    mut output_stream: impl Stream<Item = std::io::Result<SandboxOutput>> + Unpin,
     timeout: u32,
+    max_output_bytes: u32,
+    redactor: Redactor,
     tx: mpsc::Sender<CodeRunnerChunk>,
-) -> (String, String) {
-    let mut stdout = String::new();
-    let mut stderr = String::new();
+) -> CapturedOutput {
+    let mut stdout = OutputBuffer::new(
+        max_output_bytes,
+        CodeRunnerChunk::Stdout,
+        CodeRunnerChunk::StdoutBinary,
+    );
+    let mut stderr = OutputBuffer::new(
+        max_output_bytes,
+        CodeRunnerChunk::Stderr,
+        CodeRunnerChunk::StderrBinary,
+    );
     let timeout_duration = Duration::from_secs((timeout + GRACE_PERIOD_SECS).into());
 
     let _ = tokio::time::timeout(timeout_duration, async {
         while let Some(output_result) = output_stream.next().await {
             match output_result {
                 Ok(output) => match output {
-                    LogOutput::StdOut { message } => {
-                        if let Ok(message_str) = String::from_utf8(message.into()) {
-                            if stdout.len() < MAX_OUTPUT_BYTES {
-                                stdout.push_str(&message_str);
-                                stdout.push('\n');
-                            }
-                            tx.send(CodeRunnerChunk::Stdout(message_str)).await.ok();
-                        }
+                    SandboxOutput::Stdout(frame) => {
+                        let message = redactor.redact_frame(&mut stdout.held_back, &frame);
+                        stdout.process(&message, &tx).await;
                     }
-                    LogOutput::StdErr { message } => {
-                        if let Ok(message_str) = String::from_utf8(message.into()) {
-                            if stderr.len() < MAX_OUTPUT_BYTES {
-                                stderr.push_str(&message_str);
-                                stderr.push('\n');
-                            }
-                            tx.send(CodeRunnerChunk::Stderr(message_str)).await.ok();
-                        }
+                    SandboxOutput::Stderr(frame) => {
+                        let message = redactor.redact_frame(&mut stderr.held_back, &frame);
+                        stderr.process(&message, &tx).await;
                     }
-                    _ => {}
                 },
                 Err(e) => {
                     let message = format!("Error while processing output: {e}");
@@ -58,5 +66,420 @@ pub async fn output_task(
     })
     .await;
 
-    (stdout, stderr)
+    // Send the output held back for redaction, and any incomplete UTF-8 sequence
+    // left at the end of the output
+    for buffer in [&mut stdout, &mut stderr] {
+        let held_back = redactor.redact(&std::mem::take(&mut buffer.held_back));
+        buffer.process(&held_back, &tx).await;
+        if let Some(bytes) = buffer.decoder.finish() {
+            tx.send((buffer.binary_chunk)(bytes)).await.ok();
+        }
+    }
+
+    CapturedOutput {
+        stdout: stdout.bytes,
+        stderr: stderr.bytes,
+        stdout_truncated: stdout.truncated,
+        stderr_truncated: stderr.truncated,
+    }
+}
+
+/// Write the input to the stdin of the attached sandbox, and close stdin
+/// so that the program receives EOF.
+pub async fn write_stdin(
+    mut stdin: Pin<Box<dyn AsyncWrite + Send>>,
+    input: &[u8],
+    tx: &mpsc::Sender<CodeRunnerChunk>,
+) {
+    log::send_info(tx, "Writing input to container".into()).await;
+    if let Err(err) = stdin.write_all(input).await {
+        log::send_info(tx, format!("Failed to write input: {err}")).await;
+    }
+    if let Err(err) = stdin.shutdown().await {
+        log::send_info(tx, format!("Failed to flush input: {err}")).await;
+    }
+}
+
+impl CapturedOutput {
+    /// Create the execution result with the captured output
+    pub fn into_result(
+        self,
+        termination: TerminationReason,
+        exit_code: Option<i64>,
+        usage: ResourceUsage,
+    ) -> CodeRunnerResult {
+        let (stdout, stdout_binary) = output_to_string(self.stdout);
+        let (stderr, stderr_binary) = output_to_string(self.stderr);
+        CodeRunnerResult {
+            stdout,
+            stderr,
+            stdout_binary,
+            stderr_binary,
+            stdout_truncated: self.stdout_truncated,
+            stderr_truncated: self.stderr_truncated,
+            exit_code,
+            timeout: termination.is_timeout(),
+            termination,
+            usage,
+            output: None,
+            error: None,
+        }
+    }
+}
+
+/// Convert the output to a string, also returning the exact bytes if the output isn't valid UTF-8
+fn output_to_string(bytes: Vec<u8>) -> (String, Option<Vec<u8>>) {
+    match String::from_utf8(bytes) {
+        Ok(text) => (text, None),
+        Err(err) => {
+            let text = String::from_utf8_lossy(err.as_bytes()).into_owned();
+            (text, Some(err.into_bytes()))
+        }
+    }
+}
+
+/// Replaces secret values in the output. Clones share the secrets.
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    secrets: Arc<[Vec<u8>]>,
+}
+
+impl Redactor {
+    const REDACTED: &[u8] = b"[REDACTED]";
+
+    pub fn new<'a>(secrets: impl IntoIterator<Item = &'a String>) -> Self {
+        let mut secrets: Vec<_> = secrets
+            .into_iter()
+            .filter(|secret| !secret.is_empty())
+            .map(|secret| secret.as_bytes().to_vec())
+            .collect();
+        // Replace longer secrets first, in case a secret contains another one
+        secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+        Self {
+            secrets: secrets.into(),
+        }
+    }
+
+    /// Redact the secrets in the content
+    pub fn redact(&self, content: &[u8]) -> Vec<u8> {
+        let (output, rest) = self.redact_until_partial(content, false);
+        debug_assert!(rest.is_empty());
+        output
+    }
+
+    /// Redact the secrets in the next frame of an output stream. The end of the frame
+    /// that could be the start of a secret is held back in `held_back`, and redacted
+    /// along with the next frame, so that secrets split across frames are redacted.
+    /// The bytes still held back at the end of the stream must be passed to [`Self::redact`].
+    pub fn redact_frame(&self, held_back: &mut Vec<u8>, frame: &[u8]) -> Vec<u8> {
+        held_back.extend_from_slice(frame);
+        let (output, rest) = self.redact_until_partial(held_back, true);
+        *held_back = rest.to_vec();
+        output
+    }
+
+    /// Redact the secrets in the strings of the JSON value, including object keys, so
+    /// that the value stays valid JSON
+    pub fn redact_json(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::String(string) => *string = self.redact_str(string),
+            serde_json::Value::Array(values) => {
+                values.iter_mut().for_each(|value| self.redact_json(value));
+            }
+            serde_json::Value::Object(map) => {
+                *map = std::mem::take(map)
+                    .into_iter()
+                    .map(|(key, mut value)| {
+                        self.redact_json(&mut value);
+                        (self.redact_str(&key), value)
+                    })
+                    .collect();
+            }
+            _ => {}
+        }
+    }
+
+    /// Redact the secrets in the string. Secrets are valid UTF-8, so the redacted
+    /// string is as well.
+    fn redact_str(&self, string: &str) -> String {
+        String::from_utf8_lossy(&self.redact(string.as_bytes())).into_owned()
+    }
+
+    /// Replace the secrets in the bytes. If `stop_at_partial` is set, stops at the first
+    /// position where the rest of the bytes is the start of a secret, and returns the rest.
+    fn redact_until_partial<'a>(
+        &self,
+        bytes: &'a [u8],
+        stop_at_partial: bool,
+    ) -> (Vec<u8>, &'a [u8]) {
+        let mut output = Vec::with_capacity(bytes.len());
+        let mut idx = 0;
+        while idx < bytes.len() {
+            let rest = &bytes[idx..];
+            let is_partial = |s: &Vec<u8>| s.len() > rest.len() && s.starts_with(rest);
+            if stop_at_partial && self.secrets.iter().any(is_partial) {
+                return (output, rest);
+            }
+            // Secrets are sorted by length, so that the longest matching secret is replaced
+            if let Some(secret) = self.secrets.iter().find(|s| rest.starts_with(s)) {
+                output.extend_from_slice(Self::REDACTED);
+                idx += secret.len();
+            } else {
+                output.push(bytes[idx]);
+                idx += 1;
+            }
+        }
+        (output, &[])
+    }
+}
+
+struct OutputBuffer {
+    bytes: Vec<u8>,
+    truncated: bool,
+    max_bytes: usize,
+    decoder: Utf8Decoder,
+    /// End of the output that could be the start of a secret, held back until the next frame
+    held_back: Vec<u8>,
+    text_chunk: fn(String) -> CodeRunnerChunk,
+    binary_chunk: fn(Vec<u8>) -> CodeRunnerChunk,
+}
+
+impl OutputBuffer {
+    fn new(
+        max_bytes: u32,
+        text_chunk: fn(String) -> CodeRunnerChunk,
+        binary_chunk: fn(Vec<u8>) -> CodeRunnerChunk,
+    ) -> Self {
+        Self {
+            bytes: Vec::new(),
+            truncated: false,
+            max_bytes: max_bytes as usize,
+            decoder: Utf8Decoder::default(),
+            held_back: Vec::new(),
+            text_chunk,
+            binary_chunk,
+        }
+    }
+
+    /// Accumulate the (redacted) output, and send it as text, or as raw bytes if it
+    /// isn't valid UTF-8
+    async fn process(&mut self, output: &[u8], tx: &mpsc::Sender<CodeRunnerChunk>) {
+        self.push(output);
+        if let Some(decoded) = self.decoder.decode(output) {
+            let chunk = match decoded {
+                Ok(text) => (self.text_chunk)(text),
+                Err(bytes) => (self.binary_chunk)(bytes),
+            };
+            tx.send(chunk).await.ok();
+        }
+    }
+
+    fn push(&mut self, frame: &[u8]) {
+        let remaining = self.max_bytes.saturating_sub(self.bytes.len());
+        self.bytes
+            .extend_from_slice(&frame[..frame.len().min(remaining)]);
+        if frame.len() > remaining && !self.truncated {
+            // Don't keep part of a character that was cut off, so that text output
+            // isn't mistaken for binary output
+            self.truncated = true;
+            self.bytes.truncate(complete_chars_len(&self.bytes));
+        }
+    }
+}
+
+/// Length of the bytes without a UTF-8 character that is cut off at the end
+fn complete_chars_len(bytes: &[u8]) -> usize {
+    let tail_start = bytes.len().saturating_sub(3);
+    let Some(idx) = (tail_start..bytes.len())
+        .rev()
+        .find(|&idx| bytes[idx] & 0xC0 != 0x80)
+    else {
+        return bytes.len();
+    };
+    let char_len = match bytes[idx] {
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => 1,
+    };
+    if bytes.len() - idx < char_len {
+        idx
+    } else {
+        bytes.len()
+    }
+}
+
+/// Decodes output frames as UTF-8 text. A character split across frames is held back
+/// until the next frame, and frames that aren't valid UTF-8 are returned as raw bytes.
+#[derive(Debug, Default)]
+struct Utf8Decoder {
+    pending: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    /// Decode the next frame, returning the text, or the raw bytes if they aren't valid UTF-8
+    fn decode(&mut self, frame: &[u8]) -> Option<Result<String, Vec<u8>>> {
+        self.pending.extend_from_slice(frame);
+        let decoded = match std::str::from_utf8(&self.pending) {
+            Ok(_) => {
+                String::from_utf8(std::mem::take(&mut self.pending)).map_err(|e| e.into_bytes())
+            }
+            // Incomplete character at the end: keep it for the next frame
+            Err(err) if err.error_len().is_none() => {
+                let incomplete = self.pending.split_off(err.valid_up_to());
+                let valid = std::mem::replace(&mut self.pending, incomplete);
+                String::from_utf8(valid).map_err(|e| e.into_bytes())
+            }
+            Err(_) => Err(std::mem::take(&mut self.pending)),
+        };
+
+        match decoded {
+            Ok(text) if text.is_empty() => None,
+            decoded => Some(decoded),
+        }
+    }
+
+    /// Return the remaining bytes of an incomplete character
+    fn finish(&mut self) -> Option<Vec<u8>> {
+        Some(std::mem::take(&mut self.pending)).filter(|bytes| !bytes.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stdout_buffer(max_bytes: u32) -> OutputBuffer {
+        OutputBuffer::new(
+            max_bytes,
+            CodeRunnerChunk::Stdout,
+            CodeRunnerChunk::StdoutBinary,
+        )
+    }
+
+    #[test]
+    fn truncates_output_at_char_boundary() {
+        let mut buffer = stdout_buffer(5);
+        buffer.push("abcd€".as_bytes());
+        assert!(buffer.truncated);
+        assert_eq!(output_to_string(buffer.bytes), ("abcd".into(), None));
+
+        // Character split across frames at the cutoff
+        let mut buffer = stdout_buffer(6);
+        buffer.push("ab".as_bytes());
+        buffer.push("cdé".as_bytes());
+        buffer.push("ü".as_bytes());
+        assert!(buffer.truncated);
+        assert_eq!(output_to_string(buffer.bytes), ("abcdé".into(), None));
+
+        let mut buffer = stdout_buffer(5);
+        buffer.push("ab😀cd".as_bytes());
+        assert_eq!(output_to_string(buffer.bytes), ("ab".into(), None));
+
+        // Binary output stays binary
+        let mut buffer = stdout_buffer(3);
+        buffer.push(&[0xff, 0xfe, 0xfd, 0xfc]);
+        assert_eq!(buffer.bytes, [0xff, 0xfe, 0xfd]);
+    }
+
+    #[test]
+    fn decodes_text() {
+        let mut decoder = Utf8Decoder::default();
+        assert_eq!(decoder.decode(b"hello"), Some(Ok("hello".into())));
+        assert_eq!(decoder.finish(), None);
+    }
+
+    #[test]
+    fn joins_character_split_across_frames() {
+        let mut decoder = Utf8Decoder::default();
+        let bytes = "aé".as_bytes();
+        assert_eq!(decoder.decode(&bytes[..2]), Some(Ok("a".into())));
+        assert_eq!(decoder.decode(&bytes[2..]), Some(Ok("é".into())));
+        assert_eq!(decoder.finish(), None);
+    }
+
+    #[test]
+    fn returns_invalid_utf8_as_bytes() {
+        let mut decoder = Utf8Decoder::default();
+        let png_header = [0x89, b'P', b'N', b'G', 0x0d, 0x0a];
+        assert_eq!(decoder.decode(&png_header), Some(Err(png_header.to_vec())));
+        assert_eq!(decoder.decode(b"ok"), Some(Ok("ok".into())));
+    }
+
+    #[test]
+    fn returns_incomplete_character_on_finish() {
+        let mut decoder = Utf8Decoder::default();
+        assert_eq!(decoder.decode(&[0xc3]), None);
+        assert_eq!(decoder.finish(), Some(vec![0xc3]));
+    }
+
+    #[test]
+    fn redacts_secrets() {
+        let secrets = ["hunter2".to_owned(), "hunter".to_owned(), String::new()];
+        let redactor = Redactor::new(&secrets);
+        assert_eq!(
+            redactor.redact(b"token=hunter2, other=hunter"),
+            b"token=[REDACTED], other=[REDACTED]"
+        );
+        assert_eq!(Redactor::default().redact(b"hunter2"), b"hunter2");
+    }
+
+    #[test]
+    fn redacts_secrets_in_json() {
+        let redactor = Redactor::new(&["hunter2".to_owned()]);
+        let mut value = serde_json::json!({
+            "token": "hunter2",
+            "hunter2": [1, "a hunter2 b", { "nested": "hunter2" }],
+        });
+        redactor.redact_json(&mut value);
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "token": "[REDACTED]",
+                "[REDACTED]": [1, "a [REDACTED] b", { "nested": "[REDACTED]" }],
+            })
+        );
+    }
+
+    #[test]
+    fn redacts_secrets_split_across_frames() {
+        let secrets = ["hunter2".to_owned(), "hunter".to_owned()];
+        let redactor = Redactor::new(&secrets);
+        let mut held_back = Vec::new();
+        let mut output = Vec::new();
+        for frame in [&b"tok"[..], b"en=hun", b"ter2!", b" hu", b"ll", b" hunter"] {
+            output.extend(redactor.redact_frame(&mut held_back, frame));
+        }
+        assert_eq!(held_back, b"hunter");
+        output.extend(redactor.redact(&held_back));
+        assert_eq!(output, b"token=[REDACTED]! hull [REDACTED]");
+    }
+
+    #[tokio::test]
+    async fn redacts_output_stream() {
+        let redactor = Redactor::new(&["hunter2".to_owned()]);
+        let frames = ["pass", "word=hun", "ter", "2\n", "hunte"]
+            .map(|frame| Ok(SandboxOutput::Stdout(frame.as_bytes().to_vec())));
+        let (tx, mut rx) = mpsc::channel(10);
+        let output = output_task(futures::stream::iter(frames), 10, 100, redactor, tx).await;
+        assert_eq!(output.stdout, b"password=[REDACTED]\nhunte");
+
+        let mut streamed = String::new();
+        while let Some(chunk) = rx.recv().await {
+            if let CodeRunnerChunk::Stdout(text) = chunk {
+                streamed.push_str(&text);
+            }
+        }
+        assert_eq!(streamed, "password=[REDACTED]\nhunte");
+    }
+
+    #[test]
+    fn truncates_output_at_limit() {
+        let mut buffer = stdout_buffer(4);
+        buffer.push(b"abc");
+        assert!(!buffer.truncated);
+        buffer.push(b"def");
+        assert_eq!(buffer.bytes, b"abcd");
+        assert!(buffer.truncated);
+    }
 }
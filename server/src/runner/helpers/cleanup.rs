@@ -1,29 +1,51 @@
-use std::time::Duration;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
 use bollard::{
     Docker,
     query_parameters::{
         ListImagesOptionsBuilder, PruneContainersOptionsBuilder, PruneImagesOptionsBuilder,
-        RemoveContainerOptionsBuilder, RemoveImageOptions,
+        PruneVolumesOptionsBuilder, RemoveContainerOptionsBuilder, RemoveImageOptions,
     },
 };
+use fred::prelude::FredResult;
 
-use crate::runner::constants::{APP_LABEL, EXEC_LABEL, FN_LABEL};
+use crate::{
+    redis::{FunctionStatus, RedisClient},
+    runner::{
+        constants::{APP_LABEL, CACHE_LABEL, EXEC_LABEL, FN_LABEL, POOL_LABEL},
+        functions::FunctionExecutor,
+    },
+};
 
 /// Cleanup Docker resources associated with a code execution run.
 pub async fn run_cleanup(docker: &Docker, run_id: &str) {
     // Stop the container, ignoring errors in case it wasn't started or is already stopped
     let _ = docker.stop_container(run_id, None).await;
 
-    // Remove the container
-    let opt = RemoveContainerOptionsBuilder::new().force(true).build();
+    // Remove the container and its anonymous volumes
+    let opt = RemoveContainerOptionsBuilder::new()
+        .force(true)
+        .v(true)
+        .build();
     if let Err(err) = docker.remove_container(run_id, Some(opt)).await {
         tracing::info!("Could not remove container '{run_id}': {err}");
     }
 }
 
 /// Task to periodically clean up Docker images and containers created by code execution runs.
-pub async fn image_cleanup_task(docker: Docker, period: Duration) {
+/// Cached dependency images are evicted in least-recently-used order once there are more than `cache_size`.
+/// Function images are kept for the newest `fn_version_retention` versions of each function,
+/// and for the versions that its aliases point to.
+pub async fn image_cleanup_task(
+    docker: Docker,
+    redis: RedisClient,
+    period: Duration,
+    cache_size: usize,
+    fn_version_retention: usize,
+) {
     let mut interval = tokio::time::interval(period);
     loop {
         interval.tick().await;
@@ -31,9 +53,14 @@ pub async fn image_cleanup_task(docker: Docker, period: Duration) {
         // Clean up resources created **before** the specified duration
         let until = format!("{}s", period.as_secs());
 
-        // Prune any stopped containers that are lying around
+        // Prune any stopped containers that are lying around (except pre-created pooled containers)
+        let prune_filters = [
+            ("label", vec![APP_LABEL]),
+            ("label!", vec![POOL_LABEL]),
+            ("until", vec![&until]),
+        ];
         let prune_container_opt = PruneContainersOptionsBuilder::new()
-            .filters(&[("label", vec![APP_LABEL]), ("until", vec![&until])].into())
+            .filters(&prune_filters.into())
             .build();
         match docker.prune_containers(Some(prune_container_opt)).await {
             Ok(res) => {
@@ -47,6 +74,14 @@ pub async fn image_cleanup_task(docker: Docker, period: Duration) {
             Err(err) => tracing::warn!("Failed to prune containers: {err}"),
         }
 
+        // Prune any volumes left behind by removed containers
+        let prune_volume_opt = PruneVolumesOptionsBuilder::new()
+            .filters(&[("label", vec![APP_LABEL])].into())
+            .build();
+        if let Err(err) = docker.prune_volumes(Some(prune_volume_opt)).await {
+            tracing::warn!("Failed to prune volumes: {err}");
+        }
+
         // Keep track of number of images pruned
         let mut num_pruned = 0;
 
@@ -68,29 +103,152 @@ pub async fn image_cleanup_task(docker: Docker, period: Duration) {
             Err(err) => tracing::warn!("Failed to prune execution images: {err}"),
         }
 
-        // Prune old function images (any not tagged as 'latest')
-        let list_image_opt = ListImagesOptionsBuilder::new()
-            .filters(&[("label", vec![FN_LABEL]), ("until", vec![&until])].into())
-            .build();
-        let Ok(fn_images) = docker.list_images(Some(list_image_opt)).await else {
-            tracing::warn!("Failed to list function images");
+        // Evict least recently used dependency images
+        num_pruned += evict_cached_images(&docker, &redis, cache_size).await;
+
+        // Prune images of function versions that are no longer kept
+        num_pruned += prune_function_images(&docker, &redis, &until, fn_version_retention).await;
+
+        if num_pruned > 0 {
+            tracing::info!("Pruned {num_pruned} images");
+        }
+    }
+}
+
+/// Remove the least recently used dependency images beyond the cache size. Images without
+/// a recorded last use fall back to their creation time. Returns the number of images removed.
+async fn evict_cached_images(docker: &Docker, redis: &RedisClient, cache_size: usize) -> usize {
+    let list_image_opt = ListImagesOptionsBuilder::new()
+        .filters(&[("label", vec![CACHE_LABEL])].into())
+        .build();
+    let cached_images = match docker.list_images(Some(list_image_opt)).await {
+        Ok(images) => images,
+        Err(err) => {
+            tracing::warn!("Failed to list cached images: {err}");
+            return 0;
+        }
+    };
+    let last_used = redis.get_cached_image_usage().await.unwrap_or_else(|err| {
+        tracing::warn!("Failed to get cached image usage from Redis: {err}");
+        Default::default()
+    });
+
+    // Images of one-off executions inherit the label from the cached image, so are skipped here
+    let mut images_by_use: Vec<_> = cached_images
+        .into_iter()
+        .filter(|image| !image.labels.contains_key(EXEC_LABEL))
+        .filter_map(|image| {
+            let tag = image.repo_tags.into_iter().next()?;
+            let used_at = last_used.get(&tag).copied().unwrap_or(image.created);
+            Some((used_at, tag))
+        })
+        .collect();
+    images_by_use.sort_unstable();
+
+    let mut num_evicted = 0;
+    let num_to_evict = images_by_use.len().saturating_sub(cache_size);
+    for (_, tag) in images_by_use.into_iter().take(num_to_evict) {
+        match docker
+            .remove_image(&tag, None::<RemoveImageOptions>, None)
+            .await
+        {
+            Ok(deleted) => {
+                num_evicted += deleted.len();
+                if let Err(err) = redis.remove_cached_image(&tag).await {
+                    tracing::warn!("Failed to remove cached image '{tag}' from Redis: {err}");
+                }
+            }
+            Err(err) => tracing::warn!("Failed to evict cached image '{tag}': {err}"),
+        }
+    }
+
+    num_evicted
+}
+
+/// Remove the tags of function images that aren't used by the current version, one of the
+/// newest `retention` versions or an alias of the function, and remove images without tags.
+/// Returns the number of images removed.
+async fn prune_function_images(
+    docker: &Docker,
+    redis: &RedisClient,
+    until: &str,
+    retention: usize,
+) -> usize {
+    let list_image_opt = ListImagesOptionsBuilder::new()
+        .filters(&[("label", vec![FN_LABEL]), ("until", vec![until])].into())
+        .build();
+    let fn_images = match docker.list_images(Some(list_image_opt)).await {
+        Ok(images) => images,
+        Err(err) => {
+            tracing::warn!("Failed to list function images: {err}");
+            return 0;
+        }
+    };
+
+    let mut num_pruned = 0;
+    let mut kept_tags_by_fn: HashMap<String, HashSet<String>> = HashMap::new();
+    for image in fn_images {
+        let Some(fn_name) = image.labels.get(FN_LABEL) else {
             continue;
         };
-        for old_image in fn_images
-            .into_iter()
-            .filter(|image| image.repo_tags.iter().all(|tag| !tag.ends_with(":latest")))
-        {
+        if !kept_tags_by_fn.contains_key(fn_name) {
+            match kept_fn_tags(redis, fn_name, retention).await {
+                Ok(tags) => kept_tags_by_fn.insert(fn_name.to_owned(), tags),
+                Err(err) => {
+                    tracing::warn!("Failed to get versions of '{fn_name}' function: {err}");
+                    continue;
+                }
+            };
+        }
+        let kept_tags = &kept_tags_by_fn[fn_name];
+
+        let to_remove: Vec<_> = match image.repo_tags.is_empty() {
+            true => vec![&image.id],
+            false => image
+                .repo_tags
+                .iter()
+                .filter(|tag| !kept_tags.contains(*tag))
+                .collect(),
+        };
+        for image_ref in to_remove {
             match docker
-                .remove_image(&old_image.id, None::<RemoveImageOptions>, None)
+                .remove_image(image_ref, None::<RemoveImageOptions>, None)
                 .await
             {
                 Ok(deleted) => num_pruned += deleted.len(),
                 Err(err) => tracing::warn!("Failed to prune old function image: {err}"),
             }
         }
+    }
 
-        if num_pruned > 0 {
-            tracing::info!("Pruned {num_pruned} images");
-        }
+    num_pruned
+}
+
+/// Get the image tags of the function's current version, its newest versions and the
+/// versions that its aliases point to
+async fn kept_fn_tags(
+    redis: &RedisClient,
+    fn_name: &str,
+    retention: usize,
+) -> FredResult<HashSet<String>> {
+    let mut tags: HashSet<_> = redis
+        .get_fn_versions(fn_name)
+        .await?
+        .into_iter()
+        .take(retention)
+        .map(|version| FunctionExecutor::fn_tag(fn_name, version.version))
+        .collect();
+    for alias in redis.get_fn_aliases(fn_name).await?.values() {
+        tags.extend(
+            alias
+                .versions()
+                .map(|v| FunctionExecutor::fn_tag(fn_name, v)),
+        );
+    }
+    if let Some(info) = redis.get_fn_info(fn_name).await?
+        && let FunctionStatus::Ready { tag, .. } = info.status
+    {
+        tags.insert(tag);
     }
+    Ok(tags)
 }
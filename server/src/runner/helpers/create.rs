@@ -1,9 +1,14 @@
+use std::collections::HashMap;
+
 use bollard::{
-    models::{ContainerCreateBody, HostConfig, HostConfigCgroupnsModeEnum, ResourcesUlimits},
+    models::{
+        ContainerCreateBody, HostConfig, HostConfigCgroupnsModeEnum, Mount, MountTypeEnum,
+        MountVolumeOptions, MountVolumeOptionsDriverConfig, ResourcesUlimits,
+    },
     query_parameters::{CreateContainerOptions, CreateContainerOptionsBuilder},
 };
 
-use crate::runner::constants::ID_LABEL;
+use crate::runner::constants::{APP_LABEL, ID_LABEL, OUTPUT_DIR};
 
 /// Setup container creation for code execution. Attempts to isolate the
 /// container as much as possible:
@@ -54,3 +59,85 @@ pub fn setup_container(
 
     (container_body, container_options)
 }
+
+/// Append command-line arguments to the command of the container, and add environment
+/// variables. The arguments are passed to the shell as positional parameters, so
+/// they are not interpreted by the shell.
+pub fn add_args_and_env(
+    container_body: &mut ContainerCreateBody,
+    args: &[String],
+    env: &HashMap<String, String>,
+) {
+    if let Some(cmd) = container_body.cmd.as_mut().filter(|_| !args.is_empty()) {
+        if let Some(command) = cmd.last_mut() {
+            command.push_str(r#" "$@""#);
+        }
+        cmd.push("sh".into());
+        cmd.extend(args.iter().cloned());
+    }
+    container_body
+        .env
+        .get_or_insert_default()
+        .extend(env.iter().map(|(name, value)| format!("{name}={value}")));
+}
+
+/// Enable networking for the container and connect it to the given network
+pub fn connect_network(container_body: &mut ContainerCreateBody, network: &str) {
+    container_body.network_disabled = Some(false);
+    container_body
+        .host_config
+        .get_or_insert_default()
+        .network_mode = Some(network.to_owned());
+}
+
+/// Mount an anonymous volume at the given directory, so that files can be uploaded there
+/// before the container is started (the root filesystem is read-only). The volume is
+/// initialized with the image's contents at that directory, and is removed along with the container.
+pub fn mount_volume(container_body: &mut ContainerCreateBody, run_id: &str, target: &str) {
+    let volume_mount = Mount {
+        target: Some(target.to_owned()),
+        typ: Some(MountTypeEnum::VOLUME),
+        volume_options: Some(MountVolumeOptions {
+            labels: Some([(APP_LABEL.into(), run_id.into())].into()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    container_body
+        .host_config
+        .get_or_insert_default()
+        .mounts
+        .get_or_insert_default()
+        .push(volume_mount);
+}
+
+/// Mount a writable output directory that the program can write artifacts to. This is a
+/// disk-backed anonymous volume, so that its contents are still available after the
+/// container exits, and is removed along with the container. If a `quota` in bytes is
+/// given, the volume is created with a size limit, which requires the Docker data root
+/// to support quotas (e.g. XFS mounted with `pquota`).
+pub fn mount_output_dir(
+    container_body: &mut ContainerCreateBody,
+    run_id: &str,
+    quota: Option<u64>,
+) {
+    let output_mount = Mount {
+        target: Some(OUTPUT_DIR.to_owned()),
+        typ: Some(MountTypeEnum::VOLUME),
+        volume_options: Some(MountVolumeOptions {
+            labels: Some([(APP_LABEL.into(), run_id.into())].into()),
+            driver_config: quota.map(|bytes| MountVolumeOptionsDriverConfig {
+                name: Some("local".into()),
+                options: Some([("size".into(), bytes.to_string())].into()),
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    container_body
+        .host_config
+        .get_or_insert_default()
+        .mounts
+        .get_or_insert_default()
+        .push(output_mount);
+}
@@ -1,20 +1,156 @@
-use bollard::models::ContainerWaitResponse;
+use bollard::models::{ContainerState, ContainerWaitResponse};
+use tinirun_models::TerminationReason;
 use tokio::time::error::Elapsed;
 
-/// Returns a tuple containing a boolean indicating whether the exit was due to a timeout,
-/// and the exit code of the program if available.
+/// Returns the reason the program terminated, and its exit code if available. The
+/// container state (if it could be inspected) is used to detect OOM kills, and for the
+/// exit code if the wait response doesn't have it. `timed_out` is whether the container
+/// ran until the time limit, and was therefore stopped by the `timeout` wrapper.
 pub fn process_exit_status(
     exit_result: Result<Option<Result<ContainerWaitResponse, bollard::errors::Error>>, Elapsed>,
-) -> (bool, Option<i64>) {
-    let is_timeout = exit_result.is_err();
+    state: Option<&ContainerState>,
+    timed_out: bool,
+) -> (TerminationReason, Option<i64>) {
+    let Ok(exit_result) = exit_result else {
+        return (TerminationReason::ServerTimeout, None);
+    };
     let exit_code = match exit_result {
-        Ok(Some(Ok(res))) => Some(res.status_code),
-        Ok(Some(Err(err))) => match err {
-            bollard::errors::Error::DockerContainerWaitError { code, .. } => Some(code),
-            _ => None,
-        },
+        Some(Ok(res)) => Some(res.status_code),
+        Some(Err(bollard::errors::Error::DockerContainerWaitError { code, .. })) => Some(code),
         _ => None,
     };
+    let exit_code = exit_code.or_else(|| {
+        state
+            .filter(|s| s.running != Some(true))
+            .and_then(|s| s.exit_code)
+    });
+
+    let oom_killed = state.and_then(|s| s.oom_killed).unwrap_or(false);
+    (
+        termination_reason(exit_code, oom_killed, timed_out),
+        exit_code,
+    )
+}
+
+/// Returns the reason the program terminated. The OOM kill and the timeout are tracked
+/// by the backend, as the exit code of the `timeout` wrapper depends on its implementation
+/// (124 for coreutils, 143 for BusyBox) and can also be returned by the program itself.
+pub fn termination_reason(
+    exit_code: Option<i64>,
+    oom_killed: bool,
+    timed_out: bool,
+) -> TerminationReason {
+    match exit_code {
+        _ if oom_killed => TerminationReason::OomKilled,
+        _ if timed_out => TerminationReason::Timeout,
+        None => TerminationReason::Unknown,
+        // Shells and the `timeout` wrapper report a process killed by signal N with exit
+        // code 128 + N. The exit status doesn't have more details on the signal.
+        Some(code @ 129..=192) => {
+            let number = code - 128;
+            TerminationReason::Signal {
+                signal: signal_name(number),
+                number,
+            }
+        }
+        Some(_) => TerminationReason::Exited,
+    }
+}
+
+fn signal_name(number: i64) -> String {
+    let name = match number {
+        1 => "SIGHUP",
+        2 => "SIGINT",
+        3 => "SIGQUIT",
+        4 => "SIGILL",
+        5 => "SIGTRAP",
+        6 => "SIGABRT",
+        7 => "SIGBUS",
+        8 => "SIGFPE",
+        9 => "SIGKILL",
+        10 => "SIGUSR1",
+        11 => "SIGSEGV",
+        12 => "SIGUSR2",
+        13 => "SIGPIPE",
+        14 => "SIGALRM",
+        15 => "SIGTERM",
+        24 => "SIGXCPU",
+        25 => "SIGXFSZ",
+        31 => "SIGSYS",
+        _ => return format!("SIG{number}"),
+    };
+    name.to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn exited(status_code: i64) -> Result<ContainerWaitResponse, bollard::errors::Error> {
+        Ok(ContainerWaitResponse {
+            status_code,
+            error: None,
+        })
+    }
+
+    #[test]
+    fn termination_reason_from_exit_status() {
+        let reason = termination_reason;
+        assert_eq!(reason(Some(0), false, false), TerminationReason::Exited);
+        assert_eq!(reason(Some(1), false, false), TerminationReason::Exited);
+        // A program can return 124 itself, this is only a timeout if it reached the limit
+        assert_eq!(reason(Some(124), false, false), TerminationReason::Exited);
+        assert_eq!(reason(Some(124), false, true), TerminationReason::Timeout);
+        assert_eq!(reason(Some(143), false, true), TerminationReason::Timeout);
+        assert_eq!(reason(Some(137), true, false), TerminationReason::OomKilled);
+        assert_eq!(reason(Some(137), true, true), TerminationReason::OomKilled);
+        assert_eq!(
+            reason(Some(139), false, false),
+            TerminationReason::Signal {
+                signal: "SIGSEGV".into(),
+                number: 11
+            }
+        );
+        assert_eq!(reason(Some(255), false, false), TerminationReason::Exited);
+        assert_eq!(reason(None, false, false), TerminationReason::Unknown);
+    }
+
+    #[tokio::test]
+    async fn exit_status_from_container() {
+        let (reason, code) = process_exit_status(Ok(Some(exited(0))), None, false);
+        assert_eq!((reason, code), (TerminationReason::Exited, Some(0)));
+
+        let oom_state = ContainerState {
+            oom_killed: Some(true),
+            running: Some(false),
+            exit_code: Some(137),
+            ..Default::default()
+        };
+        let (reason, code) = process_exit_status(Ok(Some(exited(137))), Some(&oom_state), false);
+        assert_eq!((reason, code), (TerminationReason::OomKilled, Some(137)));
+
+        let (reason, code) = process_exit_status(Ok(Some(exited(124))), None, true);
+        assert_eq!((reason, code), (TerminationReason::Timeout, Some(124)));
+
+        let wait_error = bollard::errors::Error::DockerContainerWaitError {
+            error: String::new(),
+            code: 2,
+        };
+        let (reason, code) = process_exit_status(Ok(Some(Err(wait_error))), None, false);
+        assert_eq!((reason, code), (TerminationReason::Exited, Some(2)));
+
+        // The exit code is taken from the container state if the wait stream ended early
+        let (reason, code) = process_exit_status(Ok(None), Some(&oom_state), false);
+        assert_eq!((reason, code), (TerminationReason::OomKilled, Some(137)));
+        let (reason, code) = process_exit_status(Ok(None), None, false);
+        assert_eq!((reason, code), (TerminationReason::Unknown, None));
 
-    (is_timeout, exit_code)
+        let elapsed = tokio::time::timeout(Duration::ZERO, std::future::pending::<()>())
+            .await
+            .unwrap_err();
+        let (reason, code) = process_exit_status(Err(elapsed), Some(&oom_state), true);
+        assert_eq!((reason, code), (TerminationReason::ServerTimeout, None));
+    }
 }
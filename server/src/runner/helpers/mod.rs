@@ -6,11 +6,17 @@ mod exit;
 pub mod log;
 mod pull;
 mod sync;
+mod upload;
+mod usage;
 
-pub use attach::output_task;
+pub use attach::{Redactor, output_task, write_stdin};
 pub use build::{create_build_context, process_build_stream};
 pub use cleanup::{image_cleanup_task, run_cleanup};
-pub use create::setup_container;
-pub use exit::process_exit_status;
+pub use create::{
+    add_args_and_env, connect_network, mount_output_dir, mount_volume, setup_container,
+};
+pub use exit::{process_exit_status, termination_reason};
 pub use pull::{exists_image, pull_image};
 pub use sync::sync_function_status_task;
+pub use upload::upload_files;
+pub use usage::{inspect_state, resource_usage, stats_task};
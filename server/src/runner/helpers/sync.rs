@@ -38,7 +38,7 @@ pub async fn sync_function_status_task(docker: Docker, redis: RedisClient, perio
                 FunctionStatus::Ready { id, .. } => {
                     if !function_images.iter().any(|image| image.id == id) {
                         let _ = redis
-                            .set_fn_status(&fn_name, FunctionStatus::NotBuilt)
+                            .set_fn_status(&fn_name, fn_info.version, FunctionStatus::NotBuilt)
                             .await;
                     }
                 }
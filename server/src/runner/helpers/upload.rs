@@ -0,0 +1,22 @@
+use std::path::PathBuf;
+
+use bollard::{Docker, query_parameters::UploadToContainerOptionsBuilder};
+
+use crate::runner::helpers::create_build_context;
+
+/// Upload the files as a tar archive and extract them into the given directory
+/// of the container.
+pub async fn upload_files(
+    client: &Docker,
+    container: &str,
+    target_dir: &str,
+    files: Vec<(PathBuf, Vec<u8>)>,
+) -> Result<(), bollard::errors::Error> {
+    let archive = create_build_context(files);
+    let options = UploadToContainerOptionsBuilder::new()
+        .path(target_dir)
+        .build();
+    client
+        .upload_to_container(container, Some(options), bollard::body_try_stream(archive))
+        .await
+}
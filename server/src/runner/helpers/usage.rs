@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use bollard::{
+    Docker,
+    models::{ContainerState, ContainerStatsResponse},
+    query_parameters::StatsOptionsBuilder,
+};
+use futures::StreamExt;
+use tinirun_models::ResourceUsage;
+use tokio::sync::oneshot;
+
+/// Peak values collected from the container's stats stream
+#[derive(Debug, Default)]
+pub struct StatsSummary {
+    cpu_time_ns: Option<u64>,
+    peak_memory_bytes: Option<u64>,
+    peak_pids: Option<u64>,
+    bytes_written: Option<u64>,
+}
+
+impl StatsSummary {
+    fn update(&mut self, stats: ContainerStatsResponse) {
+        let cpu_time_ns = stats
+            .cpu_stats
+            .and_then(|cpu| cpu.cpu_usage)
+            .and_then(|usage| usage.total_usage);
+        let memory_bytes = stats
+            .memory_stats
+            .and_then(|mem| mem.max_usage.max(mem.usage));
+        let pids = stats.pids_stats.and_then(|pids| pids.current);
+        let bytes_written = stats
+            .blkio_stats
+            .and_then(|blkio| blkio.io_service_bytes_recursive)
+            .map(|entries| {
+                entries
+                    .into_iter()
+                    .filter(|e| {
+                        e.op.as_ref()
+                            .is_some_and(|op| op.eq_ignore_ascii_case("write"))
+                    })
+                    .filter_map(|e| e.value)
+                    .sum()
+            });
+
+        // Counters are cumulative, and are reset to zero once the container stops,
+        // so the maximum of each value is kept
+        self.cpu_time_ns = self.cpu_time_ns.max(cpu_time_ns);
+        self.peak_memory_bytes = self.peak_memory_bytes.max(memory_bytes);
+        self.peak_pids = self.peak_pids.max(pids);
+        self.bytes_written = self.bytes_written.max(bytes_written);
+    }
+}
+
+/// Collect stats of the running container until signalled to stop
+pub async fn stats_task(
+    client: Docker,
+    container: String,
+    stop: oneshot::Receiver<()>,
+) -> StatsSummary {
+    let mut summary = StatsSummary::default();
+    let options = StatsOptionsBuilder::new().stream(true).build();
+    let mut stats_stream = client.stats(&container, Some(options));
+
+    tokio::pin!(stop);
+    loop {
+        tokio::select! {
+            stats = stats_stream.next() => match stats {
+                Some(Ok(stats)) => summary.update(stats),
+                Some(Err(err)) => {
+                    tracing::debug!("Failed to get stats of container '{container}': {err}");
+                    break;
+                }
+                None => break,
+            },
+            _ = &mut stop => break,
+        }
+    }
+
+    summary
+}
+
+/// Get the state of the finished container
+pub async fn inspect_state(client: &Docker, container: &str) -> Option<ContainerState> {
+    match client.inspect_container(container, None).await {
+        Ok(inspect) => inspect.state,
+        Err(err) => {
+            tracing::debug!("Failed to inspect container '{container}': {err}");
+            None
+        }
+    }
+}
+
+/// Get the resource usage of the finished container. The wall-clock duration is taken from
+/// the container's start and finish times, or from the given fallback if the container
+/// is still running (e.g. after a timeout).
+pub fn resource_usage(
+    state: Option<&ContainerState>,
+    stats: StatsSummary,
+    fallback_wall_time: Duration,
+) -> ResourceUsage {
+    let wall_time = state.and_then(|state| {
+        let started = chrono::DateTime::parse_from_rfc3339(state.started_at.as_ref()?).ok()?;
+        let finished = chrono::DateTime::parse_from_rfc3339(state.finished_at.as_ref()?).ok()?;
+        (finished - started).to_std().ok()
+    });
+
+    ResourceUsage {
+        wall_time_ms: wall_time.unwrap_or(fallback_wall_time).as_millis() as u64,
+        cpu_time_ms: stats.cpu_time_ns.map(|ns| ns / 1_000_000),
+        peak_memory_bytes: stats.peak_memory_bytes,
+        peak_pids: stats.peak_pids,
+        bytes_written: stats.bytes_written,
+    }
+}
@@ -0,0 +1,344 @@
+//! Judge mode: run a program against test cases and report a verdict for each case
+
+use std::path::PathBuf;
+
+use tinirun_models::{
+    CodeRunnerChunk, CodeRunnerError, CodeRunnerInput, CodeRunnerResult, JudgeCase,
+    JudgeCaseResult, JudgeInput, JudgeSummary, JudgeVerdict, OutputComparison, TerminationReason,
+};
+use tokio::sync::mpsc;
+
+use crate::runner::{
+    backend::SandboxSpec,
+    executor::{DockerExecutor, ExecProgram, PreparedProgram},
+    helpers::log,
+    registry::{RunHandle, RunPhase},
+};
+
+/// Name of the directory with the files of the checker
+const CHECKER_FILES_DIR: &str = "files";
+
+/// Executor for judging a program against test cases. The image of the program is built
+/// once, then each case runs in a fresh container.
+pub struct JudgeExecutor {
+    pub docker: DockerExecutor,
+}
+
+impl JudgeExecutor {
+    pub fn new(docker: DockerExecutor) -> Self {
+        Self { docker }
+    }
+
+    pub async fn run(
+        &self,
+        run: &RunHandle,
+        input: JudgeInput,
+        program: ExecProgram,
+        checker: Option<ExecProgram>,
+        tx: mpsc::Sender<CodeRunnerChunk>,
+    ) -> Result<(), CodeRunnerError> {
+        let run_id = run.id();
+        let program = self.docker.prepare_program(run_id, program, &tx).await?;
+        let checker_id = checker_id(run_id);
+        let checker = match checker {
+            Some(checker) => {
+                log::send_info(&tx, "Preparing checker...".to_owned()).await;
+                Some(
+                    self.docker
+                        .prepare_program(&checker_id, checker, &tx)
+                        .await?,
+                )
+            }
+            None => None,
+        };
+        run.set_phase(RunPhase::Running);
+
+        let total = input.cases.len();
+        let mut case_results = Vec::with_capacity(total);
+        for (index, case) in input.cases.into_iter().enumerate() {
+            log::send_info(&tx, format!("Running case {}/{total}...", index + 1)).await;
+            let result = self
+                .run_case(run_id, &program, &input.program, &case, &tx)
+                .await?;
+            let (verdict, message) = match (run_verdict(&result), &checker) {
+                (Some(verdict), _) => (verdict, None),
+                (None, Some(checker)) => {
+                    self.check(&checker_id, checker, &case, &result, &tx)
+                        .await?
+                }
+                (None, None) => match compare_output(&input.compare, &result.stdout, &case) {
+                    true => (JudgeVerdict::Accepted, None),
+                    false => (JudgeVerdict::WrongAnswer, None),
+                },
+            };
+            let case_result = JudgeCaseResult {
+                index: index as u32,
+                verdict,
+                message,
+                result,
+            };
+            let _ = tx
+                .send(CodeRunnerChunk::JudgeCase(case_result.clone()))
+                .await;
+            case_results.push(case_result);
+        }
+
+        let summary = summarize(&case_results);
+        let _ = tx.send(CodeRunnerChunk::JudgeSummary(summary)).await;
+
+        Ok(())
+    }
+
+    /// Run the program with the input of the case, in a fresh container
+    async fn run_case(
+        &self,
+        run_id: &str,
+        program: &PreparedProgram,
+        input: &CodeRunnerInput,
+        case: &JudgeCase,
+        tx: &mpsc::Sender<CodeRunnerChunk>,
+    ) -> Result<CodeRunnerResult, CodeRunnerError> {
+        let spec = SandboxSpec {
+            stdin: true,
+            timeout: case.timeout.unwrap_or(input.timeout),
+            mem_limit_mb: case.mem_limit_mb.unwrap_or(input.mem_limit_mb),
+            ..program.sandbox_spec(run_id, input)
+        };
+        let stdin = Some(case.stdin.as_bytes());
+        self.docker
+            .run_isolated(spec, stdin, input.max_output_bytes, tx)
+            .await
+    }
+
+    /// Run the checker with the case's input, the expected output and the program's output.
+    /// Returns the verdict and the checker's output.
+    async fn check(
+        &self,
+        checker_id: &str,
+        checker: &PreparedProgram,
+        case: &JudgeCase,
+        result: &CodeRunnerResult,
+        tx: &mpsc::Sender<CodeRunnerChunk>,
+    ) -> Result<(JudgeVerdict, Option<String>), CodeRunnerError> {
+        let output = match &result.stdout_binary {
+            Some(bytes) => bytes.clone(),
+            None => result.stdout.clone().into_bytes(),
+        };
+        let files_dir = PathBuf::from(CHECKER_FILES_DIR);
+        let case_files = [
+            (files_dir.join("input.txt"), case.stdin.clone().into_bytes()),
+            (
+                files_dir.join("expected.txt"),
+                case.expected_stdout.clone().into_bytes(),
+            ),
+            (files_dir.join("output.txt"), output),
+        ];
+        let limits = tinirun_models::ResourceLimits::DEFAULT;
+        let spec = SandboxSpec {
+            id: checker_id.to_owned(),
+            image: checker.image.clone(),
+            command: checker.command.clone(),
+            files: checker.files.iter().cloned().chain(case_files).collect(),
+            timeout: limits.timeout,
+            mem_limit_mb: limits.mem_limit_mb,
+            cpu_limit: limits.cpu_limit,
+            profile: checker.sandbox.clone(),
+            ..Default::default()
+        };
+        let checker_result = self
+            .docker
+            .run_isolated(spec, None, limits.max_output_bytes, tx)
+            .await?;
+
+        let verdict = checker_verdict(&checker_result);
+        let message = match verdict {
+            JudgeVerdict::CheckerError => checker_result.stderr.trim(),
+            _ => checker_result.stdout.trim(),
+        };
+        let message = Some(message.to_owned()).filter(|m| !m.is_empty());
+        Ok((verdict, message))
+    }
+}
+
+/// ID of the checker's image and containers in a judge run
+pub fn checker_id(run_id: &str) -> String {
+    format!("{run_id}-checker")
+}
+
+/// Get the verdict of a run that didn't exit successfully, or `None` if the output
+/// needs to be checked
+fn run_verdict(result: &CodeRunnerResult) -> Option<JudgeVerdict> {
+    match &result.termination {
+        TerminationReason::OomKilled => Some(JudgeVerdict::MemoryLimitExceeded),
+        termination if termination.is_timeout() => Some(JudgeVerdict::TimeLimitExceeded),
+        TerminationReason::Exited if result.exit_code == Some(0) => None,
+        _ => Some(JudgeVerdict::RuntimeError),
+    }
+}
+
+/// Get the verdict from the exit code of the checker
+fn checker_verdict(result: &CodeRunnerResult) -> JudgeVerdict {
+    match (&result.termination, result.exit_code) {
+        (TerminationReason::Exited, Some(0)) => JudgeVerdict::Accepted,
+        (TerminationReason::Exited, Some(1)) => JudgeVerdict::WrongAnswer,
+        _ => JudgeVerdict::CheckerError,
+    }
+}
+
+/// Compare the output of the program to the expected output of the case
+fn compare_output(compare: &OutputComparison, output: &str, case: &JudgeCase) -> bool {
+    let expected = case.expected_stdout.as_str();
+    match compare {
+        OutputComparison::Exact => output == expected,
+        OutputComparison::Whitespace => output.split_whitespace().eq(expected.split_whitespace()),
+        OutputComparison::Float { tolerance } => {
+            let tokens: Vec<_> = output.split_whitespace().collect();
+            let expected_tokens: Vec<_> = expected.split_whitespace().collect();
+            tokens.len() == expected_tokens.len()
+                && tokens
+                    .iter()
+                    .zip(expected_tokens)
+                    .all(|(token, expected)| tokens_match(token, expected, *tolerance))
+        }
+    }
+}
+
+/// Whether the tokens are equal, or are numbers within the tolerance (absolute, or
+/// relative to the expected number)
+fn tokens_match(token: &str, expected: &str, tolerance: f64) -> bool {
+    if token == expected {
+        return true;
+    }
+    match (token.parse::<f64>(), expected.parse::<f64>()) {
+        (Ok(value), Ok(expected)) if value.is_finite() && expected.is_finite() => {
+            (value - expected).abs() <= tolerance * expected.abs().max(1.0)
+        }
+        _ => false,
+    }
+}
+
+/// Summarize the verdicts of the cases
+fn summarize(case_results: &[JudgeCaseResult]) -> JudgeSummary {
+    let verdict = case_results
+        .iter()
+        .map(|case| case.verdict)
+        .find(|verdict| *verdict != JudgeVerdict::Accepted)
+        .unwrap_or(JudgeVerdict::Accepted);
+    let passed = case_results
+        .iter()
+        .filter(|case| case.verdict == JudgeVerdict::Accepted)
+        .count();
+    let usages = case_results.iter().map(|case| &case.result.usage);
+
+    JudgeSummary {
+        verdict,
+        passed: passed as u32,
+        total: case_results.len() as u32,
+        max_wall_time_ms: usages
+            .clone()
+            .map(|u| u.wall_time_ms)
+            .max()
+            .unwrap_or_default(),
+        max_memory_bytes: usages.filter_map(|u| u.peak_memory_bytes).max(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn case(expected: &str) -> JudgeCase {
+        JudgeCase {
+            stdin: String::new(),
+            expected_stdout: expected.to_owned(),
+            timeout: None,
+            mem_limit_mb: None,
+        }
+    }
+
+    fn result(termination: TerminationReason, exit_code: Option<i64>) -> CodeRunnerResult {
+        CodeRunnerResult {
+            exit_code,
+            timeout: termination.is_timeout(),
+            termination,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn compares_exact_output() {
+        let exact = OutputComparison::Exact;
+        assert!(compare_output(&exact, "1 2\n", &case("1 2\n")));
+        assert!(!compare_output(&exact, "1 2", &case("1 2\n")));
+        assert!(!compare_output(&exact, "1  2\n", &case("1 2\n")));
+    }
+
+    #[test]
+    fn compares_output_ignoring_whitespace() {
+        let whitespace = OutputComparison::Whitespace;
+        assert!(compare_output(&whitespace, "1  2\r\n3 \n", &case("1 2\n3")));
+        assert!(!compare_output(&whitespace, "1 2", &case("1 2 3")));
+        assert!(!compare_output(&whitespace, "12", &case("1 2")));
+    }
+
+    #[test]
+    fn compares_output_with_float_tolerance() {
+        let float = OutputComparison::Float { tolerance: 1e-3 };
+        assert!(compare_output(&float, "0.3334 yes\n", &case("0.3333 yes")));
+        assert!(!compare_output(&float, "0.335 yes", &case("0.3333 yes")));
+        assert!(!compare_output(&float, "0.3333 no", &case("0.3333 yes")));
+        // Relative tolerance for large numbers
+        assert!(compare_output(&float, "1000000.5", &case("1000000")));
+        assert!(!compare_output(&float, "inf", &case("1e308")));
+        assert!(!compare_output(&float, "1.0", &case("1.0 2.0")));
+    }
+
+    #[test]
+    fn gets_verdicts_from_termination() {
+        let exited = result(TerminationReason::Exited, Some(0));
+        assert_eq!(run_verdict(&exited), None);
+        let failed = result(TerminationReason::Exited, Some(2));
+        assert_eq!(run_verdict(&failed), Some(JudgeVerdict::RuntimeError));
+        let timeout = result(TerminationReason::Timeout, Some(124));
+        assert_eq!(run_verdict(&timeout), Some(JudgeVerdict::TimeLimitExceeded));
+        let oom = result(TerminationReason::OomKilled, Some(137));
+        assert_eq!(run_verdict(&oom), Some(JudgeVerdict::MemoryLimitExceeded));
+
+        assert_eq!(checker_verdict(&exited), JudgeVerdict::Accepted);
+        let rejected = result(TerminationReason::Exited, Some(1));
+        assert_eq!(checker_verdict(&rejected), JudgeVerdict::WrongAnswer);
+        assert_eq!(checker_verdict(&failed), JudgeVerdict::CheckerError);
+    }
+
+    #[test]
+    fn summarizes_verdicts() {
+        let verdicts = [
+            JudgeVerdict::Accepted,
+            JudgeVerdict::TimeLimitExceeded,
+            JudgeVerdict::WrongAnswer,
+        ];
+        let case_results: Vec<_> = verdicts
+            .into_iter()
+            .enumerate()
+            .map(|(index, verdict)| {
+                let mut result = result(TerminationReason::Exited, Some(0));
+                result.usage.wall_time_ms = 100 * (index as u64 + 1);
+                result.usage.peak_memory_bytes = Some(1024);
+                JudgeCaseResult {
+                    index: index as u32,
+                    verdict,
+                    message: None,
+                    result,
+                }
+            })
+            .collect();
+        let summary = summarize(&case_results);
+        assert_eq!(summary.verdict, JudgeVerdict::TimeLimitExceeded);
+        assert_eq!((summary.passed, summary.total), (1, 3));
+        assert_eq!(summary.max_wall_time_ms, 300);
+        assert_eq!(summary.max_memory_bytes, Some(1024));
+
+        let summary = summarize(&case_results[..1]);
+        assert_eq!(summary.verdict, JudgeVerdict::Accepted);
+    }
+}
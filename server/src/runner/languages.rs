@@ -0,0 +1,480 @@
+//! Language definitions and templates, embedded in the server and optionally loaded
+//! from a directory on disk
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, anyhow, bail};
+use tinirun_models::CodeRunnerLanguage;
+use tokio::signal::unix::{SignalKind, signal};
+
+use crate::runner::structs::{LanguageData, LanguageTemplates};
+
+/// Static directory containing language configs and Dockerfile templates
+static DOCKER_STATIC_DIR: include_dir::Dir<'_> =
+    include_dir::include_dir!("$CARGO_MANIFEST_DIR/docker");
+
+/// Name of the language config file
+const DATA_FILE: &str = "data.toml";
+/// Name of the directory with the template folders
+const TEMPLATES_DIR: &str = "templates";
+/// Name of the Dockerfile template in each template folder
+const DOCKERFILE_TEMPLATE: &str = "Dockerfile.liquid";
+
+/// Snapshot of the loaded languages
+pub struct Languages {
+    pub data: HashMap<CodeRunnerLanguage, LanguageData>,
+    /// Parsed templates of each language, by template folder name
+    pub templates: HashMap<CodeRunnerLanguage, HashMap<String, Arc<LanguageTemplates>>>,
+}
+
+impl Languages {
+    /// Load the embedded languages, overridden and extended by the languages in the given
+    /// directory. The directory has the same layout as the embedded `docker` directory:
+    /// a `data.toml` file (optional) and a `templates` directory. Each file of a template is
+    /// taken from the directory if it exists there, otherwise from the embedded templates.
+    pub fn load(dir: Option<&Path>, sandbox_profiles: &HashSet<String>) -> anyhow::Result<Self> {
+        let embedded_data = DOCKER_STATIC_DIR
+            .get_file(DATA_FILE)
+            .ok_or_else(|| anyhow!("language data file not found"))?;
+        let mut data: HashMap<CodeRunnerLanguage, LanguageData> =
+            toml::from_slice(embedded_data.contents())
+                .context("could not parse language data file")?;
+        if let Some(dir) = dir {
+            let data_path = dir.join(DATA_FILE);
+            if data_path.is_file() {
+                let contents = std::fs::read(&data_path)
+                    .with_context(|| format!("could not read {data_path:?}"))?;
+                let dir_data: HashMap<CodeRunnerLanguage, LanguageData> =
+                    toml::from_slice(&contents)
+                        .with_context(|| format!("could not parse {data_path:?}"))?;
+                data.extend(dir_data);
+            }
+        }
+
+        // Validate the language IDs, versions and selected sandbox profiles
+        for (lang, lang_data) in data.iter() {
+            validate_language_id(lang)?;
+            for version in std::iter::once(&lang_data.version).chain(lang_data.versions.keys()) {
+                validate_version_name(lang, version)?;
+            }
+            if lang_data.versions.contains_key(&lang_data.version) {
+                bail!("duplicate version '{}' for {lang}", lang_data.version);
+            }
+            if let Some(profile) = &lang_data.sandbox
+                && !sandbox_profiles.contains(profile)
+            {
+                bail!("missing sandbox profile '{profile}' for {lang}");
+            }
+        }
+
+        // Parse the templates of all language versions
+        let parser = liquid::ParserBuilder::with_stdlib().build()?;
+        let mut templates = HashMap::new();
+        for (lang, lang_data) in data.iter() {
+            let template_names: HashSet<&String> = std::iter::once(&lang_data.template)
+                .chain(
+                    lang_data
+                        .versions
+                        .values()
+                        .filter_map(|v| v.template.as_ref()),
+                )
+                .collect();
+            let mut lang_templates = HashMap::new();
+            for template in template_names {
+                let read_file = |file_name: &str, kind: &str| {
+                    let contents = read_template_file(dir, template, file_name)?
+                        .ok_or_else(|| anyhow!("missing {kind} for {lang} ({template})"))?;
+                    String::from_utf8(contents)
+                        .map_err(|_| anyhow!("invalid UTF8 in {kind} for {lang} ({template})"))
+                };
+
+                let dockerfile_source = read_file(DOCKERFILE_TEMPLATE, "Dockerfile template")?;
+                let dockerfile = parser.parse(&dockerfile_source).with_context(|| {
+                    format!("failed to parse Dockerfile template for {lang} ({template})")
+                })?;
+                let main_file = read_file(&lang_data.main_filename, "main file")?;
+                let fn_file = read_file(&lang_data.fn_filename, "function file")?;
+
+                let template_data = LanguageTemplates {
+                    dockerfile,
+                    main_file,
+                    fn_file,
+                    // Templates that don't use the `dependencies` variable can't install them
+                    dependencies: dockerfile_source.contains("dependencies"),
+                };
+                lang_templates.insert(template.to_owned(), Arc::new(template_data));
+            }
+            templates.insert(lang.to_owned(), lang_templates);
+        }
+
+        Ok(Self { data, templates })
+    }
+}
+
+/// Keeps the current languages, which can be reloaded from the languages directory
+/// without restarting the server
+pub struct LanguageStore {
+    dir: Option<PathBuf>,
+    sandbox_profiles: HashSet<String>,
+    current: RwLock<Arc<Languages>>,
+}
+
+impl LanguageStore {
+    /// Load the languages, with the names of the sandbox profiles that they can select
+    pub fn new(dir: Option<PathBuf>, sandbox_profiles: HashSet<String>) -> anyhow::Result<Self> {
+        let languages = Languages::load(dir.as_deref(), &sandbox_profiles)?;
+        Ok(Self {
+            dir,
+            sandbox_profiles,
+            current: RwLock::new(Arc::new(languages)),
+        })
+    }
+
+    /// Get the current languages
+    pub fn get(&self) -> Arc<Languages> {
+        self.current.read().expect("language lock poisoned").clone()
+    }
+
+    /// Reload the languages. The current languages are kept if the new ones are invalid.
+    /// Returns the number of loaded languages.
+    pub fn reload(&self) -> anyhow::Result<usize> {
+        let languages = Languages::load(self.dir.as_deref(), &self.sandbox_profiles)?;
+        let count = languages.data.len();
+        *self.current.write().expect("language lock poisoned") = Arc::new(languages);
+        Ok(count)
+    }
+
+    /// Get the modification times and sizes of the files in the languages directory,
+    /// to detect changes
+    fn fingerprint(&self) -> Vec<(PathBuf, SystemTime, u64)> {
+        let mut files = Vec::new();
+        let mut dirs: Vec<PathBuf> = self.dir.iter().cloned().collect();
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let Ok(metadata) = entry.metadata() else {
+                    continue;
+                };
+                if metadata.is_dir() {
+                    dirs.push(entry.path());
+                } else {
+                    let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                    files.push((entry.path(), modified, metadata.len()));
+                }
+            }
+        }
+        files.sort();
+        files
+    }
+}
+
+/// Task that reloads the languages on `SIGHUP`, or when the files in the languages
+/// directory change
+pub async fn language_reload_task(store: Arc<LanguageStore>, poll_interval: Duration) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => Some(hangup),
+        Err(err) => {
+            tracing::warn!("Could not listen for SIGHUP to reload languages: {err}");
+            None
+        }
+    };
+    let fingerprint_store = store.clone();
+    let mut fingerprint = tokio::task::spawn_blocking(move || fingerprint_store.fingerprint())
+        .await
+        .unwrap_or_default();
+
+    loop {
+        let reason = tokio::select! {
+            Some(_) = async { hangup.as_mut()?.recv().await } => "SIGHUP",
+            _ = tokio::time::sleep(poll_interval) => {
+                let fingerprint_store = store.clone();
+                let Ok(new_fingerprint) =
+                    tokio::task::spawn_blocking(move || fingerprint_store.fingerprint()).await
+                else {
+                    continue;
+                };
+                if new_fingerprint == fingerprint {
+                    continue;
+                }
+                fingerprint = new_fingerprint;
+                "file change"
+            }
+        };
+
+        let reload_store = store.clone();
+        match tokio::task::spawn_blocking(move || reload_store.reload()).await {
+            Ok(Ok(count)) => tracing::info!("Reloaded {count} languages ({reason})"),
+            Ok(Err(err)) => tracing::error!("Failed to reload languages ({reason}): {err:#}"),
+            Err(err) => tracing::error!("Failed to reload languages ({reason}): {err}"),
+        }
+    }
+}
+
+/// Read a file of the template folder from the languages directory if it exists there,
+/// otherwise from the embedded templates
+fn read_template_file(
+    dir: Option<&Path>,
+    template: &str,
+    file_name: &str,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let rel_path = Path::new(TEMPLATES_DIR).join(template).join(file_name);
+    if rel_path
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        bail!("invalid template file {rel_path:?}");
+    }
+    if let Some(path) = dir.map(|dir| dir.join(&rel_path))
+        && path.is_file()
+    {
+        let contents = std::fs::read(&path).with_context(|| format!("could not read {path:?}"))?;
+        return Ok(Some(contents));
+    }
+
+    let embedded = DOCKER_STATIC_DIR.get_file(&rel_path);
+    Ok(embedded.map(|file| file.contents().to_vec()))
+}
+
+/// Validate that the language ID only contains lowercase letters, digits, `-`, `_`
+/// and `.`, and isn't reserved
+fn validate_language_id(lang: &CodeRunnerLanguage) -> anyhow::Result<()> {
+    let id = lang.as_str();
+    let valid_chars = id
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '_' | '.'));
+    if id.is_empty() || !valid_chars || id.starts_with('.') {
+        bail!("invalid language ID '{id}'");
+    }
+    if lang.is_wasm() {
+        bail!("language ID '{id}' is reserved");
+    }
+    Ok(())
+}
+
+/// Validate that the version name only contains letters, digits, `-`, `_` and `.`
+fn validate_version_name(lang: &CodeRunnerLanguage, version: &str) -> anyhow::Result<()> {
+    let valid_chars = version
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if version.is_empty() || !valid_chars {
+        bail!("invalid version '{version}' for {lang}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_file(path: &Path, contents: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tinirun-languages-{name}"));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn loads_embedded_languages() {
+        let languages = Languages::load(None, &HashSet::new()).unwrap();
+        for lang in ["bash", "go", "javascript", "python", "rust", "typescript"] {
+            let lang = CodeRunnerLanguage::from(lang);
+            assert!(languages.data.contains_key(&lang));
+            assert!(languages.templates.contains_key(&lang));
+        }
+    }
+
+    #[test]
+    fn directory_overrides_and_extends_languages() {
+        let dir = temp_dir("override");
+        write_file(
+            &dir.join(DATA_FILE),
+            r#"
+            [python]
+            image = "python:3.14-slim"
+            template = "python"
+            fn_file = "function.py"
+            main_file = "main.py"
+            command = "python main.py"
+            inject_code = true
+
+            [ruby]
+            image = "ruby:3.4-slim"
+            template = "ruby"
+            fn_file = "function.rb"
+            main_file = "main.rb"
+            command = "ruby main.rb"
+            "#,
+        );
+        write_file(
+            &dir.join("templates/python/Dockerfile.liquid"),
+            "FROM {{ image }}",
+        );
+        write_file(
+            &dir.join("templates/ruby/Dockerfile.liquid"),
+            "FROM {{ image }}",
+        );
+        write_file(
+            &dir.join("templates/ruby/main.rb"),
+            "require_relative 'function'",
+        );
+        write_file(&dir.join("templates/ruby/function.rb"), "def run; end");
+
+        let languages = Languages::load(Some(&dir), &HashSet::new()).unwrap();
+        let python = CodeRunnerLanguage::from("python");
+        assert_eq!(languages.data[&python].image, "python:3.14-slim");
+        let dockerfile = languages.templates[&python]["python"]
+            .dockerfile
+            .render(&liquid::object!({ "image": "python" }))
+            .unwrap();
+        assert_eq!(dockerfile, "FROM python");
+        // Files that aren't overridden are taken from the embedded template
+        assert!(
+            languages.templates[&python]["python"]
+                .fn_file
+                .contains("def")
+        );
+
+        let ruby = CodeRunnerLanguage::from("ruby");
+        assert_eq!(languages.templates[&ruby]["ruby"].fn_file, "def run; end");
+        assert!(!languages.templates[&ruby]["ruby"].dependencies);
+        assert!(languages.templates[&CodeRunnerLanguage::from("go")]["go"].dependencies);
+        assert!(languages.data[&ruby].fn_input.contains("stdin"));
+        assert!(languages.data.contains_key(&CodeRunnerLanguage::from("go")));
+    }
+
+    #[test]
+    fn loads_language_versions() {
+        let dir = temp_dir("versions");
+        write_file(
+            &dir.join(DATA_FILE),
+            r#"
+            [python]
+            image = "python:3.13-slim"
+            version = "3.13"
+            template = "python"
+            fn_file = "function.py"
+            main_file = "main.py"
+            command = "python main.py"
+            inject_code = true
+
+            [python.versions]
+            "3.12" = { image = "python:3.12-slim" }
+            "3.8" = { image = "python:3.8-slim", template = "python-legacy" }
+            "#,
+        );
+        write_file(
+            &dir.join("templates/python-legacy/Dockerfile.liquid"),
+            "FROM {{ image }} AS legacy",
+        );
+        write_file(
+            &dir.join("templates/python-legacy/main.py"),
+            "import function",
+        );
+        write_file(
+            &dir.join("templates/python-legacy/function.py"),
+            "def run(): pass",
+        );
+
+        let languages = Languages::load(Some(&dir), &HashSet::new()).unwrap();
+        let python = &languages.data[&CodeRunnerLanguage::from("python")];
+        let templates = &languages.templates[&CodeRunnerLanguage::from("python")];
+        assert_eq!(templates.len(), 2);
+
+        let default = python.for_version(None).unwrap();
+        assert_eq!(default.image, "python:3.13-slim");
+        assert_eq!(
+            python.for_version(Some("3.13")).unwrap().image,
+            default.image
+        );
+        let v312 = python.for_version(Some("3.12")).unwrap();
+        assert_eq!(
+            (v312.image.as_str(), v312.template.as_str()),
+            ("python:3.12-slim", "python")
+        );
+        let v38 = python.for_version(Some("3.8")).unwrap();
+        assert_eq!(templates[&v38.template].fn_file, "def run(): pass");
+        assert!(python.for_version(Some("2.7")).is_none());
+
+        let versions = python.version_info();
+        assert_eq!(versions.default_version, "3.13");
+        let names: Vec<_> = versions.versions.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, ["3.13", "3.12", "3.8"]);
+    }
+
+    #[test]
+    fn rejects_invalid_languages() {
+        let dir = temp_dir("invalid");
+        write_file(
+            &dir.join(DATA_FILE),
+            r#"
+            [ruby]
+            image = "ruby:3.4-slim"
+            template = "ruby"
+            fn_file = "function.rb"
+            main_file = "main.rb"
+            command = "ruby main.rb"
+            "#,
+        );
+        let err = Languages::load(Some(&dir), &HashSet::new()).err().unwrap();
+        assert!(
+            err.to_string()
+                .contains("missing Dockerfile template for ruby")
+        );
+
+        let sandboxed = r#"
+            [bash]
+            image = "bash:5.3"
+            template = "bash"
+            fn_file = "run.sh"
+            main_file = "script.sh"
+            command = "bash script.sh"
+            sandbox = "strict"
+            "#;
+        write_file(&dir.join(DATA_FILE), sandboxed);
+        let err = Languages::load(Some(&dir), &HashSet::new()).err().unwrap();
+        assert!(err.to_string().contains("missing sandbox profile 'strict'"));
+        let profiles = HashSet::from(["strict".to_owned()]);
+        assert!(Languages::load(Some(&dir), &profiles).is_ok());
+
+        for id in ["wasm", "Ruby", "../ruby"] {
+            let data = sandboxed.replace("[bash]", &format!("[\"{id}\"]"));
+            write_file(&dir.join(DATA_FILE), &data);
+            assert!(Languages::load(Some(&dir), &profiles).is_err(), "{id}");
+        }
+        // Duplicate default version and invalid version name
+        for version in [r#""default""#, r#""5 2""#] {
+            let data =
+                format!("{sandboxed}[bash.versions]\n{version} = {{ image = \"bash:5.2\" }}");
+            write_file(&dir.join(DATA_FILE), &data);
+            assert!(Languages::load(Some(&dir), &profiles).is_err(), "{version}");
+        }
+    }
+
+    #[test]
+    fn reload_keeps_languages_if_invalid() {
+        let dir = temp_dir("reload");
+        std::fs::create_dir_all(&dir).unwrap();
+        let store = LanguageStore::new(Some(dir.clone()), HashSet::new()).unwrap();
+        let count = store.get().data.len();
+        let before = store.fingerprint();
+
+        write_file(&dir.join(DATA_FILE), "[ruby]\nimage = 1");
+        assert_ne!(store.fingerprint(), before);
+        assert!(store.reload().is_err());
+        assert_eq!(store.get().data.len(), count);
+
+        std::fs::remove_file(dir.join(DATA_FILE)).unwrap();
+        assert_eq!(store.reload().unwrap(), count);
+    }
+}
@@ -1,127 +1,372 @@
 //! Code runner and server plugin
 
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
+use bollard::query_parameters::TagImageOptionsBuilder;
 use futures::Stream;
-use tinirun_models::{CodeRunnerChunk, CodeRunnerInput, CodeRunnerLanguage, RunFunctionInput};
+use tinirun_models::{
+    BenchmarkInput, CodeRunnerChunk, CodeRunnerError, CodeRunnerInput, CodeRunnerLanguage,
+    CodeRunnerRuntime, ExecutionStage, FunctionIo, JudgeInput, ResourceLimits, RunFunctionInput,
+};
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 
+#[cfg(feature = "wasm")]
+use crate::runner::executor::WasmExecutor;
 use crate::{
     errors::AppError,
     redis::{FunctionDetail, FunctionInfo, FunctionStatus, RedisClient},
     runner::{
-        constants::{SET_BUILD_ID, SET_USER_AND_HOME_DIR, UID_GID},
-        executor::DockerExecutor,
-        functions::FunctionExecutor,
-        helpers::log,
-        structs::{LanguageData, LanguageTemplates},
+        admission::AdmissionController,
+        artifacts::ArtifactStore,
+        backend::{DockerBackend, LocalBackend, SandboxBackend},
+        benchmark::BenchmarkExecutor,
+        cache::{ExecDockerfile, ImageCache},
+        constants::{SET_BUILD_ID, SET_JSON_IO, SET_USER_AND_HOME_DIR, UID_GID},
+        egress::{EgressProxy, EgressSession},
+        executor::{self, DockerExecutor, ExecProgram, LocalExecutor},
+        functions::{FunctionCall, FunctionExecutor},
+        helpers::{self, log},
+        judge::{self, JudgeExecutor},
+        languages::LanguageStore,
+        pool::FunctionPool,
+        registry::{RunHandle, RunInfo, RunKind, RunPhase, RunRegistry},
+        sandbox::SandboxProfile,
+        schemas::{FunctionSchemas, SchemaCache},
+        structs::{
+            FunctionContract, LanguageData, LanguageInfo, LanguageTemplates, LanguageVersions,
+        },
     },
 };
 
+mod admission;
+mod artifacts;
+mod backend;
+mod benchmark;
+mod cache;
 mod constants;
+mod egress;
 mod executor;
 mod functions;
 mod helpers;
+mod judge;
+mod languages;
 mod plugin;
+mod pool;
+mod registry;
+mod sandbox;
+mod schemas;
 mod structs;
 mod validators;
 
+pub use artifacts::ArtifactConfig;
+pub use backend::{LocalBackend, LocalConfig};
+#[cfg(feature = "wasm")]
+pub use backend::{WasmBackend, WasmConfig, WasmInterpreterConfig};
 pub use plugin::plugin;
-pub use validators::validate_deps_input;
+pub use registry::RunInfo;
+pub use sandbox::SandboxProfileConfig;
+pub use structs::{LanguageInfo, LanguageVersions};
+pub use validators::{validate_alias_name, validate_deps_input, validate_env_input};
 
 const CHANNEL_BUFFER_SIZE: usize = 1024;
+/// Maximum number of function versions with compiled schemas in the cache
+const MAX_CACHED_SCHEMAS: usize = 1000;
 
 /// # Code runner using Docker containers
 ///
-/// Containers are created and destroyed for each execution.
+/// Containers are created and destroyed for each execution. Images with the dependencies of one-off
+/// executions are cached and shared between executions, and containers for functions are pre-created
+/// in a pool. The number of concurrent builds and runs is limited, and executions beyond the
+/// limits are queued. Runs in progress are tracked so that they can be listed and cancelled.
+/// Networking is disabled, unless egress through the allowlisting proxy is enabled for the run.
+/// Containers can be further hardened with the sandbox profile of the language or function.
+/// Alternatively, one-off code can run in a local sandbox without Docker (see [`LocalBackend`]),
+/// or in the in-process WebAssembly runtime if it's enabled.
 /// Security precautions are taken to ensure that the containers are as isolated and secure as possible - however,
 /// there are always risks associated with running untrusted code in Docker.
 pub struct DockerRunner {
-    client: bollard::Docker,
+    docker: DockerBackend,
+    local: Option<Arc<LocalBackend>>,
+    #[cfg(feature = "wasm")]
+    wasm: Option<Arc<WasmBackend>>,
     redis: RedisClient,
-    language_data: HashMap<CodeRunnerLanguage, LanguageData>,
-    pub templates: HashMap<CodeRunnerLanguage, LanguageTemplates>,
+    image_cache: Arc<ImageCache>,
+    fn_pool: Arc<FunctionPool>,
+    admission: Arc<AdmissionController>,
+    artifacts: Arc<ArtifactStore>,
+    runs: Arc<RunRegistry>,
+    egress: Option<Arc<EgressProxy>>,
+    sandbox_profiles: HashMap<String, Arc<SandboxProfile>>,
+    schemas: SchemaCache,
+    languages: Arc<LanguageStore>,
 }
 
 impl DockerRunner {
     pub fn new(
         client: bollard::Docker,
         redis: RedisClient,
-        language_data: HashMap<CodeRunnerLanguage, LanguageData>,
-        templates: HashMap<CodeRunnerLanguage, LanguageTemplates>,
+        fn_pool: Arc<FunctionPool>,
+        admission: Arc<AdmissionController>,
+        artifacts: ArtifactConfig,
+        languages: Arc<LanguageStore>,
     ) -> Self {
         Self {
-            client,
+            docker: DockerBackend::new(client, artifacts.output_dir_quota()),
+            local: None,
+            #[cfg(feature = "wasm")]
+            wasm: None,
+            image_cache: Arc::new(ImageCache::new(redis.clone(), admission.clone())),
+            fn_pool,
+            admission,
+            artifacts: Arc::new(ArtifactStore::new(redis.clone(), artifacts)),
+            runs: Arc::default(),
+            egress: None,
+            sandbox_profiles: HashMap::new(),
+            schemas: SchemaCache::new(MAX_CACHED_SCHEMAS),
             redis,
-            language_data,
-            templates,
+            languages,
         }
     }
 
+    /// Enable opt-in network egress through the given proxy
+    pub fn with_egress(mut self, egress: Arc<EgressProxy>) -> Self {
+        self.egress = Some(egress);
+        self
+    }
+
+    /// Add the sandbox profiles that languages and functions can select
+    pub fn with_sandbox_profiles(mut self, profiles: HashMap<String, SandboxProfile>) -> Self {
+        self.sandbox_profiles = profiles
+            .into_iter()
+            .map(|(name, profile)| (name, Arc::new(profile)))
+            .collect();
+        self
+    }
+
+    /// Run one-off code in the local backend instead of Docker. Functions are not
+    /// supported with the local backend.
+    pub fn with_local_backend(mut self, local: Arc<LocalBackend>) -> Self {
+        self.local = Some(local);
+        self
+    }
+
+    /// Enable the WebAssembly runtime, for uploaded modules and languages with an interpreter
+    #[cfg(feature = "wasm")]
+    pub fn with_wasm_backend(mut self, wasm: Arc<WasmBackend>) -> Self {
+        self.wasm = Some(wasm);
+        self
+    }
+
     /// Execute the given code in a Docker container and return a stream of events.
-    /// Returns an error immediately if the Docker service was unreachable, or the
-    /// Dockerfile was unable to be rendered.
+    /// Returns an error immediately if the Docker service was unreachable, the
+    /// Dockerfile was unable to be rendered, or the execution queue is full.
     pub async fn execute(
         &self,
         input: CodeRunnerInput,
     ) -> Result<impl Stream<Item = CodeRunnerChunk> + use<>, AppError> {
-        // Validate dependency names
-        if let Some(deps) = &input.dependencies {
-            validators::validate_deps_input(deps).map_err(AppError::BadRequest)?;
+        self.validate_input(&input)?;
+
+        if input.lang.is_wasm() || input.runtime == CodeRunnerRuntime::Wasm {
+            return self.execute_wasm(input);
+        }
+
+        // Validate that the source files don't replace the main file or attached files
+        let (lang_data, templates) = self.get_lang_info(&input.lang, input.version.as_deref())?;
+        Self::check_sources(&input, &lang_data)?;
+        if let Some(local) = &self.local {
+            return self.execute_local(local.clone(), input, lang_data);
         }
 
         // Render the Dockerfile
-        let (lang_data, templates) = self.get_lang_info(&input.lang)?;
-        let dockerfile_vars = liquid::object!({
-            "image": lang_data.image,
-            "main_file": lang_data.main_filename,
-            "dependencies": input.dependencies.as_ref().map(|deps| deps.join(" ")),
-            "uid_gid": &UID_GID,
-            "set_build_id": &SET_BUILD_ID,
-            "set_user_and_home_dir": &SET_USER_AND_HOME_DIR,
-        });
-        let dockerfile = templates
-            .dockerfile
-            .render(&dockerfile_vars)
-            .map_err(|err| AppError::Server(format!("failed to render Dockerfile: {err}")))?;
+        let deps = input.dependencies.as_deref();
+        let dockerfile = Self::render_dockerfile(&input.lang, &lang_data, &templates, deps)?;
 
-        // Ping the Docker service to ensure it is reachable
-        self.client.ping().await?;
+        // Ping the Docker service to ensure it is reachable, and check there is room in the queue
+        self.docker.client().ping().await?;
+        self.admission
+            .check_capacity(&[ExecutionStage::Build, ExecutionStage::Run])?;
 
         // Spawn a task to run the code in a Docker container and send back events
         let (tx, rx) = mpsc::channel::<CodeRunnerChunk>(CHANNEL_BUFFER_SIZE);
-        let client = self.client.clone();
+        let docker = self.docker.clone();
+        let image_cache = self.image_cache.clone();
+        let admission = self.admission.clone();
+        let artifacts = self.artifacts.clone();
+        let egress = self.start_egress(input.egress.as_deref(), &tx)?;
+        let sandbox = self.get_sandbox_profile(lang_data.sandbox.as_deref())?;
+        let run = self.register_run(RunKind::Code, input.lang.clone(), RunPhase::Building);
         tokio::spawn(async move {
-            let executor = DockerExecutor::new(client);
-            let run_id = Self::gen_run_id();
+            let executor =
+                DockerExecutor::new(docker, image_cache, admission, artifacts, egress, sandbox);
+            let run_id = run.id();
+            let _ = tx.send(CodeRunnerChunk::RunId(run_id.to_owned())).await;
 
             tracing::info!("Starting code execution with ID '{run_id}'");
             tokio::select! {
-                res = executor.run(&run_id, input, dockerfile, lang_data, tx.clone()) => {
+                res = executor.run(&run, input, dockerfile, lang_data, tx.clone()) => {
                     if let Err(err) = res {
                         log::send_error(&tx, err).await;
                     }
                     tracing::info!("Code execution '{run_id}' completed");
                 }
+                _ = run.cancelled() => {
+                    log::send_error(&tx, Self::cancelled_error()).await;
+                    tracing::info!("Code execution '{run_id}' cancelled (by request)");
+                }
                 _ = tx.closed() => {
                     tracing::info!("Code execution '{run_id}' cancelled (connection dropped)");
                 }
             }
-            helpers::run_cleanup(&executor.client, &run_id).await;
+            executor.backend.cleanup(run_id).await;
         });
 
         // Return the stream of events from the code runner
         Ok(ReceiverStream::new(rx))
     }
 
-    /// Build the function image
+    /// Judge the program against the test cases. The image of the program is built once,
+    /// then each case runs in a fresh container, and the verdict of each case is streamed
+    /// followed by a summary.
+    pub async fn judge(
+        &self,
+        input: JudgeInput,
+    ) -> Result<impl Stream<Item = CodeRunnerChunk> + use<>, AppError> {
+        self.check_container_mode(&input.program, "judge mode")?;
+        let program = self.exec_program(&input.program)?;
+        let checker = match &input.checker {
+            Some(checker) => {
+                if checker.lang.is_wasm() {
+                    return Err(AppError::BadRequest(
+                        "The checker can't be a WebAssembly module".to_owned(),
+                    ));
+                }
+                let checker_input = CodeRunnerInput {
+                    code: checker.code.clone(),
+                    lang: checker.lang.clone(),
+                    version: checker.version.clone(),
+                    ..Default::default()
+                };
+                Some(self.exec_program(&checker_input)?)
+            }
+            None => None,
+        };
+
+        // Ping the Docker service to ensure it is reachable, and check there is room in the queue
+        self.docker.client().ping().await?;
+        self.admission
+            .check_capacity(&[ExecutionStage::Build, ExecutionStage::Run])?;
+
+        // Spawn a task to judge the program and send back events
+        let (tx, rx) = mpsc::channel::<CodeRunnerChunk>(CHANNEL_BUFFER_SIZE);
+        let docker = self.docker.clone();
+        let image_cache = self.image_cache.clone();
+        let admission = self.admission.clone();
+        let artifacts = self.artifacts.clone();
+        let lang = input.program.lang.clone();
+        let run = self.register_run(RunKind::Judge, lang, RunPhase::Building);
+        tokio::spawn(async move {
+            let docker_executor =
+                DockerExecutor::new(docker, image_cache, admission, artifacts, None, None);
+            let executor = JudgeExecutor::new(docker_executor);
+            let run_id = run.id();
+            let _ = tx.send(CodeRunnerChunk::RunId(run_id.to_owned())).await;
+
+            tracing::info!("Starting judge run with ID '{run_id}'");
+            tokio::select! {
+                res = executor.run(&run, input, program, checker, tx.clone()) => {
+                    if let Err(err) = res {
+                        log::send_error(&tx, err).await;
+                    }
+                    tracing::info!("Judge run '{run_id}' completed");
+                }
+                _ = run.cancelled() => {
+                    log::send_error(&tx, Self::cancelled_error()).await;
+                    tracing::info!("Judge run '{run_id}' cancelled (by request)");
+                }
+                _ = tx.closed() => {
+                    tracing::info!("Judge run '{run_id}' cancelled (connection dropped)");
+                }
+            }
+            executor.docker.backend.cleanup(run_id).await;
+            let checker_id = judge::checker_id(run_id);
+            executor.docker.backend.cleanup(&checker_id).await;
+        });
+
+        Ok(ReceiverStream::new(rx))
+    }
+
+    /// Benchmark the program. The image of the program is built once, then the warmup
+    /// and measured runs each run in a fresh container. Each run is streamed, followed
+    /// by the statistics of the measured runs.
+    pub async fn benchmark(
+        &self,
+        input: BenchmarkInput,
+    ) -> Result<impl Stream<Item = CodeRunnerChunk> + use<>, AppError> {
+        self.check_container_mode(&input.program, "benchmark mode")?;
+        let program = self.exec_program(&input.program)?;
+
+        // Ping the Docker service to ensure it is reachable, and check there is room in the queue
+        self.docker.client().ping().await?;
+        self.admission
+            .check_capacity(&[ExecutionStage::Build, ExecutionStage::Run])?;
+
+        // Spawn a task to benchmark the program and send back events
+        let (tx, rx) = mpsc::channel::<CodeRunnerChunk>(CHANNEL_BUFFER_SIZE);
+        let docker = self.docker.clone();
+        let image_cache = self.image_cache.clone();
+        let admission = self.admission.clone();
+        let artifacts = self.artifacts.clone();
+        let lang = input.program.lang.clone();
+        let run = self.register_run(RunKind::Benchmark, lang, RunPhase::Building);
+        tokio::spawn(async move {
+            let docker_executor =
+                DockerExecutor::new(docker, image_cache, admission, artifacts, None, None);
+            let executor = BenchmarkExecutor::new(docker_executor);
+            let run_id = run.id();
+            let _ = tx.send(CodeRunnerChunk::RunId(run_id.to_owned())).await;
+
+            tracing::info!("Starting benchmark with ID '{run_id}'");
+            tokio::select! {
+                res = executor.run(&run, input, program, tx.clone()) => {
+                    if let Err(err) = res {
+                        log::send_error(&tx, err).await;
+                    }
+                    tracing::info!("Benchmark '{run_id}' completed");
+                }
+                _ = run.cancelled() => {
+                    log::send_error(&tx, Self::cancelled_error()).await;
+                    tracing::info!("Benchmark '{run_id}' cancelled (by request)");
+                }
+                _ = tx.closed() => {
+                    tracing::info!("Benchmark '{run_id}' cancelled (connection dropped)");
+                }
+            }
+            executor.docker.backend.cleanup(run_id).await;
+        });
+
+        Ok(ReceiverStream::new(rx))
+    }
+
+    /// Save the function's new version with the `Building` status, and build the function
+    /// image. The oldest versions beyond `retention` are removed from the version history.
     pub async fn build_function(
         &self,
         name: &str,
         info: FunctionDetail,
+        retention: usize,
     ) -> Result<impl Stream<Item = CodeRunnerChunk> + use<>, AppError> {
-        let (lang_data, templates) = self.get_lang_info(&info.lang)?;
+        self.check_functions_supported()?;
+        let (lang_data, templates) =
+            self.get_lang_info(&info.lang, info.lang_version.as_deref())?;
+        if let Some(sources) = &info.sources {
+            let reserved = [lang_data.main_filename.as_str(), &lang_data.fn_filename];
+            validators::validate_sources_input(sources, None, &reserved)
+                .map_err(AppError::BadRequest)?;
+        }
+        let schemas =
+            FunctionSchemas::compile(info.input_schema.as_ref(), info.output_schema.as_ref())
+                .map_err(AppError::BadRequest)?;
 
         // Render the Dockerfile
         let dockerfile_vars = liquid::object!({
@@ -129,38 +374,93 @@ impl DockerRunner {
             "main_file": lang_data.main_filename,
             "fn_file": lang_data.fn_filename,
             "dependencies": info.dependencies,
+            "json_io": info.io == FunctionIo::Json,
             "uid_gid": &UID_GID,
             "set_build_id": &SET_BUILD_ID,
             "set_user_and_home_dir": &SET_USER_AND_HOME_DIR,
         });
-        let dockerfile = templates
+        let mut dockerfile = templates
             .dockerfile
             .render(&dockerfile_vars)
             .map_err(|err| AppError::Server(format!("Failed to render Dockerfile: {err}")))?;
+        if info.io == FunctionIo::Json {
+            dockerfile.push_str(SET_JSON_IO);
+        }
 
-        // Ping the Docker service to ensure it is reachable
-        self.client.ping().await?;
+        // Ping the Docker service to ensure it is reachable, and check there is room in the queue
+        self.docker.client().ping().await?;
+        self.admission.check_capacity(&[ExecutionStage::Build])?;
+
+        // Claim the new version of an updated function, so that a concurrent update or
+        // rollback can't create the same version
+        if info.version > 0
+            && !self
+                .redis
+                .swap_fn_version(name, info.version - 1, info.version, false)
+                .await?
+        {
+            return Err(AppError::BadRequest(
+                "The function was changed during the update. Please try again.".to_owned(),
+            ));
+        }
+
+        // Save the function before the build starts, so that the status set at the end of
+        // the build can't be overwritten
+        self.redis.add_fn_version(name, &info, retention).await?;
+        self.redis.set_fn(name, info.clone()).await?;
+        self.schemas.insert(name, info.version, schemas);
 
         // Spawn a task to build the function image and send back events
         let (tx, rx) = mpsc::channel::<CodeRunnerChunk>(CHANNEL_BUFFER_SIZE);
-        let client = self.client.clone();
+        let docker = self.docker.clone();
         let redis = self.redis.clone();
+        let fn_pool = self.fn_pool.clone();
+        let admission = self.admission.clone();
+        let artifacts = self.artifacts.clone();
+        let kind = RunKind::FunctionBuild {
+            name: name.to_owned(),
+        };
+        let run = self.register_run(kind, info.lang.clone(), RunPhase::Queued);
         let name = name.to_owned();
+        let version = info.version;
         let main_code = templates.main_file.to_owned();
         tokio::spawn(async move {
+            let _ = tx.send(CodeRunnerChunk::RunId(run.id().to_owned())).await;
+
             // Build the function and update its status
-            let executor = FunctionExecutor::new(client);
-            let status = match executor
-                .build_fn(&name, info, lang_data, dockerfile, main_code, tx.clone())
-                .await
-            {
-                Ok((tag, id)) => FunctionStatus::Ready { tag, id },
+            let executor =
+                FunctionExecutor::new(docker, fn_pool.clone(), admission, artifacts, None, None);
+            let build = executor.build_fn(
+                &run,
+                &name,
+                info,
+                lang_data,
+                dockerfile,
+                main_code,
+                tx.clone(),
+            );
+            let build_result = tokio::select! {
+                res = build => res,
+                _ = run.cancelled() => Err(Self::cancelled_error()),
+            };
+            if let Err(CodeRunnerError::Cancelled { .. }) = build_result {
+                // Dropping the build stream closed the connection, which stops the build in
+                // Docker. The image may have been tagged just before the cancellation.
+                tracing::info!("Build of '{name}' function cancelled (by request)");
+                executor.remove_fn_image(&name, version).await;
+            }
+            let status = match build_result {
+                Ok((tag, id)) => {
+                    // Pooled containers were created from the previous image
+                    fn_pool.invalidate(&name);
+                    FunctionStatus::Ready { tag, id }
+                }
                 Err(err) => {
                     log::send_error(&tx, err.clone()).await;
                     FunctionStatus::Error(err)
                 }
             };
-            if let Err(err) = redis.set_fn_status(&name, status).await {
+            if let Err(err) = redis.set_fn_status(&name, version, status).await {
                 tracing::error!("Failed to set status of '{name}' function in Redis: {err}");
             }
         });
@@ -168,60 +468,603 @@ impl DockerRunner {
         Ok(ReceiverStream::new(rx))
     }
 
-    /// Run the function with the given inputs
+    /// Make a previous version of the function the given new version, by tagging the image
+    /// of the previous version. Returns the status of the new version.
+    pub async fn rollback_function(
+        &self,
+        name: &str,
+        previous: &FunctionDetail,
+        version: u32,
+    ) -> Result<FunctionStatus, AppError> {
+        self.check_functions_supported()?;
+        let FunctionStatus::Ready { tag, id } = &previous.status else {
+            return Err(AppError::BadRequest(format!(
+                "Version {} of the function was not built successfully",
+                previous.version
+            )));
+        };
+        if !helpers::exists_image(self.docker.client(), tag).await? {
+            return Err(AppError::BadRequest(format!(
+                "The image of version {} of the function no longer exists",
+                previous.version
+            )));
+        }
+
+        let tag_options = TagImageOptionsBuilder::new()
+            .repo(&FunctionExecutor::fn_repo(name))
+            .tag(&format!("v{version}"))
+            .build();
+        self.docker
+            .client()
+            .tag_image(tag, Some(tag_options))
+            .await?;
+
+        // Pooled containers were created from the previous image
+        self.fn_pool.invalidate(name);
+        Ok(FunctionStatus::Ready {
+            tag: FunctionExecutor::fn_tag(name, version),
+            id: id.to_owned(),
+        })
+    }
+
+    /// Run the function with the given inputs. The secrets are added to the
+    /// container's environment, and redacted from the output. The egress allowlist
+    /// of the input takes precedence over the function's allowlist. If the input or
+    /// the alias selects a previous version, it is run from the function's version history.
     pub async fn run_function(
         &self,
         name: String,
+        alias: Option<String>,
         fn_info: FunctionInfo,
         input: RunFunctionInput,
+        secrets: HashMap<String, String>,
     ) -> Result<impl Stream<Item = CodeRunnerChunk> + use<>, AppError> {
-        let (lang_data, _) = self.get_lang_info(&fn_info.lang)?;
+        self.check_functions_supported()?;
+        let (version, canary) = match &alias {
+            Some(alias) => {
+                if input.version.is_some() {
+                    return Err(AppError::BadRequest(format!(
+                        "A version can't be selected when running the '{alias}' alias"
+                    )));
+                }
+                let (version, canary) = self.select_alias_version(&name, alias).await?;
+                (Some(version), canary)
+            }
+            None => (input.version, false),
+        };
+        let current = version.is_none_or(|version| version == fn_info.version);
+        let fn_info = match version {
+            Some(version) if !current => self
+                .redis
+                .get_fn_version(&name, version)
+                .await?
+                .map(FunctionInfo::from)
+                .ok_or_else(|| {
+                    AppError::BadRequest(format!("Version {version} of the function not found"))
+                })?,
+            _ => fn_info,
+        };
+        let FunctionStatus::Ready { tag: image_tag, .. } = &fn_info.status else {
+            return Err(AppError::BadRequest("Function not ready".to_owned()));
+        };
+        let (lang_data, _) = self.get_lang_info(&fn_info.lang, fn_info.lang_version.as_deref())?;
+        if fn_info.io == FunctionIo::Text && !input.input.is_string() {
+            return Err(AppError::BadRequest(
+                "The input must be a string, as the function is not in JSON mode".to_owned(),
+            ));
+        }
+        let schemas = self
+            .schemas
+            .get_or_compile(
+                &name,
+                fn_info.version,
+                fn_info.input_schema.as_ref(),
+                fn_info.output_schema.as_ref(),
+            )
+            .map_err(AppError::Server)?;
+        if let Some(validator) = &schemas.input {
+            validators::validate_json_value(validator, &input.input).map_err(|errors| {
+                AppError::BadRequest(format!("Input doesn't match the input schema: {errors}"))
+            })?;
+        }
 
-        // Ping the Docker service to ensure it is reachable
-        self.client.ping().await?;
+        // Ping the Docker service to ensure it is reachable, and check there is room in the queue
+        self.docker.client().ping().await?;
+        self.admission.check_capacity(&[ExecutionStage::Run])?;
 
         // Spawn a task to run the function in a Docker container and send back events
         let (tx, rx) = mpsc::channel::<CodeRunnerChunk>(CHANNEL_BUFFER_SIZE);
-        let client = self.client.clone();
+        let docker = self.docker.clone();
+        let fn_pool = self.fn_pool.clone();
+        let admission = self.admission.clone();
+        let artifacts = self.artifacts.clone();
+        let allowlist = input.egress.as_deref().or(fn_info.egress.as_deref());
+        let egress = self.start_egress(allowlist, &tx)?;
+        let sandbox = fn_info.sandbox.as_deref().or(lang_data.sandbox.as_deref());
+        let sandbox = self.get_sandbox_profile(sandbox)?;
+        let call = FunctionCall {
+            name: name.clone(),
+            input,
+            io: fn_info.io,
+            schemas,
+            image_tag: image_tag.to_owned(),
+            warm_pool: current,
+        };
+        let version_chunk = CodeRunnerChunk::FunctionVersion {
+            version: fn_info.version,
+            alias,
+            canary,
+        };
+        let kind = RunKind::Function { name: name.clone() };
+        let run = self.register_run(kind, fn_info.lang.clone(), RunPhase::Queued);
         tokio::spawn(async move {
-            let executor = FunctionExecutor::new(client);
-            let run_id = Self::gen_run_id();
+            let executor =
+                FunctionExecutor::new(docker, fn_pool, admission, artifacts, egress, sandbox);
+            let run_id = run.id();
+            let _ = tx.send(CodeRunnerChunk::RunId(run_id.to_owned())).await;
+            let _ = tx.send(version_chunk).await;
 
             tracing::info!("Running function '{name}' with run ID '{run_id}'");
+            let run_fn = executor.run_function(&run, call, lang_data, secrets, tx.clone());
             tokio::select! {
-                res = executor.run_function(&run_id, &name, input, lang_data, tx.clone()) => {
+                res = run_fn => {
                     if let Err(err) = res {
                         log::send_error(&tx, err).await;
                     }
                     tracing::info!("Code execution '{run_id}' completed");
                 }
+                _ = run.cancelled() => {
+                    log::send_error(&tx, Self::cancelled_error()).await;
+                    tracing::info!("Code execution '{run_id}' cancelled (by request)");
+                }
                 _ = tx.closed() => {
                     tracing::info!("Code execution '{run_id}' cancelled (connection dropped)");
                 }
             }
-            helpers::run_cleanup(&executor.client, &run_id).await;
+            executor.backend.cleanup(run_id).await;
         });
 
         Ok(ReceiverStream::new(rx))
     }
 
+    /// Get the sample function file of the language version, used as the code of new functions
+    pub fn get_fn_template(
+        &self,
+        lang: &CodeRunnerLanguage,
+        version: Option<&str>,
+    ) -> Result<String, AppError> {
+        let (_, templates) = self.get_lang_info(lang, version)?;
+        Ok(templates.fn_file.to_owned())
+    }
+
+    /// Get the info of all languages, sorted by ID
+    pub fn list_languages(&self) -> Vec<LanguageInfo> {
+        let languages = self.languages.get();
+        let mut infos: Vec<_> = languages
+            .data
+            .iter()
+            .filter_map(|(lang, lang_data)| {
+                let templates = languages.templates.get(lang)?.get(&lang_data.template)?;
+                Some(LanguageInfo {
+                    id: lang.clone(),
+                    versions: lang_data.version_info(),
+                    image: lang_data.image.clone(),
+                    main_file: lang_data.main_filename.clone(),
+                    fn_file: lang_data.fn_filename.clone(),
+                    fn_template: templates.fn_file.clone(),
+                    function: FunctionContract {
+                        input: lang_data.fn_input.clone(),
+                        output: lang_data.fn_output.clone(),
+                    },
+                    default_limits: ResourceLimits::DEFAULT,
+                    max_limits: ResourceLimits::MAX,
+                    dependencies: templates.dependencies && self.local.is_none(),
+                })
+            })
+            .collect();
+        infos.sort_by(|a, b| a.id.cmp(&b.id));
+        infos
+    }
+
+    /// Get the available versions of the language
+    pub fn get_lang_versions(
+        &self,
+        lang: &CodeRunnerLanguage,
+    ) -> Result<LanguageVersions, AppError> {
+        let languages = self.languages.get();
+        let lang_data = languages.data.get(lang).ok_or(AppError::NotFound)?;
+        Ok(lang_data.version_info())
+    }
+
+    /// Check that functions can be built and run on this server
+    pub fn check_functions_supported(&self) -> Result<(), AppError> {
+        match self.local {
+            Some(_) => Err(AppError::BadRequest(
+                "Functions require the Docker backend".to_owned(),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Check that the egress allowlist is configured on this server
+    pub fn check_egress_allowlist(&self, allowlist: &str) -> Result<(), AppError> {
+        match &self.egress {
+            Some(egress) if egress.config.allowlists.contains_key(allowlist) => Ok(()),
+            Some(_) => Err(AppError::BadRequest(format!(
+                "Egress allowlist '{allowlist}' not found"
+            ))),
+            None => Err(AppError::BadRequest(
+                "Network egress is not enabled on this server".to_owned(),
+            )),
+        }
+    }
+
+    /// Check that the sandbox profile is configured on this server
+    pub fn check_sandbox_profile(&self, profile: &str) -> Result<(), AppError> {
+        self.get_sandbox_profile(Some(profile)).map(|_| ())
+    }
+
+    /// Remove the pooled containers of the function, e.g. after its secrets changed
+    pub fn invalidate_pool(&self, name: &str) {
+        self.fn_pool.invalidate(name);
+    }
+
+    /// List the runs in progress on this server
+    pub fn list_runs(&self) -> Vec<RunInfo> {
+        self.runs.list()
+    }
+
+    /// Cancel a run in progress, stopping its build or container. Returns `false` if
+    /// the run was not found.
+    pub fn cancel_run(&self, id: &str) -> bool {
+        self.runs.cancel(id)
+    }
+
+    /// Get the content of a stored artifact of a run. Returns `None` if the
+    /// artifact was not found or has expired.
+    pub async fn get_artifact(
+        &self,
+        run_id: &str,
+        path: &str,
+    ) -> Result<Option<Vec<u8>>, AppError> {
+        Ok(self.artifacts.get(run_id, path).await?)
+    }
+
+    /// Execute the given code in the local backend. Only languages that don't need a
+    /// build step are supported, and dependencies can't be installed.
+    fn execute_local(
+        &self,
+        local: Arc<LocalBackend>,
+        input: CodeRunnerInput,
+        lang_data: LanguageData,
+    ) -> Result<ReceiverStream<CodeRunnerChunk>, AppError> {
+        if !lang_data.inject_code {
+            return Err(AppError::BadRequest(format!(
+                "{} is not supported by the local backend",
+                input.lang
+            )));
+        }
+        if input
+            .dependencies
+            .as_ref()
+            .is_some_and(|deps| !deps.is_empty())
+        {
+            return Err(AppError::BadRequest(
+                "Dependencies are not supported by the local backend".to_owned(),
+            ));
+        }
+        if input.version.is_some() {
+            return Err(AppError::BadRequest(
+                "Language versions are not supported by the local backend".to_owned(),
+            ));
+        }
+        self.admission.check_capacity(&[ExecutionStage::Run])?;
+
+        // Spawn a task to run the code in the local sandbox and send back events
+        let (tx, rx) = mpsc::channel::<CodeRunnerChunk>(CHANNEL_BUFFER_SIZE);
+        let admission = self.admission.clone();
+        let artifacts = self.artifacts.clone();
+        let run = self.register_run(RunKind::Code, input.lang.clone(), RunPhase::Queued);
+        tokio::spawn(async move {
+            let executor = LocalExecutor::new(local, admission, artifacts);
+            let run_id = run.id();
+            let _ = tx.send(CodeRunnerChunk::RunId(run_id.to_owned())).await;
+
+            tracing::info!("Starting local code execution with ID '{run_id}'");
+            tokio::select! {
+                res = executor.run(&run, input, lang_data, tx.clone()) => {
+                    if let Err(err) = res {
+                        log::send_error(&tx, err).await;
+                    }
+                    tracing::info!("Code execution '{run_id}' completed");
+                }
+                _ = run.cancelled() => {
+                    log::send_error(&tx, Self::cancelled_error()).await;
+                    tracing::info!("Code execution '{run_id}' cancelled (by request)");
+                }
+                _ = tx.closed() => {
+                    tracing::info!("Code execution '{run_id}' cancelled (connection dropped)");
+                }
+            }
+            executor.backend.cleanup(run_id).await;
+        });
+
+        Ok(ReceiverStream::new(rx))
+    }
+
+    /// Execute the uploaded module or the code in the WebAssembly runtime. Only languages
+    /// with a configured interpreter are supported, and dependencies can't be installed.
+    #[cfg(feature = "wasm")]
+    fn execute_wasm(
+        &self,
+        input: CodeRunnerInput,
+    ) -> Result<ReceiverStream<CodeRunnerChunk>, AppError> {
+        let Some(wasm) = self.wasm.clone() else {
+            return Err(Self::wasm_disabled_error());
+        };
+        if input
+            .dependencies
+            .as_ref()
+            .is_some_and(|deps| !deps.is_empty())
+        {
+            return Err(AppError::BadRequest(
+                "Dependencies are not supported by the WebAssembly runtime".to_owned(),
+            ));
+        }
+        if input.egress.is_some() {
+            return Err(AppError::BadRequest(
+                "Network egress is not supported by the WebAssembly runtime".to_owned(),
+            ));
+        }
+        if input.version.is_some() {
+            return Err(AppError::BadRequest(
+                "Language versions are not supported by the WebAssembly runtime".to_owned(),
+            ));
+        }
+        let lang_data = match &input.lang {
+            lang if lang.is_wasm() => None,
+            lang if wasm.supports(lang) => {
+                let (lang_data, _) = self.get_lang_info(lang, None)?;
+                Self::check_sources(&input, &lang_data)?;
+                Some(lang_data)
+            }
+            lang => {
+                return Err(AppError::BadRequest(format!(
+                    "{lang} is not supported by the WebAssembly runtime"
+                )));
+            }
+        };
+        self.admission.check_capacity(&[ExecutionStage::Run])?;
+
+        // Spawn a task to run the module and send back events
+        let (tx, rx) = mpsc::channel::<CodeRunnerChunk>(CHANNEL_BUFFER_SIZE);
+        let admission = self.admission.clone();
+        let artifacts = self.artifacts.clone();
+        let run = self.register_run(RunKind::Code, input.lang.clone(), RunPhase::Queued);
+        tokio::spawn(async move {
+            let executor = WasmExecutor::new(wasm, admission, artifacts);
+            let run_id = run.id();
+            let _ = tx.send(CodeRunnerChunk::RunId(run_id.to_owned())).await;
+
+            tracing::info!("Starting WebAssembly code execution with ID '{run_id}'");
+            tokio::select! {
+                res = executor.run(&run, input, lang_data, tx.clone()) => {
+                    if let Err(err) = res {
+                        log::send_error(&tx, err).await;
+                    }
+                    tracing::info!("Code execution '{run_id}' completed");
+                }
+                _ = run.cancelled() => {
+                    log::send_error(&tx, Self::cancelled_error()).await;
+                    tracing::info!("Code execution '{run_id}' cancelled (by request)");
+                }
+                _ = tx.closed() => {
+                    tracing::info!("Code execution '{run_id}' cancelled (connection dropped)");
+                }
+            }
+            executor.backend.cleanup(run_id).await;
+        });
+
+        Ok(ReceiverStream::new(rx))
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    fn execute_wasm(
+        &self,
+        _input: CodeRunnerInput,
+    ) -> Result<ReceiverStream<CodeRunnerChunk>, AppError> {
+        Err(Self::wasm_disabled_error())
+    }
+
+    fn wasm_disabled_error() -> AppError {
+        AppError::BadRequest("The WebAssembly runtime is not enabled on this server".to_owned())
+    }
+
+    /// Validate the dependencies, environment variables and egress allowlist of the input
+    fn validate_input(&self, input: &CodeRunnerInput) -> Result<(), AppError> {
+        if let Some(deps) = &input.dependencies {
+            validators::validate_deps_input(deps).map_err(AppError::BadRequest)?;
+        }
+        if let Some(env) = &input.env {
+            validators::validate_env_input(env.keys()).map_err(AppError::BadRequest)?;
+        }
+        if let Some(allowlist) = &input.egress {
+            self.check_egress_allowlist(allowlist)?;
+        }
+        Ok(())
+    }
+
+    /// Validate the input of a one-off execution before it's run later (e.g. by a job):
+    /// the checks of [`Self::validate_input`], plus the language version, source files
+    /// and sandbox profile of programs that run in Docker containers
+    pub(crate) fn validate_code_input(&self, input: &CodeRunnerInput) -> Result<(), AppError> {
+        self.validate_input(input)?;
+        if input.lang.is_wasm() || input.runtime == CodeRunnerRuntime::Wasm {
+            return Ok(());
+        }
+        let (lang_data, _) = self.get_lang_info(&input.lang, input.version.as_deref())?;
+        Self::check_sources(input, &lang_data)?;
+        self.get_sandbox_profile(lang_data.sandbox.as_deref())?;
+        Ok(())
+    }
+
+    /// Check that the program can run in a mode that runs several containers of the
+    /// same image (judge and benchmark modes)
+    fn check_container_mode(&self, input: &CodeRunnerInput, mode: &str) -> Result<(), AppError> {
+        self.validate_input(input)?;
+        if input.lang.is_wasm() || input.runtime == CodeRunnerRuntime::Wasm || self.local.is_some()
+        {
+            return Err(AppError::BadRequest(format!(
+                "Only Docker containers are supported in {mode}"
+            )));
+        }
+        if input.egress.is_some() {
+            return Err(AppError::BadRequest(format!(
+                "Network egress is not supported in {mode}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Render the Dockerfile and gather the files of a program to build once and run in
+    /// several containers
+    fn exec_program(&self, input: &CodeRunnerInput) -> Result<ExecProgram, AppError> {
+        let (lang_data, templates) = self.get_lang_info(&input.lang, input.version.as_deref())?;
+        Self::check_sources(input, &lang_data)?;
+        let deps = input.dependencies.as_deref();
+        let files = executor::gather_files(
+            input.code.clone(),
+            input.sources.clone(),
+            input.entrypoint.clone(),
+            input.files.clone(),
+            lang_data.main_filename.clone(),
+            lang_data.src_dir.clone(),
+        );
+        Ok(ExecProgram {
+            dockerfile: Self::render_dockerfile(&input.lang, &lang_data, &templates, deps)?,
+            sandbox: self.get_sandbox_profile(lang_data.sandbox.as_deref())?,
+            files,
+            lang_data,
+        })
+    }
+
+    /// Render the Dockerfile of a one-off execution, split into the dependency and
+    /// code parts
+    fn render_dockerfile(
+        lang: &CodeRunnerLanguage,
+        lang_data: &LanguageData,
+        templates: &LanguageTemplates,
+        dependencies: Option<&[String]>,
+    ) -> Result<ExecDockerfile, AppError> {
+        let dockerfile_vars = liquid::object!({
+            "image": lang_data.image,
+            "main_file": lang_data.main_filename,
+            "dependencies": dependencies.map(|deps| deps.join(" ")),
+            "uid_gid": &UID_GID,
+            "set_build_id": &SET_BUILD_ID,
+            "set_user_and_home_dir": &SET_USER_AND_HOME_DIR,
+        });
+        let dockerfile = templates
+            .dockerfile
+            .render(&dockerfile_vars)
+            .map_err(|err| AppError::Server(format!("failed to render Dockerfile: {err}")))?;
+        ExecDockerfile::split(lang, &dockerfile, dependencies)
+            .ok_or_else(|| AppError::Server("Dockerfile is missing build ID".into()))
+    }
+
+    /// Validate that the source files don't replace the main file or attached files
+    fn check_sources(input: &CodeRunnerInput, lang_data: &LanguageData) -> Result<(), AppError> {
+        let Some(sources) = &input.sources else {
+            return Ok(());
+        };
+        let mut reserved = vec![lang_data.main_filename.as_str()];
+        if lang_data.src_dir.is_none() {
+            reserved.push("files");
+        }
+        let entrypoint = input.entrypoint.as_deref();
+        validators::validate_sources_input(sources, entrypoint, &reserved)
+            .map_err(AppError::BadRequest)
+    }
+
+    /// Start an egress session for the run, if an allowlist was selected
+    fn start_egress(
+        &self,
+        allowlist: Option<&str>,
+        tx: &mpsc::Sender<CodeRunnerChunk>,
+    ) -> Result<Option<EgressSession>, AppError> {
+        let Some(allowlist) = allowlist else {
+            return Ok(None);
+        };
+        self.check_egress_allowlist(allowlist)?;
+        let egress = self
+            .egress
+            .as_ref()
+            .and_then(|e| e.start_session(allowlist, tx.clone()));
+        Ok(egress)
+    }
+
+    fn get_sandbox_profile(
+        &self,
+        profile: Option<&str>,
+    ) -> Result<Option<Arc<SandboxProfile>>, AppError> {
+        let Some(profile) = profile else {
+            return Ok(None);
+        };
+        match self.sandbox_profiles.get(profile) {
+            Some(sandbox) => Ok(Some(sandbox.clone())),
+            None => Err(AppError::BadRequest(format!(
+                "Sandbox profile '{profile}' not found"
+            ))),
+        }
+    }
+
+    /// Select the version of the function for a run through the alias. Returns the version,
+    /// and whether it is the canary version of the alias.
+    async fn select_alias_version(&self, name: &str, alias: &str) -> Result<(u32, bool), AppError> {
+        let alias_info = self.redis.get_fn_alias(name, alias).await?.ok_or_else(|| {
+            AppError::BadRequest(format!("Alias '{alias}' of the function not found"))
+        })?;
+        // Random number from 0 to 99 to split the runs by the canary's weight
+        let roll = (uuid::Uuid::new_v4().as_u128() % 100) as u8;
+        Ok(alias_info.select_version(roll))
+    }
+
+    fn register_run(&self, kind: RunKind, lang: CodeRunnerLanguage, phase: RunPhase) -> RunHandle {
+        self.runs.register(Self::gen_run_id(), kind, lang, phase)
+    }
+
     fn gen_run_id() -> String {
         format!("code-runner-{}", uuid::Uuid::new_v4())
     }
 
+    fn cancelled_error() -> CodeRunnerError {
+        CodeRunnerError::Cancelled {
+            message: "Run was cancelled".into(),
+        }
+    }
+
+    /// Get the data and templates of the given version of the language, or the default version
     fn get_lang_info(
         &self,
         lang: &CodeRunnerLanguage,
-    ) -> Result<(LanguageData, &LanguageTemplates), AppError> {
-        let lang_data = self
-            .language_data
+        version: Option<&str>,
+    ) -> Result<(LanguageData, Arc<LanguageTemplates>), AppError> {
+        let languages = self.languages.get();
+        let lang_data = languages
+            .data
             .get(lang)
-            .ok_or_else(|| AppError::Server("Language data not found".into()))?
-            .to_owned();
-        let templates = self
+            .ok_or_else(|| AppError::BadRequest(format!("Language '{lang}' not found")))?
+            .for_version(version)
+            .ok_or_else(|| {
+                let version = version.unwrap_or_default();
+                AppError::BadRequest(format!("Version '{version}' of {lang} not found"))
+            })?;
+        let templates = languages
             .templates
             .get(lang)
-            .ok_or_else(|| AppError::Server("Dockerfile template not found".into()))?;
+            .and_then(|templates| templates.get(&lang_data.template))
+            .ok_or_else(|| AppError::Server("Dockerfile template not found".into()))?
+            .clone();
 
         Ok((lang_data, templates))
     }
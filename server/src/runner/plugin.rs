@@ -1,23 +1,26 @@
-use std::{collections::HashMap, time::Duration};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
 
 use anyhow::{Context, anyhow};
 use axum_app_wrapper::AdHocPlugin;
 use bollard::Docker;
 
 use crate::{
-    config::AppConfig,
+    config::{AppConfig, BackendKind},
     redis::RedisClient,
     runner::{
-        DockerRunner,
+        ArtifactConfig, DockerRunner, LocalBackend, LocalConfig,
+        admission::{AdmissionConfig, AdmissionController},
+        egress::{DEFAULT_PROXY_PORT, EgressConfig, EgressProxy, EgressRule, ensure_network},
         helpers::{image_cleanup_task, sync_function_status_task},
-        structs::LanguageTemplates,
+        languages::{LanguageStore, language_reload_task},
+        pool::{FunctionPool, PoolConfig, pool_eviction_task},
+        sandbox::{APPARMOR_PROFILES_FILE, SandboxProfile},
     },
     state::AppState,
 };
 
-/// Static directory containing language configs and Dockerfile templates
-static DOCKER_STATIC_DIR: include_dir::Dir<'_> =
-    include_dir::include_dir!("$CARGO_MANIFEST_DIR/docker");
+/// Interval between checks for changes in the languages directory
+const LANGUAGES_POLL_INTERVAL: Duration = Duration::from_secs(5);
 
 /// Server plugin that runs on startup to connect to Docker, parse language config and Dockerfile templates,
 /// and add the code runner service to Axum state
@@ -32,81 +35,214 @@ pub fn plugin() -> AdHocPlugin<AppState> {
             .await?
             .context("could not connect to Docker")?;
 
-        // Parse language config
-        let language_data: HashMap<super::CodeRunnerLanguage, super::structs::LanguageData> = {
-            let language_data_file = DOCKER_STATIC_DIR
-                .get_file("data.toml")
-                .ok_or_else(|| anyhow!("language data file not found"))?;
-
-            toml::from_slice(language_data_file.contents())
-                .context("could not parse language data file")?
-        };
-
-        // Parse all language templates
-        let templates: HashMap<super::CodeRunnerLanguage, LanguageTemplates> = {
-            let parser = liquid::ParserBuilder::with_stdlib().build()?;
-            let templates_dir = DOCKER_STATIC_DIR
-                .get_dir("templates")
-                .ok_or_else(|| anyhow!("template directory not found"))?;
-
-            let mut templates = HashMap::new();
-            for (lang, lang_data) in language_data.iter() {
-                let lang_dir = templates_dir.path().join(&lang_data.template);
-                let folder = templates_dir
-                    .get_dir(&lang_dir)
-                    .ok_or_else(|| anyhow!("missing template directory for {lang:?}"))?;
-
-                let template_file = folder
-                    .get_file(lang_dir.join("Dockerfile.liquid"))
-                    .ok_or_else(|| anyhow!("missing Dockerfile template for {lang:?}"))?;
-                let template = parser
-                    .parse(template_file.contents_utf8().ok_or_else(|| {
-                        anyhow!("invalid UTF8 in Dockerfile template for {lang:?}")
-                    })?)
-                    .with_context(|| format!("failed to parse Dockerfile template for {lang:?}"))?;
-
-                let main_file = folder
-                    .get_file(lang_dir.join(&lang_data.main_filename))
-                    .ok_or_else(|| anyhow!("missing main file for {lang:?}"))?
-                    .contents_utf8()
-                    .ok_or_else(|| anyhow!("invalid UTF8 in main file for {lang:?}"))?;
-                let fn_file = folder
-                    .get_file(lang_dir.join(&lang_data.fn_filename))
-                    .ok_or_else(|| anyhow!("missing function file for {lang:?}"))?
-                    .contents_utf8()
-                    .ok_or_else(|| anyhow!("invalid UTF8 in function file for {lang:?}"))?;
-
-                templates.insert(
-                    lang.to_owned(),
-                    LanguageTemplates {
-                        dockerfile: template,
-                        main_file: main_file.to_owned(),
-                        fn_file: fn_file.to_owned(),
-                    },
-                );
+        // Load sandbox profiles, and validate them against the Docker daemon
+        let local = app_config.backend == BackendKind::Local;
+        let sandbox_profiles: HashMap<String, SandboxProfile> = {
+            if local && !app_config.sandbox_profiles.is_empty() {
+                return Err(anyhow!("sandbox profiles require the Docker backend"));
+            }
+            if local && !app_config.egress_allowlists.is_empty() {
+                return Err(anyhow!("egress allowlists require the Docker backend"));
             }
+            match app_config.sandbox_profiles.is_empty() {
+                true => HashMap::new(),
+                false => {
+                    let docker_info = client.info().await.context("could not get Docker info")?;
+                    let apparmor_profiles =
+                        tokio::fs::read_to_string(APPARMOR_PROFILES_FILE).await.ok();
+                    app_config
+                        .sandbox_profiles
+                        .iter()
+                        .map(|(name, config)| {
+                            let profiles = apparmor_profiles.as_deref();
+                            let profile = SandboxProfile::load(config, &docker_info, profiles)
+                                .with_context(|| format!("invalid sandbox profile '{name}'"))?;
+                            Ok((name.to_owned(), profile))
+                        })
+                        .collect::<anyhow::Result<HashMap<_, _>>>()?
+                }
+            }
+        };
 
-            templates
+        // Load and validate the languages, and reload them when the languages directory changes
+        let languages = {
+            let dir = app_config.languages_dir.clone();
+            let profile_names = sandbox_profiles.keys().cloned().collect();
+            let store = tokio::task::spawn_blocking(move || LanguageStore::new(dir, profile_names))
+                .await?
+                .context("could not load languages")?;
+            Arc::new(store)
         };
+        if app_config.languages_dir.is_some() {
+            tokio::spawn(language_reload_task(
+                languages.clone(),
+                LANGUAGES_POLL_INTERVAL,
+            ));
+        }
 
         let redis = state
             .get::<RedisClient>()
             .ok_or_else(|| anyhow!("redis not in state"))?
             .to_owned();
 
-        // Start image cleanup task
-        let cleanup_period = Duration::from_secs(app_config.cleanup_interval.into());
-        tokio::spawn(image_cleanup_task(client.clone(), cleanup_period));
+        // Start image cleanup and function status sync tasks (Docker backend only)
+        if !local {
+            let cleanup_period = Duration::from_secs(app_config.cleanup_interval.into());
+            tokio::spawn(image_cleanup_task(
+                client.clone(),
+                redis.clone(),
+                cleanup_period,
+                app_config.image_cache_size,
+                app_config.fn_version_retention,
+            ));
+            tokio::spawn(sync_function_status_task(
+                client.clone(),
+                redis.clone(),
+                Duration::from_secs(120),
+            ));
+        }
+
+        // Limits of output artifacts
+        let artifacts_max_bytes = u64::from(app_config.artifacts_max_size_mb) * 1024 * 1024;
+        let artifacts = ArtifactConfig {
+            max_files: app_config.artifacts_max_files,
+            max_total_bytes: artifacts_max_bytes,
+            inline_max_bytes: app_config.artifact_inline_max_bytes,
+            ttl: app_config.artifact_ttl,
+            volume_quota: app_config.artifacts_volume_quota,
+        };
 
-        // Start function status sync task
-        tokio::spawn(sync_function_status_task(
-            client.clone(),
-            redis.clone(),
-            Duration::from_secs(120),
-        ));
+        // Start function container pool, and task to remove idle containers
+        let pool_config = PoolConfig {
+            size: app_config.fn_pool_size,
+            idle_ttl: Duration::from_secs(app_config.fn_pool_idle_ttl.into()),
+            max_containers: app_config.fn_pool_max_containers,
+            output_dir_quota: artifacts.output_dir_quota(),
+        };
+        let fn_pool = Arc::new(FunctionPool::new(client.clone(), pool_config));
+        if !local {
+            tokio::spawn(pool_eviction_task(fn_pool.clone(), Duration::from_secs(30)));
+        }
+
+        // Limit concurrent builds and runs
+        let admission = Arc::new(AdmissionController::new(AdmissionConfig {
+            max_builds: app_config.max_concurrent_builds,
+            max_runs: app_config.max_concurrent_runs,
+            max_queue: app_config.max_queue_size,
+        }));
+
+        // Start the egress proxy if any allowlists are configured
+        let egress = if app_config.egress_allowlists.is_empty() {
+            None
+        } else {
+            let allowlists = app_config
+                .egress_allowlists
+                .iter()
+                .map(|(name, rules)| {
+                    let rules = rules
+                        .iter()
+                        .map(|rule| EgressRule::parse(rule))
+                        .collect::<Result<Arc<[_]>, _>>()
+                        .map_err(|err| anyhow!("invalid egress allowlist '{name}': {err}"))?;
+                    Ok((name.to_owned(), rules))
+                })
+                .collect::<anyhow::Result<_>>()?;
+            let gateway = ensure_network(&client, &app_config.egress_network)
+                .await
+                .context("could not create egress network")?;
+            let bind = match app_config.egress_proxy_bind {
+                Some(bind) => bind,
+                None => {
+                    let gateway = gateway.context("egress network has no gateway address")?;
+                    SocketAddr::from((gateway, DEFAULT_PROXY_PORT))
+                }
+            };
+            let proxy_address = match &app_config.egress_proxy_address {
+                Some(address) => address.to_owned(),
+                None if !bind.ip().is_unspecified() => bind.to_string(),
+                None => {
+                    return Err(anyhow!(
+                        "egress proxy address is required if the proxy binds to all interfaces"
+                    ));
+                }
+            };
+
+            let listener = tokio::net::TcpListener::bind(bind)
+                .await
+                .context("could not bind egress proxy")?;
+            let proxy = Arc::new(EgressProxy::new(EgressConfig {
+                allowlists,
+                network: app_config.egress_network.clone(),
+                proxy_address,
+            }));
+            tokio::spawn(proxy.clone().serve(listener));
+            Some(proxy)
+        };
+
+        // Check that bubblewrap works if using the local backend
+        let local_backend = if local {
+            let cgroup_dir = app_config
+                .local_cgroup_dir
+                .clone()
+                .context("the local backend needs a cgroup to enforce the memory and CPU limits")?;
+            let backend = LocalBackend::new(LocalConfig {
+                bwrap_path: app_config.local_bwrap_path.clone(),
+                work_dir: app_config.local_work_dir.clone(),
+                ro_binds: app_config.local_ro_binds.clone(),
+                cgroup_dir,
+                output_dir_bytes: artifacts_max_bytes,
+            });
+            let version = backend
+                .check()
+                .await
+                .context("local backend is unavailable")?;
+            tracing::info!("Using local backend with {version}");
+            Some(Arc::new(backend))
+        } else {
+            None
+        };
+
+        // Compile the WASI interpreters if the WebAssembly runtime is enabled
+        #[cfg(feature = "wasm")]
+        let wasm_backend = {
+            let current = languages.get();
+            for lang in app_config.wasm_interpreters.keys() {
+                if !current.data.contains_key(lang) {
+                    return Err(anyhow!(
+                        "WASI interpreters can't be configured for '{lang}'"
+                    ));
+                }
+            }
+            let config = super::WasmConfig {
+                work_dir: app_config.wasm_work_dir.clone(),
+                interpreters: app_config.wasm_interpreters.clone(),
+                output_dir_bytes: artifacts_max_bytes,
+            };
+            let backend = tokio::task::spawn_blocking(move || super::WasmBackend::new(config))
+                .await?
+                .context("WebAssembly runtime is unavailable")?;
+            let interpreters: Vec<_> = app_config
+                .wasm_interpreters
+                .keys()
+                .map(|l| l.as_str())
+                .collect();
+            tracing::info!("Using WebAssembly runtime with interpreters for {interpreters:?}");
+            Arc::new(backend)
+        };
 
         // Add runner to state
-        let runner = DockerRunner::new(client, redis, language_data, templates);
+        let runner = DockerRunner::new(client, redis, fn_pool, admission, artifacts, languages);
+        let runner = match egress {
+            Some(egress) => runner.with_egress(egress),
+            None => runner,
+        }
+        .with_sandbox_profiles(sandbox_profiles);
+        let runner = match local_backend {
+            Some(local_backend) => runner.with_local_backend(local_backend),
+            None => runner,
+        };
+        #[cfg(feature = "wasm")]
+        let runner = runner.with_wasm_backend(wasm_backend);
         state.insert(runner);
 
         Ok(state)
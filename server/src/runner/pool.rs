@@ -0,0 +1,432 @@
+//! Pool of pre-created containers for ready functions
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bollard::{
+    Docker,
+    query_parameters::{
+        ListContainersOptionsBuilder, RemoveContainerOptionsBuilder, RenameContainerOptionsBuilder,
+    },
+};
+
+use crate::runner::{constants::POOL_LABEL, helpers, sandbox::SandboxProfile};
+
+/// Configuration of the warm container pool
+#[derive(Debug, Clone, Copy)]
+pub struct PoolConfig {
+    /// Number of containers to keep ready for each function
+    pub size: usize,
+    /// Time after which an unused container is removed
+    pub idle_ttl: Duration,
+    /// Maximum number of pooled containers across all functions
+    pub max_containers: usize,
+    /// Disk quota of the output directory volume of the containers, if enabled
+    pub output_dir_quota: Option<u64>,
+}
+
+/// Settings that the pooled containers are created with. A container can only
+/// be used for a run with the same settings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolSpec {
+    /// Tag of the function image
+    pub image_tag: String,
+    /// Sandbox profile of the containers
+    pub sandbox: Option<Arc<SandboxProfile>>,
+    pub timeout: u32,
+    pub mem_limit_mb: u32,
+    pub cpu_limit: f32,
+}
+
+struct PooledContainer {
+    name: String,
+    spec: PoolSpec,
+    created_at: Instant,
+}
+
+#[derive(Default)]
+struct FunctionContainers {
+    ready: Vec<PooledContainer>,
+    /// Number of containers currently being created
+    pending: usize,
+    /// Incremented when the pool is invalidated, so that containers still being
+    /// created from the old image are discarded
+    epoch: u64,
+}
+
+/// # Warm container pool
+///
+/// Keeps pre-created (not yet started) containers for each function that has been run,
+/// so that an invocation can skip creating the container. The pool of a function
+/// is replenished in the background after each invocation, and invalidated
+/// when the function is rebuilt. Runs with egress or secrets don't use the pool.
+pub struct FunctionPool {
+    client: Docker,
+    config: PoolConfig,
+    functions: Mutex<HashMap<String, FunctionContainers>>,
+}
+
+impl FunctionPool {
+    pub fn new(client: Docker, config: PoolConfig) -> Self {
+        Self {
+            client,
+            config,
+            functions: Mutex::default(),
+        }
+    }
+
+    /// Take a pooled container for the function and rename it to the run ID. Returns
+    /// `false` if no container with matching settings was available.
+    pub async fn take(&self, fn_name: &str, spec: &PoolSpec, run_id: &str) -> bool {
+        let Some(container) = self.take_ready(fn_name, spec) else {
+            return false;
+        };
+
+        let rename_opt = RenameContainerOptionsBuilder::new().name(run_id).build();
+        match self
+            .client
+            .rename_container(&container.name, rename_opt)
+            .await
+        {
+            Ok(_) => true,
+            Err(err) => {
+                tracing::warn!(
+                    "Failed to rename pooled container '{}': {err}",
+                    container.name
+                );
+                helpers::run_cleanup(&self.client, &container.name).await;
+                false
+            }
+        }
+    }
+
+    /// Remove a ready container with matching settings from the function's pool
+    fn take_ready(&self, fn_name: &str, spec: &PoolSpec) -> Option<PooledContainer> {
+        let mut functions = self.functions.lock().expect("pool lock poisoned");
+        let containers = functions.get_mut(fn_name)?;
+        let idle_ttl = self.config.idle_ttl;
+        containers
+            .ready
+            .iter()
+            .position(|c| c.spec == *spec && c.created_at.elapsed() < idle_ttl)
+            .map(|idx| containers.ready.swap_remove(idx))
+    }
+
+    /// Create containers in the background until the function's pool is full
+    pub fn replenish(self: &Arc<Self>, fn_name: &str, command: &str, spec: PoolSpec) {
+        let (num_to_create, epoch) = self.reserve(fn_name);
+        if num_to_create == 0 {
+            return;
+        }
+
+        let pool = self.clone();
+        let fn_name = fn_name.to_owned();
+        let command = command.to_owned();
+        tokio::spawn(async move {
+            for _ in 0..num_to_create {
+                let container = pool.create(&command, &spec).await;
+                pool.finish_pending(&fn_name, epoch, container);
+            }
+        });
+    }
+
+    /// Reserve room for the containers needed to fill the function's pool, within the
+    /// limit of containers across all functions. Returns the number of containers to
+    /// create, and the current epoch of the function's pool.
+    fn reserve(&self, fn_name: &str) -> (usize, u64) {
+        let mut functions = self.functions.lock().expect("pool lock poisoned");
+        let total: usize = functions.values().map(|c| c.ready.len() + c.pending).sum();
+        let containers = functions.entry(fn_name.to_owned()).or_default();
+        let num_needed = self
+            .config
+            .size
+            .saturating_sub(containers.ready.len() + containers.pending);
+        let num_available = self.config.max_containers.saturating_sub(total);
+        let num_to_create = num_needed.min(num_available);
+        containers.pending += num_to_create;
+        (num_to_create, containers.epoch)
+    }
+
+    /// Add a created container to the function's pool, unless the pool has
+    /// been invalidated in the meantime
+    fn finish_pending(&self, fn_name: &str, epoch: u64, container: Option<PooledContainer>) {
+        let stale = {
+            let mut functions = self.functions.lock().expect("pool lock poisoned");
+            let containers = functions.entry(fn_name.to_owned()).or_default();
+            containers.pending = containers.pending.saturating_sub(1);
+            match container {
+                Some(container) if containers.epoch == epoch => {
+                    containers.ready.push(container);
+                    None
+                }
+                stale => stale,
+            }
+        };
+        if let Some(container) = stale {
+            self.remove_containers(vec![container]);
+        }
+    }
+
+    /// Remove all pooled containers of the function (e.g. after the function is rebuilt)
+    pub fn invalidate(&self, fn_name: &str) {
+        let removed = {
+            let mut functions = self.functions.lock().expect("pool lock poisoned");
+            functions.get_mut(fn_name).map(|containers| {
+                containers.epoch += 1;
+                std::mem::take(&mut containers.ready)
+            })
+        };
+        if let Some(containers) = removed {
+            self.remove_containers(containers);
+        }
+    }
+
+    async fn create(&self, command: &str, spec: &PoolSpec) -> Option<PooledContainer> {
+        let name = format!("code-runner-pool-{}", uuid::Uuid::new_v4());
+        let (mut body, options) = helpers::setup_container(
+            &name,
+            &spec.image_tag,
+            command,
+            true,
+            spec.timeout,
+            spec.mem_limit_mb,
+            spec.cpu_limit,
+        );
+        if let Some(sandbox) = &spec.sandbox {
+            sandbox.apply(&mut body);
+        }
+        helpers::mount_output_dir(&mut body, &name, self.config.output_dir_quota);
+        body.labels
+            .get_or_insert_default()
+            .insert(POOL_LABEL.into(), name.clone());
+        match self.client.create_container(Some(options), body).await {
+            Ok(_) => Some(PooledContainer {
+                name,
+                spec: spec.clone(),
+                created_at: Instant::now(),
+            }),
+            Err(err) => {
+                let image_tag = &spec.image_tag;
+                tracing::warn!("Failed to create pooled container for '{image_tag}': {err}");
+                None
+            }
+        }
+    }
+
+    fn remove_containers(&self, containers: Vec<PooledContainer>) {
+        if containers.is_empty() {
+            return;
+        }
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            for container in containers {
+                helpers::run_cleanup(&client, &container.name).await;
+            }
+        });
+    }
+}
+
+/// Task to periodically remove pooled containers that have been idle longer than the TTL,
+/// and taken containers that weren't removed after their run. On startup, any pooled
+/// containers left over from a previous run of the server are removed.
+pub async fn pool_eviction_task(pool: Arc<FunctionPool>, period: Duration) {
+    remove_orphaned_containers(&pool.client).await;
+
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        remove_used_containers(&pool.client, period).await;
+
+        let expired: Vec<_> = {
+            let mut functions = pool.functions.lock().expect("pool lock poisoned");
+            let idle_ttl = pool.config.idle_ttl;
+            let expired = functions
+                .values_mut()
+                .flat_map(|containers| {
+                    let (expired, ready): (Vec<_>, Vec<_>) = std::mem::take(&mut containers.ready)
+                        .into_iter()
+                        .partition(|c| c.created_at.elapsed() >= idle_ttl);
+                    containers.ready = ready;
+                    expired
+                })
+                .collect();
+            functions.retain(|_, c| !c.ready.is_empty() || c.pending > 0);
+            expired
+        };
+        if !expired.is_empty() {
+            tracing::info!("Removing {} idle pooled containers", expired.len());
+            pool.remove_containers(expired);
+        }
+    }
+}
+
+/// Remove containers that were taken from the pool and exited more than `grace` ago, in
+/// case the cleanup after their run failed. Containers keep the pool label after being
+/// taken (labels can't be changed), so they're skipped when pruning stopped containers.
+async fn remove_used_containers(client: &Docker, grace: Duration) {
+    let filters = [
+        ("label", vec![POOL_LABEL]),
+        ("status", vec!["exited", "dead"]),
+    ];
+    let list_opt = ListContainersOptionsBuilder::new()
+        .all(true)
+        .filters(&filters.into())
+        .build();
+    let containers = match client.list_containers(Some(list_opt)).await {
+        Ok(containers) => containers,
+        Err(err) => {
+            tracing::warn!("Failed to list used pooled containers: {err}");
+            return;
+        }
+    };
+    for id in containers.into_iter().filter_map(|c| c.id) {
+        let Ok(info) = client.inspect_container(&id, None).await else {
+            continue;
+        };
+        let exited_for = info
+            .state
+            .and_then(|state| state.finished_at)
+            .and_then(|finished_at| chrono::DateTime::parse_from_rfc3339(&finished_at).ok())
+            .and_then(|finished_at| (chrono::Utc::now() - finished_at.to_utc()).to_std().ok());
+        if exited_for.is_some_and(|exited_for| exited_for >= grace) {
+            helpers::run_cleanup(client, &id).await;
+        }
+    }
+}
+
+async fn remove_orphaned_containers(client: &Docker) {
+    let list_opt = ListContainersOptionsBuilder::new()
+        .all(true)
+        .filters(&[("label", vec![POOL_LABEL])].into())
+        .build();
+    let containers = match client.list_containers(Some(list_opt)).await {
+        Ok(containers) => containers,
+        Err(err) => {
+            tracing::warn!("Failed to list pooled containers: {err}");
+            return;
+        }
+    };
+    let remove_opt = RemoveContainerOptionsBuilder::new()
+        .force(true)
+        .v(true)
+        .build();
+    for id in containers.into_iter().filter_map(|c| c.id) {
+        if let Err(err) = client.remove_container(&id, Some(remove_opt.clone())).await {
+            tracing::warn!("Failed to remove orphaned pooled container: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bollard::{API_DEFAULT_VERSION, models::SystemInfo};
+
+    use super::*;
+    use crate::runner::sandbox::SandboxProfileConfig;
+
+    fn pool(size: usize, max_containers: usize) -> FunctionPool {
+        // The client is never connected, as these tests don't create containers
+        let client = Docker::connect_with_http("http://127.0.0.1:9", 1, API_DEFAULT_VERSION)
+            .expect("client should be created");
+        let config = PoolConfig {
+            size,
+            idle_ttl: Duration::from_secs(60),
+            max_containers,
+            output_dir_quota: None,
+        };
+        FunctionPool::new(client, config)
+    }
+
+    fn spec(image_tag: &str) -> PoolSpec {
+        PoolSpec {
+            image_tag: image_tag.into(),
+            sandbox: None,
+            timeout: 10,
+            mem_limit_mb: 128,
+            cpu_limit: 0.5,
+        }
+    }
+
+    fn container(name: &str, spec: PoolSpec) -> Option<PooledContainer> {
+        Some(PooledContainer {
+            name: name.into(),
+            spec,
+            created_at: Instant::now(),
+        })
+    }
+
+    #[test]
+    fn takes_container_with_matching_spec() {
+        let pool = pool(2, 10);
+        let (num_to_create, epoch) = pool.reserve("greet");
+        assert_eq!(num_to_create, 2);
+        pool.finish_pending("greet", epoch, container("pool-1", spec("fn:v1")));
+        pool.finish_pending("greet", epoch, container("pool-2", spec("fn:v2")));
+
+        let other_limits = PoolSpec {
+            mem_limit_mb: 256,
+            ..spec("fn:v1")
+        };
+        assert!(pool.take_ready("greet", &other_limits).is_none());
+        assert!(pool.take_ready("other", &spec("fn:v1")).is_none());
+        let taken = pool.take_ready("greet", &spec("fn:v2"));
+        assert_eq!(taken.map(|c| c.name), Some("pool-2".into()));
+        assert!(pool.take_ready("greet", &spec("fn:v2")).is_none());
+        let taken = pool.take_ready("greet", &spec("fn:v1"));
+        assert_eq!(taken.map(|c| c.name), Some("pool-1".into()));
+    }
+
+    #[test]
+    fn does_not_take_container_with_other_sandbox() {
+        let pool = pool(1, 10);
+        let (_, epoch) = pool.reserve("greet");
+        let config = SandboxProfileConfig {
+            ulimits: [("nofile".into(), 64)].into(),
+            ..Default::default()
+        };
+        let profile = SandboxProfile::load(&config, &SystemInfo::default(), None).unwrap();
+        let sandboxed = PoolSpec {
+            sandbox: Some(Arc::new(profile)),
+            ..spec("fn:v1")
+        };
+        pool.finish_pending("greet", epoch, container("pool-1", sandboxed.clone()));
+
+        assert!(pool.take_ready("greet", &spec("fn:v1")).is_none());
+        assert!(pool.take_ready("greet", &sandboxed).is_some());
+    }
+
+    #[test]
+    fn replenishes_up_to_limits() {
+        let pool = pool(3, 4);
+        let (num_to_create, epoch) = pool.reserve("greet");
+        assert_eq!(num_to_create, 3);
+        // Containers still being created count towards the pool size
+        assert_eq!(pool.reserve("greet").0, 0);
+        // Limited by the maximum number of containers across functions
+        assert_eq!(pool.reserve("other").0, 1);
+        assert_eq!(pool.reserve("other").0, 0);
+
+        // A failed creation frees its reservation
+        pool.finish_pending("greet", epoch, None);
+        assert_eq!(pool.reserve("other").0, 1);
+
+        // Ready containers count until they're taken
+        pool.finish_pending("greet", epoch, container("pool-1", spec("fn:v1")));
+        assert_eq!(pool.reserve("greet").0, 0);
+        assert!(pool.take_ready("greet", &spec("fn:v1")).is_some());
+        assert_eq!(pool.reserve("greet").0, 1);
+    }
+
+    #[tokio::test]
+    async fn discards_containers_created_before_invalidation() {
+        let pool = pool(1, 10);
+        let (_, epoch) = pool.reserve("greet");
+        pool.invalidate("greet");
+        pool.finish_pending("greet", epoch, container("pool-1", spec("fn:v1")));
+        assert!(pool.take_ready("greet", &spec("fn:v1")).is_none());
+        assert_eq!(pool.reserve("greet").0, 1);
+    }
+}
@@ -0,0 +1,205 @@
+//! Registry of in-flight runs on this server
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use schemars::JsonSchema;
+use serde::Serialize;
+use tinirun_models::CodeRunnerLanguage;
+use tokio_util::sync::CancellationToken;
+
+/// What is being run
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RunKind {
+    /// One-off code execution
+    Code,
+    /// One-off code execution against test cases, in judge mode
+    Judge,
+    /// Repeated one-off code execution with timing statistics, in benchmark mode
+    Benchmark,
+    /// Build of a function image
+    FunctionBuild { name: String },
+    /// Function invocation
+    Function { name: String },
+}
+
+/// Current phase of a run
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RunPhase {
+    /// Waiting for a build or run slot
+    Queued,
+    /// Checking, pulling, or building images
+    Building,
+    /// Running the container
+    Running,
+}
+
+/// Info about an in-flight run
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct RunInfo {
+    pub id: String,
+    #[serde(flatten)]
+    pub kind: RunKind,
+    pub lang: CodeRunnerLanguage,
+    pub phase: RunPhase,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+}
+
+struct ActiveRun {
+    info: RunInfo,
+    cancel: CancellationToken,
+}
+
+/// Keeps track of the runs in progress on this server, so that they can be listed and cancelled
+#[derive(Default)]
+pub struct RunRegistry {
+    runs: Mutex<HashMap<String, ActiveRun>>,
+}
+
+impl RunRegistry {
+    /// Register a new run, which is removed from the registry when the handle is dropped.
+    pub fn register(
+        self: &Arc<Self>,
+        id: String,
+        kind: RunKind,
+        lang: CodeRunnerLanguage,
+        phase: RunPhase,
+    ) -> RunHandle {
+        let cancel = CancellationToken::new();
+        let info = RunInfo {
+            id: id.clone(),
+            kind,
+            lang,
+            phase,
+            started_at: chrono::Utc::now(),
+        };
+        let active_run = ActiveRun {
+            info,
+            cancel: cancel.clone(),
+        };
+        self.runs
+            .lock()
+            .expect("run registry lock poisoned")
+            .insert(id.clone(), active_run);
+
+        RunHandle {
+            id,
+            cancel,
+            registry: self.clone(),
+        }
+    }
+
+    /// List the runs in progress, sorted by start time
+    pub fn list(&self) -> Vec<RunInfo> {
+        let runs = self.runs.lock().expect("run registry lock poisoned");
+        let mut list: Vec<_> = runs.values().map(|run| run.info.clone()).collect();
+        list.sort_by_key(|info| info.started_at);
+        list
+    }
+
+    /// Request cancellation of the run. Returns `false` if the run was not found.
+    pub fn cancel(&self, id: &str) -> bool {
+        let runs = self.runs.lock().expect("run registry lock poisoned");
+        match runs.get(id) {
+            Some(run) => {
+                run.cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Handle of a registered run
+pub struct RunHandle {
+    id: String,
+    cancel: CancellationToken,
+    registry: Arc<RunRegistry>,
+}
+
+impl RunHandle {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn set_phase(&self, phase: RunPhase) {
+        let mut runs = self
+            .registry
+            .runs
+            .lock()
+            .expect("run registry lock poisoned");
+        if let Some(run) = runs.get_mut(&self.id) {
+            run.info.phase = phase;
+        }
+    }
+
+    /// Wait until the run is cancelled
+    pub async fn cancelled(&self) {
+        self.cancel.cancelled().await
+    }
+}
+
+impl Drop for RunHandle {
+    fn drop(&mut self) {
+        self.registry
+            .runs
+            .lock()
+            .expect("run registry lock poisoned")
+            .remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn register(registry: &Arc<RunRegistry>, id: &str) -> RunHandle {
+        let kind = RunKind::Function {
+            name: "test".to_owned(),
+        };
+        let lang = CodeRunnerLanguage::from("python");
+        registry.register(id.to_owned(), kind, lang, RunPhase::Queued)
+    }
+
+    #[test]
+    fn lists_runs_by_start_time() {
+        let registry = Arc::new(RunRegistry::default());
+        let _first = register(&registry, "run-1");
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        let second = register(&registry, "run-2");
+        second.set_phase(RunPhase::Running);
+
+        let runs = registry.list();
+        let ids: Vec<_> = runs.iter().map(|run| run.id.as_str()).collect();
+        assert_eq!(ids, ["run-1", "run-2"]);
+        assert_eq!(runs[0].phase, RunPhase::Queued);
+        assert_eq!(runs[1].phase, RunPhase::Running);
+    }
+
+    #[tokio::test]
+    async fn cancels_run() {
+        let registry = Arc::new(RunRegistry::default());
+        let run = register(&registry, "run-1");
+
+        assert!(!registry.cancel("run-2"));
+        assert!(registry.cancel("run-1"));
+        tokio::time::timeout(std::time::Duration::from_secs(1), run.cancelled())
+            .await
+            .expect("run should be cancelled");
+    }
+
+    #[test]
+    fn removes_run_when_handle_is_dropped() {
+        let registry = Arc::new(RunRegistry::default());
+        let run = register(&registry, "run-1");
+        assert_eq!(registry.list().len(), 1);
+
+        drop(run);
+        assert!(registry.list().is_empty());
+        assert!(!registry.cancel("run-1"));
+    }
+}
@@ -0,0 +1,303 @@
+//! Sandbox profiles that harden the containers of runs
+
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::{Context, bail};
+use bollard::models::{ContainerCreateBody, ResourcesUlimits, SystemInfo};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+/// File listing the AppArmor profiles loaded in the kernel, as `name (mode)` lines
+pub const APPARMOR_PROFILES_FILE: &str = "/sys/kernel/security/apparmor/profiles";
+
+/// Names of the ulimits that can be set by a profile
+const ULIMIT_NAMES: &[&str] = &[
+    "core",
+    "cpu",
+    "data",
+    "fsize",
+    "locks",
+    "memlock",
+    "msgqueue",
+    "nice",
+    "nofile",
+    "nproc",
+    "rss",
+    "rtprio",
+    "rttime",
+    "sigpending",
+    "stack",
+];
+
+/// Sandbox profile as defined in the server config
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct SandboxProfileConfig {
+    /// Path to a custom seccomp profile (JSON) on the server, used instead of
+    /// Docker's default profile
+    pub seccomp: Option<PathBuf>,
+    /// Name of an AppArmor profile loaded on the Docker host
+    pub apparmor: Option<String>,
+    /// User namespace mode of the containers
+    pub userns_mode: Option<UsernsMode>,
+    /// OCI runtime to use for the containers (e.g. `runsc`), as registered with Docker
+    pub runtime: Option<String>,
+    /// Resource limits by name (e.g. `fsize` or `nofile`). The soft and hard limits are
+    /// set to the same value.
+    #[serde(default)]
+    pub ulimits: HashMap<String, i64>,
+}
+
+/// User namespace mode of the containers
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UsernsMode {
+    /// Require the Docker daemon to remap container users to unprivileged host users
+    /// (the `userns-remap` daemon option)
+    Remap,
+    /// Use the host's user namespace, even if the daemon remaps users
+    Host,
+}
+
+/// Sandbox profile loaded and validated at startup, applied to the containers of runs
+#[derive(Debug, Clone, PartialEq)]
+pub struct SandboxProfile {
+    security_opt: Vec<String>,
+    userns_mode: Option<String>,
+    runtime: Option<String>,
+    ulimits: Vec<(String, i64)>,
+}
+
+impl SandboxProfile {
+    /// Load the profile, validating that the Docker daemon supports its options and that
+    /// the referenced runtime, seccomp profile and AppArmor profile exist. The loaded
+    /// AppArmor profiles are read from [`APPARMOR_PROFILES_FILE`] on the Docker host.
+    pub fn load(
+        config: &SandboxProfileConfig,
+        info: &SystemInfo,
+        apparmor_profiles: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let security_options = info.security_options.as_deref().unwrap_or_default();
+        let daemon_supports = |name: &str| {
+            security_options
+                .iter()
+                .any(|opt| opt.split(',').any(|part| part == format!("name={name}")))
+        };
+
+        let mut security_opt = Vec::new();
+        if let Some(path) = &config.seccomp {
+            if !daemon_supports("seccomp") {
+                bail!("seccomp is not enabled in Docker");
+            }
+            let profile = std::fs::read_to_string(path)
+                .with_context(|| format!("could not read seccomp profile {path:?}"))?;
+            let profile: serde_json::Value = serde_json::from_str(&profile)
+                .with_context(|| format!("invalid seccomp profile {path:?}"))?;
+            security_opt.push(format!("seccomp={profile}"));
+        }
+        if let Some(apparmor) = &config.apparmor {
+            if !daemon_supports("apparmor") {
+                bail!("AppArmor is not enabled in Docker");
+            }
+            if apparmor != "unconfined" {
+                let Some(profiles) = apparmor_profiles else {
+                    bail!(
+                        "could not read the loaded AppArmor profiles from {APPARMOR_PROFILES_FILE} \
+                        (the server must run on the Docker host)"
+                    );
+                };
+                if !apparmor_profile_loaded(profiles, apparmor) {
+                    bail!("AppArmor profile '{apparmor}' is not loaded");
+                }
+            }
+            security_opt.push(format!("apparmor={apparmor}"));
+        }
+
+        let userns_mode = match config.userns_mode {
+            Some(UsernsMode::Remap) if !daemon_supports("userns") => {
+                bail!("user namespace remapping is not enabled in Docker");
+            }
+            Some(UsernsMode::Remap) | None => None,
+            Some(UsernsMode::Host) => Some("host".to_owned()),
+        };
+
+        if let Some(runtime) = &config.runtime {
+            let runtimes = info.runtimes.as_ref();
+            if runtimes.is_none_or(|runtimes| !runtimes.contains_key(runtime)) {
+                bail!("runtime '{runtime}' is not registered with Docker");
+            }
+        }
+
+        let mut ulimits: Vec<_> = config
+            .ulimits
+            .iter()
+            .map(|(name, limit)| {
+                if !ULIMIT_NAMES.contains(&name.as_str()) {
+                    bail!("unknown ulimit '{name}'");
+                }
+                if *limit < 0 {
+                    bail!("ulimit '{name}' cannot be negative");
+                }
+                Ok((name.to_owned(), *limit))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        ulimits.sort();
+
+        Ok(Self {
+            security_opt,
+            userns_mode,
+            runtime: config.runtime.clone(),
+            ulimits,
+        })
+    }
+
+    /// Apply the profile to the container. Ulimits of the profile replace the default
+    /// ulimits with the same name.
+    pub fn apply(&self, container_body: &mut ContainerCreateBody) {
+        let host_config = container_body.host_config.get_or_insert_default();
+        host_config
+            .security_opt
+            .get_or_insert_default()
+            .extend(self.security_opt.iter().cloned());
+        if let Some(userns_mode) = &self.userns_mode {
+            host_config.userns_mode = Some(userns_mode.to_owned());
+        }
+        if let Some(runtime) = &self.runtime {
+            host_config.runtime = Some(runtime.to_owned());
+        }
+
+        let ulimits = host_config.ulimits.get_or_insert_default();
+        for (name, limit) in &self.ulimits {
+            ulimits.retain(|ulimit| ulimit.name.as_ref() != Some(name));
+            ulimits.push(ResourcesUlimits {
+                name: Some(name.to_owned()),
+                soft: Some(*limit),
+                hard: Some(*limit),
+            });
+        }
+    }
+}
+
+/// Whether the AppArmor profile is in the list of loaded profiles
+fn apparmor_profile_loaded(profiles: &str, name: &str) -> bool {
+    profiles
+        .lines()
+        .filter_map(|line| line.rsplit_once(" ("))
+        .any(|(profile, _)| profile == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use bollard::models::{HostConfig, Runtime};
+
+    use super::*;
+
+    fn docker_info() -> SystemInfo {
+        SystemInfo {
+            security_options: Some(vec![
+                "name=apparmor".into(),
+                "name=seccomp,profile=builtin".into(),
+            ]),
+            runtimes: Some([("runc".into(), Runtime::default())].into()),
+            ..Default::default()
+        }
+    }
+
+    const APPARMOR_PROFILES: &str = "docker-default (enforce)\ntinirun (enforce)\n";
+
+    #[test]
+    fn validates_profile() {
+        let info = docker_info();
+        let profiles = Some(APPARMOR_PROFILES);
+        let valid = SandboxProfileConfig {
+            apparmor: Some("tinirun".into()),
+            runtime: Some("runc".into()),
+            userns_mode: Some(UsernsMode::Host),
+            ulimits: [("fsize".into(), 1024)].into(),
+            ..Default::default()
+        };
+        assert!(SandboxProfile::load(&valid, &info, profiles).is_ok());
+
+        let invalid_configs = [
+            SandboxProfileConfig {
+                runtime: Some("runsc".into()),
+                ..Default::default()
+            },
+            SandboxProfileConfig {
+                userns_mode: Some(UsernsMode::Remap),
+                ..Default::default()
+            },
+            SandboxProfileConfig {
+                seccomp: Some("/nonexistent/seccomp.json".into()),
+                ..Default::default()
+            },
+            SandboxProfileConfig {
+                ulimits: [("files".into(), 10)].into(),
+                ..Default::default()
+            },
+            SandboxProfileConfig {
+                ulimits: [("nofile".into(), -1)].into(),
+                ..Default::default()
+            },
+            SandboxProfileConfig {
+                apparmor: Some("tinirun-strict".into()),
+                ..Default::default()
+            },
+        ];
+        for config in invalid_configs {
+            assert!(
+                SandboxProfile::load(&config, &info, profiles).is_err(),
+                "{config:?}"
+            );
+        }
+
+        // The loaded profiles must be readable, except for running unconfined
+        assert!(SandboxProfile::load(&valid, &info, None).is_err());
+        let unconfined = SandboxProfileConfig {
+            apparmor: Some("unconfined".into()),
+            ..Default::default()
+        };
+        assert!(SandboxProfile::load(&unconfined, &info, None).is_ok());
+    }
+
+    #[test]
+    fn applies_profile() {
+        let config = SandboxProfileConfig {
+            apparmor: Some("tinirun".into()),
+            runtime: Some("runc".into()),
+            ulimits: [("nproc".into(), 20), ("nofile".into(), 64)].into(),
+            ..Default::default()
+        };
+        let profile =
+            SandboxProfile::load(&config, &docker_info(), Some(APPARMOR_PROFILES)).unwrap();
+        let mut body = ContainerCreateBody {
+            host_config: Some(HostConfig {
+                security_opt: Some(vec!["no-new-privileges".into()]),
+                ulimits: Some(vec![ResourcesUlimits {
+                    name: Some("nproc".into()),
+                    soft: Some(50),
+                    hard: Some(50),
+                }]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        profile.apply(&mut body);
+
+        let host_config = body.host_config.unwrap();
+        assert_eq!(
+            host_config.security_opt.unwrap(),
+            ["no-new-privileges", "apparmor=tinirun"]
+        );
+        assert_eq!(host_config.runtime.as_deref(), Some("runc"));
+        let ulimits: Vec<_> = host_config
+            .ulimits
+            .unwrap()
+            .into_iter()
+            .map(|u| (u.name.unwrap(), u.soft.unwrap(), u.hard.unwrap()))
+            .collect();
+        assert_eq!(
+            ulimits,
+            [("nofile".into(), 64, 64), ("nproc".into(), 20, 20)]
+        );
+    }
+}
@@ -0,0 +1,155 @@
+//! Compiled JSON Schemas of the functions
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use crate::runner::validators;
+
+/// Compiled input and output schemas of a function version
+#[derive(Default)]
+pub struct FunctionSchemas {
+    pub input: Option<jsonschema::Validator>,
+    pub output: Option<jsonschema::Validator>,
+}
+
+impl FunctionSchemas {
+    pub fn compile(
+        input: Option<&serde_json::Value>,
+        output: Option<&serde_json::Value>,
+    ) -> Result<Self, String> {
+        let compile = |schema: Option<&serde_json::Value>, kind: &str| {
+            schema
+                .map(validators::compile_json_schema)
+                .transpose()
+                .map_err(|err| format!("Invalid {kind} schema: {err}"))
+        };
+        Ok(Self {
+            input: compile(input, "input")?,
+            output: compile(output, "output")?,
+        })
+    }
+}
+
+struct CachedSchemas {
+    schemas: Arc<FunctionSchemas>,
+    last_used: Instant,
+}
+
+/// Cache of the compiled schemas by function name and version, so that they are compiled
+/// when the function is created or updated instead of on every run. The least recently
+/// used versions are evicted when the cache is full.
+pub struct SchemaCache {
+    capacity: usize,
+    entries: Mutex<HashMap<(String, u32), CachedSchemas>>,
+}
+
+impl SchemaCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Add the compiled schemas of the function's version
+    pub fn insert(
+        &self,
+        name: &str,
+        version: u32,
+        schemas: FunctionSchemas,
+    ) -> Arc<FunctionSchemas> {
+        let schemas = Arc::new(schemas);
+        let mut entries = self.entries.lock().expect("schema cache lock poisoned");
+        let key = (name.to_owned(), version);
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        let entry = CachedSchemas {
+            schemas: schemas.clone(),
+            last_used: Instant::now(),
+        };
+        entries.insert(key, entry);
+        schemas
+    }
+
+    /// Get the compiled schemas of the function's version, compiling them if they aren't
+    /// cached (e.g. after a restart, or for versions restored by a rollback)
+    pub fn get_or_compile(
+        &self,
+        name: &str,
+        version: u32,
+        input: Option<&serde_json::Value>,
+        output: Option<&serde_json::Value>,
+    ) -> Result<Arc<FunctionSchemas>, String> {
+        {
+            let mut entries = self.entries.lock().expect("schema cache lock poisoned");
+            if let Some(entry) = entries.get_mut(&(name.to_owned(), version)) {
+                entry.last_used = Instant::now();
+                return Ok(entry.schemas.clone());
+            }
+        }
+        let schemas = FunctionSchemas::compile(input, output)?;
+        Ok(self.insert(name, version, schemas))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn compiles_schemas() {
+        let schema = json!({ "type": "string" });
+        let schemas = FunctionSchemas::compile(Some(&schema), None).unwrap();
+        assert!(
+            schemas
+                .input
+                .is_some_and(|input| input.is_valid(&json!("hi")))
+        );
+        assert!(schemas.output.is_none());
+
+        let invalid = json!({ "type": "nope" });
+        let err = FunctionSchemas::compile(None, Some(&invalid))
+            .err()
+            .unwrap();
+        assert!(err.starts_with("Invalid output schema: "), "{err}");
+    }
+
+    #[test]
+    fn caches_schemas_by_version() {
+        let cache = SchemaCache::new(2);
+        let schema = json!({ "type": "string" });
+        let v1 = cache.insert("greet", 1, FunctionSchemas::default());
+        let cached = cache
+            .get_or_compile("greet", 1, Some(&schema), None)
+            .unwrap();
+        assert!(Arc::ptr_eq(&v1, &cached));
+        assert!(cached.input.is_none());
+
+        // Version 2 isn't cached yet, and is compiled from the given schemas
+        let v2 = cache
+            .get_or_compile("greet", 2, Some(&schema), None)
+            .unwrap();
+        assert!(v2.input.is_some());
+
+        // Version 1 is the least recently used when the cache is full
+        cache.get_or_compile("greet", 2, None, None).unwrap();
+        cache.insert("other", 1, FunctionSchemas::default());
+        let recompiled = cache
+            .get_or_compile("greet", 1, Some(&schema), None)
+            .unwrap();
+        assert!(!Arc::ptr_eq(&v1, &recompiled));
+        assert!(recompiled.input.is_some());
+    }
+}
@@ -1,13 +1,23 @@
-use serde::Deserialize;
+use std::collections::BTreeMap;
 
-/// Config data for each language (`docker/data.toml` file)
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tinirun_models::{CodeRunnerLanguage, ResourceLimits};
+
+/// Config data for each language (`data.toml` file)
 #[derive(Debug, Clone, Deserialize)]
 pub struct LanguageData {
-    /// The base Docker image tag
+    /// The base Docker image tag of the default version
     pub image: String,
+    /// Name of the default version
+    #[serde(default = "default_version")]
+    pub version: String,
+    /// Other versions of the language, by name
+    #[serde(default)]
+    pub versions: BTreeMap<String, LanguageVersion>,
     /// The command to run the program
     pub command: String,
-    /// Name of the template folder within the `docker/templates` directory
+    /// Name of the template folder within the `templates` directory
     pub template: String,
     /// The name of the function file
     #[serde(rename = "fn_file")]
@@ -15,9 +25,103 @@ pub struct LanguageData {
     /// The name of the main file to run
     #[serde(rename = "main_file")]
     pub main_filename: String,
+    /// How the function receives its input
+    #[serde(default = "default_fn_input")]
+    pub fn_input: String,
+    /// How the function returns its output
+    #[serde(default = "default_fn_output")]
+    pub fn_output: String,
+    /// Directory that additional source files are placed in, relative to the app
+    /// directory. Defaults to the app directory itself.
+    pub src_dir: Option<String>,
+    /// Whether one-off code can be uploaded into a container of the cached dependency
+    /// image, instead of building an image with the code (i.e. no compile step is needed)
+    #[serde(default)]
+    pub inject_code: bool,
+    /// Name of the sandbox profile used for the language's runs, unless the
+    /// function selects a different profile
+    pub sandbox: Option<String>,
+}
+
+/// A version of a language
+#[derive(Debug, Clone, Deserialize)]
+pub struct LanguageVersion {
+    /// The base Docker image tag
+    pub image: String,
+    /// Name of the template folder to use instead of the language's template
+    pub template: Option<String>,
+}
+
+impl LanguageData {
+    /// Get the data of the given version of the language, or the default version
+    pub fn for_version(&self, version: Option<&str>) -> Option<LanguageData> {
+        let Some(version) = version.filter(|v| *v != self.version) else {
+            return Some(self.clone());
+        };
+        let version_data = self.versions.get(version)?;
+        let mut lang_data = self.clone();
+        lang_data.image = version_data.image.clone();
+        if let Some(template) = &version_data.template {
+            lang_data.template = template.clone();
+        }
+        lang_data.version = version.to_owned();
+        Some(lang_data)
+    }
+
+    /// Get the available versions, starting with the default version
+    pub fn version_info(&self) -> LanguageVersions {
+        let default_version = LanguageVersionInfo {
+            name: self.version.clone(),
+            image: self.image.clone(),
+        };
+        let other_versions = self
+            .versions
+            .iter()
+            .map(|(name, version)| LanguageVersionInfo {
+                name: name.clone(),
+                image: version.image.clone(),
+            });
+        LanguageVersions {
+            default_version: self.version.clone(),
+            versions: std::iter::once(default_version)
+                .chain(other_versions)
+                .collect(),
+        }
+    }
+}
+
+fn default_version() -> String {
+    "default".to_owned()
+}
+
+fn default_fn_input() -> String {
+    "The run's input string, read from stdin and passed to the `run` function".to_owned()
 }
 
-/// Template data for each language (in the `docker/templates` directory)
+fn default_fn_output() -> String {
+    "The return value of the `run` function, printed to stdout. Errors are printed to \
+     stderr with a non-zero exit code."
+        .to_owned()
+}
+
+/// Available versions of a language
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct LanguageVersions {
+    /// Name of the version used if none is selected
+    pub default_version: String,
+    pub versions: Vec<LanguageVersionInfo>,
+}
+
+/// A version of a language
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct LanguageVersionInfo {
+    /// Name of the version, e.g. `3.13`
+    pub name: String,
+    /// The base Docker image of the version
+    pub image: String,
+}
+
+/// Template data for each language (in the `templates` directory)
 pub struct LanguageTemplates {
     /// The Dockerfile template
     pub dockerfile: liquid::Template,
@@ -25,4 +129,38 @@ pub struct LanguageTemplates {
     pub main_file: String,
     /// A sample function file showing the correct inputs / outputs for the function
     pub fn_file: String,
+    /// Whether the Dockerfile template installs dependencies
+    pub dependencies: bool,
+}
+
+/// Info about a language, for clients
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct LanguageInfo {
+    /// ID of the language, used as the `lang` of runs and functions
+    pub id: CodeRunnerLanguage,
+    #[serde(flatten)]
+    pub versions: LanguageVersions,
+    /// The base Docker image of the default version
+    pub image: String,
+    /// Name of the entrypoint file of one-off code
+    pub main_file: String,
+    /// Name of the function file
+    pub fn_file: String,
+    /// Sample function file, used as the code of new functions
+    pub fn_template: String,
+    /// How functions receive their input and return their output
+    pub function: FunctionContract,
+    /// Default resource limits of runs
+    pub default_limits: ResourceLimits,
+    /// Maximum resource limits of runs
+    pub max_limits: ResourceLimits,
+    /// Whether dependencies can be installed
+    pub dependencies: bool,
+}
+
+/// How a function receives its input and returns its output
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct FunctionContract {
+    pub input: String,
+    pub output: String,
 }
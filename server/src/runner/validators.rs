@@ -1,3 +1,7 @@
+use std::path::{Component, Path};
+
+use tinirun_models::SourceFile;
+
 pub fn validate_deps_input(deps: &[String]) -> Result<(), String> {
     for dep in deps {
         validate_dependency_name(&dep)
@@ -6,6 +10,97 @@ pub fn validate_deps_input(deps: &[String]) -> Result<(), String> {
     Ok(())
 }
 
+/// Environment variables that are needed by the runtime or language setup
+const DENIED_ENV_VARS: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "PWD",
+    "SHELL",
+    "HOSTNAME",
+    "PNPM_HOME",
+    "PYTHONUSERBASE",
+    "GOCACHE",
+    "GOTMPDIR",
+    "HTTP_PROXY",
+    "HTTPS_PROXY",
+    "NO_PROXY",
+    "ALL_PROXY",
+];
+/// Prefixes of environment variables that cannot be set
+const DENIED_ENV_PREFIXES: &[&str] = &["LD_", "TINIRUN_"];
+
+pub fn validate_env_input<'a>(names: impl IntoIterator<Item = &'a String>) -> Result<(), String> {
+    for name in names {
+        validate_env_name(name)
+            .map_err(|err| format!("Invalid environment variable '{name}': {err}"))?;
+    }
+    Ok(())
+}
+
+/// Validate an environment variable name. Names must be valid shell identifiers, and
+/// can't override variables that the runtime depends on.
+fn validate_env_name(name: &str) -> Result<(), String> {
+    let mut chars = name.chars();
+    let valid_start = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+    if !valid_start || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err("name must only contain letters, digits and '_', \
+             and must not start with a digit"
+            .into());
+    }
+
+    let upper = name.to_ascii_uppercase();
+    if DENIED_ENV_VARS.contains(&upper.as_str())
+        || DENIED_ENV_PREFIXES.iter().any(|p| upper.starts_with(p))
+    {
+        return Err("variable is reserved".into());
+    }
+
+    Ok(())
+}
+
+/// Validate the name of a function alias. Names are used in `name@alias` references.
+pub fn validate_alias_name(alias: &str) -> Result<(), String> {
+    if alias.is_empty() || alias.len() > 50 {
+        return Err("Alias must be between 1 and 50 characters".into());
+    }
+    if !alias
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err("Alias must only contain letters, digits, '-' and '_'".into());
+    }
+    Ok(())
+}
+
+/// Validate that the source files don't replace any of the reserved files or directories
+/// that the runner places in the source directory (e.g. the main file). The entrypoint
+/// is skipped, as it will be placed as the main file.
+pub fn validate_sources_input(
+    sources: &[SourceFile],
+    entrypoint: Option<&Path>,
+    reserved: &[&str],
+) -> Result<(), String> {
+    for source in sources
+        .iter()
+        .filter(|s| Some(s.path.as_path()) != entrypoint)
+    {
+        let first = source.path.components().next();
+        if let Some(name) = reserved
+            .iter()
+            .find(|name| first == Some(Component::Normal(name.as_ref())))
+        {
+            return Err(format!(
+                "Invalid source file {:?}: '{name}' is reserved",
+                source.path
+            ));
+        }
+    }
+    Ok(())
+}
+
 /**
 Validate a dependency name so it is safe to interpolate into a Dockerfile `RUN` shell command.
 
@@ -45,9 +140,40 @@ fn is_safe_dep_char(c: char) -> bool {
         )
 }
 
+/// Compile the JSON Schema, checking that it is valid. References to external schemas
+/// can't be resolved.
+pub fn compile_json_schema(schema: &serde_json::Value) -> Result<jsonschema::Validator, String> {
+    jsonschema::validator_for(schema).map_err(|err| err.to_string())
+}
+
+/// Validate the value against the compiled JSON Schema. Returns all validation errors,
+/// with the path of each invalid value.
+pub fn validate_json_value(
+    validator: &jsonschema::Validator,
+    value: &serde_json::Value,
+) -> Result<(), String> {
+    let errors: Vec<_> = validator
+        .iter_errors(value)
+        .map(|err| match err.instance_path().as_str() {
+            "" => err.to_string(),
+            path => format!("{path}: {err}"),
+        })
+        .collect();
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors.join("; ")),
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::validate_dependency_name;
+    use serde_json::json;
+    use tinirun_models::SourceFile;
+
+    use super::{
+        compile_json_schema, validate_alias_name, validate_dependency_name, validate_env_name,
+        validate_json_value, validate_sources_input,
+    };
 
     #[test]
     fn accepts_valid_names() {
@@ -95,4 +221,91 @@ mod tests {
     fn rejects_empty_name() {
         assert!(validate_dependency_name("").is_err());
     }
+
+    #[test]
+    fn accepts_valid_env_names() {
+        for name in ["DEBUG", "my_var", "_PRIVATE", "LEVEL2"] {
+            assert!(
+                validate_env_name(name).is_ok(),
+                "expected '{name}' to be valid"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_and_reserved_env_names() {
+        let invalid = [
+            "",
+            "2FAST",
+            "MY-VAR",
+            "A B",
+            "X=1",
+            "PATH",
+            "home",
+            "LD_PRELOAD",
+        ];
+        for name in invalid {
+            assert!(
+                validate_env_name(name).is_err(),
+                "expected '{name}' to be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn validates_alias_names() {
+        for alias in ["prod", "staging-2", "v1_beta"] {
+            assert!(
+                validate_alias_name(alias).is_ok(),
+                "expected '{alias}' to be valid"
+            );
+        }
+        for alias in ["", "a@b", "has space", "../prod", &"a".repeat(51)] {
+            assert!(
+                validate_alias_name(alias).is_err(),
+                "expected '{alias}' to be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_reserved_source_paths() {
+        let source = |path: &str| SourceFile {
+            path: path.into(),
+            content: String::new(),
+        };
+        let reserved = ["main.py", "files"];
+        let valid = [source("utils.py"), source("lib/main.py")];
+        assert!(validate_sources_input(&valid, None, &reserved).is_ok());
+        assert!(validate_sources_input(&[source("main.py")], None, &reserved).is_err());
+        assert!(validate_sources_input(&[source("files/data.py")], None, &reserved).is_err());
+    }
+
+    #[test]
+    fn allows_entrypoint_with_reserved_name() {
+        let sources = [SourceFile {
+            path: "main.py".into(),
+            content: String::new(),
+        }];
+        let entrypoint = Some(std::path::Path::new("main.py"));
+        assert!(validate_sources_input(&sources, entrypoint, &["main.py"]).is_ok());
+    }
+
+    #[test]
+    fn validates_json_schemas() {
+        let schema = json!({
+            "type": "object",
+            "properties": { "name": { "type": "string" }, "count": { "type": "integer" } },
+            "required": ["name"]
+        });
+        assert!(compile_json_schema(&json!({ "type": "nope" })).is_err());
+        let remote_ref = json!({ "$ref": "https://example.com/schema.json" });
+        assert!(compile_json_schema(&remote_ref).is_err());
+
+        let validator = compile_json_schema(&schema).unwrap();
+        assert!(validate_json_value(&validator, &json!({ "name": "world" })).is_ok());
+        let errors = validate_json_value(&validator, &json!({ "count": "1" })).unwrap_err();
+        assert!(errors.contains(r#""name" is a required property"#));
+        assert!(errors.contains(r#"/count: "1" is not of type "integer""#));
+    }
 }
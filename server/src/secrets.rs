@@ -0,0 +1,137 @@
+//! Per-function secrets, encrypted at rest in Redis
+
+use std::collections::HashMap;
+
+use aes_gcm::{
+    Aes256Gcm, KeyInit, Nonce,
+    aead::{Aead, AeadCore, OsRng, Payload},
+};
+use anyhow::{Context, anyhow};
+use axum_app_wrapper::AdHocPlugin;
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+
+use crate::{config::AppConfig, errors::AppError, redis::RedisClient, state::AppState};
+
+/// Length of the AES-GCM nonce prepended to each encrypted value
+const NONCE_LEN: usize = 12;
+
+/// Add the secret store to server state. Validates the configured encryption key.
+pub fn plugin() -> AdHocPlugin<AppState> {
+    AdHocPlugin::new().on_init(|mut state| async {
+        let app_config = state
+            .get::<AppConfig>()
+            .ok_or_else(|| anyhow!("app config not found"))?;
+        let redis = state
+            .get::<RedisClient>()
+            .ok_or_else(|| anyhow!("redis not in state"))?
+            .to_owned();
+
+        let cipher = match &app_config.secrets_key {
+            Some(key) => {
+                let key = BASE64
+                    .decode(key)
+                    .context("secrets key is not valid base64")?;
+                let cipher = Aes256Gcm::new_from_slice(&key)
+                    .map_err(|_| anyhow!("secrets key must be 32 bytes"))?;
+                Some(cipher)
+            }
+            None => None,
+        };
+
+        state.insert(SecretStore { redis, cipher });
+        Ok(state)
+    })
+}
+
+/// # Secret store
+///
+/// Stores the secrets of each function in Redis, encrypted with AES-256-GCM using the
+/// server's secrets key. Secret values are only decrypted to inject them into the
+/// function's containers, and are never returned from the API.
+#[derive(Clone)]
+pub struct SecretStore {
+    redis: RedisClient,
+    cipher: Option<Aes256Gcm>,
+}
+
+impl SecretStore {
+    /// List the names of the function's secrets
+    pub async fn list(&self, fn_name: &str) -> Result<Vec<String>, AppError> {
+        let mut names = self.redis.get_secret_names(fn_name).await?;
+        names.sort();
+        Ok(names)
+    }
+
+    /// Encrypt and save a secret of the function, replacing any existing value
+    pub async fn set(&self, fn_name: &str, name: &str, value: &str) -> Result<(), AppError> {
+        let cipher = self.cipher()?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let aad = Self::aad(fn_name, name);
+        let payload = Payload {
+            msg: value.as_bytes(),
+            aad: aad.as_bytes(),
+        };
+        let ciphertext = cipher
+            .encrypt(&nonce, payload)
+            .map_err(|_| AppError::Server(format!("failed to encrypt secret '{name}'")))?;
+
+        let mut encrypted = nonce.to_vec();
+        encrypted.extend(ciphertext);
+        self.redis
+            .set_secret(fn_name, name, BASE64.encode(encrypted))
+            .await?;
+        Ok(())
+    }
+
+    /// Delete a secret of the function. Returns `false` if the secret was not found.
+    pub async fn delete(&self, fn_name: &str, name: &str) -> Result<bool, AppError> {
+        Ok(self.redis.delete_secret(fn_name, name).await?)
+    }
+
+    /// Get the decrypted secrets of the function
+    pub async fn get_all(&self, fn_name: &str) -> Result<HashMap<String, String>, AppError> {
+        let encrypted = self.redis.get_secrets(fn_name).await?;
+        if encrypted.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let cipher = self.cipher()?;
+        encrypted
+            .into_iter()
+            .map(
+                |(name, value)| match Self::decrypt(cipher, fn_name, &name, &value) {
+                    Some(value) => Ok((name, value)),
+                    None => Err(AppError::Server(format!(
+                        "failed to decrypt secret '{name}' of '{fn_name}'"
+                    ))),
+                },
+            )
+            .collect()
+    }
+
+    fn decrypt(cipher: &Aes256Gcm, fn_name: &str, name: &str, value: &str) -> Option<String> {
+        let encrypted = BASE64.decode(value).ok()?;
+        if encrypted.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_LEN);
+        let aad = Self::aad(fn_name, name);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: aad.as_bytes(),
+        };
+        let plaintext = cipher.decrypt(Nonce::from_slice(nonce), payload).ok()?;
+        String::from_utf8(plaintext).ok()
+    }
+
+    /// Associated data that binds the encrypted value to the function and secret name
+    fn aad(fn_name: &str, name: &str) -> String {
+        format!("{fn_name}:{name}")
+    }
+
+    fn cipher(&self) -> Result<&Aes256Gcm, AppError> {
+        self.cipher.as_ref().ok_or_else(|| {
+            AppError::BadRequest("Secrets are not enabled on this server".to_owned())
+        })
+    }
+}
@@ -4,7 +4,10 @@ use std::{any::type_name, ops::Deref, sync::Arc};
 
 use type_map::concurrent::TypeMap;
 
-use crate::{config::AppConfig, redis::RedisClient, runner::DockerRunner};
+use crate::{
+    config::AppConfig, jobs::JobManager, redis::RedisClient, runner::DockerRunner,
+    secrets::SecretStore,
+};
 
 /// App state stored in the Axum router
 #[derive(Clone)]
@@ -14,6 +17,8 @@ pub struct AppStateInner {
     pub config: AppConfig,
     pub runner: DockerRunner,
     pub redis: RedisClient,
+    pub jobs: JobManager,
+    pub secrets: SecretStore,
 }
 
 impl Deref for AppState {
@@ -32,6 +37,8 @@ impl TryFrom<TypeMap> for AppState {
             config: extract(&mut map)?,
             runner: extract(&mut map)?,
             redis: extract(&mut map)?,
+            jobs: extract(&mut map)?,
+            secrets: extract(&mut map)?,
         })))
     }
 }